@@ -0,0 +1,148 @@
+// Throughput of the JSON backend under concurrent load, in each durability
+// mode and with every call serialized behind one lock the way the storage
+// used to be. Run with `cargo bench --bench throughput`.
+//
+// Two workloads: mostly reads with a write every tenth operation, and
+// nothing but writes. How much concurrent reads gain depends on the number
+// of cores, how much debouncing gains on how long an fsync takes. READ P99
+// shows whether reads wait for the writes going on next to them.
+
+use rest_api::models::{BatchOperation, CreateTodoRequest, TodoQuery, UpdateTodoRequest};
+use rest_api::repository::{StorageRepository, TodoRepository};
+use rest_api::writer::{DEFAULT_FLUSH_DELAY, Durability};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const WORKERS: usize = 16;
+const OPS_PER_WORKER: usize = 1_000;
+const TODOS: u32 = 500;
+
+struct Run {
+    name: &'static str,
+    durability: Durability,
+    // One lock around every call, reads included
+    serialized: bool,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    let runs = [
+        Run {
+            name: "sync, one lock (before)",
+            durability: Durability::Sync,
+            serialized: true,
+        },
+        Run {
+            name: "sync",
+            durability: Durability::Sync,
+            serialized: false,
+        },
+        Run {
+            name: "debounced",
+            durability: Durability::Debounced {
+                delay: DEFAULT_FLUSH_DELAY,
+            },
+            serialized: false,
+        },
+    ];
+
+    println!("{} workers x {} operations\n", WORKERS, OPS_PER_WORKER);
+    // A write every `write_every` operations
+    for (workload, write_every) in [("mostly reads", 10), ("only writes", 1)] {
+        println!(
+            "{:<26} {:>10} {:>12} {:>12} {:>10}",
+            workload, "TIME", "OPS/S", "WRITES/S", "READ P99"
+        );
+        let mut baseline = None;
+        for run in &runs {
+            let (elapsed, reads) = measure(run, write_every).await;
+            let ops = (WORKERS * OPS_PER_WORKER) as f64;
+            let per_second = ops / elapsed.as_secs_f64();
+            let gain = per_second / *baseline.get_or_insert(per_second);
+            let read_p99 = percentile(reads, 0.99)
+                .map_or("-".to_string(), |p99| format!("{:.2}ms", p99.as_secs_f64() * 1000.0));
+            println!(
+                "  {:<24} {:>8.0}ms {:>12.0} {:>12.0} {:>10}   x{:.1}",
+                run.name,
+                elapsed.as_secs_f64() * 1000.0,
+                per_second,
+                per_second / write_every as f64,
+                read_p99,
+                gain
+            );
+        }
+        println!();
+    }
+}
+
+// Time the whole run took, and how long each read took
+async fn measure(run: &Run, write_every: usize) -> (Duration, Vec<Duration>) {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = StorageRepository::json_file(dir.path().join("todos.json"))
+        .unwrap()
+        .with_durability(run.durability)
+        .unwrap();
+    let repo: Arc<dyn TodoRepository> = Arc::new(repo);
+    let batch = (1..=TODOS)
+        .map(|i| BatchOperation::Create {
+            todo: CreateTodoRequest {
+                title: format!("todo {}", i),
+                ..Default::default()
+            },
+        })
+        .collect();
+    repo.batch(batch).await.unwrap();
+    let lock = run.serialized.then(|| Arc::new(Mutex::new(())));
+
+    let started = Instant::now();
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|worker| {
+            let repo = repo.clone();
+            let lock = lock.clone();
+            tokio::spawn(async move {
+                let mut reads = Vec::new();
+                for op in 0..OPS_PER_WORKER {
+                    let op_started = Instant::now();
+                    let _guard = match &lock {
+                        Some(lock) => Some(lock.lock().await),
+                        None => None,
+                    };
+                    let id = ((worker * OPS_PER_WORKER + op) as u32 % TODOS) + 1;
+                    if op % write_every == 0 {
+                        let changes = UpdateTodoRequest {
+                            completed: Some(op % 2 == 0),
+                            ..Default::default()
+                        };
+                        repo.update(id, changes, None).await.unwrap();
+                        continue;
+                    }
+                    if op % 2 == 0 {
+                        repo.get(id).await.unwrap();
+                    } else {
+                        let query = TodoQuery {
+                            limit: Some(20),
+                            ..Default::default()
+                        };
+                        repo.query(&query).await.unwrap();
+                    }
+                    reads.push(op_started.elapsed());
+                }
+                reads
+            })
+        })
+        .collect();
+    let mut reads = Vec::new();
+    for worker in workers {
+        reads.extend(worker.await.unwrap());
+    }
+    // Debounced writes only count once they are on disk
+    repo.flush().await.unwrap();
+    (started.elapsed(), reads)
+}
+
+fn percentile(mut durations: Vec<Duration>, fraction: f64) -> Option<Duration> {
+    durations.sort();
+    let index = ((durations.len() as f64 * fraction) as usize).min(durations.len().checked_sub(1)?);
+    Some(durations[index])
+}
@@ -0,0 +1,446 @@
+// User accounts and their API keys. A key is a random token that is shown
+// once when it is created and only kept as a SHA-256 hash. Requests send it
+// as `Authorization: Bearer <key>`, or as the password of HTTP Basic auth
+// for calendar apps that cannot send anything else.
+
+use crate::error::{ApiError, StorageError};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{HeaderMap, header, request::Parts},
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use utoipa::ToSchema;
+
+// Every key starts with this, so leaked keys are easy to search for
+pub const KEY_PREFIX: &str = "tk_";
+// Name of the admin created when there are no users yet
+pub const FIRST_ADMIN: &str = "admin";
+// File its key is written to, see `Accounts::bootstrap_to`
+pub const FIRST_KEY_FILE: &str = "admin.key";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    // Manages users and keys; sees only their own todos like everyone else
+    Admin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct User {
+    pub id: u32,
+    pub name: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+// A key as it is stored: its hash, plus its first characters so people can
+// tell their keys apart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredKey {
+    id: u32,
+    user_id: u32,
+    hint: String,
+    hash: String,
+    created_at: DateTime<Utc>,
+}
+
+// What is shown of a key after it was created
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct KeyInfo {
+    pub id: u32,
+    // `tk_1a2b3c4d…`
+    pub hint: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UserInfo {
+    #[serde(flatten)]
+    pub user: User,
+    pub keys: Vec<KeyInfo>,
+}
+
+// Where the accounts are kept
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccountStore {
+    File(PathBuf),
+}
+
+impl AccountStore {
+    pub fn open(&self) -> Result<Accounts, StorageError> {
+        match self {
+            AccountStore::File(path) => Accounts::open(path),
+        }
+    }
+
+    // Where the key of the first admin is written: next to users.json
+    pub fn first_key_path(&self) -> PathBuf {
+        match self {
+            AccountStore::File(path) => path.with_file_name(FIRST_KEY_FILE),
+        }
+    }
+}
+
+// Why an account change was refused; nothing was changed
+#[derive(Debug)]
+pub enum AccountError {
+    InvalidName(String),
+    NameTaken(String),
+    UserNotFound(u32),
+    KeyNotFound(u32),
+    // Revoking it would leave no admin who can log in
+    LastAdminKey(u32),
+    Storage(StorageError),
+}
+
+impl From<StorageError> for AccountError {
+    fn from(err: StorageError) -> Self {
+        AccountError::Storage(err)
+    }
+}
+
+impl From<AccountError> for ApiError {
+    fn from(err: AccountError) -> Self {
+        match err {
+            AccountError::InvalidName(message) => ApiError::BadRequest("invalid_name", message),
+            AccountError::NameTaken(name) => ApiError::Conflict(
+                "user_exists",
+                format!("A user named '{}' already exists", name),
+            ),
+            AccountError::UserNotFound(_) => ApiError::UserNotFound,
+            AccountError::KeyNotFound(_) => ApiError::KeyNotFound,
+            AccountError::LastAdminKey(id) => ApiError::Conflict(
+                "last_admin_key",
+                format!("Key {} is the last key of any admin and cannot be revoked", id),
+            ),
+            AccountError::Storage(err) => ApiError::Storage(err),
+        }
+    }
+}
+
+// Layout of users.json
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct AccountsFile {
+    users: Vec<User>,
+    keys: Vec<StoredKey>,
+}
+
+pub struct Accounts {
+    state: Mutex<AccountsFile>,
+    // `None` keeps everything in memory
+    path: Option<PathBuf>,
+}
+
+impl Accounts {
+    // Accounts kept in a JSON file; a missing file means no users yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let file = match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => AccountsFile::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Accounts {
+            state: Mutex::new(file),
+            path: Some(path),
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Accounts {
+            state: Mutex::new(AccountsFile::default()),
+            path: None,
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, AccountsFile>, StorageError> {
+        self.state
+            .lock()
+            .map_err(|_| StorageError::Unavailable("accounts lock poisoned".to_string()))
+    }
+
+    // Create the first admin if there are no users yet, and return it with
+    // its key. Its id is 1, so it owns the todos from before accounts.
+    pub fn bootstrap(&self) -> Result<Option<(UserInfo, String)>, AccountError> {
+        self.bootstrap_with(|_| Ok(()))
+    }
+
+    // Like `bootstrap`, but the key goes to `path` only, readable by its
+    // owner alone, never to the terminal or the logs. It is written before
+    // users.json: when it cannot be, there is no admin whose key was lost.
+    pub fn bootstrap_to(&self, path: &Path) -> Result<Option<UserInfo>, AccountError> {
+        let admin = self.bootstrap_with(|key| write_key_file(path, key))?;
+        Ok(admin.map(|(admin, _)| admin))
+    }
+
+    fn bootstrap_with(
+        &self,
+        keep: impl FnOnce(&str) -> io::Result<()>,
+    ) -> Result<Option<(UserInfo, String)>, AccountError> {
+        if !self.lock()?.users.is_empty() {
+            return Ok(None);
+        }
+        self.add_user(FIRST_ADMIN, Role::Admin, keep).map(Some)
+    }
+
+    // The user a key belongs to, `None` for unknown and revoked keys
+    pub fn authenticate(&self, key: &str) -> Option<User> {
+        let hash = hash_key(key.trim());
+        let state = self.lock().ok()?;
+        let key = state.keys.iter().find(|stored| stored.hash == hash)?;
+        state.users.iter().find(|user| user.id == key.user_id).cloned()
+    }
+
+    // Ordered by id
+    pub fn users(&self) -> Result<Vec<UserInfo>, StorageError> {
+        let state = self.lock()?;
+        Ok(state.users.iter().map(|user| info(&state, user)).collect())
+    }
+
+    pub fn create_user(&self, name: &str, role: Role) -> Result<(UserInfo, String), AccountError> {
+        self.add_user(name, role, |_| Ok(()))
+    }
+
+    // `keep` gets the new key before the user is saved, and an error from it
+    // leaves the accounts as they were
+    fn add_user(
+        &self,
+        name: &str,
+        role: Role,
+        keep: impl FnOnce(&str) -> io::Result<()>,
+    ) -> Result<(UserInfo, String), AccountError> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > 64 {
+            return Err(AccountError::InvalidName(
+                "User name must have 1 to 64 characters".to_string(),
+            ));
+        }
+        let mut state = self.lock()?;
+        if state.users.iter().any(|user| user.name.eq_ignore_ascii_case(name)) {
+            return Err(AccountError::NameTaken(name.to_string()));
+        }
+
+        let mut next = state.clone();
+        let user = User {
+            id: next.users.iter().map(|user| user.id).max().unwrap_or(0) + 1,
+            name: name.to_string(),
+            role,
+            created_at: Utc::now(),
+        };
+        next.users.push(user.clone());
+        let key = add_key(&mut next, user.id)?;
+        keep(&key).map_err(StorageError::from)?;
+        self.save(&next)?;
+        *state = next;
+        Ok((info(&state, &user), key))
+    }
+
+    // A new key for a user, on top of the ones they have
+    pub fn create_key(&self, user_id: u32) -> Result<(KeyInfo, String), AccountError> {
+        let mut state = self.lock()?;
+        if !state.users.iter().any(|user| user.id == user_id) {
+            return Err(AccountError::UserNotFound(user_id));
+        }
+        let mut next = state.clone();
+        let key = add_key(&mut next, user_id)?;
+        self.save(&next)?;
+        *state = next;
+        let stored = state.keys.last().expect("the key was just added");
+        Ok((key_info(stored), key))
+    }
+
+    // Requests with the key fail from now on
+    pub fn revoke_key(&self, key_id: u32) -> Result<(), AccountError> {
+        let mut state = self.lock()?;
+        let Some(index) = state.keys.iter().position(|key| key.id == key_id) else {
+            return Err(AccountError::KeyNotFound(key_id));
+        };
+        let is_admin = |state: &AccountsFile, user_id: u32| {
+            state.users.iter().any(|user| user.id == user_id && user.is_admin())
+        };
+        let mut next = state.clone();
+        let revoked = next.keys.remove(index);
+        if is_admin(&next, revoked.user_id)
+            && !next.keys.iter().any(|key| is_admin(&next, key.user_id))
+        {
+            return Err(AccountError::LastAdminKey(key_id));
+        }
+        self.save(&next)?;
+        *state = next;
+        Ok(())
+    }
+
+    // Written to a temp file and renamed over the old one, so a crash never
+    // leaves half a file behind
+    fn save(&self, file: &AccountsFile) -> Result<(), StorageError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut tmp_name = path.as_os_str().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_file = PathBuf::from(tmp_name);
+        let mut out = fs::File::create(&tmp_file)?;
+        out.write_all(serde_json::to_string_pretty(file)?.as_bytes())?;
+        out.sync_all()?;
+        fs::rename(&tmp_file, path)?;
+        Ok(())
+    }
+}
+
+fn info(state: &AccountsFile, user: &User) -> UserInfo {
+    UserInfo {
+        user: user.clone(),
+        keys: state
+            .keys
+            .iter()
+            .filter(|key| key.user_id == user.id)
+            .map(key_info)
+            .collect(),
+    }
+}
+
+fn key_info(key: &StoredKey) -> KeyInfo {
+    KeyInfo {
+        id: key.id,
+        hint: key.hint.clone(),
+        created_at: key.created_at,
+    }
+}
+
+// Writes `key` to a new file only its owner can read. A file left from
+// before is replaced rather than reused, as anyone may have read it.
+fn write_key_file(path: &Path, key: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", key)?;
+    file.sync_all()
+}
+
+// Adds a fresh key for `user_id` and returns it in the clear
+fn add_key(state: &mut AccountsFile, user_id: u32) -> Result<String, StorageError> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).map_err(|err| StorageError::Unavailable(err.to_string()))?;
+    let mut key = KEY_PREFIX.to_string();
+    for byte in bytes {
+        let _ = write!(key, "{:02x}", byte);
+    }
+    state.keys.push(StoredKey {
+        id: state.keys.iter().map(|key| key.id).max().unwrap_or(0) + 1,
+        user_id,
+        hint: format!("{}…", &key[..KEY_PREFIX.len() + 8]),
+        hash: hash_key(&key),
+        created_at: Utc::now(),
+    });
+    Ok(key)
+}
+
+// Keys are long and random, so a plain hash is as good as a slow one
+fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+// The key of `Authorization: Bearer <key>` or the password of
+// `Authorization: Basic <user:key>`
+fn presented_key(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?.trim();
+    let (scheme, credentials) = value.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return Some(credentials.trim().to_string());
+    }
+    if scheme.eq_ignore_ascii_case("basic") {
+        let decoded = STANDARD.decode(credentials.trim()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        return decoded.split_once(':').map(|(_, password)| password.to_string());
+    }
+    None
+}
+
+// The user a request is made by. `None` when the server runs without
+// accounts, and then every request sees every todo.
+pub struct Caller(pub Option<User>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Caller {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let Some(accounts) = parts.extensions.get::<Arc<Accounts>>() else {
+            return Ok(Caller(None));
+        };
+        let Some(key) = presented_key(&parts.headers) else {
+            return Err(ApiError::Unauthorized(
+                "Send an API key as 'Authorization: Bearer <key>'".to_string(),
+            ));
+        };
+        match accounts.authenticate(&key) {
+            Some(user) => Ok(Caller(Some(user))),
+            None => Err(ApiError::Unauthorized("Invalid API key".to_string())),
+        }
+    }
+}
+
+// An admin, together with the accounts they manage
+pub struct Admin {
+    pub user: User,
+    pub accounts: Arc<Accounts>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Caller(user) = Caller::from_request_parts(parts, state).await?;
+        let (Some(user), Some(accounts)) = (user, parts.extensions.get::<Arc<Accounts>>()) else {
+            return Err(accounts_disabled());
+        };
+        if !user.is_admin() {
+            return Err(ApiError::Forbidden(
+                "admin_only",
+                "Only admins can manage users and keys".to_string(),
+            ));
+        }
+        Ok(Admin {
+            user,
+            accounts: accounts.clone(),
+        })
+    }
+}
+
+pub fn accounts_disabled() -> ApiError {
+    ApiError::Forbidden(
+        "accounts_disabled",
+        "This server runs without accounts".to_string(),
+    )
+}
@@ -0,0 +1,412 @@
+// Snapshots of the whole todo store, taken on a schedule and on demand. Each
+// backup is a todos.json of its own (encrypted like the data file when
+// there is a key), named after the time it was taken, with a checksum file
+// next to it in `sha256sum` format:
+//
+//   backups/todos-20250131T170000.000Z.json
+//   backups/todos-20250131T170000.000Z.json.sha256
+//
+// Only the newest `keep` backups are kept. When the data file gets another
+// key, `reencrypt` moves the backups over to it.
+
+use crate::encryption::{self, Cipher};
+use crate::error::{ApiError, StorageError};
+use crate::repository::TodoRepository;
+use crate::schema::{self, DataFile, LoadError};
+use crate::storage::SnapshotFile;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+pub const DEFAULT_KEEP: usize = 7;
+pub const DEFAULT_EVERY: Duration = Duration::from_secs(24 * 60 * 60);
+
+const PREFIX: &str = "todos-";
+const EXTENSION: &str = ".json";
+// Sorts like the time it stands for
+const STAMP: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+// Where backups go, how many are kept and how often one is taken
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupPolicy {
+    pub dir: PathBuf,
+    pub keep: usize,
+    // `None` takes backups only on demand
+    pub every: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Backup {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub size: u64,
+    // Hex SHA-256 of the file as its checksum file records it, `None`
+    // without a checksum file
+    pub sha256: Option<String>,
+    // Id of the key it is encrypted with, `None` when it is not encrypted
+    pub key_id: Option<String>,
+    // Whether the file still matches its checksum and can be read with the
+    // configured key, i.e. whether it can be restored
+    pub valid: bool,
+}
+
+// What a restore did
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Restored {
+    pub restored: Backup,
+    // Taken right before, so the restore can be undone
+    pub previous: Backup,
+}
+
+// Why a backup could not be restored; nothing was changed
+#[derive(Debug)]
+pub enum BackupError {
+    NotFound(String),
+    // No checksum, a checksum that does not match, or not a snapshot this
+    // server can read
+    Corrupt(String),
+    // Encrypted with another key than the configured one, or encrypted
+    // while no key is configured
+    KeyMismatch(String),
+    Storage(StorageError),
+}
+
+impl From<StorageError> for BackupError {
+    fn from(err: StorageError) -> Self {
+        BackupError::Storage(err)
+    }
+}
+
+impl From<BackupError> for ApiError {
+    fn from(err: BackupError) -> Self {
+        match err {
+            BackupError::NotFound(_) => ApiError::BackupNotFound,
+            BackupError::Corrupt(message) => ApiError::Unprocessable("backup_corrupt", message),
+            BackupError::KeyMismatch(message) => {
+                ApiError::Unprocessable("backup_key_mismatch", message)
+            }
+            BackupError::Storage(err) => err.into(),
+        }
+    }
+}
+
+impl From<io::Error> for BackupError {
+    fn from(err: io::Error) -> Self {
+        BackupError::Storage(err.into())
+    }
+}
+
+pub struct Backups {
+    repo: Arc<dyn TodoRepository>,
+    policy: BackupPolicy,
+    cipher: Option<Cipher>,
+    // One backup or restore at a time
+    busy: Mutex<()>,
+}
+
+impl Backups {
+    // Backups of everything in `repo`, which has to be the whole store and
+    // not a wrapper showing part of it
+    pub fn new(
+        repo: Arc<dyn TodoRepository>,
+        policy: BackupPolicy,
+        cipher: Option<Cipher>,
+    ) -> Self {
+        Backups {
+            repo,
+            policy,
+            cipher,
+            busy: Mutex::new(()),
+        }
+    }
+
+    pub fn policy(&self) -> &BackupPolicy {
+        &self.policy
+    }
+
+    // Take a backup now and drop the ones beyond `keep`
+    pub async fn create(&self) -> Result<Backup, StorageError> {
+        let _busy = self.busy.lock().await;
+        let backup = self.take().await?;
+        self.prune()?;
+        Ok(backup)
+    }
+
+    // Newest first, each checked against its checksum and the key
+    pub fn list(&self) -> Result<Vec<Backup>, StorageError> {
+        names(&self.policy.dir)?
+            .iter()
+            .map(|name| self.inspect(name).map(|(backup, _)| backup))
+            .collect()
+    }
+
+    // When the schedule takes the next backup: `policy.every` after the
+    // newest one there is, so restarts neither push backups out nor skip
+    // one that is due; right away when there are none yet. `None` without
+    // a schedule.
+    pub fn next_scheduled(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let every = chrono::Duration::from_std(self.policy.every?).ok()?;
+        let newest = names(&self.policy.dir)
+            .ok()
+            .and_then(|names| names.first().and_then(|name| created_at(name)));
+        Some(newest.map_or(now, |newest| newest + every))
+    }
+
+    // Replace everything in the store with backup `name`, after a last
+    // backup of what is there now. The backup is checked and read in full
+    // before anything is changed.
+    pub async fn restore(&self, name: &str) -> Result<Restored, BackupError> {
+        self.restore_through(name, self.repo.as_ref()).await
+    }
+
+    // `restore`, writing the backup through `repo`: the same store behind a
+    // wrapper, like the one that tells event subscribers about it
+    pub async fn restore_through(
+        &self,
+        name: &str,
+        repo: &dyn TodoRepository,
+    ) -> Result<Restored, BackupError> {
+        let _busy = self.busy.lock().await;
+        if created_at(name).is_none() || !self.policy.dir.join(name).is_file() {
+            return Err(BackupError::NotFound(name.to_string()));
+        }
+        let (restored, data) = self.inspect(name)?;
+        if !matches_checksum(&restored, &data) {
+            let reason = match restored.sha256 {
+                Some(_) => "does not match its checksum",
+                None => "has no checksum file",
+            };
+            return Err(BackupError::Corrupt(format!("backup {} {}", name, reason)));
+        }
+        // Matches its checksum, so it is the key that does not fit
+        if !restored.valid {
+            let configured = match &self.cipher {
+                Some(cipher) => format!("the configured key is {}", cipher.id()),
+                None => "no key is configured".to_string(),
+            };
+            return Err(BackupError::KeyMismatch(format!(
+                "backup {} is encrypted with key {}, but {}",
+                name,
+                restored.key_id.as_deref().unwrap_or_default(),
+                configured
+            )));
+        }
+        let snapshot = self.decode(name, data)?;
+
+        // Not pruned: that could remove the backup being restored
+        let previous = self.take().await?;
+        repo.restore_snapshot(snapshot).await?;
+        repo.flush().await?;
+        Ok(Restored { restored, previous })
+    }
+
+    async fn take(&self) -> Result<Backup, StorageError> {
+        let mut snapshot = self.repo.snapshot().await?;
+        fs::create_dir_all(&self.policy.dir)?;
+        // Names are unique to the millisecond; a second backup within the
+        // same one is named after the next
+        let mut created_at = Utc::now();
+        let (name, path) = loop {
+            let name = format!("{}{}{}", PREFIX, created_at.format(STAMP), EXTENSION);
+            let path = self.policy.dir.join(&name);
+            if !path.exists() {
+                break (name, path);
+            }
+            created_at += chrono::Duration::milliseconds(1);
+        };
+        let file = SnapshotFile {
+            path: path.clone(),
+            cipher: self.cipher.clone(),
+        };
+        file.write(&mut snapshot)?;
+
+        // The checksum is of what actually landed on disk
+        let data = fs::read(&path)?;
+        let sha256 = hex(&Sha256::digest(&data));
+        write_atomic(&checksum_file(&path), &format!("{}  {}\n", sha256, name))?;
+        Ok(Backup {
+            name,
+            created_at,
+            size: data.len() as u64,
+            sha256: Some(sha256),
+            key_id: self.cipher.as_ref().map(|cipher| cipher.id().to_string()),
+            valid: true,
+        })
+    }
+
+    // Oldest first, together with their checksum files
+    fn prune(&self) -> Result<(), StorageError> {
+        for backup in self.list()?.into_iter().skip(self.policy.keep) {
+            let path = self.policy.dir.join(&backup.name);
+            fs::remove_file(&path)?;
+            match fs::remove_file(checksum_file(&path)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // What is known about backup `name`, and its contents
+    fn inspect(&self, name: &str) -> Result<(Backup, Vec<u8>), StorageError> {
+        let (mut backup, data) = inspect(&self.policy.dir, name)?;
+        backup.valid &= match (&backup.key_id, &self.cipher) {
+            (None, _) => true,
+            (Some(key_id), Some(cipher)) => key_id == cipher.id(),
+            (Some(_), None) => false,
+        };
+        Ok((backup, data))
+    }
+
+    fn decode(&self, name: &str, data: Vec<u8>) -> Result<DataFile, BackupError> {
+        let corrupt = |reason: String| BackupError::Corrupt(format!("backup {}: {}", name, reason));
+        let text = String::from_utf8(data).map_err(|err| corrupt(err.to_string()))?;
+        let path = self.policy.dir.join(name);
+        let (text, _) = encryption::open_file(self.cipher.as_ref(), &path, text)
+            .map_err(|err| BackupError::Corrupt(err.to_string()))?;
+        match schema::parse(&text) {
+            Ok((snapshot, _)) => Ok(snapshot),
+            Err(LoadError::TooNew(version)) => Err(StorageError::UnsupportedSchema(version).into()),
+            Err(LoadError::Unreadable(reason)) => Err(corrupt(reason)),
+        }
+    }
+}
+
+// Write every backup in `dir` again with `new`, or unencrypted without it,
+// together with a new checksum. Used when the data file moves to another
+// key, so its backups can still be restored afterwards. Backups that do not
+// match their checksum or cannot be read with `old` are left as they are;
+// returns how many were rewritten and the names of those left alone.
+pub fn reencrypt(
+    dir: &Path,
+    old: Option<&Cipher>,
+    new: Option<&Cipher>,
+) -> Result<(usize, Vec<String>), StorageError> {
+    let mut rewritten = 0;
+    let mut skipped = Vec::new();
+    for name in names(dir)? {
+        let (backup, data) = inspect(dir, &name)?;
+        let path = dir.join(&name);
+        let plain = match String::from_utf8(data) {
+            Ok(text) if backup.valid => encryption::open_file(old, &path, text).ok(),
+            _ => None,
+        };
+        let Some((plain, _)) = plain else {
+            skipped.push(name);
+            continue;
+        };
+        let sealed = encryption::seal_file(new, plain)?;
+        // A crash in between leaves a backup that no longer matches its
+        // checksum, which is refused rather than restored wrongly
+        write_atomic(&path, &sealed)?;
+        let sha256 = hex(&Sha256::digest(sealed.as_bytes()));
+        write_atomic(&checksum_file(&path), &format!("{}  {}\n", sha256, name))?;
+        rewritten += 1;
+    }
+    Ok((rewritten, skipped))
+}
+
+// Take a backup every `policy.every`, see `Backups::next_scheduled` for the
+// first one. `None` when backups are only taken on demand.
+pub fn spawn_schedule(backups: Arc<Backups>) -> Option<JoinHandle<()>> {
+    let every = backups.policy.every?;
+    let now = Utc::now();
+    let first = backups.next_scheduled(now)?;
+    // Overdue when negative: then right away
+    let wait = (first - now).to_std().unwrap_or(Duration::ZERO);
+    Some(tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + wait;
+        let mut interval = tokio::time::interval_at(start, every);
+        loop {
+            interval.tick().await;
+            match backups.create().await {
+                Ok(backup) => crate::info!("💾 Backed up the todos to {}", backup.name),
+                Err(err) => crate::warn!("⚠️  Backup failed, will retry: {}", err),
+            }
+        }
+    }))
+}
+
+// Names of the backups in `dir`, newest first
+fn names(dir: &Path) -> Result<Vec<String>, StorageError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if created_at(&name).is_some() {
+            names.push(name);
+        }
+    }
+    names.sort_by(|a, b| b.cmp(a));
+    Ok(names)
+}
+
+// Backup `name` in `dir` and its contents, `valid` when it matches its
+// checksum; whether it can be read with a key is up to the caller
+fn inspect(dir: &Path, name: &str) -> Result<(Backup, Vec<u8>), StorageError> {
+    let path = dir.join(name);
+    let data = fs::read(&path)?;
+    // `<hex>  <name>`, as `sha256sum` writes it
+    let sha256 = match fs::read_to_string(checksum_file(&path)) {
+        Ok(line) => line.split_whitespace().next().map(str::to_ascii_lowercase),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    let key_id = std::str::from_utf8(&data).ok().and_then(encryption::key_id);
+    let mut backup = Backup {
+        name: name.to_string(),
+        created_at: created_at(name).unwrap_or_default(),
+        size: data.len() as u64,
+        sha256,
+        key_id,
+        valid: false,
+    };
+    backup.valid = matches_checksum(&backup, &data);
+    Ok((backup, data))
+}
+
+fn matches_checksum(backup: &Backup, data: &[u8]) -> bool {
+    backup.sha256.as_deref() == Some(hex(&Sha256::digest(data)).as_str())
+}
+
+// When a backup named `name` was taken; `None` if it is not a backup name
+fn created_at(name: &str) -> Option<DateTime<Utc>> {
+    let stamp = name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION)?;
+    NaiveDateTime::parse_from_str(stamp, STAMP).ok().map(|time| time.and_utc())
+}
+
+fn checksum_file(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+fn write_atomic(path: &Path, text: &str) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_file = PathBuf::from(tmp_name);
+    let mut out = fs::File::create(&tmp_file)?;
+    out.write_all(text.as_bytes())?;
+    out.sync_all()?;
+    fs::rename(&tmp_file, path)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
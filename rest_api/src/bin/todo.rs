@@ -0,0 +1,311 @@
+// `todo` - command-line client for the todo server, e.g.
+//   todo add "Buy milk" --due 2025-01-31
+//   todo list --pending
+//   todo done 3
+//   todo rm 3
+//
+// Exit codes: 0 success, 1 the server refused the request, 2 bad usage,
+// 3 no such todo, 4 the server could not be reached or failed.
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rest_api::client::{Client, ClientError, DEFAULT_SERVER, ListFilter};
+use rest_api::models::{CreateTodoRequest, Priority, Todo, UpdateTodoRequest};
+use serde_json::json;
+use std::process::ExitCode;
+
+const REFUSED: u8 = 1;
+const NOT_FOUND: u8 = 3;
+const UNAVAILABLE: u8 = 4;
+
+#[derive(Parser)]
+#[command(name = "todo", version, about = "Manage todos on a todo server")]
+struct Cli {
+    /// Base URL of the server
+    #[arg(long, global = true, env = "TODO_SERVER", default_value = DEFAULT_SERVER)]
+    server: String,
+    /// API key, needed when the server has accounts
+    #[arg(long, global = true, env = "TODO_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Table for people, JSON for scripts
+    #[arg(long, short, global = true, value_enum, default_value_t = Output::Table)]
+    output: Output,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a todo
+    Add(AddArgs),
+    /// List todos
+    #[command(alias = "ls")]
+    List(ListArgs),
+    /// Show one todo
+    Show { id: u32 },
+    /// Mark todos as done
+    Done {
+        #[arg(required = true)]
+        ids: Vec<u32>,
+    },
+    /// Mark todos as not done
+    Reopen {
+        #[arg(required = true)]
+        ids: Vec<u32>,
+    },
+    /// Move todos to the trash
+    Rm {
+        #[arg(required = true)]
+        ids: Vec<u32>,
+        /// Also delete their subtasks
+        #[arg(long)]
+        cascade: bool,
+    },
+}
+
+#[derive(Args)]
+struct AddArgs {
+    title: String,
+    #[arg(long, short)]
+    description: Option<String>,
+    /// A date (midnight UTC) or an RFC 3339 time
+    #[arg(long, value_parser = parse_due)]
+    due: Option<DateTime<Utc>>,
+    /// low, medium, high or urgent
+    #[arg(long, short, value_parser = parse_priority)]
+    priority: Option<Priority>,
+    /// Can be given more than once
+    #[arg(long = "tag", short)]
+    tags: Vec<String>,
+    /// Create it as a subtask of this todo
+    #[arg(long)]
+    parent: Option<u32>,
+    /// Defaults to the default list
+    #[arg(long)]
+    list: Option<u32>,
+}
+
+#[derive(Args)]
+struct ListArgs {
+    /// Only todos that are not done
+    #[arg(long, conflicts_with = "done")]
+    pending: bool,
+    /// Only todos that are done
+    #[arg(long)]
+    done: bool,
+    #[arg(long, short)]
+    tag: Option<String>,
+    /// Defaults to the default list
+    #[arg(long)]
+    list: Option<u32>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut client = Client::new(&cli.server);
+    if let Some(token) = cli.token {
+        client = client.with_token(token);
+    }
+    match run(&client, cli.command, cli.output).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => ExitCode::from(code),
+    }
+}
+
+// Errors are reported on stderr as they happen; the exit code of the first
+// one is returned
+async fn run(client: &Client, command: Command, output: Output) -> Result<(), u8> {
+    match command {
+        Command::Add(args) => {
+            let request = CreateTodoRequest {
+                title: args.title,
+                description: args.description,
+                due_at: args.due,
+                priority: args.priority.unwrap_or_default(),
+                tags: args.tags,
+                parent_id: args.parent,
+                list_id: args.list,
+                ..Default::default()
+            };
+            let todo = client.create(&request).await.map_err(report)?;
+            print_todo(&todo, output);
+        }
+        Command::List(args) => {
+            let filter = ListFilter {
+                list_id: args.list,
+                completed: (args.pending || args.done).then_some(args.done),
+                tag: args.tag,
+            };
+            let todos = client.list(&filter).await.map_err(report)?;
+            print_todos(&todos, output);
+        }
+        Command::Show { id } => {
+            let todo = client.get(id).await.map_err(|err| report_todo(id, err))?;
+            print_todo(&todo, output);
+        }
+        Command::Done { ids } => return set_completed(client, &ids, true, output).await,
+        Command::Reopen { ids } => return set_completed(client, &ids, false, output).await,
+        Command::Rm { ids, cascade } => {
+            let mut deleted = Vec::new();
+            let mut failed = None;
+            for id in ids {
+                match client.delete(id, cascade).await {
+                    Ok(()) => deleted.push(id),
+                    Err(err) => {
+                        failed = failed.or(Some(report_todo(id, err)));
+                    }
+                }
+            }
+            match output {
+                Output::Json => println!("{}", json!({ "deleted": deleted })),
+                Output::Table => deleted.iter().for_each(|id| println!("Deleted todo {}", id)),
+            }
+            return failed.map_or(Ok(()), Err);
+        }
+    }
+    Ok(())
+}
+
+// Updates every todo it can, then prints those
+async fn set_completed(
+    client: &Client,
+    ids: &[u32],
+    completed: bool,
+    output: Output,
+) -> Result<(), u8> {
+    let changes = UpdateTodoRequest {
+        completed: Some(completed),
+        ..Default::default()
+    };
+    let mut todos = Vec::new();
+    let mut failed = None;
+    for id in ids {
+        match client.update(*id, &changes).await {
+            Ok(todo) => todos.push(todo),
+            Err(err) => failed = failed.or(Some(report_todo(*id, err))),
+        }
+    }
+    print_todos(&todos, output);
+    failed.map_or(Ok(()), Err)
+}
+
+fn report(err: ClientError) -> u8 {
+    eprintln!("todo: {}", err);
+    exit_code(&err)
+}
+
+fn report_todo(id: u32, err: ClientError) -> u8 {
+    if err.is_not_found() {
+        eprintln!("todo: no todo {}", id);
+    } else {
+        eprintln!("todo: todo {}: {}", id, err);
+    }
+    exit_code(&err)
+}
+
+fn exit_code(err: &ClientError) -> u8 {
+    match err {
+        _ if err.is_not_found() => NOT_FOUND,
+        ClientError::Api { status, .. } if status.is_client_error() => REFUSED,
+        _ => UNAVAILABLE,
+    }
+}
+
+fn print_todo(todo: &Todo, output: Output) {
+    match output {
+        Output::Json => println!("{}", to_json(todo)),
+        Output::Table => print_table(std::slice::from_ref(todo)),
+    }
+}
+
+fn print_todos(todos: &[Todo], output: Output) {
+    match output {
+        Output::Json => println!("{}", to_json(&todos)),
+        Output::Table if todos.is_empty() => eprintln!("No todos"),
+        Output::Table => print_table(todos),
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string_pretty(value).expect("todos serialize to JSON")
+}
+
+fn print_table(todos: &[Todo]) {
+    let header = ["ID", "DONE", "PRIORITY", "DUE", "TAGS", "TITLE"].map(String::from);
+    let rows: Vec<[String; 6]> = todos
+        .iter()
+        .map(|todo| {
+            [
+                todo.id.to_string(),
+                if todo.completed { "[x]" } else { "[ ]" }.to_string(),
+                priority_name(todo.priority).to_string(),
+                todo.due_at.map(format_due).unwrap_or_default(),
+                todo.tags.join(","),
+                todo.title.clone(),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.clone().map(|cell| cell.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let mut line = String::new();
+        for (index, (cell, width)) in row.iter().zip(widths).enumerate() {
+            // The title is last and not padded
+            if index == row.len() - 1 {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{:<width$}  ", cell, width = width));
+            }
+        }
+        println!("{}", line.trim_end());
+    }
+}
+
+fn priority_name(priority: Priority) -> &'static str {
+    match priority {
+        Priority::Low => "low",
+        Priority::Medium => "medium",
+        Priority::High => "high",
+        Priority::Urgent => "urgent",
+    }
+}
+
+fn parse_priority(value: &str) -> Result<Priority, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "low" => Ok(Priority::Low),
+        "medium" => Ok(Priority::Medium),
+        "high" => Ok(Priority::High),
+        "urgent" => Ok(Priority::Urgent),
+        _ => Err("expected low, medium, high or urgent".to_string()),
+    }
+}
+
+// A date for due dates at midnight
+fn format_due(due: DateTime<Utc>) -> String {
+    if due.time() == NaiveTime::MIN {
+        due.format("%Y-%m-%d").to_string()
+    } else {
+        due.format("%Y-%m-%d %H:%M").to_string()
+    }
+}
+
+fn parse_due(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(due) = DateTime::parse_from_rfc3339(value) {
+        return Ok(due.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| "expected a date (2025-01-31) or an RFC 3339 time".to_string())?;
+    Ok(date.and_time(NaiveTime::MIN).and_utc())
+}
@@ -0,0 +1,169 @@
+// Client for the HTTP API, behind the `todo` command-line tool.
+
+use crate::models::{CreateTodoRequest, Todo, TodoPage, TodoQuery, UpdateTodoRequest};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fmt;
+
+pub const DEFAULT_SERVER: &str = "http://127.0.0.1:3000";
+
+#[derive(Debug)]
+pub enum ClientError {
+    // No answer from the server: it is down, or the URL is wrong
+    Unreachable(String),
+    // The server answered with an error
+    Api {
+        status: StatusCode,
+        code: String,
+        message: String,
+    },
+    // The server answered with something that is not the API's JSON
+    InvalidResponse(String),
+}
+
+impl ClientError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ClientError::Api { status, .. } if *status == StatusCode::NOT_FOUND)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Unreachable(err) => write!(f, "cannot reach the server: {}", err),
+            ClientError::Api { status, message, .. } => {
+                write!(f, "{} ({})", message, status.as_u16())
+            }
+            ClientError::InvalidResponse(err) => write!(f, "unexpected answer: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+// Body of an error answer, see `ApiError`
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+    code: String,
+}
+
+// Filters of `Client::list`; the server's default list without `list_id`
+#[derive(Debug, Clone, Default)]
+pub struct ListFilter {
+    pub list_id: Option<u32>,
+    pub completed: Option<bool>,
+    pub tag: Option<String>,
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    // Without a trailing slash
+    base: String,
+    // API key, sent as a bearer token when the server has accounts
+    token: Option<String>,
+}
+
+impl Client {
+    pub fn new(server: &str) -> Self {
+        Client {
+            http: reqwest::Client::new(),
+            base: server.trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    // Every matching todo, fetching page after page
+    pub async fn list(&self, filter: &ListFilter) -> Result<Vec<Todo>, ClientError> {
+        let mut params = vec![("limit", TodoQuery::MAX_LIMIT.to_string())];
+        if let Some(list_id) = filter.list_id {
+            params.push(("list_id", list_id.to_string()));
+        }
+        if let Some(completed) = filter.completed {
+            params.push(("completed", completed.to_string()));
+        }
+        if let Some(tag) = &filter.tag {
+            params.push(("tag", tag.clone()));
+        }
+
+        let mut todos = Vec::new();
+        loop {
+            let offset = ("offset", todos.len().to_string());
+            let request = self.request(Method::GET, "/todos").query(&params).query(&[offset]);
+            let page: TodoPage = self.send(request).await?;
+            let done = page.next.is_none() || page.todos.is_empty();
+            todos.extend(page.todos);
+            if done {
+                return Ok(todos);
+            }
+        }
+    }
+
+    pub async fn get(&self, id: u32) -> Result<Todo, ClientError> {
+        self.send(self.request(Method::GET, &format!("/todos/{}", id))).await
+    }
+
+    pub async fn create(&self, todo: &CreateTodoRequest) -> Result<Todo, ClientError> {
+        self.send(self.request(Method::POST, "/todos").json(todo)).await
+    }
+
+    pub async fn update(&self, id: u32, changes: &UpdateTodoRequest) -> Result<Todo, ClientError> {
+        self.send(self.request(Method::PUT, &format!("/todos/{}", id)).json(changes)).await
+    }
+
+    // Moves the todo to the trash; `cascade` takes its subtasks along
+    pub async fn delete(&self, id: u32, cascade: bool) -> Result<(), ClientError> {
+        let request = self
+            .request(Method::DELETE, &format!("/todos/{}", id))
+            .query(&[("cascade", cascade)]);
+        self.execute(request).await.map(drop)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.base, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ClientError> {
+        let response = self.execute(request).await?;
+        response
+            .json()
+            .await
+            .map_err(|err| ClientError::InvalidResponse(err.to_string()))
+    }
+
+    // The response if it is a success, the API error otherwise
+    async fn execute(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let response = request.send().await.map_err(|err| {
+            if err.is_builder() {
+                ClientError::InvalidResponse(err.to_string())
+            } else {
+                ClientError::Unreachable(err.to_string())
+            }
+        })?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let text = response.text().await.unwrap_or_default();
+        // Some errors (bad query strings, unknown routes) are not JSON
+        let body = serde_json::from_str(&text).unwrap_or(ErrorBody {
+            error: text,
+            code: String::new(),
+        });
+        Err(ClientError::Api {
+            status,
+            code: body.code,
+            message: body.error,
+        })
+    }
+}
@@ -0,0 +1,98 @@
+// Maintenance commands that work on the data file instead of serving it.
+// They open todos.json like the server does, so the server must not run
+// at the same time.
+
+use crate::backup;
+use crate::config::{Command, Config};
+use crate::encryption::Cipher;
+use crate::logging::{self, LogLevel};
+use crate::repository::Backend;
+use crate::storage::Storage;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub fn run(command: &Command, config: &Config) -> Result<(), String> {
+    match command {
+        Command::Keygen => keygen(),
+        Command::RotateKey { new_key_file } => rotate_key(config, new_key_file),
+        Command::Decrypt { output, in_place } => decrypt(config, output.as_deref(), *in_place),
+    }
+}
+
+// Needs no configuration, so a broken one does not stand in the way
+pub fn keygen() -> Result<(), String> {
+    let key = Cipher::generate_key().map_err(|err| err.to_string())?;
+    println!("{}", key);
+    Ok(())
+}
+
+fn rotate_key(config: &Config, new_key_file: &Path) -> Result<(), String> {
+    let new = Cipher::from_file(new_key_file)
+        .map_err(|err| format!("key_file {}: {}", new_key_file.display(), err))?;
+    let mut storage = open(config)?;
+    storage.reencrypt(Some(new.clone())).map_err(|err| err.to_string())?;
+    match &config.cipher {
+        Some(old) => println!("🔐 Re-encrypted with key {} (was key {})", new.id(), old.id()),
+        None => println!("🔐 Encrypted with key {}", new.id()),
+    }
+    reencrypt_backups(config, Some(&new))?;
+    println!("   Configure the new key before starting the server again");
+    Ok(())
+}
+
+// Backups follow the data file to its new key, or out of encryption
+fn reencrypt_backups(config: &Config, new: Option<&Cipher>) -> Result<(), String> {
+    let Some(policy) = &config.backups else {
+        return Ok(());
+    };
+    let (rewritten, skipped) = backup::reencrypt(&policy.dir, config.cipher.as_ref(), new)
+        .map_err(|err| format!("backups in {}: {}", policy.dir.display(), err))?;
+    if rewritten > 0 {
+        println!("   Rewrote {} backup(s) in {}", rewritten, policy.dir.display());
+    }
+    for name in skipped {
+        crate::warn!(
+            "⚠️  Left backup {} as it is: damaged or encrypted with yet another key",
+            name
+        );
+    }
+    Ok(())
+}
+
+// Without `output` the todos go to standard output, so nothing else may
+fn decrypt(config: &Config, output: Option<&Path>, in_place: bool) -> Result<(), String> {
+    if output.is_none() && !in_place && logging::enabled(LogLevel::Info) {
+        logging::set_level(LogLevel::Warn);
+    }
+    let mut storage = open(config)?;
+    if in_place {
+        storage.reencrypt(None).map_err(|err| err.to_string())?;
+        println!("🔓 Stored unencrypted, remove the key from the configuration");
+        reencrypt_backups(config, None)?;
+        return Ok(());
+    }
+    let json = storage.export().map_err(|err| err.to_string())?;
+    match output {
+        Some(path) => {
+            fs::write(path, json).map_err(|err| format!("{}: {}", path.display(), err))?;
+            println!("🔓 Wrote the decrypted todos to {}", path.display());
+        }
+        None => {
+            // Piped into `head` and the like, which may stop reading early
+            match writeln!(io::stdout().lock(), "{}", json) {
+                Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err.to_string()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn open(config: &Config) -> Result<Storage, String> {
+    let path: &PathBuf = match &config.backend {
+        Backend::Json(path) => path,
+        other => return Err(format!("only the json backend is encrypted, not {:?}", other)),
+    };
+    Storage::open(path, config.cipher.clone()).map_err(|err| err.to_string())
+}
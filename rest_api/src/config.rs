@@ -0,0 +1,533 @@
+// Server configuration. Each setting comes from the first of these that has
+// it: command-line flags, `TODO_*` environment variables, the TOML file,
+// built-in defaults. Everything is checked before the server starts, and
+// all problems are reported together.
+
+use crate::auth::AccountStore;
+use crate::backup::{self, BackupPolicy};
+use crate::encryption::Cipher;
+use crate::history::HistoryRetention;
+use crate::logging::LogLevel;
+use crate::repository::Backend;
+use crate::trash::TrashRetention;
+use crate::writer::{DEFAULT_FLUSH_DELAY, Durability};
+use chrono::Duration;
+use clap::{Args, Parser, Subcommand};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Read from the working directory when it exists and no other file is given
+pub const DEFAULT_CONFIG_FILE: &str = "rest_api.toml";
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const DEFAULT_PORT: u16 = 3000;
+// Debounced writes a crash can lose are bounded by this
+pub const MAX_FLUSH_MS: u64 = 10_000;
+// A year
+pub const MAX_BACKUP_HOURS: u64 = 24 * 365;
+
+// Command line of the server
+#[derive(Debug, Default, Parser)]
+#[command(name = "rest_api", version, about = "Todo REST API server")]
+pub struct Flags {
+    /// TOML configuration file [env: TODO_CONFIG] [default: rest_api.toml if it exists]
+    #[arg(long, short)]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub settings: Settings,
+    // Without one the server runs
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// Maintenance of the data file, see `commands`. Stop the server first.
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Print a new random encryption key
+    Keygen,
+    /// Encrypt the data file with another key (or for the first time)
+    RotateKey {
+        /// File holding the new key
+        #[arg(long)]
+        new_key_file: PathBuf,
+    },
+    /// Write all todos decrypted, as a plain todos.json
+    Decrypt {
+        /// File to write to [default: standard output]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Store the data file unencrypted from now on
+        #[arg(long, conflicts_with = "output")]
+        in_place: bool,
+    },
+}
+
+// One source of settings; what it leaves out comes from the next one
+#[derive(Debug, Clone, Default, PartialEq, Args)]
+pub struct Settings {
+    /// Address to listen on [env: TODO_HOST] [default: 127.0.0.1]
+    #[arg(long)]
+    pub host: Option<String>,
+    /// Port to listen on [env: TODO_PORT] [default: 3000]
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// error, warn, info or debug (logs every request) [env: TODO_LOG_LEVEL] [default: info]
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// json, memory or sqlite [env: TODO_BACKEND] [default: json]
+    #[arg(long)]
+    pub backend: Option<String>,
+    /// Data file [env: TODO_DATA_PATH] [default: todos.json, or todos.db for sqlite]
+    #[arg(long)]
+    pub data_path: Option<PathBuf>,
+    /// Revisions kept per todo, 0 turns history off [env: TODO_HISTORY_LIMIT] [default: 50]
+    #[arg(long)]
+    pub history_limit: Option<usize>,
+    /// Drop revisions older than this many days [env: TODO_HISTORY_DAYS]
+    #[arg(long)]
+    pub history_days: Option<i64>,
+    /// Purge deleted todos after this many days, 0 never [env: TODO_TRASH_DAYS] [default: 30]
+    #[arg(long)]
+    pub trash_days: Option<i64>,
+    /// sync (on disk before answering) or debounced [env: TODO_DURABILITY] [default: sync]
+    #[arg(long)]
+    pub durability: Option<String>,
+    /// Milliseconds debounced writes are collected [env: TODO_FLUSH_MS] [default: 50]
+    #[arg(long)]
+    pub flush_ms: Option<u64>,
+    /// Require API keys and give every user their own todos; needs the json
+    /// or sqlite backend [env: TODO_AUTH] [default: false]
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub auth: Option<bool>,
+    /// Users and key hashes [env: TODO_USERS_PATH] [default: users.json next to the data file]
+    #[arg(long)]
+    pub users_path: Option<PathBuf>,
+    /// File with the key todos.json is encrypted with [env: TODO_KEY_FILE]
+    #[arg(long)]
+    pub key_file: Option<PathBuf>,
+    // The key itself, only from TODO_ENCRYPTION_KEY: on the command line
+    // every user of the machine could read it
+    #[arg(skip)]
+    pub key: Option<String>,
+    /// Directory for backups [env: TODO_BACKUP_DIR] [default: backups next to the data file]
+    #[arg(long)]
+    pub backup_dir: Option<PathBuf>,
+    /// Backups kept, 0 turns backups off [env: TODO_BACKUP_KEEP] [default: 7]
+    #[arg(long)]
+    pub backup_keep: Option<usize>,
+    /// Hours between backups, 0 only on demand [env: TODO_BACKUP_HOURS] [default: 24]
+    #[arg(long)]
+    pub backup_hours: Option<u64>,
+}
+
+impl Settings {
+    // Settings from `TODO_*` variables, looked up with `var`, and the
+    // variables that could not be read. Empty variables count as unset.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> (Settings, Vec<String>) {
+        let var = &|name: &str| var(name).filter(|value| !value.trim().is_empty());
+        let mut problems = Vec::new();
+        let settings = Settings {
+            port: parse_var("TODO_PORT", var, "a port", &mut problems),
+            history_limit: parse_var("TODO_HISTORY_LIMIT", var, "a number", &mut problems),
+            history_days: parse_var("TODO_HISTORY_DAYS", var, "a number", &mut problems),
+            trash_days: parse_var("TODO_TRASH_DAYS", var, "a number", &mut problems),
+            flush_ms: parse_var("TODO_FLUSH_MS", var, "a number", &mut problems),
+            auth: parse_var("TODO_AUTH", var, "true or false", &mut problems),
+            backup_keep: parse_var("TODO_BACKUP_KEEP", var, "a number", &mut problems),
+            backup_hours: parse_var("TODO_BACKUP_HOURS", var, "a number", &mut problems),
+            host: var("TODO_HOST"),
+            log_level: var("TODO_LOG_LEVEL"),
+            backend: var("TODO_BACKEND"),
+            durability: var("TODO_DURABILITY"),
+            data_path: var("TODO_DATA_PATH").map(PathBuf::from),
+            users_path: var("TODO_USERS_PATH").map(PathBuf::from),
+            key_file: var("TODO_KEY_FILE").map(PathBuf::from),
+            key: var("TODO_ENCRYPTION_KEY"),
+            backup_dir: var("TODO_BACKUP_DIR").map(PathBuf::from),
+        };
+        (settings, problems)
+    }
+
+    // What this sets, anything else from `lower`
+    pub fn over(self, lower: Settings) -> Settings {
+        Settings {
+            host: self.host.or(lower.host),
+            port: self.port.or(lower.port),
+            log_level: self.log_level.or(lower.log_level),
+            backend: self.backend.or(lower.backend),
+            data_path: self.data_path.or(lower.data_path),
+            history_limit: self.history_limit.or(lower.history_limit),
+            history_days: self.history_days.or(lower.history_days),
+            trash_days: self.trash_days.or(lower.trash_days),
+            durability: self.durability.or(lower.durability),
+            flush_ms: self.flush_ms.or(lower.flush_ms),
+            auth: self.auth.or(lower.auth),
+            users_path: self.users_path.or(lower.users_path),
+            key_file: self.key_file.or(lower.key_file),
+            key: self.key.or(lower.key),
+            backup_dir: self.backup_dir.or(lower.backup_dir),
+            backup_keep: self.backup_keep.or(lower.backup_keep),
+            backup_hours: self.backup_hours.or(lower.backup_hours),
+        }
+    }
+}
+
+fn parse_var<T: FromStr>(
+    name: &str,
+    var: impl Fn(&str) -> Option<String>,
+    what: &str,
+    problems: &mut Vec<String>,
+) -> Option<T> {
+    let value = var(name)?;
+    let parsed = value.trim().parse().ok();
+    if parsed.is_none() {
+        problems.push(format!("{} '{}' is not {}", name, value, what));
+    }
+    parsed
+}
+
+// Layout of the TOML file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileSettings {
+    server: ServerSection,
+    storage: StorageSection,
+    auth: AuthSection,
+    encryption: EncryptionSection,
+    backups: BackupsSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    host: Option<String>,
+    port: Option<u16>,
+    log_level: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StorageSection {
+    backend: Option<String>,
+    data_path: Option<PathBuf>,
+    history_limit: Option<usize>,
+    history_days: Option<i64>,
+    trash_days: Option<i64>,
+    durability: Option<String>,
+    flush_ms: Option<u64>,
+}
+
+// No `key`: keys do not belong in a file that gets copied around
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct EncryptionSection {
+    key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BackupsSection {
+    dir: Option<PathBuf>,
+    keep: Option<usize>,
+    every_hours: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSection {
+    enabled: Option<bool>,
+    users_path: Option<PathBuf>,
+}
+
+impl Settings {
+    // Settings of a TOML file. Relative paths are taken relative to the
+    // directory of the file.
+    pub fn from_toml(text: &str, dir: &Path) -> Result<Settings, String> {
+        let file: FileSettings = toml::from_str(text).map_err(|err| err.to_string())?;
+        Ok(Settings {
+            host: file.server.host,
+            port: file.server.port,
+            log_level: file.server.log_level,
+            backend: file.storage.backend,
+            data_path: file.storage.data_path.map(|path| dir.join(path)),
+            history_limit: file.storage.history_limit,
+            history_days: file.storage.history_days,
+            trash_days: file.storage.trash_days,
+            durability: file.storage.durability,
+            flush_ms: file.storage.flush_ms,
+            auth: file.auth.enabled,
+            users_path: file.auth.users_path.map(|path| dir.join(path)),
+            key_file: file.encryption.key_file.map(|path| dir.join(path)),
+            key: None,
+            backup_dir: file.backups.dir.map(|path| dir.join(path)),
+            backup_keep: file.backups.keep,
+            backup_hours: file.backups.every_hours,
+        })
+    }
+}
+
+// Everything the server runs with
+#[derive(Debug, Clone)]
+pub struct Config {
+    // The TOML file that was read, if any
+    pub file: Option<PathBuf>,
+    pub addr: SocketAddr,
+    pub log_level: LogLevel,
+    pub backend: Backend,
+    pub history: HistoryRetention,
+    pub trash: TrashRetention,
+    pub durability: Durability,
+    // Encrypts todos.json and its journal when set
+    pub cipher: Option<Cipher>,
+    // `None` takes no backups
+    pub backups: Option<BackupPolicy>,
+    // `None` runs without accounts: no API keys, everyone sees every todo
+    pub accounts: Option<AccountStore>,
+}
+
+// Every problem found in the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // Read the file, the environment (through `var`) and the flags, and
+    // check the result
+    pub fn load(flags: Flags, var: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigError> {
+        let mut problems = Vec::new();
+
+        // A file that was asked for has to be there, the default one not
+        let named = flags.config.or_else(|| var("TODO_CONFIG").map(PathBuf::from));
+        let path = named.clone().or_else(|| {
+            let default = PathBuf::from(DEFAULT_CONFIG_FILE);
+            default.is_file().then_some(default)
+        });
+        let mut file = Settings::default();
+        if let Some(path) = &path {
+            match fs::read_to_string(path) {
+                Ok(text) => {
+                    let dir = path.parent().unwrap_or(Path::new(""));
+                    match Settings::from_toml(&text, dir) {
+                        Ok(settings) => file = settings,
+                        Err(err) => problems.push(format!("{}: {}", path.display(), err.trim())),
+                    }
+                }
+                Err(err) => problems.push(format!("cannot read {}: {}", path.display(), err)),
+            }
+        }
+        let (env, env_problems) = Settings::from_env(&var);
+        problems.extend(env_problems);
+
+        let settings = flags.settings.over(env).over(file);
+        match Config::from_settings(settings) {
+            Ok(config) if problems.is_empty() => Ok(Config { file: path, ..config }),
+            Ok(_) => Err(ConfigError(problems)),
+            Err(ConfigError(more)) => {
+                problems.extend(more);
+                Err(ConfigError(problems))
+            }
+        }
+    }
+
+    // Check merged settings, filling in defaults
+    pub fn from_settings(settings: Settings) -> Result<Config, ConfigError> {
+        let mut problems = Vec::new();
+
+        let host = settings.host.as_deref().unwrap_or(DEFAULT_HOST);
+        let port = settings.port.unwrap_or(DEFAULT_PORT);
+        if port == 0 {
+            problems.push("port must be between 1 and 65535".to_string());
+        }
+        let addr = match (host, port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+            Ok(Some(addr)) => Some(addr),
+            _ => {
+                problems.push(format!("host '{}' is not an IP address or known host name", host));
+                None
+            }
+        };
+
+        let log_level = settings
+            .log_level
+            .as_deref()
+            .map(LogLevel::from_str)
+            .transpose()
+            .unwrap_or_else(|err| {
+                problems.push(err);
+                None
+            })
+            .unwrap_or(LogLevel::Info);
+
+        let backend = match settings.backend.as_deref().map(str::trim) {
+            None | Some("json") => {
+                let path = settings.data_path.unwrap_or_else(|| PathBuf::from("todos.json"));
+                check_data_path(&path, "data_path", &mut problems);
+                Some(Backend::Json(path))
+            }
+            // Keeps nothing, so `data_path` does not matter
+            Some("memory") => Some(Backend::Memory),
+            Some("sqlite") => {
+                let path = settings.data_path.unwrap_or_else(|| PathBuf::from("todos.db"));
+                check_data_path(&path, "data_path", &mut problems);
+                Some(Backend::Sqlite(path))
+            }
+            Some(other) => {
+                problems.push(format!(
+                    "unknown backend '{}', expected json, memory or sqlite",
+                    other
+                ));
+                None
+            }
+        };
+
+        // Only when asked for: clients without a key would get 401. The first
+        // admin's key goes next to users.json, so the memory backend, which
+        // has no data directory, cannot have accounts.
+        let accounts = match (&backend, settings.auth) {
+            (_, None | Some(false)) => None,
+            (Some(Backend::Json(data) | Backend::Sqlite(data)), Some(true)) => {
+                // Next to the data file, whose directory is checked already
+                let path = match settings.users_path {
+                    Some(path) => {
+                        check_data_path(&path, "users_path", &mut problems);
+                        path
+                    }
+                    None => data.with_file_name("users.json"),
+                };
+                Some(AccountStore::File(path))
+            }
+            (Some(Backend::Memory), Some(true)) => {
+                problems.push("auth needs the json or sqlite backend".to_string());
+                None
+            }
+            (None, Some(true)) => None,
+        };
+
+        let mut history = HistoryRetention::default();
+        if let Some(limit) = settings.history_limit {
+            history.max_revisions = limit;
+        }
+        match settings.history_days {
+            Some(days) if days <= 0 => {
+                problems.push(format!("history_days must be positive, not {}", days));
+            }
+            Some(days) => history.max_age = Some(Duration::days(days)),
+            None => {}
+        }
+
+        let mut trash = TrashRetention::default();
+        match settings.trash_days {
+            Some(days) if days < 0 => {
+                problems.push(format!("trash_days cannot be negative, not {}", days));
+            }
+            Some(days) => trash.max_age = (days > 0).then(|| Duration::days(days)),
+            None => {}
+        }
+
+        let delay = settings.flush_ms.map_or(DEFAULT_FLUSH_DELAY, std::time::Duration::from_millis);
+        let durability = match settings.durability.as_deref().map(str::trim) {
+            None | Some("sync") => Durability::Sync,
+            Some("debounced") => Durability::Debounced { delay },
+            Some(other) => {
+                problems.push(format!(
+                    "unknown durability '{}', expected sync or debounced",
+                    other
+                ));
+                Durability::Sync
+            }
+        };
+        if settings.flush_ms.is_some_and(|ms| ms > MAX_FLUSH_MS) {
+            problems.push(format!("flush_ms cannot be above {}", MAX_FLUSH_MS));
+        }
+
+        let cipher = match (settings.key, settings.key_file) {
+            (Some(_), Some(_)) => {
+                problems.push("set TODO_ENCRYPTION_KEY or key_file, not both".to_string());
+                None
+            }
+            (Some(key), None) => Cipher::from_key(&key)
+                .map_err(|err| problems.push(format!("TODO_ENCRYPTION_KEY: {}", err)))
+                .ok(),
+            (None, Some(path)) => Cipher::from_file(&path)
+                .map_err(|err| problems.push(format!("key_file {}: {}", path.display(), err)))
+                .ok(),
+            (None, None) => None,
+        };
+        if cipher.is_some() && matches!(backend, Some(Backend::Sqlite(_))) {
+            problems.push("encryption needs the json backend".to_string());
+        }
+
+        // The memory backend has no data file to put them next to
+        let backup_dir = match (settings.backup_dir, &backend) {
+            (Some(dir), _) => Some(dir),
+            (None, Some(Backend::Json(data) | Backend::Sqlite(data))) => {
+                Some(data.with_file_name("backups"))
+            }
+            (None, _) => None,
+        };
+        let keep = settings.backup_keep.unwrap_or(backup::DEFAULT_KEEP);
+        let every = match settings.backup_hours {
+            Some(0) => None,
+            Some(hours) if hours > MAX_BACKUP_HOURS => {
+                problems.push(format!("backup_hours cannot be above {}", MAX_BACKUP_HOURS));
+                None
+            }
+            Some(hours) => Some(std::time::Duration::from_secs(hours * 60 * 60)),
+            None => Some(backup::DEFAULT_EVERY),
+        };
+        let backups = backup_dir.filter(|_| keep > 0).map(|dir| {
+            // Created with the first backup
+            if dir.exists() && !dir.is_dir() {
+                problems.push(format!("backup_dir {} is not a directory", dir.display()));
+            }
+            BackupPolicy { dir, keep, every }
+        });
+
+        match (addr, backend) {
+            (Some(addr), Some(backend)) if problems.is_empty() => Ok(Config {
+                file: None,
+                addr,
+                log_level,
+                backend,
+                history,
+                trash,
+                durability,
+                cipher,
+                backups,
+                accounts,
+            }),
+            _ => Err(ConfigError(problems)),
+        }
+    }
+}
+
+// Data files are created on first start, but their directory has to exist
+fn check_data_path(path: &Path, setting: &str, problems: &mut Vec<String>) {
+    if path.is_dir() {
+        problems.push(format!("{} {} is a directory", setting, path.display()));
+        return;
+    }
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    if let Some(dir) = dir
+        && !dir.is_dir()
+    {
+        problems.push(format!(
+            "the directory of {} {} does not exist",
+            setting,
+            path.display()
+        ));
+    }
+}
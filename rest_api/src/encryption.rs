@@ -0,0 +1,186 @@
+// Encryption at rest for todos.json and its journal. XChaCha20-Poly1305 with
+// a random nonce per message: every file and journal record is
+// authenticated, so a wrong key or a changed byte is noticed instead of
+// being read as garbage.
+
+use crate::error::StorageError;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::Path;
+
+pub const CIPHER_NAME: &str = "xchacha20poly1305";
+// Bytes of a key; keys are written down in base64
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+// What a sealed message belongs to, bound in as associated data so a
+// journal record cannot be passed off as a snapshot or the other way round
+pub const SNAPSHOT_CONTEXT: &str = "rest_api snapshot";
+pub const JOURNAL_CONTEXT: &str = "rest_api journal";
+
+#[derive(Clone)]
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+    // First bytes of the key's SHA-256, to tell keys apart in messages
+    id: String,
+}
+
+// Never shows the key
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cipher({}, key {})", CIPHER_NAME, self.id)
+    }
+}
+
+impl Cipher {
+    // A key as `rest_api keygen` prints it
+    pub fn from_key(encoded: &str) -> Result<Cipher, String> {
+        let key = STANDARD
+            .decode(encoded.trim())
+            .ok()
+            .filter(|key| key.len() == KEY_LEN)
+            .ok_or_else(|| {
+                format!(
+                    "a key is {} bytes in base64, `rest_api keygen` makes one",
+                    KEY_LEN
+                )
+            })?;
+        let id = Sha256::digest(&key)[..4].iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        });
+        Ok(Cipher {
+            aead: XChaCha20Poly1305::new(Key::from_slice(&key)),
+            id,
+        })
+    }
+
+    // A file holding nothing but a key
+    pub fn from_file(path: &Path) -> Result<Cipher, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Cipher::from_key(&text)
+    }
+
+    // A fresh random key, encoded for `from_key`
+    pub fn generate_key() -> Result<String, StorageError> {
+        let mut key = [0u8; KEY_LEN];
+        getrandom::fill(&mut key).map_err(|err| StorageError::Unavailable(err.to_string()))?;
+        Ok(STANDARD.encode(key))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    // Nonce and ciphertext of `plaintext`, in base64
+    pub fn seal(&self, context: &str, plaintext: &[u8]) -> Result<String, StorageError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::fill(&mut nonce).map_err(|err| StorageError::Unavailable(err.to_string()))?;
+        let payload = Payload {
+            msg: plaintext,
+            aad: context.as_bytes(),
+        };
+        let sealed = self
+            .aead
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| StorageError::Encryption("encryption failed".to_string()))?;
+        let mut message = nonce.to_vec();
+        message.extend(sealed);
+        Ok(STANDARD.encode(message))
+    }
+
+    // `None` when the message was not sealed with this key and context, or
+    // was changed since
+    pub fn open(&self, context: &str, sealed: &str) -> Option<Vec<u8>> {
+        let message = STANDARD.decode(sealed.trim()).ok()?;
+        if message.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = message.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: context.as_bytes(),
+        };
+        self.aead.decrypt(XNonce::from_slice(nonce), payload).ok()
+    }
+}
+
+// Layout of an encrypted todos.json
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    cipher: String,
+    key_id: String,
+    data: String,
+}
+
+// The id of the key a file written by `seal_file` is encrypted with, `None`
+// when it is not encrypted
+pub fn key_id(text: &str) -> Option<String> {
+    serde_json::from_str::<Envelope>(text).ok().map(|envelope| envelope.key_id)
+}
+
+// `text` encrypted into an envelope, or as it is without a cipher
+pub fn seal_file(cipher: Option<&Cipher>, text: String) -> Result<String, StorageError> {
+    let Some(cipher) = cipher else {
+        return Ok(text);
+    };
+    let envelope = Envelope {
+        cipher: CIPHER_NAME.to_string(),
+        key_id: cipher.id.clone(),
+        data: cipher.seal(SNAPSHOT_CONTEXT, text.as_bytes())?,
+    };
+    Ok(serde_json::to_string_pretty(&envelope)?)
+}
+
+// The plain contents of a file written by `seal_file`, and the id of the
+// key it was encrypted with. Encrypted files are never guessed at: without
+// the right key this fails, so the file is not mistaken for a corrupt one.
+pub fn open_file(
+    cipher: Option<&Cipher>,
+    path: &Path,
+    text: String,
+) -> Result<(String, Option<String>), StorageError> {
+    let Ok(envelope) = serde_json::from_str::<Envelope>(&text) else {
+        return Ok((text, None));
+    };
+    let refuse = |reason: String| Err(StorageError::Encryption(reason));
+    if envelope.cipher != CIPHER_NAME {
+        return refuse(format!(
+            "{} is encrypted with {}, this server only knows {}",
+            path.display(),
+            envelope.cipher,
+            CIPHER_NAME
+        ));
+    }
+    let Some(cipher) = cipher else {
+        return refuse(format!(
+            "{} is encrypted (key {}), but no key is configured",
+            path.display(),
+            envelope.key_id
+        ));
+    };
+    if envelope.key_id != cipher.id {
+        return refuse(format!(
+            "{} is encrypted with key {}, not with the configured key {}",
+            path.display(),
+            envelope.key_id,
+            cipher.id
+        ));
+    }
+    let plain = cipher
+        .open(SNAPSHOT_CONTEXT, &envelope.data)
+        .and_then(|plain| String::from_utf8(plain).ok());
+    match plain {
+        Some(plain) => Ok((plain, Some(envelope.key_id))),
+        None => refuse(format!(
+            "{} does not match its authentication tag, it was changed or is damaged",
+            path.display()
+        )),
+    }
+}
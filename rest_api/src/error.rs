@@ -0,0 +1,222 @@
+use axum::{
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use utoipa::ToSchema;
+
+// Everything that can go wrong while reading or persisting todos
+#[derive(Debug)]
+pub enum StorageError {
+    // Disk or quota is full, nothing more can be written
+    Full(io::Error),
+    // Data dir is read-only / not accessible, or the storage lock is poisoned
+    Unavailable(String),
+    Io(io::Error),
+    Serialization(serde_json::Error),
+    Database(rusqlite::Error),
+    // todos.json was written by a newer version of the server
+    UnsupportedSchema(u32),
+    // The todo was changed since the revision the caller based its write on
+    RevisionMismatch { id: u32, current: u64 },
+    // The todo belongs to another user, see `ScopedRepository`
+    NotOwned(u32),
+    // Encrypted data that cannot be read with the configured key (or
+    // without one), or that was changed on disk
+    Encryption(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Full(err) => write!(f, "storage is full: {}", err),
+            StorageError::Unavailable(reason) => write!(f, "storage unavailable: {}", reason),
+            StorageError::Io(err) => write!(f, "storage I/O error: {}", err),
+            StorageError::Serialization(err) => write!(f, "failed to serialize todos: {}", err),
+            StorageError::Database(err) => write!(f, "database error: {}", err),
+            StorageError::UnsupportedSchema(version) => write!(
+                f,
+                "data file has schema version {}, this server only understands up to {}",
+                version,
+                crate::schema::CURRENT_VERSION
+            ),
+            StorageError::RevisionMismatch { id, current } => write!(
+                f,
+                "todo {} was modified concurrently, it is now at revision {}",
+                id, current
+            ),
+            StorageError::NotOwned(id) => write!(f, "todo {} belongs to another user", id),
+            StorageError::Encryption(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => StorageError::Full(err),
+            io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::PermissionDenied => {
+                StorageError::Unavailable(err.to_string())
+            }
+            _ => StorageError::Io(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(err: serde_json::Error) -> Self {
+        StorageError::Serialization(err)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode;
+
+        match err.sqlite_error_code() {
+            Some(ErrorCode::DiskFull) => StorageError::Full(io::Error::other(err.to_string())),
+            Some(
+                ErrorCode::ReadOnly
+                | ErrorCode::CannotOpen
+                | ErrorCode::PermissionDenied
+                | ErrorCode::DatabaseBusy
+                | ErrorCode::DatabaseLocked,
+            ) => StorageError::Unavailable(err.to_string()),
+            _ => StorageError::Database(err),
+        }
+    }
+}
+
+// Why a batch was not applied; none of its operations took effect
+#[derive(Debug)]
+pub enum BatchError {
+    // Operation `index` refers to a todo that does not exist
+    NotFound { index: usize, id: u32 },
+    // Operation `index` expected another revision of the todo
+    RevisionMismatch { index: usize, id: u32, current: u64 },
+    Storage(StorageError),
+}
+
+impl From<StorageError> for BatchError {
+    fn from(err: StorageError) -> Self {
+        BatchError::Storage(err)
+    }
+}
+
+// Error returned by handlers, rendered as `{"error": "...", "code": "..."}`
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound,
+    ListNotFound,
+    UserNotFound,
+    KeyNotFound,
+    BackupNotFound,
+    // The requested revision is not (or no longer) in the todo's history
+    RevisionNotFound,
+    // 400 with a machine-readable code
+    BadRequest(&'static str, String),
+    // 409 with a machine-readable code
+    Conflict(&'static str, String),
+    // 422: the request is well-formed but its result would be invalid
+    Unprocessable(&'static str, String),
+    // 415, e.g. a PATCH body that is neither merge-patch nor json-patch
+    UnsupportedMediaType(String),
+    // 401: no API key, or one that is not (or no longer) valid
+    Unauthorized(String),
+    // 403 with a machine-readable code, e.g. a user on an admin route
+    Forbidden(&'static str, String),
+    Storage(StorageError),
+}
+
+impl From<StorageError> for ApiError {
+    fn from(err: StorageError) -> Self {
+        match err {
+            // Todos of other users do not exist as far as the caller knows
+            StorageError::NotOwned(_) => ApiError::NotFound,
+            err => ApiError::Storage(err),
+        }
+    }
+}
+
+impl ApiError {
+    pub fn status_and_code(&self) -> (StatusCode, &'static str) {
+        match self {
+            ApiError::NotFound => (StatusCode::NOT_FOUND, "not_found"),
+            ApiError::ListNotFound => (StatusCode::NOT_FOUND, "list_not_found"),
+            ApiError::UserNotFound => (StatusCode::NOT_FOUND, "user_not_found"),
+            ApiError::KeyNotFound => (StatusCode::NOT_FOUND, "key_not_found"),
+            ApiError::BackupNotFound => (StatusCode::NOT_FOUND, "backup_not_found"),
+            ApiError::RevisionNotFound => (StatusCode::NOT_FOUND, "revision_not_found"),
+            ApiError::BadRequest(code, _) => (StatusCode::BAD_REQUEST, code),
+            ApiError::Conflict(code, _) => (StatusCode::CONFLICT, code),
+            ApiError::Unprocessable(code, _) => (StatusCode::UNPROCESSABLE_ENTITY, code),
+            ApiError::UnsupportedMediaType(_) => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type")
+            }
+            ApiError::Unauthorized(_) => (StatusCode::UNAUTHORIZED, "unauthorized"),
+            ApiError::Forbidden(code, _) => (StatusCode::FORBIDDEN, code),
+            ApiError::Storage(StorageError::Full(_)) => {
+                (StatusCode::INSUFFICIENT_STORAGE, "storage_full")
+            }
+            ApiError::Storage(StorageError::Unavailable(_)) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "storage_unavailable")
+            }
+            ApiError::Storage(StorageError::RevisionMismatch { .. }) => {
+                (StatusCode::PRECONDITION_FAILED, "precondition_failed")
+            }
+            ApiError::Storage(_) => (StatusCode::INTERNAL_SERVER_ERROR, "storage_error"),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NotFound => write!(f, "Todo not found"),
+            ApiError::ListNotFound => write!(f, "List not found"),
+            ApiError::UserNotFound => write!(f, "User not found"),
+            ApiError::KeyNotFound => write!(f, "API key not found"),
+            ApiError::BackupNotFound => write!(f, "Backup not found"),
+            ApiError::RevisionNotFound => write!(f, "Revision not found"),
+            ApiError::BadRequest(_, message)
+            | ApiError::Conflict(_, message)
+            | ApiError::Unprocessable(_, message)
+            | ApiError::UnsupportedMediaType(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(_, message) => write!(f, "{}", message),
+            ApiError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// Body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+    // Stable, e.g. `not_found` or `precondition_failed`
+    pub code: &'static str,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, code) = self.status_and_code();
+        if let ApiError::Storage(err) = &self
+            && !matches!(err, StorageError::RevisionMismatch { .. })
+        {
+            crate::error!("❌ {}", err);
+        }
+
+        let body = Json(ErrorBody {
+            error: self.to_string(),
+            code,
+        });
+        if let ApiError::Unauthorized(_) = self {
+            return (status, [(header::WWW_AUTHENTICATE, "Bearer")], body).into_response();
+        }
+        (status, body).into_response()
+    }
+}
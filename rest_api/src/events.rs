@@ -0,0 +1,191 @@
+use crate::models::Todo;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+// Events kept for clients that reconnect with `Last-Event-ID`
+const KEEP_EVENTS: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub enum TodoChange {
+    Created { todo: Todo },
+    Updated { todo: Todo },
+    // Moved to the trash
+    Deleted { id: u32, owner_id: u32 },
+    // Any todo may have changed (a backup was restored): reload them all
+    Reset,
+}
+
+impl TodoChange {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TodoChange::Created { .. } => "created",
+            TodoChange::Updated { .. } => "updated",
+            TodoChange::Deleted { .. } => "deleted",
+            TodoChange::Reset => "reset",
+        }
+    }
+
+    // The user whose todo changed; `None` when it concerns every user
+    pub fn owner_id(&self) -> Option<u32> {
+        match self {
+            TodoChange::Created { todo } | TodoChange::Updated { todo } => Some(todo.owner_id),
+            TodoChange::Deleted { owner_id, .. } => Some(*owner_id),
+            TodoChange::Reset => None,
+        }
+    }
+}
+
+// What goes out as the SSE `id`: `<epoch>-<seq>`. The epoch is picked anew
+// on every start, so an id from before a restart is never mistaken for one
+// of the new sequence numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventId {
+    pub epoch: u32,
+    pub seq: u64,
+}
+
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}-{}", self.epoch, self.seq)
+    }
+}
+
+impl FromStr for EventId {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, seq) = s.trim().split_once('-').ok_or(())?;
+        Ok(EventId {
+            epoch: u32::from_str_radix(epoch, 16).map_err(|_| ())?,
+            seq: seq.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+// One change, numbered in the order it was published; `EventedRepository`
+// publishes writes in the order they were stored
+#[derive(Debug, Clone)]
+pub struct TodoEvent {
+    pub epoch: u32,
+    pub seq: u64,
+    pub change: TodoChange,
+}
+
+impl TodoEvent {
+    pub fn id(&self) -> EventId {
+        EventId {
+            epoch: self.epoch,
+            seq: self.seq,
+        }
+    }
+}
+
+// Where a subscriber starts
+#[derive(Debug)]
+pub enum Backlog {
+    // Everything after the last event the client saw, possibly nothing
+    Events(Vec<TodoEvent>),
+    // The client's last event is no longer known (too old, or from before a
+    // restart); it has to reload its todos. `last` is the newest event id.
+    Reset { last: EventId },
+}
+
+struct FeedState {
+    last_seq: u64,
+    // Oldest first, at most KEEP_EVENTS
+    recent: VecDeque<TodoEvent>,
+}
+
+// Fan-out of todo changes to the open event streams, plus a short backlog so
+// reconnecting clients do not miss anything. Sequence ids restart at 1 with
+// the server, under a new epoch.
+pub struct ChangeFeed {
+    epoch: u32,
+    state: Mutex<FeedState>,
+    sender: broadcast::Sender<TodoEvent>,
+}
+
+impl Default for ChangeFeed {
+    fn default() -> Self {
+        ChangeFeed::new()
+    }
+}
+
+impl ChangeFeed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(KEEP_EVENTS);
+        // Only has to differ from the previous run's; the clock will do if
+        // the OS has no randomness to give
+        let epoch = getrandom::u32().unwrap_or_else(|_| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.subsec_nanos() ^ elapsed.as_secs() as u32)
+        });
+        ChangeFeed {
+            epoch,
+            state: Mutex::new(FeedState {
+                last_seq: 0,
+                recent: VecDeque::new(),
+            }),
+            sender,
+        }
+    }
+
+    pub fn publish(&self, change: TodoChange) -> TodoEvent {
+        // Numbering and sending under one lock keeps every stream in order
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.last_seq += 1;
+        let event = TodoEvent {
+            epoch: self.epoch,
+            seq: state.last_seq,
+            change,
+        };
+        if state.recent.len() == KEEP_EVENTS {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(event.clone());
+        // No open streams is not an error
+        let _ = self.sender.send(event.clone());
+        event
+    }
+
+    // Events after `last_seen` plus a receiver for everything published from
+    // now on; together they have no gaps and no duplicates
+    pub fn subscribe(
+        &self,
+        last_seen: Option<EventId>,
+    ) -> (Backlog, broadcast::Receiver<TodoEvent>) {
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let receiver = self.sender.subscribe();
+        let Some(EventId {
+            epoch,
+            seq: last_seen,
+        }) = last_seen
+        else {
+            return (Backlog::Events(Vec::new()), receiver);
+        };
+
+        let oldest = state.recent.front().map_or(state.last_seq + 1, |event| event.seq);
+        if epoch != self.epoch || last_seen > state.last_seq || last_seen + 1 < oldest {
+            let last = EventId {
+                epoch: self.epoch,
+                seq: state.last_seq,
+            };
+            return (Backlog::Reset { last }, receiver);
+        }
+        let events = state
+            .recent
+            .iter()
+            .filter(|event| event.seq > last_seen)
+            .cloned()
+            .collect();
+        (Backlog::Events(events), receiver)
+    }
+
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+}
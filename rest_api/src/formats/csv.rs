@@ -0,0 +1,196 @@
+// RFC 4180 CSV with a header row. Export writes every column below; import
+// needs a `title` column, reads the others it knows in any order and
+// rebuilds subtasks from `id` / `parent_id`. Tags are separated by `;`.
+
+use super::{ImportedTodo, ParseError, format_due, parse_due, parse_priority, priority_name};
+use crate::models::Todo;
+use chrono::SecondsFormat;
+use std::collections::HashMap;
+
+const COLUMNS: &[&str] = &[
+    "id",
+    "title",
+    "completed",
+    "description",
+    "due_at",
+    "priority",
+    "tags",
+    "parent_id",
+    "created_at",
+    "completed_at",
+];
+
+pub(super) fn export(todos: &[(usize, &Todo)]) -> String {
+    let mut out = String::new();
+    write_record(&mut out, COLUMNS.iter().map(|column| column.to_string()));
+    for (_, todo) in todos {
+        let timestamp = |at: Option<chrono::DateTime<chrono::Utc>>| {
+            at.map(|at| at.to_rfc3339_opts(SecondsFormat::Secs, true)).unwrap_or_default()
+        };
+        write_record(
+            &mut out,
+            [
+                todo.id.to_string(),
+                todo.title.clone(),
+                todo.completed.to_string(),
+                todo.description.clone().unwrap_or_default(),
+                todo.due_at.map(format_due).unwrap_or_default(),
+                priority_name(todo.priority).to_string(),
+                todo.tags.join(";"),
+                todo.parent_id.map(|id| id.to_string()).unwrap_or_default(),
+                timestamp(todo.created_at),
+                timestamp(todo.completed_at),
+            ],
+        );
+    }
+    out
+}
+
+fn write_record(out: &mut String, fields: impl IntoIterator<Item = String>) {
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(&field);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+pub(super) fn parse(text: &str) -> (Vec<ImportedTodo>, Vec<ParseError>) {
+    let records = match read_records(text) {
+        Ok(records) => records,
+        Err(err) => return (Vec::new(), vec![err]),
+    };
+    let mut records = records.into_iter();
+    let Some((_, header)) = records.next() else {
+        return (Vec::new(), Vec::new());
+    };
+    let header: Vec<String> = header.iter().map(|name| name.trim().to_ascii_lowercase()).collect();
+    let column = |name: &str| header.iter().position(|column| column == name);
+    let Some(title_column) = column("title") else {
+        return (Vec::new(), vec![ParseError::new(1, "the header has no 'title' column")]);
+    };
+
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    // `id` column value -> index of the item
+    let mut indexes: HashMap<String, usize> = HashMap::new();
+    for (line, record) in records {
+        // Blank lines
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let field = |name: &str| {
+            column(name)
+                .and_then(|index| record.get(index))
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+        };
+
+        let title = record.get(title_column).map(|title| title.trim()).unwrap_or_default();
+        if title.is_empty() {
+            errors.push(ParseError::new(line, "title is empty"));
+            continue;
+        }
+        let mut item = ImportedTodo::new(line, title.to_string());
+        match field("completed").map(|value| value.to_ascii_lowercase()).as_deref() {
+            None | Some("false" | "0" | "no") => {}
+            Some("true" | "1" | "yes" | "x") => item.completed = true,
+            Some(other) => {
+                errors.push(ParseError::new(line, format!("bad completed '{}'", other)));
+                continue;
+            }
+        }
+        item.description = field("description").map(str::to_string);
+        if let Some(due) = field("due_at") {
+            let Some(due) = parse_due(due) else {
+                errors.push(ParseError::new(line, format!("bad due_at '{}'", due)));
+                continue;
+            };
+            item.due_at = Some(due);
+        }
+        if let Some(priority) = field("priority") {
+            let Some(priority) = parse_priority(priority) else {
+                errors.push(ParseError::new(line, format!("bad priority '{}'", priority)));
+                continue;
+            };
+            item.priority = priority;
+        }
+        if let Some(tags) = field("tags") {
+            item.tags = tags.split(';').map(|tag| tag.trim().to_string()).collect();
+        }
+        if let Some(parent) = field("parent_id") {
+            let Some(index) = indexes.get(parent) else {
+                let message = format!("parent_id {} is not the id of an earlier row", parent);
+                errors.push(ParseError::new(line, message));
+                continue;
+            };
+            item.parent = Some(*index);
+        }
+
+        if let Some(id) = field("id") {
+            indexes.insert(id.to_string(), items.len());
+        }
+        items.push(item);
+    }
+    (items, errors)
+}
+
+// Records with the line each starts on; quoted fields can span lines
+fn read_records(text: &str) -> Result<Vec<(usize, Vec<String>)>, ParseError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    let mut quote_start = 0;
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => {
+                quoted = true;
+                quote_start = line;
+            }
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push((start, std::mem::take(&mut record)));
+                line += 1;
+                start = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(ParseError::new(quote_start, "quoted field is never closed"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start, record));
+    }
+    Ok(records)
+}
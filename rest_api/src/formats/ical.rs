@@ -0,0 +1,332 @@
+// iCalendar (RFC 5545) VTODO components. Each todo gets the UID
+// `todo-<id>@rest-api`, so calendar apps can track it and importing it back
+// updates the todo instead of creating a copy. Times are UTC; local times
+// (TZID or floating) are read as if they were UTC.
+
+use super::{ImportedTodo, ParseError};
+use crate::models::{Priority, Todo};
+use crate::recurrence::Recurrence;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use std::collections::HashMap;
+
+const UID_PREFIX: &str = "todo-";
+const UID_SUFFIX: &str = "@rest-api";
+// Longest content line in octets, without the line break
+const MAX_LINE: usize = 75;
+
+pub fn uid(id: u32) -> String {
+    format!("{}{}{}", UID_PREFIX, id, UID_SUFFIX)
+}
+
+// The todo id in a UID this server handed out
+pub fn id_from_uid(uid: &str) -> Option<u32> {
+    uid.strip_prefix(UID_PREFIX)?.strip_suffix(UID_SUFFIX)?.parse().ok()
+}
+
+pub(super) fn export(todos: &[(usize, &Todo)]) -> String {
+    let mut out = String::new();
+    for line in ["BEGIN:VCALENDAR", "VERSION:2.0", "PRODID:-//rest_api//Todos//EN"] {
+        push_line(&mut out, line);
+    }
+    for (_, todo) in todos {
+        push_line(&mut out, "BEGIN:VTODO");
+        push_line(&mut out, &format!("UID:{}", uid(todo.id)));
+        let stamp = todo.updated_at.or(todo.created_at).unwrap_or_else(Utc::now);
+        push_line(&mut out, &format!("DTSTAMP:{}", date_time(stamp)));
+        if let Some(created) = todo.created_at {
+            push_line(&mut out, &format!("CREATED:{}", date_time(created)));
+        }
+        if let Some(updated) = todo.updated_at {
+            push_line(&mut out, &format!("LAST-MODIFIED:{}", date_time(updated)));
+        }
+        push_line(&mut out, &format!("SUMMARY:{}", escape(&todo.title)));
+        if let Some(description) = &todo.description {
+            push_line(&mut out, &format!("DESCRIPTION:{}", escape(description)));
+        }
+        if let Some(due) = todo.due_at {
+            push_line(&mut out, &format!("DUE{}", date_or_time(due)));
+            // A recurring todo's series starts at its due date
+            if let Some(rule) = &todo.recurrence {
+                push_line(&mut out, &format!("DTSTART{}", date_or_time(due)));
+                push_line(&mut out, &format!("RRULE:{}", rule));
+            }
+        }
+        if todo.completed {
+            push_line(&mut out, "STATUS:COMPLETED");
+            if let Some(completed) = todo.completed_at {
+                push_line(&mut out, &format!("COMPLETED:{}", date_time(completed)));
+            }
+        } else {
+            push_line(&mut out, "STATUS:NEEDS-ACTION");
+        }
+        push_line(&mut out, &format!("PRIORITY:{}", priority_number(todo.priority)));
+        if !todo.tags.is_empty() {
+            let tags: Vec<String> = todo.tags.iter().map(|tag| escape(tag)).collect();
+            push_line(&mut out, &format!("CATEGORIES:{}", tags.join(",")));
+        }
+        if let Some(parent) = todo.parent_id {
+            push_line(&mut out, &format!("RELATED-TO;RELTYPE=PARENT:{}", uid(parent)));
+        }
+        push_line(&mut out, "END:VTODO");
+    }
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+// Content lines end in CRLF and are folded after 75 octets
+fn push_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE {
+            out.push_str("\r\n ");
+            // The leading space counts
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+fn date_time(at: DateTime<Utc>) -> String {
+    at.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// `;VALUE=DATE:20250131` for midnight, `:20250131T170000Z` otherwise
+fn date_or_time(at: DateTime<Utc>) -> String {
+    if at.time() == NaiveTime::MIN {
+        format!(";VALUE=DATE:{}", at.format("%Y%m%d"))
+    } else {
+        format!(":{}", date_time(at))
+    }
+}
+
+fn parse_date_or_time(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    let local = value.strip_suffix('Z').unwrap_or(value);
+    if let Ok(at) = NaiveDateTime::parse_from_str(local, "%Y%m%dT%H%M%S") {
+        return Some(at.and_utc());
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+    Some(date.and_time(NaiveTime::MIN).and_utc())
+}
+
+// 1 is the highest, 9 the lowest and 0 undefined
+fn priority_number(priority: Priority) -> u8 {
+    match priority {
+        Priority::Urgent => 1,
+        Priority::High => 3,
+        Priority::Medium => 5,
+        Priority::Low => 9,
+    }
+}
+
+fn number_priority(number: u8) -> Option<Priority> {
+    match number {
+        1 => Some(Priority::Urgent),
+        2..=4 => Some(Priority::High),
+        0 | 5 => Some(Priority::Medium),
+        6..=9 => Some(Priority::Low),
+        _ => None,
+    }
+}
+
+// One unfolded content line: `NAME;PARAM=VALUE:value`
+struct ContentLine<'a> {
+    number: usize,
+    name: String,
+    // `PARAM=VALUE` pairs, separated by `;`
+    params: &'a str,
+    value: &'a str,
+}
+
+impl ContentLine<'_> {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.split(';').find_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim().trim_matches('"'))
+        })
+    }
+}
+
+fn content_line(number: usize, line: &str) -> Option<ContentLine<'_>> {
+    // The value starts at the first `:` outside a quoted parameter value
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(index, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(index),
+        _ => None,
+    })?;
+    let (name, params) = line[..colon].split_once(';').unwrap_or((&line[..colon], ""));
+    Some(ContentLine {
+        number,
+        name: name.trim().to_ascii_uppercase(),
+        params,
+        value: &line[colon + 1..],
+    })
+}
+
+pub(super) fn parse(text: &str) -> (Vec<ImportedTodo>, Vec<ParseError>) {
+    // Unfold, keeping the number of the line each logical line starts on
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, last))) => last.push_str(rest),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push((number + 1, line.to_string())),
+        }
+    }
+
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    // Index of each UID seen so far, for RELATED-TO
+    let mut indexes: HashMap<String, usize> = HashMap::new();
+    // Components we are in, innermost last
+    let mut components: Vec<String> = Vec::new();
+    // Properties of the VTODO being read, and the line it begins on
+    let mut current: Vec<ContentLine> = Vec::new();
+    let mut start = 1;
+    for (number, line) in &lines {
+        let Some(line) = content_line(*number, line) else {
+            errors.push(ParseError::new(*number, "not a content line"));
+            continue;
+        };
+        match line.name.as_str() {
+            "BEGIN" => {
+                let component = line.value.trim().to_ascii_uppercase();
+                if component == "VTODO" {
+                    current.clear();
+                    start = line.number;
+                }
+                components.push(component);
+            }
+            "END" => {
+                let Some(component) = components.pop() else {
+                    errors.push(ParseError::new(line.number, "END without BEGIN"));
+                    continue;
+                };
+                if component == "VTODO" {
+                    match vtodo(start, &current, &indexes) {
+                        Ok(item) => {
+                            if let Some(uid) = &item.uid {
+                                indexes.insert(uid.clone(), items.len());
+                            }
+                            items.push(item);
+                        }
+                        Err(err) => errors.push(err),
+                    }
+                }
+            }
+            // Only the VTODO's own properties, not those of a VALARM in it
+            _ if components.last().is_some_and(|component| component == "VTODO") => {
+                current.push(line);
+            }
+            _ => {}
+        }
+    }
+    if let Some(component) = components.last() {
+        let number = lines.last().map_or(1, |(number, _)| *number);
+        errors.push(ParseError::new(number, format!("{} is never closed", component)));
+    }
+    (items, errors)
+}
+
+fn vtodo(
+    start: usize,
+    properties: &[ContentLine],
+    indexes: &HashMap<String, usize>,
+) -> Result<ImportedTodo, ParseError> {
+    let property = |name: &str| properties.iter().find(|line| line.name == name);
+    let bad = |line: &ContentLine, what: &str| {
+        ParseError::new(line.number, format!("bad {} '{}'", what, line.value))
+    };
+
+    let title = property("SUMMARY").map(|line| unescape(line.value)).unwrap_or_default();
+    if title.trim().is_empty() {
+        return Err(ParseError::new(start, "VTODO has no SUMMARY"));
+    }
+    let mut item = ImportedTodo::new(start, title.trim().to_string());
+    item.uid = property("UID").map(|line| line.value.trim().to_string());
+    item.description = property("DESCRIPTION").map(|line| unescape(line.value));
+    item.completed = property("COMPLETED").is_some()
+        || property("STATUS").is_some_and(|line| line.value.trim() == "COMPLETED");
+    if let Some(line) = property("DUE") {
+        item.due_at = Some(parse_date_or_time(line.value).ok_or_else(|| bad(line, "DUE"))?);
+    }
+    if let Some(line) = property("PRIORITY") {
+        let number = line.value.trim().parse().ok();
+        item.priority = number.and_then(number_priority).ok_or_else(|| bad(line, "PRIORITY"))?;
+    }
+    // CATEGORIES may be given more than once
+    for line in properties.iter().filter(|line| line.name == "CATEGORIES") {
+        split_list(line.value).for_each(|tag| item.tags.push(tag));
+    }
+    if let Some(line) = property("RRULE") {
+        let rule = line.value.trim().parse::<Recurrence>();
+        item.recurrence = Some(rule.map_err(|err| ParseError::new(line.number, err.to_string()))?);
+    }
+    // RELATED-TO without RELTYPE means the parent. A parent that is not in
+    // the file is left out.
+    let parent = properties.iter().find(|line| {
+        line.name == "RELATED-TO"
+            && line.param("RELTYPE").is_none_or(|kind| kind.eq_ignore_ascii_case("PARENT"))
+    });
+    item.parent = parent.and_then(|line| indexes.get(line.value.trim()).copied());
+    Ok(item)
+}
+
+// Comma separated text values, with escaped commas kept
+fn split_list(value: &str) -> impl Iterator<Item = String> + '_ {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                part.push(c);
+                part.extend(chars.next());
+            }
+            ',' => parts.push(std::mem::take(&mut part)),
+            _ => part.push(c),
+        }
+    }
+    parts.push(part);
+    parts.into_iter().map(|part| unescape(part.trim()))
+}
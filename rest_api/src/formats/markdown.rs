@@ -0,0 +1,104 @@
+// Markdown task lists: `- [ ] Title #tag due:2025-01-31`, `- [x]` when
+// done, subtasks indented below their parent. Import takes `-`, `*`, `+`
+// and numbered items and skips every line that is not a task item.
+
+use super::{ImportedTodo, ParseError, format_due, parse_due};
+use crate::models::Todo;
+
+pub(super) fn export(todos: &[(usize, &Todo)]) -> String {
+    let mut out = String::new();
+    for (depth, todo) in todos {
+        out.push_str(&"  ".repeat(*depth));
+        out.push_str(if todo.completed { "- [x] " } else { "- [ ] " });
+        out.push_str(&todo.title);
+        for tag in &todo.tags {
+            out.push_str(" #");
+            out.push_str(&tag.replace(char::is_whitespace, "-"));
+        }
+        if let Some(due) = todo.due_at {
+            out.push_str(" due:");
+            out.push_str(&format_due(due));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+pub(super) fn parse(text: &str) -> (Vec<ImportedTodo>, Vec<ParseError>) {
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    // Indentation and index of the items the next one may be nested in
+    let mut open: Vec<(usize, usize)> = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let Some((indent, completed, text)) = task_item(line) else {
+            continue;
+        };
+        while open.last().is_some_and(|(parent_indent, _)| *parent_indent >= indent) {
+            open.pop();
+        }
+
+        let mut words: Vec<&str> = text.split_whitespace().collect();
+        let mut tags = Vec::new();
+        let mut due = None;
+        // Tags and the due date trail the title
+        while let Some(word) = words.last() {
+            if let Some(tag) = word.strip_prefix('#').filter(|tag| !tag.is_empty()) {
+                tags.insert(0, tag.to_string());
+            } else if let Some(value) = word.strip_prefix("due:") {
+                due = Some(value);
+            } else {
+                break;
+            }
+            words.pop();
+        }
+        if words.is_empty() {
+            errors.push(ParseError::new(number, "task has no title"));
+            continue;
+        }
+
+        let mut item = ImportedTodo::new(number, words.join(" "));
+        item.completed = completed;
+        item.tags = tags;
+        if let Some(due) = due {
+            let Some(due) = parse_due(due) else {
+                errors.push(ParseError::new(number, format!("bad due date '{}'", due)));
+                continue;
+            };
+            item.due_at = Some(due);
+        }
+        item.parent = open.last().map(|(_, index)| *index);
+        open.push((indent, items.len()));
+        items.push(item);
+    }
+    (items, errors)
+}
+
+// Indentation, whether it is checked, and the rest of a task list item
+fn task_item(line: &str) -> Option<(usize, bool, &str)> {
+    let trimmed = line.trim_start();
+    // A tab counts as four spaces
+    let indent: usize = line[..line.len() - trimmed.len()]
+        .chars()
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum();
+
+    let rest = match trimmed.strip_prefix(['-', '*', '+']) {
+        Some(rest) => rest,
+        None => {
+            let rest = trimmed.trim_start_matches(|c: char| c.is_ascii_digit());
+            if rest.len() == trimmed.len() {
+                return None;
+            }
+            rest.strip_prefix(['.', ')'])?
+        }
+    };
+    let rest = rest.strip_prefix(' ')?.trim_start();
+    let completed = match rest.get(..3)? {
+        "[ ]" => false,
+        "[x]" | "[X]" => true,
+        _ => return None,
+    };
+    Some((indent, completed, rest[3..].trim()))
+}
@@ -0,0 +1,298 @@
+// Todos in the formats of other tools, for GET /todos/export and
+// POST /todos/import. Each format turns todos into text and text into
+// `ImportedTodo`s; creating the todos is up to the caller.
+
+mod csv;
+pub mod ical;
+mod markdown;
+mod todotxt;
+
+use crate::models::{Priority, Todo, normalize_tags};
+use crate::recurrence::Recurrence;
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Csv,
+    Markdown,
+    Todotxt,
+    Ical,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Markdown => "text/markdown; charset=utf-8",
+            Format::Todotxt => "text/plain; charset=utf-8",
+            Format::Ical => "text/calendar; charset=utf-8",
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Format::Csv => "todos.csv",
+            Format::Markdown => "todos.md",
+            Format::Todotxt => "todo.txt",
+            Format::Ical => "todos.ics",
+        }
+    }
+
+    // Parents always come before their subtasks
+    pub fn export(self, todos: &[Todo]) -> String {
+        let todos = tree_order(todos);
+        match self {
+            Format::Csv => csv::export(&todos),
+            Format::Markdown => markdown::export(&todos),
+            Format::Todotxt => todotxt::export(&todos),
+            Format::Ical => ical::export(&todos),
+        }
+    }
+
+    // Everything that could be read, and what could not
+    pub fn parse(self, text: &str) -> (Vec<ImportedTodo>, Vec<ParseError>) {
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        match self {
+            Format::Csv => csv::parse(text),
+            Format::Markdown => markdown::parse(text),
+            Format::Todotxt => todotxt::parse(text),
+            Format::Ical => ical::parse(text),
+        }
+    }
+}
+
+// A todo read from an import, not created yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedTodo {
+    // Where it starts in the text, from 1
+    pub line: usize,
+    // Index of the imported todo this one is a subtask of, always an
+    // earlier one
+    pub parent: Option<usize>,
+    pub title: String,
+    pub completed: bool,
+    pub description: Option<String>,
+    pub due_at: Option<DateTime<Utc>>,
+    pub priority: Priority,
+    pub tags: Vec<String>,
+    pub recurrence: Option<Recurrence>,
+    // Set by formats that identify todos, see `ical::uid`
+    pub uid: Option<String>,
+}
+
+impl ImportedTodo {
+    fn new(line: usize, title: String) -> Self {
+        ImportedTodo {
+            line,
+            parent: None,
+            title,
+            completed: false,
+            description: None,
+            due_at: None,
+            priority: Priority::default(),
+            tags: Vec::new(),
+            recurrence: None,
+            uid: None,
+        }
+    }
+
+    // The todo it was exported from, when it is among `existing`
+    fn exported_from<'a>(&self, existing: &[&'a Todo]) -> Option<&'a Todo> {
+        let id = self.uid.as_deref().and_then(ical::id_from_uid)?;
+        existing.iter().find(|todo| todo.id == id).copied()
+    }
+
+    // Write the imported fields over those of `todo`; the parent and list
+    // stay as they are
+    pub fn apply_to(&self, todo: &mut Todo, now: DateTime<Utc>) {
+        if !self.keeps_rule_of(todo) {
+            todo.recurrence = self.recurrence.clone();
+        }
+        todo.title = self.title.clone();
+        todo.set_completed(self.completed, now);
+        todo.description = self.description.clone();
+        todo.due_at = self.due_at;
+        todo.priority = self.priority;
+        todo.tags = normalize_tags(self.tags.clone());
+    }
+
+    fn differs_from(&self, todo: &Todo) -> bool {
+        self.title != todo.title
+            || self.completed != todo.completed
+            || self.description != todo.description
+            || self.due_at != todo.due_at
+            || self.priority != todo.priority
+            || normalize_tags(self.tags.clone()) != todo.tags
+            || (self.recurrence != todo.recurrence && !self.keeps_rule_of(todo))
+    }
+
+    // Completing a recurring todo hands its rule on to the next occurrence,
+    // so a completed todo keeps having none
+    fn keeps_rule_of(&self, todo: &Todo) -> bool {
+        self.completed && todo.completed
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ParseError {
+    pub line: usize,
+    pub error: String,
+}
+
+impl ParseError {
+    fn new(line: usize, error: impl Into<String>) -> Self {
+        ParseError {
+            line,
+            error: error.into(),
+        }
+    }
+}
+
+// Where the subtasks of an imported todo go
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Target {
+    // A todo that is already there
+    Existing(u32),
+    // The imported todo with this index
+    Imported(usize),
+}
+
+// What to do with one imported todo
+#[derive(Debug, Clone, PartialEq)]
+pub struct Planned {
+    // `false` for a duplicate that is skipped
+    pub create: bool,
+    // The todo it was exported from, which the import changes
+    pub update: Option<u32>,
+    pub parent: Option<Target>,
+    // The todo with the same title in the same place, if any
+    pub duplicate_of: Option<Target>,
+}
+
+// Decide which imported todos to create. A todo is a duplicate when a todo
+// with the same title (ignoring case and surrounding spaces) has the same
+// parent, among `existing` or earlier in the import. Subtasks of a skipped
+// duplicate go below the todo it duplicates. An item with the UID of one
+// of `existing` updates that todo, or is a duplicate of it when nothing
+// changed.
+pub fn plan(items: &[ImportedTodo], existing: &[Todo], allow_duplicates: bool) -> Vec<Planned> {
+    let mut seen: HashMap<(Option<Target>, String), Target> = HashMap::new();
+    // The oldest wins among duplicates that are already there
+    let mut existing: Vec<&Todo> = existing.iter().collect();
+    existing.sort_by_key(|todo| todo.id);
+    for todo in &existing {
+        let key = (todo.parent_id.map(Target::Existing), title_key(&todo.title));
+        seen.entry(key).or_insert(Target::Existing(todo.id));
+    }
+
+    // Where the subtasks of each item go
+    let mut targets: Vec<Target> = Vec::with_capacity(items.len());
+    let mut planned = Vec::with_capacity(items.len());
+    for (index, item) in items.iter().enumerate() {
+        let parent = item.parent.map(|parent| targets[parent]);
+        if let Some(todo) = item.exported_from(&existing) {
+            let changed = item.differs_from(todo);
+            targets.push(Target::Existing(todo.id));
+            planned.push(Planned {
+                create: false,
+                update: changed.then_some(todo.id),
+                parent,
+                duplicate_of: (!changed).then_some(Target::Existing(todo.id)),
+            });
+            continue;
+        }
+        let key = (parent, title_key(&item.title));
+        let duplicate_of = seen.get(&key).copied();
+        let create = duplicate_of.is_none() || allow_duplicates;
+        match duplicate_of {
+            Some(found) if !create => targets.push(found),
+            _ => {
+                targets.push(Target::Imported(index));
+                seen.entry(key).or_insert(Target::Imported(index));
+            }
+        }
+        planned.push(Planned {
+            create,
+            update: None,
+            parent,
+            duplicate_of,
+        });
+    }
+    planned
+}
+
+fn title_key(title: &str) -> String {
+    title.trim().to_lowercase()
+}
+
+// Todos with every parent before its subtasks, each level by id. Subtasks
+// whose parent is not among `todos` count as top level.
+fn tree_order(todos: &[Todo]) -> Vec<(usize, &Todo)> {
+    let ids: HashSet<u32> = todos.iter().map(|todo| todo.id).collect();
+    let mut children: HashMap<Option<u32>, Vec<&Todo>> = HashMap::new();
+    for todo in todos {
+        let parent = todo.parent_id.filter(|parent| ids.contains(parent));
+        children.entry(parent).or_default().push(todo);
+    }
+    for siblings in children.values_mut() {
+        siblings.sort_by_key(|todo| todo.id);
+    }
+
+    let mut ordered = Vec::with_capacity(todos.len());
+    visit(&children, None, 0, &mut ordered);
+    ordered
+}
+
+fn visit<'a>(
+    children: &HashMap<Option<u32>, Vec<&'a Todo>>,
+    parent: Option<u32>,
+    depth: usize,
+    ordered: &mut Vec<(usize, &'a Todo)>,
+) {
+    for todo in children.get(&parent).into_iter().flatten() {
+        ordered.push((depth, *todo));
+        visit(children, Some(todo.id), depth + 1, ordered);
+    }
+}
+
+fn priority_name(priority: Priority) -> &'static str {
+    match priority {
+        Priority::Low => "low",
+        Priority::Medium => "medium",
+        Priority::High => "high",
+        Priority::Urgent => "urgent",
+    }
+}
+
+fn parse_priority(value: &str) -> Option<Priority> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "low" => Some(Priority::Low),
+        "medium" | "" => Some(Priority::Medium),
+        "high" => Some(Priority::High),
+        "urgent" => Some(Priority::Urgent),
+        _ => None,
+    }
+}
+
+// A date for due dates at midnight, otherwise RFC 3339
+fn format_due(due: DateTime<Utc>) -> String {
+    if due.time() == NaiveTime::MIN {
+        due.format("%Y-%m-%d").to_string()
+    } else {
+        due.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    }
+}
+
+// RFC 3339, or a date meaning midnight UTC
+fn parse_due(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(due) = DateTime::parse_from_rfc3339(value) {
+        return Some(due.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    Some(date.and_time(NaiveTime::MIN).and_utc())
+}
@@ -0,0 +1,129 @@
+// todo.txt (https://github.com/todotxt/todo.txt): one task per line,
+// `x <completed> <created>` for done tasks, `(A)` priority, `+project` and
+// `@context` words, `due:` dates. Priorities map A = urgent, B = high,
+// none = medium and C..Z = low; projects and contexts both become tags.
+
+use super::{ImportedTodo, ParseError, format_due, parse_due};
+use crate::models::{Priority, Todo};
+use chrono::NaiveDate;
+
+pub(super) fn export(todos: &[(usize, &Todo)]) -> String {
+    let mut out = String::new();
+    for (_, todo) in todos {
+        let mut words: Vec<String> = Vec::new();
+        let created = todo.created_at.map(|at| at.format("%Y-%m-%d").to_string());
+        let priority = priority_letter(todo.priority);
+        if todo.completed {
+            words.push("x".to_string());
+            // A completion date needs a creation date after it
+            if let (Some(completed), Some(created)) = (todo.completed_at, &created) {
+                words.push(completed.format("%Y-%m-%d").to_string());
+                words.push(created.clone());
+            }
+        } else {
+            words.extend(priority.map(|letter| format!("({})", letter)));
+            words.extend(created);
+        }
+        words.push(todo.title.clone());
+        for tag in &todo.tags {
+            words.push(format!("+{}", tag.replace(char::is_whitespace, "-")));
+        }
+        if let Some(due) = todo.due_at {
+            words.push(format!("due:{}", format_due(due)));
+        }
+        // Completed tasks keep their priority as a tag
+        if todo.completed
+            && let Some(letter) = priority
+        {
+            words.push(format!("pri:{}", letter));
+        }
+        out.push_str(&words.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+fn priority_letter(priority: Priority) -> Option<char> {
+    match priority {
+        Priority::Urgent => Some('A'),
+        Priority::High => Some('B'),
+        Priority::Medium => None,
+        Priority::Low => Some('C'),
+    }
+}
+
+fn letter_priority(letter: &str) -> Option<Priority> {
+    let mut chars = letter.chars();
+    let (Some(letter), None) = (chars.next(), chars.next()) else {
+        return None;
+    };
+    match letter {
+        'A' => Some(Priority::Urgent),
+        'B' => Some(Priority::High),
+        'C'..='Z' => Some(Priority::Low),
+        _ => None,
+    }
+}
+
+pub(super) fn parse(text: &str) -> (Vec<ImportedTodo>, Vec<ParseError>) {
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let mut words = line.split_whitespace().peekable();
+        if words.peek().is_none() {
+            continue;
+        }
+        let is_date = |word: &&str| NaiveDate::parse_from_str(word, "%Y-%m-%d").is_ok();
+
+        let completed = words.next_if_eq(&"x").is_some();
+        let mut priority = None;
+        if completed {
+            // Completion and creation date
+            words.next_if(is_date);
+            words.next_if(is_date);
+        } else {
+            priority = words
+                .next_if(|word| word.len() == 3 && word.starts_with('(') && word.ends_with(')'))
+                .and_then(|word| letter_priority(&word[1..2]));
+            words.next_if(is_date);
+        }
+
+        let mut title = Vec::new();
+        let mut tags = Vec::new();
+        let mut due = None;
+        for word in words {
+            let tag = word.strip_prefix(['+', '@']).filter(|tag| !tag.is_empty());
+            if let Some(tag) = tag {
+                tags.push(tag.to_string());
+            } else if let Some(value) = word.strip_prefix("due:") {
+                due = Some(value);
+            } else if let Some(letter) = word.strip_prefix("pri:")
+                && let Some(found) = letter_priority(letter)
+            {
+                priority = Some(found);
+            } else {
+                title.push(word);
+            }
+        }
+        if title.is_empty() {
+            errors.push(ParseError::new(number, "task has no text"));
+            continue;
+        }
+
+        let mut item = ImportedTodo::new(number, title.join(" "));
+        item.completed = completed;
+        item.priority = priority.unwrap_or_default();
+        item.tags = tags;
+        if let Some(due) = due {
+            let Some(due) = parse_due(due) else {
+                errors.push(ParseError::new(number, format!("bad due date '{}'", due)));
+                continue;
+            };
+            item.due_at = Some(due);
+        }
+        items.push(item);
+    }
+    (items, errors)
+}
@@ -0,0 +1,115 @@
+use crate::auth::{Admin, Caller, KeyInfo, Role, User, UserInfo, accounts_disabled};
+use crate::error::{ApiError, ErrorBody};
+use axum::{extract::Path, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateUserRequest {
+    pub name: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+// The only time a key is shown in the clear
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NewUser {
+    pub user: UserInfo,
+    pub key: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NewKey {
+    #[serde(flatten)]
+    pub info: KeyInfo,
+    pub key: String,
+}
+
+// GET /me - The user the API key belongs to
+#[utoipa::path(
+    get,
+    path = "/me",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "The caller", body = User),
+        (status = 403, description = "The server runs without accounts", body = ErrorBody),
+    )
+)]
+pub async fn get_me(Caller(user): Caller) -> Result<Json<User>, ApiError> {
+    user.map(Json).ok_or_else(accounts_disabled)
+}
+
+// GET /admin/users - Every user with their keys (hints only)
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "Every user with their keys", body = Vec<UserInfo>),
+        (status = 403, description = "Not an admin, or no accounts", body = ErrorBody),
+    )
+)]
+pub async fn get_users(admin: Admin) -> Result<Json<Vec<UserInfo>>, ApiError> {
+    Ok(Json(admin.accounts.users()?))
+}
+
+// POST /admin/users - Create a user together with their first key
+#[utoipa::path(
+    post,
+    path = "/admin/users",
+    tag = "accounts",
+    request_body = CreateUserRequest,
+    responses(
+        (status = 201, description = "The user and their key, shown only now", body = NewUser),
+        (status = 400, description = "Not a usable name", body = ErrorBody),
+        (status = 403, description = "Not an admin, or no accounts", body = ErrorBody),
+        (status = 409, description = "The name is taken", body = ErrorBody),
+    )
+)]
+pub async fn create_user(
+    admin: Admin,
+    Json(payload): Json<CreateUserRequest>,
+) -> Result<(StatusCode, Json<NewUser>), ApiError> {
+    let (user, key) = admin.accounts.create_user(&payload.name, payload.role)?;
+    Ok((StatusCode::CREATED, Json(NewUser { user, key })))
+}
+
+// POST /admin/users/:user_id/keys - Another key for a user, e.g. to rotate
+// the old one out
+#[utoipa::path(
+    post,
+    path = "/admin/users/{user_id}/keys",
+    tag = "accounts",
+    params(("user_id" = u32, Path)),
+    responses(
+        (status = 201, description = "The key, shown only now", body = NewKey),
+        (status = 403, description = "Not an admin, or no accounts", body = ErrorBody),
+        (status = 404, description = "No such user", body = ErrorBody),
+    )
+)]
+pub async fn create_key(
+    admin: Admin,
+    Path(user_id): Path<u32>,
+) -> Result<(StatusCode, Json<NewKey>), ApiError> {
+    let (info, key) = admin.accounts.create_key(user_id)?;
+    Ok((StatusCode::CREATED, Json(NewKey { info, key })))
+}
+
+// DELETE /admin/keys/:key_id - Revoke a key; the last key of the last
+// admin cannot be revoked
+#[utoipa::path(
+    delete,
+    path = "/admin/keys/{key_id}",
+    tag = "accounts",
+    params(("key_id" = u32, Path)),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 403, description = "Not an admin, or no accounts", body = ErrorBody),
+        (status = 404, description = "No such key", body = ErrorBody),
+        (status = 409, description = "The last key of the last admin", body = ErrorBody),
+    )
+)]
+pub async fn revoke_key(admin: Admin, Path(key_id): Path<u32>) -> Result<StatusCode, ApiError> {
+    admin.accounts.revoke_key(key_id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
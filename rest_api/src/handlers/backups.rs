@@ -0,0 +1,92 @@
+use super::AppState;
+use crate::auth::Caller;
+use crate::backup::{Backup, Backups, Restored};
+use crate::error::{ApiError, ErrorBody};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{StatusCode, request::Parts},
+    response::Json,
+};
+use std::sync::Arc;
+
+// The backups, for an admin or, on a server without accounts, anyone: there
+// everyone can change every todo anyway
+pub struct BackupAdmin(pub Arc<Backups>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for BackupAdmin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Caller(user) = Caller::from_request_parts(parts, state).await?;
+        if user.is_some_and(|user| !user.is_admin()) {
+            return Err(ApiError::Forbidden(
+                "admin_only",
+                "Only admins can manage backups".to_string(),
+            ));
+        }
+        match parts.extensions.get::<Arc<Backups>>() {
+            Some(backups) => Ok(BackupAdmin(backups.clone())),
+            None => Err(ApiError::Forbidden(
+                "backups_disabled",
+                "This server runs without backups".to_string(),
+            )),
+        }
+    }
+}
+
+// GET /admin/backups - Backups, newest first, checked against their checksums
+#[utoipa::path(
+    get,
+    path = "/admin/backups",
+    tag = "backups",
+    responses(
+        (status = 200, description = "Backups, newest first", body = Vec<Backup>),
+        (status = 403, description = "Not an admin, or no backups", body = ErrorBody),
+    )
+)]
+pub async fn get_backups(BackupAdmin(backups): BackupAdmin) -> Result<Json<Vec<Backup>>, ApiError> {
+    Ok(Json(backups.list()?))
+}
+
+// POST /admin/backups - Take a backup now
+#[utoipa::path(
+    post,
+    path = "/admin/backups",
+    tag = "backups",
+    responses(
+        (status = 201, description = "The new backup", body = Backup),
+        (status = 403, description = "Not an admin, or no backups", body = ErrorBody),
+    )
+)]
+pub async fn create_backup(
+    BackupAdmin(backups): BackupAdmin,
+) -> Result<(StatusCode, Json<Backup>), ApiError> {
+    Ok((StatusCode::CREATED, Json(backups.create().await?)))
+}
+
+// POST /admin/backups/:name/restore - Replace every todo with the backup's,
+// after backing up what is there now
+#[utoipa::path(
+    post,
+    path = "/admin/backups/{name}/restore",
+    tag = "backups",
+    params(("name" = String, Path, example = "todos-20250131T170000.000Z.json")),
+    responses(
+        (status = 200, description = "Restored, after a backup of what was there",
+            body = Restored),
+        (status = 403, description = "Not an admin, or no backups", body = ErrorBody),
+        (status = 404, description = "No such backup", body = ErrorBody),
+        (status = 422, description = "Damaged or unreadable, or encrypted with another key",
+            body = ErrorBody),
+    )
+)]
+pub async fn restore_backup(
+    BackupAdmin(backups): BackupAdmin,
+    State(repo): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Restored>, ApiError> {
+    // Through the handlers' repository, so open event streams get a `reset`
+    Ok(Json(backups.restore_through(&name, repo.as_ref()).await?))
+}
@@ -0,0 +1,282 @@
+use super::{AppState, UserRepo};
+use super::plan::Plan;
+use super::todos::{prepare_create, usable_list};
+use crate::error::{ApiError, BatchError, ErrorBody, StorageError};
+use crate::hierarchy;
+use crate::models::{BatchOperation, BatchOutcome, Todo};
+use axum::{http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+// Most operations accepted in one batch
+const MAX_OPERATIONS: usize = 1000;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResponse {
+    // `false` when the batch was rejected and none of it was applied
+    pub committed: bool,
+    pub results: Vec<OperationResult>,
+}
+
+// Result of one operation, `status` is what the same request on its own
+// would have answered
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OperationResult {
+    pub index: usize,
+    pub op: &'static str,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<Todo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl OperationResult {
+    fn new(index: usize, op: &'static str, status: StatusCode) -> Self {
+        OperationResult {
+            index,
+            op,
+            status: status.as_u16(),
+            todo: None,
+            id: None,
+            code: None,
+            error: None,
+        }
+    }
+}
+
+// POST /todos/batch - Apply create/update/delete operations all or nothing.
+// 200 when everything was applied, 409 with the failing operation otherwise.
+#[utoipa::path(
+    post,
+    path = "/todos/batch",
+    tag = "todos",
+    request_body = BatchRequest,
+    responses(
+        (status = 200, description = "Every operation was applied", body = BatchResponse),
+        (status = 400, description = "Too many operations", body = ErrorBody),
+        (status = 409, description = "Nothing was applied, see the failing operation",
+            body = BatchResponse),
+    )
+)]
+pub async fn batch_todos(
+    UserRepo(repo): UserRepo,
+    Json(request): Json<BatchRequest>,
+) -> Result<(StatusCode, Json<BatchResponse>), ApiError> {
+    let mut ops = request.operations;
+    if ops.len() > MAX_OPERATIONS {
+        return Err(ApiError::BadRequest(
+            "batch_too_large",
+            format!("A batch can have at most {} operations", MAX_OPERATIONS),
+        ));
+    }
+    let names: Vec<&'static str> = ops.iter().map(BatchOperation::name).collect();
+
+    let deleted: HashSet<u32> = ops
+        .iter()
+        .filter_map(|op| match op {
+            BatchOperation::Delete { id, .. } => Some(*id),
+            _ => None,
+        })
+        .collect();
+
+    // The operations come first in the batch, at their own index, and the
+    // follow-up writes after them
+    loop {
+        let mut plan = Plan::new(&repo).await?;
+        // Everything the single-todo endpoints check, against the todos as
+        // they are before the batch
+        let todos = plan.todos().to_vec();
+        let mut new_lists = Vec::with_capacity(ops.len());
+        for (index, op) in ops.iter_mut().enumerate() {
+            match check_operation(&repo, &todos, &deleted, op).await {
+                Ok(new_list) => new_lists.push(new_list),
+                Err(err) => return Ok(rejected(&names, index, err)),
+            }
+        }
+
+        let mut updated = Vec::new();
+        for (index, op) in ops.iter().enumerate() {
+            let planned = match op {
+                BatchOperation::Create { todo } => {
+                    plan.create(todo.clone());
+                    Ok(())
+                }
+                BatchOperation::Update { id, changes, revision } => {
+                    // Completed as of the operations before this one
+                    let was_completed = plan.get(*id).is_some_and(|todo| todo.completed);
+                    plan.update(*id, changes.clone(), *revision)
+                        .map(|todo| updated.push((index, todo.id, was_completed)))
+                }
+                BatchOperation::Delete { id, revision } => plan.delete(*id, *revision),
+                BatchOperation::Replace { .. } | BatchOperation::Restore { .. } => Err(unknown_operation()),
+            };
+            if let Err(err) = planned {
+                return Ok(rejected(&names, index, err));
+            }
+        }
+
+        // Same follow-up writes as PUT, unless the batch deletes the todo
+        // again; the revision an operation's result is shown at
+        let mut shown = vec![None; ops.len()];
+        for (index, id, was_completed) in updated {
+            let Some(todo) = plan.get(id).filter(|_| !deleted.contains(&id)) else {
+                continue;
+            };
+            let revision = todo.revision;
+            let todo = plan.after_update(todo, was_completed, new_lists[index]);
+            if todo.revision != revision {
+                shown[index] = Some(todo.revision);
+            }
+        }
+
+        let outcomes = match plan.commit_batch(&repo).await {
+            Ok(Some(outcomes)) => outcomes,
+            // Written in between by someone else; plan again
+            Ok(None) => continue,
+            Err(BatchError::NotFound { index, .. }) => {
+                return Ok(rejected(&names, index, ApiError::NotFound));
+            }
+            Err(BatchError::RevisionMismatch { index, id, current }) => {
+                let err = ApiError::Storage(StorageError::RevisionMismatch { id, current });
+                return Ok(rejected(&names, index, err));
+            }
+            Err(BatchError::Storage(err)) => return Err(err.into()),
+        };
+        let results = names
+            .iter()
+            .enumerate()
+            .map(|(index, op)| result(index, op, &outcomes, shown[index]))
+            .collect();
+        return Ok((
+            StatusCode::OK,
+            Json(BatchResponse {
+                committed: true,
+                results,
+            }),
+        ));
+    }
+}
+
+// Result of operation `index` of a committed batch, with the todo at
+// `shown` when its follow-up writes changed it again
+fn result(
+    index: usize,
+    op: &'static str,
+    outcomes: &[BatchOutcome],
+    shown: Option<u64>,
+) -> OperationResult {
+    match &outcomes[index] {
+        BatchOutcome::Created(todo) => OperationResult {
+            todo: Some(todo.clone()),
+            ..OperationResult::new(index, op, StatusCode::CREATED)
+        },
+        BatchOutcome::Updated(todo) => {
+            let followed = outcomes.iter().find_map(|outcome| match outcome {
+                BatchOutcome::Updated(t) if t.id == todo.id && Some(t.revision) == shown => {
+                    Some(t)
+                }
+                _ => None,
+            });
+            OperationResult {
+                todo: Some(followed.unwrap_or(todo).clone()),
+                ..OperationResult::new(index, op, StatusCode::OK)
+            }
+        }
+        BatchOutcome::Deleted(id) => OperationResult {
+            id: Some(*id),
+            ..OperationResult::new(index, op, StatusCode::NO_CONTENT)
+        },
+    }
+}
+
+// Validate one operation; returns the list an update moves the todo to
+async fn check_operation(
+    repo: &AppState,
+    todos: &[Todo],
+    deleted: &HashSet<u32>,
+    op: &mut BatchOperation,
+) -> Result<Option<u32>, ApiError> {
+    match op {
+        BatchOperation::Create { todo } => {
+            prepare_create(repo, todo).await?;
+            Ok(None)
+        }
+        BatchOperation::Update { id, changes, .. } => {
+            let current = todos.iter().find(|t| t.id == *id);
+            let new_list = changes
+                .list_id
+                .filter(|list_id| current.is_some_and(|t| t.list_id != *list_id));
+            if let Some(list_id) = new_list {
+                usable_list(repo, list_id).await?;
+            }
+            Ok(new_list)
+        }
+        BatchOperation::Delete { id, .. } => {
+            // Subtasks have to be deleted in the same batch
+            let remaining = hierarchy::descendants(todos, *id)
+                .into_iter()
+                .filter(|subtask| !deleted.contains(subtask))
+                .count();
+            if remaining > 0 {
+                return Err(ApiError::Conflict(
+                    "has_children",
+                    format!("Todo {} has {} subtask(s) that are not deleted too", id, remaining),
+                ));
+            }
+            Ok(None)
+        }
+        // Never deserialized from a request
+        BatchOperation::Replace { .. } | BatchOperation::Restore { .. } => Err(unknown_operation()),
+    }
+}
+
+// An operation only the server itself puts in a batch
+pub(super) fn unknown_operation() -> ApiError {
+    ApiError::BadRequest("invalid_operation", "Unknown operation".to_string())
+}
+
+// 409 response for a batch whose operation `index` failed with `err`
+fn rejected(
+    names: &[&'static str],
+    index: usize,
+    err: ApiError,
+) -> (StatusCode, Json<BatchResponse>) {
+    let results = names
+        .iter()
+        .enumerate()
+        .map(|(i, op)| {
+            if i == index {
+                let (status, code) = err.status_and_code();
+                OperationResult {
+                    code: Some(code),
+                    error: Some(err.to_string()),
+                    ..OperationResult::new(i, op, status)
+                }
+            } else {
+                OperationResult {
+                    code: Some("not_applied"),
+                    ..OperationResult::new(i, op, StatusCode::FAILED_DEPENDENCY)
+                }
+            }
+        })
+        .collect();
+
+    (
+        StatusCode::CONFLICT,
+        Json(BatchResponse {
+            committed: false,
+            results,
+        }),
+    )
+}
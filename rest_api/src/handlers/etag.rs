@@ -0,0 +1,62 @@
+use crate::error::{ApiError, StorageError};
+use crate::models::Todo;
+use axum::{
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+
+// A single todo, sent with its revision as a strong `ETag` (`"3"`)
+pub struct TodoResponse(pub Todo);
+
+impl IntoResponse for TodoResponse {
+    fn into_response(self) -> Response {
+        ([(header::ETAG, etag(&self.0))], Json(self.0)).into_response()
+    }
+}
+
+fn etag(todo: &Todo) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", todo.revision)).expect("digits are a valid header")
+}
+
+// GET of a single todo: 304 without a body when `If-None-Match` names the
+// current revision, the todo otherwise
+pub fn conditional_get(headers: &HeaderMap, todo: Todo) -> Response {
+    if matches(headers, header::IF_NONE_MATCH, &todo, true) {
+        (StatusCode::NOT_MODIFIED, [(header::ETAG, etag(&todo))]).into_response()
+    } else {
+        TodoResponse(todo).into_response()
+    }
+}
+
+// Revision a write to `todo` has to be conditional on: none without
+// `If-Match`, 412 when `If-Match` names another revision than the current one
+pub fn if_match(headers: &HeaderMap, todo: &Todo) -> Result<Option<u64>, ApiError> {
+    if !headers.contains_key(header::IF_MATCH) {
+        return Ok(None);
+    }
+    if matches(headers, header::IF_MATCH, todo, false) {
+        Ok(Some(todo.revision))
+    } else {
+        Err(ApiError::Storage(StorageError::RevisionMismatch {
+            id: todo.id,
+            current: todo.revision,
+        }))
+    }
+}
+
+// Whether any entity tag in the header (or `*`) matches the todo. Weak tags
+// (`W/"3"`) only match with weak comparison, i.e. for If-None-Match.
+fn matches(headers: &HeaderMap, name: HeaderName, todo: &Todo, weak: bool) -> bool {
+    let current = format!("\"{}\"", todo.revision);
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| match tag.strip_prefix("W/") {
+            _ if tag == "*" => true,
+            Some(tag) => weak && tag == current,
+            None => tag == current,
+        })
+}
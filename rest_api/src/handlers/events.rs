@@ -0,0 +1,88 @@
+use crate::auth::Caller;
+use crate::events::{Backlog, ChangeFeed, EventId, TodoChange, TodoEvent};
+use axum::{
+    Extension,
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+// GET /todos/events - Server-Sent Events stream of todo changes:
+// `created` / `updated` (data: the todo) and `deleted` (data: `{"id": N}`).
+// Reconnecting with `Last-Event-ID` replays what was missed. A `reset` event
+// tells the client to reload its todos: when that is no longer possible, and
+// after a backup was restored.
+// With accounts, every user only hears about their own todos.
+#[utoipa::path(
+    get,
+    path = "/todos/events",
+    tag = "sync",
+    params(
+        ("Last-Event-ID" = Option<String>, Header,
+            description = "Replay what came after it (`<epoch>-<seq>`)"),
+    ),
+    responses(
+        (status = 200, description = "`created`, `updated`, `deleted` and `reset` events",
+            body = String, content_type = "text/event-stream"),
+    )
+)]
+pub async fn todo_events(
+    Extension(feed): Extension<Arc<ChangeFeed>>,
+    Caller(user): Caller,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let owner_id = user.map(|user| user.id);
+    let visible = move |event: &TodoEvent| {
+        let owner = event.change.owner_id();
+        owner_id.is_none_or(|id| owner.is_none_or(|owner| owner == id))
+    };
+
+    // An id we cannot parse is as good as an unknown one
+    let last_seen = headers.get("last-event-id").map(|value| {
+        value.to_str().ok().and_then(|id| id.parse().ok()).unwrap_or(EventId {
+            epoch: 0,
+            seq: u64::MAX,
+        })
+    });
+
+    let (backlog, receiver) = feed.subscribe(last_seen);
+    let backlog = match backlog {
+        Backlog::Events(events) => events.iter().filter(|e| visible(e)).map(sse_event).collect(),
+        Backlog::Reset { last } => vec![reset_event().id(last.to_string())],
+    };
+
+    let live = stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) if visible(&event) => return Some((sse_event(&event), receiver)),
+                Ok(_) => {}
+                // This client fell too far behind and missed events
+                Err(RecvError::Lagged(_)) => return Some((reset_event(), receiver)),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Sse::new(stream::iter(backlog).chain(live).map(Ok)).keep_alive(KeepAlive::default())
+}
+
+fn sse_event(event: &TodoEvent) -> Event {
+    let data = match &event.change {
+        TodoChange::Created { todo } | TodoChange::Updated { todo } => serde_json::to_string(todo),
+        TodoChange::Deleted { id, .. } => {
+            serde_json::to_string(&serde_json::json!({ "id": id }))
+        }
+        TodoChange::Reset => Ok("{}".to_string()),
+    };
+    Event::default()
+        .id(event.id().to_string())
+        .event(event.change.name())
+        .data(data.unwrap_or_default())
+}
+
+fn reset_event() -> Event {
+    Event::default().event("reset").data("{}")
+}
@@ -0,0 +1,320 @@
+use super::{AppState, UserRepo};
+use super::plan::Plan;
+use super::todos::usable_list;
+use crate::error::{ApiError, ErrorBody};
+use crate::formats::{self, Format, ImportedTodo, ParseError, Target};
+use crate::models::{BatchOutcome, CreateTodoRequest, DEFAULT_LIST_ID, Todo};
+use axum::{
+    extract::Query,
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+// Most todos accepted in one import
+const MAX_IMPORT: usize = 5000;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    pub format: Format,
+    // Defaults to the default list
+    pub list_id: Option<u32>,
+}
+
+// GET /todos/export?format=csv|markdown|todotxt|ical&list_id=N - Todos of
+// a list as a file for other tools
+#[utoipa::path(
+    get,
+    path = "/todos/export",
+    tag = "import/export",
+    params(ExportParams),
+    responses(
+        (status = 200, description = "The todos as a file", content(
+            (String = "text/csv"),
+            (String = "text/markdown"),
+            (String = "text/plain"),
+            (String = "text/calendar"),
+        )),
+        (status = 404, description = "No such list", body = ErrorBody),
+    )
+)]
+pub async fn export_todos(
+    Query(params): Query<ExportParams>,
+    UserRepo(repo): UserRepo,
+) -> Result<Response, ApiError> {
+    let todos = list_todos(&repo, params.list_id).await?;
+    let disposition = format!("attachment; filename=\"{}\"", params.format.file_name());
+    let headers = [
+        (header::CONTENT_TYPE, params.format.content_type().to_string()),
+        (header::CONTENT_DISPOSITION, disposition),
+    ];
+    Ok((headers, params.format.export(&todos)).into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CalendarParams {
+    pub list_id: Option<u32>,
+}
+
+// GET /todos.ics?list_id=N - Todos of a list as VTODOs, for calendar apps
+// to subscribe to. POST /todos/import?format=ical takes them back.
+#[utoipa::path(
+    get,
+    path = "/todos.ics",
+    tag = "import/export",
+    params(CalendarParams),
+    responses(
+        (status = 200, description = "The todos as VTODOs", body = String,
+            content_type = "text/calendar"),
+        (status = 404, description = "No such list", body = ErrorBody),
+    )
+)]
+pub async fn todos_calendar(
+    Query(params): Query<CalendarParams>,
+    UserRepo(repo): UserRepo,
+) -> Result<Response, ApiError> {
+    let todos = list_todos(&repo, params.list_id).await?;
+    let content_type = Format::Ical.content_type();
+    Ok(([(header::CONTENT_TYPE, content_type)], Format::Ical.export(&todos)).into_response())
+}
+
+// Todos of a list, the default list without `list_id`
+async fn list_todos(repo: &AppState, list_id: Option<u32>) -> Result<Vec<Todo>, ApiError> {
+    let list_id = list_id.unwrap_or(DEFAULT_LIST_ID);
+    if repo.get_list(list_id).await?.is_none() {
+        return Err(ApiError::ListNotFound);
+    }
+    let mut todos = repo.list().await?;
+    todos.retain(|todo| todo.list_id == list_id);
+    Ok(todos)
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportParams {
+    pub format: Format,
+    pub list_id: Option<u32>,
+    // Only report what would be imported
+    #[serde(default)]
+    pub dry_run: bool,
+    // Create duplicates instead of skipping them
+    #[serde(default)]
+    pub allow_duplicates: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub list_id: u32,
+    // Todos created, or that would be created on a dry run
+    pub created: usize,
+    // Todos an import of their own export changed
+    pub updated: usize,
+    pub skipped_duplicates: usize,
+    // Lines that could not be read; nothing is imported while there are any
+    pub errors: Vec<ParseError>,
+    pub items: Vec<ImportItem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Create,
+    Update,
+    SkipDuplicate,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ImportItem {
+    pub line: usize,
+    pub title: String,
+    pub action: ImportAction,
+    // The todo with the same title that is already there...
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<u32>,
+    // ...or the earlier line of the import it repeats
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of_line: Option<usize>,
+    // The created or updated todo, not on a dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<Todo>,
+}
+
+// POST /todos/import?format=csv|markdown|todotxt|ical&list_id=N
+// &dry_run=true&allow_duplicates=true - Create todos from a file of
+// another tool. The body is the file. Todos whose title is already taken
+// at the same place are skipped unless `allow_duplicates` is set. VTODOs
+// exported from this list update their todo. 422 with the report when any
+// line cannot be read, nothing is imported then.
+#[utoipa::path(
+    post,
+    path = "/todos/import",
+    tag = "import/export",
+    params(ImportParams),
+    request_body(content = String, description = "The file", content_type = "text/plain"),
+    responses(
+        (status = 200, description = "What was (or would be) imported", body = ImportReport),
+        (status = 400, description = "No such list, or too many todos", body = ErrorBody),
+        (status = 409, description = "The list is archived", body = ErrorBody),
+        (status = 422, description = "Unreadable lines, nothing was imported",
+            body = ImportReport),
+    )
+)]
+pub async fn import_todos(
+    Query(params): Query<ImportParams>,
+    UserRepo(repo): UserRepo,
+    body: String,
+) -> Result<(StatusCode, Json<ImportReport>), ApiError> {
+    let list_id = params.list_id.unwrap_or(DEFAULT_LIST_ID);
+    usable_list(&repo, list_id).await?;
+
+    let (imported, errors) = params.format.parse(&body);
+    if imported.len() > MAX_IMPORT {
+        return Err(ApiError::BadRequest(
+            "import_too_large",
+            format!("An import can have at most {} todos", MAX_IMPORT),
+        ));
+    }
+    let report_head = ImportReport {
+        dry_run: params.dry_run,
+        list_id,
+        created: 0,
+        updated: 0,
+        skipped_duplicates: 0,
+        errors,
+        items: Vec::new(),
+    };
+    if !report_head.errors.is_empty() {
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(report_head)));
+    }
+
+    // Written in one batch; when a todo changed in between, plan again
+    loop {
+        let mut plan = Plan::new(&repo).await?;
+        let mut existing = plan.todos().to_vec();
+        existing.retain(|todo| todo.list_id == list_id);
+        let planned = formats::plan(&imported, &existing, params.allow_duplicates);
+
+        let mut report = ImportReport {
+            items: Vec::with_capacity(imported.len()),
+            ..report_head.clone()
+        };
+        // Per item: the op that creates it, and how its todo is found once
+        // the batch is written
+        let mut created: Vec<Option<usize>> = Vec::with_capacity(imported.len());
+        let mut written = Vec::with_capacity(imported.len());
+        for (item, planned) in imported.iter().zip(planned) {
+            let (duplicate_of, duplicate_of_line) = match planned.duplicate_of {
+                Some(Target::Existing(id)) => (Some(id), None),
+                Some(Target::Imported(index)) => (None, Some(report.items[index].line)),
+                None => (None, None),
+            };
+            let mut result = ImportItem {
+                line: item.line,
+                title: item.title.clone(),
+                action: ImportAction::Create,
+                duplicate_of,
+                duplicate_of_line,
+                todo: None,
+            };
+            if planned.update.is_some() {
+                result.action = ImportAction::Update;
+                report.updated += 1;
+            } else if !planned.create {
+                result.action = ImportAction::SkipDuplicate;
+                report.skipped_duplicates += 1;
+            } else {
+                report.created += 1;
+            }
+            report.items.push(result);
+
+            let mut op = None;
+            let target = if let Some(id) = planned.update {
+                let todo = update_imported(&mut plan, item, id)?;
+                Some(Written::Updated(todo.id, todo.revision))
+            } else if planned.create {
+                let mut request = create_imported(item, list_id);
+                match planned.parent {
+                    Some(Target::Existing(id)) => request.parent_id = Some(id),
+                    Some(Target::Imported(index)) => request.parent_op = created[index],
+                    None => {}
+                }
+                let index = plan.create(request);
+                op = Some(index);
+                Some(Written::Created(index))
+            } else {
+                None
+            };
+            created.push(op);
+            written.push((target, planned.duplicate_of));
+        }
+        if params.dry_run {
+            return Ok((StatusCode::OK, Json(report)));
+        }
+
+        let Some(outcomes) = plan.commit(&repo).await? else {
+            continue;
+        };
+        for (result, (target, duplicate_of)) in report.items.iter_mut().zip(written) {
+            result.todo = target.and_then(|target| target.find(&outcomes));
+            if let Some(Target::Imported(index)) = duplicate_of {
+                result.duplicate_of = created[index]
+                    .and_then(|op| Written::Created(op).find(&outcomes))
+                    .map(|todo| todo.id);
+            }
+        }
+        return Ok((StatusCode::OK, Json(report)));
+    }
+}
+
+// Where the todo of an import item is among the outcomes of the batch
+enum Written {
+    Created(usize),
+    Updated(u32, u64),
+}
+
+impl Written {
+    fn find(self, outcomes: &[BatchOutcome]) -> Option<Todo> {
+        outcomes.iter().enumerate().find_map(|(index, outcome)| match (outcome, &self) {
+            (BatchOutcome::Created(todo), Written::Created(op)) if index == *op => {
+                Some(todo.clone())
+            }
+            (BatchOutcome::Updated(todo), Written::Updated(id, revision))
+                if todo.id == *id && todo.revision == *revision =>
+            {
+                Some(todo.clone())
+            }
+            _ => None,
+        })
+    }
+}
+
+fn create_imported(item: &ImportedTodo, list_id: u32) -> CreateTodoRequest {
+    CreateTodoRequest {
+        title: item.title.clone(),
+        description: item.description.clone(),
+        due_at: item.due_at,
+        priority: item.priority,
+        tags: item.tags.clone(),
+        recurrence: item.recurrence.clone(),
+        completed: item.completed,
+        list_id: Some(list_id),
+        ..Default::default()
+    }
+}
+
+fn update_imported(plan: &mut Plan, item: &ImportedTodo, id: u32) -> Result<Todo, ApiError> {
+    let Some(current) = plan.get(id) else {
+        return Err(ApiError::NotFound);
+    };
+    let mut todo = current.clone();
+    let now = Utc::now();
+    item.apply_to(&mut todo, now);
+    todo.updated_at = Some(now);
+    let todo = plan.replace(todo, false);
+    Ok(plan.after_update(todo, current.completed, None))
+}
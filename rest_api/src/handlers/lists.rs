@@ -0,0 +1,372 @@
+use super::{AppState, UserRepo};
+use super::etag::{self, TodoResponse};
+use super::todos::{self, DeleteParams};
+use crate::error::{ApiError, ErrorBody};
+use crate::hierarchy;
+use crate::models::{
+    CreateListRequest, CreateTodoRequest, DEFAULT_LIST_ID, Todo, TodoList, TodoPage, TodoQuery,
+    UpdateListRequest, UpdateTodoRequest, is_valid_color,
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{Json, Response},
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListsParams {
+    // Only archived (true) or only active (false) lists
+    pub archived: Option<bool>,
+}
+
+// GET /lists - Get all lists
+#[utoipa::path(
+    get,
+    path = "/lists",
+    tag = "lists",
+    params(ListsParams),
+    responses((status = 200, description = "The lists", body = Vec<TodoList>))
+)]
+pub async fn get_lists(
+    Query(params): Query<ListsParams>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<Vec<TodoList>>, ApiError> {
+    let lists = repo
+        .lists()
+        .await?
+        .into_iter()
+        .filter(|list| params.archived.is_none_or(|archived| list.archived == archived))
+        .collect();
+    Ok(Json(lists))
+}
+
+// GET /lists/:list_id - Get specific list
+#[utoipa::path(
+    get,
+    path = "/lists/{list_id}",
+    tag = "lists",
+    params(("list_id" = u32, Path)),
+    responses(
+        (status = 200, description = "The list", body = TodoList),
+        (status = 404, description = "No such list", body = ErrorBody),
+    )
+)]
+pub async fn get_list(
+    Path(list_id): Path<u32>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<TodoList>, ApiError> {
+    Ok(Json(find_list(&repo, list_id).await?))
+}
+
+// POST /lists - Create new list
+#[utoipa::path(
+    post,
+    path = "/lists",
+    tag = "lists",
+    request_body = CreateListRequest,
+    responses(
+        (status = 200, description = "The new list", body = TodoList),
+        (status = 400, description = "Empty name or not a `#rrggbb` color", body = ErrorBody),
+    )
+)]
+pub async fn create_list(
+    UserRepo(repo): UserRepo,
+    Json(payload): Json<CreateListRequest>,
+) -> Result<Json<TodoList>, ApiError> {
+    validate_list(Some(&payload.name), payload.color.as_deref())?;
+    Ok(Json(repo.create_list(payload).await?))
+}
+
+// PUT /lists/:list_id - Rename, recolor or (un)archive a list
+#[utoipa::path(
+    put,
+    path = "/lists/{list_id}",
+    tag = "lists",
+    params(("list_id" = u32, Path)),
+    request_body = UpdateListRequest,
+    responses(
+        (status = 200, description = "The updated list", body = TodoList),
+        (status = 400, description = "Empty name or not a `#rrggbb` color", body = ErrorBody),
+        (status = 403, description = "Someone else's list", body = ErrorBody),
+        (status = 404, description = "No such list", body = ErrorBody),
+        (status = 409, description = "Archiving the default list", body = ErrorBody),
+    )
+)]
+pub async fn update_list(
+    Path(list_id): Path<u32>,
+    UserRepo(repo): UserRepo,
+    Json(payload): Json<UpdateListRequest>,
+) -> Result<Json<TodoList>, ApiError> {
+    validate_list(payload.name.as_deref(), payload.color.as_deref())?;
+    if list_id == DEFAULT_LIST_ID && payload.archived == Some(true) {
+        return Err(ApiError::Conflict(
+            "default_list",
+            "The default list cannot be archived".to_string(),
+        ));
+    }
+    match repo.update_list(list_id, payload).await? {
+        Some(list) => Ok(Json(list)),
+        // The shared default list, seen by everyone but not theirs to change
+        None if find_list(&repo, list_id).await.is_ok() => Err(ApiError::Forbidden(
+            "list_not_owned",
+            format!("Only the owner of list {} can change it", list_id),
+        )),
+        None => Err(ApiError::ListNotFound),
+    }
+}
+
+// DELETE /lists/:list_id - Delete list (?cascade=true to delete its todos too)
+#[utoipa::path(
+    delete,
+    path = "/lists/{list_id}",
+    tag = "lists",
+    params(("list_id" = u32, Path), DeleteParams),
+    responses(
+        (status = 204, description = "Deleted, its todos moved to the trash"),
+        (status = 404, description = "No such list", body = ErrorBody),
+        (status = 409, description = "The default list, or not empty and no `cascade`",
+            body = ErrorBody),
+    )
+)]
+pub async fn delete_list(
+    Path(list_id): Path<u32>,
+    Query(params): Query<DeleteParams>,
+    UserRepo(repo): UserRepo,
+) -> Result<StatusCode, ApiError> {
+    if list_id == DEFAULT_LIST_ID {
+        return Err(ApiError::Conflict(
+            "default_list",
+            "The default list cannot be deleted".to_string(),
+        ));
+    }
+    find_list(&repo, list_id).await?;
+
+    let todos = repo.list().await?;
+    let in_list: Vec<&Todo> = todos.iter().filter(|t| t.list_id == list_id).collect();
+    if !in_list.is_empty() {
+        if !params.cascade {
+            return Err(ApiError::Conflict(
+                "list_not_empty",
+                format!(
+                    "List {} has {} todo(s), delete with ?cascade=true",
+                    list_id,
+                    in_list.len()
+                ),
+            ));
+        }
+        // Subtasks first, then the top-level todos of the list
+        for root in in_list.iter().filter(|t| t.parent_id.is_none()) {
+            for subtask in hierarchy::descendants(&todos, root.id) {
+                repo.delete(subtask, None).await?;
+            }
+            repo.delete(root.id, None).await?;
+        }
+    }
+
+    if repo.delete_list(list_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::ListNotFound)
+    }
+}
+
+// GET /lists/:list_id/todos - Todos of a list, same query params as GET /todos
+#[utoipa::path(
+    get,
+    path = "/lists/{list_id}/todos",
+    tag = "lists",
+    params(("list_id" = u32, Path), TodoQuery),
+    responses(
+        (status = 200, description = "One page of the list's todos", body = TodoPage),
+        (status = 404, description = "No such list", body = ErrorBody),
+    )
+)]
+pub async fn get_list_todos(
+    Path(list_id): Path<u32>,
+    Query(query): Query<TodoQuery>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<TodoPage>, ApiError> {
+    find_list(&repo, list_id).await?;
+    let query = TodoQuery {
+        list_id: Some(list_id),
+        ..query
+    };
+    todos::query_page(&repo, query, &format!("/lists/{}/todos", list_id)).await
+}
+
+// POST /lists/:list_id/todos - Create new todo in a list
+#[utoipa::path(
+    post,
+    path = "/lists/{list_id}/todos",
+    tag = "lists",
+    params(("list_id" = u32, Path)),
+    request_body = CreateTodoRequest,
+    responses(
+        (status = 200, description = "The new todo", body = Todo),
+        (status = 400, description = "No such parent, or one in another list", body = ErrorBody),
+        (status = 404, description = "No such list", body = ErrorBody),
+        (status = 409, description = "The list is archived", body = ErrorBody),
+    )
+)]
+pub async fn create_list_todo(
+    Path(list_id): Path<u32>,
+    UserRepo(repo): UserRepo,
+    Json(payload): Json<CreateTodoRequest>,
+) -> Result<TodoResponse, ApiError> {
+    find_list(&repo, list_id).await?;
+    let payload = CreateTodoRequest {
+        list_id: Some(list_id),
+        ..payload
+    };
+    todos::create_todo(UserRepo(repo), Json(payload)).await
+}
+
+// GET /lists/:list_id/todos/:id - Get specific todo of a list
+#[utoipa::path(
+    get,
+    path = "/lists/{list_id}/todos/{id}",
+    tag = "lists",
+    params(
+        ("list_id" = u32, Path),
+        ("id" = u32, Path),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a cached copy"),
+    ),
+    responses(
+        (status = 200, description = "The todo", body = Todo,
+            headers(("ETag" = String, description = "The todo's revision"))),
+        (status = 304, description = "The cached copy is current"),
+        (status = 404, description = "No such list, or no such todo in it", body = ErrorBody),
+    )
+)]
+pub async fn get_list_todo(
+    Path((list_id, id)): Path<(u32, u32)>,
+    UserRepo(repo): UserRepo,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let todo = find_list_todo(&repo, list_id, id).await?;
+    Ok(etag::conditional_get(&headers, todo))
+}
+
+// PUT /lists/:list_id/todos/:id - Update todo of a list
+#[utoipa::path(
+    put,
+    path = "/lists/{list_id}/todos/{id}",
+    tag = "lists",
+    params(
+        ("list_id" = u32, Path),
+        ("id" = u32, Path),
+        ("If-Match" = Option<String>, Header, description = "Only update this revision"),
+    ),
+    request_body = UpdateTodoRequest,
+    responses(
+        (status = 200, description = "The updated todo", body = Todo),
+        (status = 404, description = "No such list, or no such todo in it", body = ErrorBody),
+        (status = 412, description = "The todo changed since", body = ErrorBody),
+    )
+)]
+pub async fn update_list_todo(
+    Path((list_id, id)): Path<(u32, u32)>,
+    UserRepo(repo): UserRepo,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTodoRequest>,
+) -> Result<TodoResponse, ApiError> {
+    find_list_todo(&repo, list_id, id).await?;
+    todos::update_todo(Path(id), UserRepo(repo), headers, Json(payload)).await
+}
+
+// PATCH /lists/:list_id/todos/:id - Patch todo of a list
+#[utoipa::path(
+    patch,
+    path = "/lists/{list_id}/todos/{id}",
+    tag = "lists",
+    params(
+        ("list_id" = u32, Path),
+        ("id" = u32, Path),
+        ("If-Match" = Option<String>, Header, description = "Only patch this revision"),
+    ),
+    request_body(
+        description = "A JSON Merge Patch (RFC 7396) or a JSON Patch (RFC 6902)",
+        content(
+            (serde_json::Value = "application/merge-patch+json"),
+            (Vec<serde_json::Value> = "application/json-patch+json"),
+        )
+    ),
+    responses(
+        (status = 200, description = "The patched todo", body = Todo),
+        (status = 404, description = "No such list, or no such todo in it", body = ErrorBody),
+        (status = 412, description = "The todo changed since", body = ErrorBody),
+        (status = 415, description = "Neither patch format", body = ErrorBody),
+    )
+)]
+pub async fn patch_list_todo(
+    Path((list_id, id)): Path<(u32, u32)>,
+    UserRepo(repo): UserRepo,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<TodoResponse, ApiError> {
+    find_list_todo(&repo, list_id, id).await?;
+    todos::patch_todo(Path(id), UserRepo(repo), headers, body).await
+}
+
+// DELETE /lists/:list_id/todos/:id - Delete todo of a list
+#[utoipa::path(
+    delete,
+    path = "/lists/{list_id}/todos/{id}",
+    tag = "lists",
+    params(
+        ("list_id" = u32, Path),
+        ("id" = u32, Path),
+        DeleteParams,
+        ("If-Match" = Option<String>, Header, description = "Only delete this revision"),
+    ),
+    responses(
+        (status = 204, description = "Moved to the trash"),
+        (status = 404, description = "No such list, or no such todo in it", body = ErrorBody),
+        (status = 409, description = "Has subtasks and no `cascade`", body = ErrorBody),
+        (status = 412, description = "The todo changed since", body = ErrorBody),
+    )
+)]
+pub async fn delete_list_todo(
+    Path((list_id, id)): Path<(u32, u32)>,
+    params: Query<DeleteParams>,
+    UserRepo(repo): UserRepo,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    find_list_todo(&repo, list_id, id).await?;
+    todos::delete_todo(Path(id), params, UserRepo(repo), headers).await
+}
+
+async fn find_list(repo: &AppState, list_id: u32) -> Result<TodoList, ApiError> {
+    repo.get_list(list_id).await?.ok_or(ApiError::ListNotFound)
+}
+
+// The todo, if it exists and belongs to the list
+async fn find_list_todo(repo: &AppState, list_id: u32, id: u32) -> Result<Todo, ApiError> {
+    find_list(repo, list_id).await?;
+    match repo.get(id).await? {
+        Some(todo) if todo.list_id == list_id => Ok(todo),
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+fn validate_list(name: Option<&str>, color: Option<&str>) -> Result<(), ApiError> {
+    if name.is_some_and(|name| name.trim().is_empty()) {
+        return Err(ApiError::BadRequest(
+            "invalid_name",
+            "List name must not be empty".to_string(),
+        ));
+    }
+    if let Some(color) = color
+        && !is_valid_color(color)
+    {
+        return Err(ApiError::BadRequest(
+            "invalid_color",
+            format!("Color '{}' is not a #rrggbb hex color", color),
+        ));
+    }
+    Ok(())
+}
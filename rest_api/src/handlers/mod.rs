@@ -0,0 +1,48 @@
+mod admin;
+mod backups;
+mod batch;
+mod etag;
+mod events;
+mod import_export;
+mod lists;
+mod plan;
+mod sync;
+mod todos;
+mod trash;
+
+pub use admin::*;
+pub use backups::*;
+pub use batch::*;
+pub use events::*;
+pub use import_export::*;
+pub use lists::*;
+pub use sync::*;
+pub use todos::*;
+pub use trash::*;
+
+use crate::auth::Caller;
+use crate::error::ApiError;
+use crate::repository::{ScopedRepository, TodoRepository};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use std::sync::Arc;
+
+// Type alias untuk state yang akan dishare antar handlers
+pub type AppState = Arc<dyn TodoRepository>;
+
+// The repository as the caller gets to see it: only their own todos and
+// lists (see `ScopedRepository`), or everything when the server runs
+// without accounts
+pub struct UserRepo(pub AppState);
+
+#[async_trait]
+impl FromRequestParts<AppState> for UserRepo {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, ApiError> {
+        let Caller(user) = Caller::from_request_parts(parts, state).await?;
+        Ok(UserRepo(match user {
+            Some(user) => Arc::new(ScopedRepository::new(state.clone(), user.id)),
+            None => state.clone(),
+        }))
+    }
+}
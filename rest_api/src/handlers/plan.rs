@@ -0,0 +1,254 @@
+use super::AppState;
+use crate::error::{ApiError, BatchError, StorageError};
+use crate::hierarchy;
+use crate::models::{BatchOperation, BatchOutcome, CreateTodoRequest, Todo, UpdateTodoRequest};
+use crate::recurrence;
+use chrono::Utc;
+use std::collections::HashMap;
+
+// Writes worked out against one read of the todos and stored with a single
+// `repo.batch`: a change goes in together with the follow-up writes it
+// causes (subtasks moving along, parents completing, the next occurrence of
+// a series), or none of it does.
+//
+// Every write carries the revision it was planned against. When someone
+// else wrote one of those todos in between, `commit` returns `Ok(None)` and
+// the caller plans again from a fresh read; only a revision the client gave
+// itself makes the commit fail.
+pub(super) struct Plan {
+    // Every todo as the batch leaves it; created ones have stand-in ids
+    todos: Vec<Todo>,
+    ops: Vec<BatchOperation>,
+    // Per op: its revision came from the client
+    checked: Vec<bool>,
+    // Op that restores a todo, by id
+    restored: HashMap<u32, usize>,
+}
+
+impl Plan {
+    pub async fn new(repo: &AppState) -> Result<Self, ApiError> {
+        Ok(Plan {
+            todos: repo.list().await?,
+            ops: Vec::new(),
+            checked: Vec::new(),
+            restored: HashMap::new(),
+        })
+    }
+
+    pub fn get(&self, id: u32) -> Option<Todo> {
+        self.todos.iter().find(|todo| todo.id == id).cloned()
+    }
+
+    pub fn todos(&self) -> &[Todo] {
+        &self.todos
+    }
+
+    // Changes `id` like `TodoRepository::update`; `revision` is the one the
+    // client expects, if it gave one
+    pub fn update(
+        &mut self,
+        id: u32,
+        changes: UpdateTodoRequest,
+        revision: Option<u64>,
+    ) -> Result<Todo, ApiError> {
+        let Some(mut todo) = self.get(id) else {
+            return Err(ApiError::NotFound);
+        };
+        check_revision(&todo, revision)?;
+        self.push(
+            BatchOperation::Update { id, changes: changes.clone(), revision: Some(todo.revision) },
+            revision.is_some(),
+        );
+        todo.apply(changes);
+        Ok(self.wrote(todo))
+    }
+
+    // Overwrites a todo read from this plan; `checked` when its revision is
+    // the one the client sent
+    pub fn replace(&mut self, todo: Todo, checked: bool) -> Todo {
+        if let Some(&index) = self.restored.get(&todo.id) {
+            self.ops[index] = BatchOperation::Restore { todo: todo.clone() };
+            self.set(todo.clone());
+            return todo;
+        }
+        self.push(BatchOperation::Replace { todo: todo.clone() }, checked);
+        self.wrote(todo)
+    }
+
+    // Writes an earlier state of a todo back, see `TodoRepository::restore`.
+    // Its new revision is only known once the batch ran, so later writes to
+    // it in this plan go into the same op.
+    pub fn restore(&mut self, todo: Todo) -> Todo {
+        self.restored.insert(todo.id, self.ops.len());
+        self.push(BatchOperation::Restore { todo: todo.clone() }, false);
+        self.set(todo.clone());
+        todo
+    }
+
+    // Deletes `id`, see `update` for `revision`
+    pub fn delete(&mut self, id: u32, revision: Option<u64>) -> Result<(), ApiError> {
+        let Some(todo) = self.get(id) else {
+            return Err(ApiError::NotFound);
+        };
+        check_revision(&todo, revision)?;
+        self.push(BatchOperation::Delete { id, revision: Some(todo.revision) }, revision.is_some());
+        self.todos.retain(|todo| todo.id != id);
+        Ok(())
+    }
+
+    // Returns the index of the op, for the `parent_op` of later creates
+    pub fn create(&mut self, request: CreateTodoRequest) -> usize {
+        let index = self.ops.len();
+        // Stand-in ids until the batch hands out the real ones
+        let mut todo = Todo::new(stand_in_id(index), request.clone());
+        if let Some(parent_op) = request.parent_op {
+            todo.parent_id = Some(stand_in_id(parent_op));
+        }
+        self.push(BatchOperation::Create { todo: request }, false);
+        self.todos.push(todo);
+        index
+    }
+
+    // Follow-up writes after a todo changed: a todo moved to another list
+    // takes its subtasks along, a recurring todo that was just completed gets
+    // its next occurrence, and a completed subtask can complete its parents
+    pub fn after_update(
+        &mut self,
+        mut todo: Todo,
+        was_completed: bool,
+        new_list: Option<u32>,
+    ) -> Todo {
+        if let Some(list_id) = new_list {
+            // Moving a subtask to another list takes it out of its parent
+            if todo.parent_id.is_some() {
+                todo.parent_id = None;
+                todo = self.replace(todo, false);
+            }
+            self.move_subtasks_to_list(todo.id, list_id);
+        }
+        if todo.completed && !was_completed && todo.recurrence.is_some() {
+            todo = self.continue_series(todo);
+        }
+        if let Some(parent_id) = todo.parent_id
+            && todo.completed
+        {
+            self.complete_parents(parent_id);
+        }
+        todo
+    }
+
+    // Keep every subtask below `id` in the same list as `id`
+    pub fn move_subtasks_to_list(&mut self, id: u32, list_id: u32) {
+        for subtask_id in hierarchy::descendants(&self.todos, id) {
+            if let Some(mut subtask) = self.get(subtask_id)
+                && subtask.list_id != list_id
+            {
+                subtask.list_id = list_id;
+                subtask.updated_at = Some(Utc::now());
+                self.replace(subtask, false);
+            }
+        }
+    }
+
+    // Complete `parent_id` and the todos above it if they have
+    // `auto_complete` set and their subtasks are now all done
+    pub fn complete_parents(&mut self, parent_id: u32) {
+        for parent in hierarchy::auto_completed_parents(&self.todos, parent_id) {
+            let parent = self.replace(parent, false);
+            if parent.recurrence.is_some() {
+                self.continue_series(parent);
+            }
+        }
+    }
+
+    // Create the next occurrence of a recurring todo that was just completed.
+    // The rule moves on to the new todo, so completing this one again later
+    // does not repeat the series twice.
+    fn continue_series(&mut self, todo: Todo) -> Todo {
+        let now = Utc::now();
+        if let Some(next) = recurrence::next_occurrence(&todo, now) {
+            self.create(next);
+        }
+        let cleared = Todo {
+            recurrence: None,
+            updated_at: Some(now),
+            ..todo
+        };
+        self.replace(cleared, false)
+    }
+
+    // Stores every planned write in one batch. `Ok(None)`: a todo changed
+    // after it was read, plan again.
+    pub async fn commit(self, repo: &AppState) -> Result<Option<Vec<BatchOutcome>>, ApiError> {
+        match self.commit_batch(repo).await {
+            Ok(outcomes) => Ok(outcomes),
+            Err(BatchError::RevisionMismatch { id, current, .. }) => {
+                Err(StorageError::RevisionMismatch { id, current }.into())
+            }
+            Err(BatchError::NotFound { .. }) => Err(ApiError::NotFound),
+            Err(BatchError::Storage(err)) => Err(err.into()),
+        }
+    }
+
+    // `commit`, failing with the index of the op whose revision was wrong
+    pub async fn commit_batch(
+        self,
+        repo: &AppState,
+    ) -> Result<Option<Vec<BatchOutcome>>, BatchError> {
+        if self.ops.is_empty() {
+            return Ok(Some(Vec::new()));
+        }
+        match repo.batch(self.ops).await {
+            Ok(outcomes) => Ok(Some(outcomes)),
+            Err(err @ BatchError::RevisionMismatch { index, .. }) if self.checked[index] => {
+                Err(err)
+            }
+            Err(err @ BatchError::Storage(_)) => Err(err),
+            Err(BatchError::NotFound { .. }) | Err(BatchError::RevisionMismatch { .. }) => {
+                Ok(None)
+            }
+        }
+    }
+
+    fn push(&mut self, op: BatchOperation, checked: bool) {
+        self.ops.push(op);
+        self.checked.push(checked);
+    }
+
+    // `todo` as the batch will leave it once its op is staged
+    fn wrote(&mut self, mut todo: Todo) -> Todo {
+        todo.revision += 1;
+        self.set(todo.clone());
+        todo
+    }
+
+    fn set(&mut self, todo: Todo) {
+        match self.todos.iter_mut().find(|t| t.id == todo.id) {
+            Some(slot) => *slot = todo,
+            None => self.todos.push(todo),
+        }
+    }
+}
+
+fn stand_in_id(index: usize) -> u32 {
+    u32::MAX - index as u32
+}
+
+fn check_revision(todo: &Todo, revision: Option<u64>) -> Result<(), ApiError> {
+    match revision {
+        Some(expected) if expected != todo.revision => {
+            Err(StorageError::RevisionMismatch { id: todo.id, current: todo.revision }.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+// The todo `id` as the last op of a committed batch that wrote it left it
+pub(super) fn written(outcomes: &[BatchOutcome], id: u32) -> Option<Todo> {
+    outcomes.iter().rev().find_map(|outcome| match outcome {
+        BatchOutcome::Created(todo) | BatchOutcome::Updated(todo) if todo.id == id => {
+            Some(todo.clone())
+        }
+        _ => None,
+    })
+}
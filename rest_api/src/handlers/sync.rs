@@ -0,0 +1,263 @@
+use super::{AppState, UserRepo};
+use super::batch::unknown_operation;
+use super::plan::{Plan, written};
+use super::todos::{prepare_create, subtree_deletes, usable_list};
+use crate::error::{ApiError, BatchError, ErrorBody, StorageError};
+use crate::hierarchy;
+use crate::models::{BatchOperation, ChangeSet, Todo};
+use axum::{
+    extract::Query,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_LIMIT: usize = 500;
+const MAX_LIMIT: usize = 1000;
+// Most changes accepted in one push
+const MAX_CHANGES: usize = 1000;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChangesParams {
+    #[serde(default)]
+    pub since: u64,
+    pub limit: Option<usize>,
+}
+
+// GET /todos/changes?since=N&limit=N - Todos created, changed or deleted
+// after change N, across all lists. Start with 0 and pass the returned
+// `seq` next time; ask again right away while `has_more` is true.
+#[utoipa::path(
+    get,
+    path = "/todos/changes",
+    tag = "sync",
+    params(ChangesParams),
+    responses((status = 200, description = "Changes after `since`", body = ChangeSet))
+)]
+pub async fn get_changes(
+    Query(params): Query<ChangesParams>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<ChangeSet>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    Ok(Json(repo.changes_since(params.since, limit).await?))
+}
+
+// Changes a client made while offline; the same operations as a batch, with
+// `revision` being the revision the client last saw
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PushRequest {
+    pub changes: Vec<BatchOperation>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PushResponse {
+    pub results: Vec<PushResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PushOutcome {
+    Applied,
+    // The todo was changed or deleted on the server since the client saw it;
+    // nothing was written
+    Conflict,
+    // Invalid on its own, e.g. an unknown list; nothing was written
+    Rejected,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PushResult {
+    pub index: usize,
+    pub op: &'static str,
+    pub result: PushOutcome,
+    // The todo as written by an applied create/update
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<Todo>,
+    // Id of an applied delete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    // The server's version on a conflict, missing when it was deleted there
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<Todo>,
+    // HTTP status a rejected change would have had on its own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl PushResult {
+    fn new(index: usize, op: &'static str, result: PushOutcome) -> Self {
+        PushResult {
+            index,
+            op,
+            result,
+            todo: None,
+            id: None,
+            current: None,
+            status: None,
+            code: None,
+            error: None,
+        }
+    }
+}
+
+// What pushing one change did
+enum Pushed {
+    Written(Todo),
+    Deleted(u32),
+    Conflict {
+        code: &'static str,
+        current: Option<Todo>,
+    },
+}
+
+// POST /todos/changes - Apply changes a client made offline, each on its
+// own and in order. Changes whose todo moved on since the client saw it are
+// not applied but reported as conflicts, together with the server's version.
+#[utoipa::path(
+    post,
+    path = "/todos/changes",
+    tag = "sync",
+    request_body = PushRequest,
+    responses(
+        (status = 200, description = "What became of each change", body = PushResponse),
+        (status = 400, description = "Too many changes", body = ErrorBody),
+    )
+)]
+pub async fn push_changes(
+    UserRepo(repo): UserRepo,
+    Json(request): Json<PushRequest>,
+) -> Result<Json<PushResponse>, ApiError> {
+    if request.changes.len() > MAX_CHANGES {
+        return Err(ApiError::BadRequest(
+            "push_too_large",
+            format!("A push can have at most {} changes", MAX_CHANGES),
+        ));
+    }
+
+    let mut results = Vec::with_capacity(request.changes.len());
+    for (index, change) in request.changes.into_iter().enumerate() {
+        let op = change.name();
+        let result = match push_one(&repo, change).await {
+            Ok(Pushed::Written(todo)) => PushResult {
+                todo: Some(todo),
+                ..PushResult::new(index, op, PushOutcome::Applied)
+            },
+            Ok(Pushed::Deleted(id)) => PushResult {
+                id: Some(id),
+                ..PushResult::new(index, op, PushOutcome::Applied)
+            },
+            Ok(Pushed::Conflict { code, current }) => PushResult {
+                current,
+                code: Some(code),
+                ..PushResult::new(index, op, PushOutcome::Conflict)
+            },
+            Err(err) => {
+                let (status, code) = err.status_and_code();
+                PushResult {
+                    status: Some(status.as_u16()),
+                    code: Some(code),
+                    error: Some(err.to_string()),
+                    ..PushResult::new(index, op, PushOutcome::Rejected)
+                }
+            }
+        };
+        results.push(result);
+    }
+    Ok(Json(PushResponse { results }))
+}
+
+async fn push_one(repo: &AppState, change: BatchOperation) -> Result<Pushed, ApiError> {
+    match change {
+        BatchOperation::Create { mut todo } => {
+            prepare_create(repo, &mut todo).await?;
+            Ok(Pushed::Written(repo.create(todo).await?))
+        }
+        BatchOperation::Update { id, changes, revision } => {
+            loop {
+                let mut plan = Plan::new(repo).await?;
+                let Some(current) = plan.get(id) else {
+                    return deleted_on_server(repo, id).await;
+                };
+                let new_list = changes.list_id.filter(|list_id| *list_id != current.list_id);
+                if let Some(list_id) = new_list {
+                    usable_list(repo, list_id).await?;
+                }
+                let todo = match plan.update(id, changes.clone(), revision) {
+                    Ok(todo) => todo,
+                    Err(ApiError::Storage(StorageError::RevisionMismatch { .. })) => {
+                        return changed_on_server(repo, id).await;
+                    }
+                    Err(err) => return Err(err),
+                };
+                plan.after_update(todo, current.completed, new_list);
+                match plan.commit(repo).await {
+                    Ok(Some(outcomes)) => {
+                        let todo = written(&outcomes, id).ok_or(ApiError::NotFound)?;
+                        return Ok(Pushed::Written(todo));
+                    }
+                    // Written on the server in between; plan against it
+                    Ok(None) => {}
+                    Err(ApiError::Storage(StorageError::RevisionMismatch { .. })) => {
+                        return changed_on_server(repo, id).await;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        BatchOperation::Delete { id, revision } => {
+            let todos = repo.list().await?;
+            if !todos.iter().any(|todo| todo.id == id) {
+                // Deleted on both sides is no conflict
+                return if in_trash(repo, id).await? {
+                    Ok(Pushed::Deleted(id))
+                } else {
+                    Err(ApiError::NotFound)
+                };
+            }
+            // The subtasks go along, like DELETE /todos/:id?cascade=true, and
+            // stay when the todo turns out to have changed on the server
+            let subtasks = hierarchy::descendants(&todos, id);
+            match repo.batch(subtree_deletes(subtasks, id, revision)).await {
+                Ok(_) => Ok(Pushed::Deleted(id)),
+                // Deleted in between, which is what the client wanted
+                Err(BatchError::NotFound { id: gone, .. }) if gone == id => Ok(Pushed::Deleted(id)),
+                // A subtask went in between: the tree changed on the server
+                Err(BatchError::NotFound { .. }) | Err(BatchError::RevisionMismatch { .. }) => {
+                    changed_on_server(repo, id).await
+                }
+                Err(BatchError::Storage(err)) => Err(err.into()),
+            }
+        }
+        // Never deserialized from a request
+        BatchOperation::Replace { .. } | BatchOperation::Restore { .. } => Err(unknown_operation()),
+    }
+}
+
+async fn changed_on_server(repo: &AppState, id: u32) -> Result<Pushed, ApiError> {
+    Ok(Pushed::Conflict {
+        code: "changed",
+        current: repo.get(id).await?,
+    })
+}
+
+// A todo the client knows but the server does not have (anymore): a
+// conflict while it is in the trash, unknown once it was purged
+async fn deleted_on_server(repo: &AppState, id: u32) -> Result<Pushed, ApiError> {
+    if in_trash(repo, id).await? {
+        Ok(Pushed::Conflict {
+            code: "deleted",
+            current: None,
+        })
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+async fn in_trash(repo: &AppState, id: u32) -> Result<bool, ApiError> {
+    Ok(repo.trash().await?.iter().any(|todo| todo.id == id))
+}
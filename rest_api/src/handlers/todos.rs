@@ -0,0 +1,799 @@
+use super::{AppState, UserRepo};
+use super::etag::{self, TodoResponse};
+use super::plan::{Plan, written};
+use crate::error::{ApiError, BatchError, ErrorBody, StorageError};
+use crate::hierarchy;
+use crate::history::{self, TodoRevision};
+use crate::models::{
+    BatchOperation, DEFAULT_LIST_ID, DueFilter, MoveTodoRequest, Todo, CreateTodoRequest, TodoList, TodoPage,
+    TodoQuery, TodoTree, UpdateTodoRequest, normalize_tags,
+};
+use crate::patch::{self, PatchError, PatchOp};
+use crate::recurrence::Recurrence;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode, header},
+    response::{Json, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+
+// GET /todos - Get todos of the default list (or ?list_id=N),
+// filtered/sorted/paginated by query params
+// ?completed=true|false&q=<title substring>&tag=<tag>&due=overdue|today
+// &sort=id|-id|title|-title&limit=N&offset=N
+#[utoipa::path(
+    get,
+    path = "/todos",
+    tag = "todos",
+    params(TodoQuery),
+    responses(
+        (status = 200, description = "One page of todos", body = TodoPage),
+    )
+)]
+pub async fn get_todos(
+    Query(query): Query<TodoQuery>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<TodoPage>, ApiError> {
+    let query = TodoQuery {
+        list_id: Some(query.list_id.unwrap_or(DEFAULT_LIST_ID)),
+        ..query
+    };
+    query_page(&repo, query, "/todos").await
+}
+
+// GET /todos/overdue - Open todos past their due date
+#[utoipa::path(
+    get,
+    path = "/todos/overdue",
+    tag = "todos",
+    params(TodoQuery),
+    responses((status = 200, description = "Open todos past their due date", body = TodoPage))
+)]
+pub async fn get_overdue_todos(
+    Query(query): Query<TodoQuery>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<TodoPage>, ApiError> {
+    let query = TodoQuery {
+        due: Some(DueFilter::Overdue),
+        ..query
+    };
+    query_page(&repo, query, "/todos").await
+}
+
+// GET /todos/due-today - Open todos due today (UTC)
+#[utoipa::path(
+    get,
+    path = "/todos/due-today",
+    tag = "todos",
+    params(TodoQuery),
+    responses((status = 200, description = "Open todos due today (UTC)", body = TodoPage))
+)]
+pub async fn get_due_today_todos(
+    Query(query): Query<TodoQuery>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<TodoPage>, ApiError> {
+    let query = TodoQuery {
+        due: Some(DueFilter::Today),
+        ..query
+    };
+    query_page(&repo, query, "/todos").await
+}
+
+// GET /todos/tags/:tag - Todos carrying a tag
+#[utoipa::path(
+    get,
+    path = "/todos/tags/{tag}",
+    tag = "todos",
+    params(("tag" = String, Path), TodoQuery),
+    responses((status = 200, description = "Todos carrying the tag", body = TodoPage))
+)]
+pub async fn get_todos_by_tag(
+    Path(tag): Path<String>,
+    Query(query): Query<TodoQuery>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<TodoPage>, ApiError> {
+    let query = TodoQuery {
+        tag: Some(tag),
+        ..query
+    };
+    query_page(&repo, query, "/todos").await
+}
+
+// `base` is the route the next-page link points back to
+pub(super) async fn query_page(
+    repo: &AppState,
+    query: TodoQuery,
+    base: &str,
+) -> Result<Json<TodoPage>, ApiError> {
+    let (todos, total) = repo.query(&query).await?;
+    let limit = query.limit();
+
+    let next_offset = query.offset + todos.len();
+    let next = (next_offset < total).then(|| next_page_link(base, &query, limit, next_offset));
+
+    Ok(Json(TodoPage {
+        todos,
+        total,
+        limit,
+        offset: query.offset,
+        next,
+    }))
+}
+
+fn next_page_link(base: &str, query: &TodoQuery, limit: usize, offset: usize) -> String {
+    let mut params = Vec::new();
+    if let Some(list_id) = query.list_id {
+        params.push(format!("list_id={}", list_id));
+    }
+    if let Some(completed) = query.completed {
+        params.push(format!("completed={}", completed));
+    }
+    if let Some(q) = &query.q {
+        params.push(format!("q={}", encode_query_value(q)));
+    }
+    if let Some(tag) = &query.tag {
+        params.push(format!("tag={}", encode_query_value(tag)));
+    }
+    if let Some(due) = query.due {
+        params.push(format!("due={}", due.as_str()));
+    }
+    params.push(format!("sort={}", encode_query_value(query.sort.as_str())));
+    params.push(format!("limit={}", limit));
+    params.push(format!("offset={}", offset));
+    format!("{}?{}", base, params.join("&"))
+}
+
+// Percent-encode everything except RFC 3986 unreserved characters
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// GET /todos/:id - Get specific todo (304 if `If-None-Match` is current)
+#[utoipa::path(
+    get,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = u32, Path),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a cached copy"),
+    ),
+    responses(
+        (status = 200, description = "The todo", body = Todo,
+            headers(("ETag" = String, description = "The todo's revision, e.g. `\"3\"`"))),
+        (status = 304, description = "The cached copy is current"),
+        (status = 404, description = "No such todo", body = ErrorBody),
+    )
+)]
+pub async fn get_todo(
+    Path(id): Path<u32>,
+    UserRepo(repo): UserRepo,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    match repo.get(id).await? {
+        Some(todo) => Ok(etag::conditional_get(&headers, todo)),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+// POST /todos - Create new todo (in the default list unless `list_id` is given)
+#[utoipa::path(
+    post,
+    path = "/todos",
+    tag = "todos",
+    request_body = CreateTodoRequest,
+    responses(
+        (status = 200, description = "The new todo", body = Todo),
+        (status = 400, description = "No such parent or list", body = ErrorBody),
+        (status = 409, description = "The list is archived", body = ErrorBody),
+    )
+)]
+pub async fn create_todo(
+    UserRepo(repo): UserRepo,
+    Json(mut payload): Json<CreateTodoRequest>,
+) -> Result<TodoResponse, ApiError> {
+    prepare_create(&repo, &mut payload).await?;
+    let todo = repo.create(payload).await?;
+    Ok(TodoResponse(todo))
+}
+
+// Check the parent and list of a new todo. A subtask always lives in the
+// list of its parent, so that list is filled in when none is given.
+pub(super) async fn prepare_create(
+    repo: &AppState,
+    payload: &mut CreateTodoRequest,
+) -> Result<(), ApiError> {
+    if let Some(parent_id) = payload.parent_id {
+        let parent = ensure_parent_exists(repo, parent_id).await?;
+        match payload.list_id {
+            Some(list_id) if list_id != parent.list_id => {
+                return Err(ApiError::BadRequest(
+                    "list_mismatch",
+                    format!("Parent todo {} is in list {}, not {}", parent_id, parent.list_id, list_id),
+                ));
+            }
+            _ => payload.list_id = Some(parent.list_id),
+        }
+    }
+    usable_list(repo, payload.list_id.unwrap_or(DEFAULT_LIST_ID)).await?;
+    Ok(())
+}
+
+// PUT /todos/:id - Update existing todo (412 if `If-Match` is not current)
+#[utoipa::path(
+    put,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = u32, Path),
+        ("If-Match" = Option<String>, Header, description = "Only update this revision"),
+    ),
+    request_body = UpdateTodoRequest,
+    responses(
+        (status = 200, description = "The updated todo", body = Todo),
+        (status = 400, description = "No such list", body = ErrorBody),
+        (status = 404, description = "No such todo", body = ErrorBody),
+        (status = 409, description = "The list is archived", body = ErrorBody),
+        (status = 412, description = "The todo changed since", body = ErrorBody),
+    )
+)]
+pub async fn update_todo(
+    Path(id): Path<u32>,
+    UserRepo(repo): UserRepo,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTodoRequest>,
+) -> Result<TodoResponse, ApiError> {
+    loop {
+        let mut plan = Plan::new(&repo).await?;
+        let Some(current) = plan.get(id) else {
+            return Err(ApiError::NotFound);
+        };
+        let expected_revision = etag::if_match(&headers, &current)?;
+        let new_list = payload.list_id.filter(|list_id| *list_id != current.list_id);
+        if let Some(list_id) = new_list {
+            usable_list(&repo, list_id).await?;
+        }
+
+        let todo = plan.update(id, payload.clone(), expected_revision)?;
+        plan.after_update(todo, current.completed, new_list);
+        if let Some(outcomes) = plan.commit(&repo).await? {
+            return written(&outcomes, id).map(TodoResponse).ok_or(ApiError::NotFound);
+        }
+    }
+}
+
+// PATCH /todos/:id - Partially update a todo with a JSON Merge Patch
+// (application/merge-patch+json) or a JSON Patch (application/json-patch+json)
+#[utoipa::path(
+    patch,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = u32, Path),
+        ("If-Match" = Option<String>, Header, description = "Only patch this revision"),
+    ),
+    request_body(
+        description = "A JSON Merge Patch (RFC 7396) or a JSON Patch (RFC 6902)",
+        content(
+            (Value = "application/merge-patch+json"),
+            (Vec<Value> = "application/json-patch+json"),
+        )
+    ),
+    responses(
+        (status = 200, description = "The patched todo", body = Todo),
+        (status = 400, description = "Not JSON, or no such list", body = ErrorBody),
+        (status = 404, description = "No such todo", body = ErrorBody),
+        (status = 409, description = "A `test` failed or the list is archived", body = ErrorBody),
+        (status = 412, description = "The todo changed since", body = ErrorBody),
+        (status = 415, description = "Neither patch format", body = ErrorBody),
+        (status = 422, description = "Not a valid patch or todo", body = ErrorBody),
+    )
+)]
+pub async fn patch_todo(
+    Path(id): Path<u32>,
+    UserRepo(repo): UserRepo,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<TodoResponse, ApiError> {
+    loop {
+        let mut plan = Plan::new(&repo).await?;
+        let Some(current) = plan.get(id) else {
+            return Err(ApiError::NotFound);
+        };
+        etag::if_match(&headers, &current)?;
+        let todo = patched_todo(&headers, &body, &current)?;
+
+        let new_list = (todo.list_id != current.list_id).then_some(todo.list_id);
+        if let Some(list_id) = new_list {
+            usable_list(&repo, list_id).await?;
+        }
+        // The write only succeeds while the todo is still at the revision
+        // the patch was applied to
+        let todo = plan.replace(todo, true);
+        plan.after_update(todo, current.completed, new_list);
+        if let Some(outcomes) = plan.commit(&repo).await? {
+            return written(&outcomes, id).map(TodoResponse).ok_or(ApiError::NotFound);
+        }
+    }
+}
+
+// `current` with the patch in the request body applied and checked
+fn patched_todo(headers: &HeaderMap, body: &Bytes, current: &Todo) -> Result<Todo, ApiError> {
+    let document = serde_json::to_value(current).map_err(StorageError::from)?;
+    let patched = match patch_format(headers)? {
+        PatchFormat::Merge => {
+            let patch: Value = parse_body(body)?;
+            let mut patched = document.clone();
+            patch::merge_patch(&mut patched, &patch);
+            patched
+        }
+        PatchFormat::Json => {
+            let ops: Vec<PatchOp> = parse_body(body)?;
+            patch::json_patch(&document, &ops).map_err(|err| match err {
+                PatchError::TestFailed(_) => ApiError::Conflict("test_failed", err.to_string()),
+                PatchError::Invalid(_) => {
+                    ApiError::Unprocessable("invalid_patch", err.to_string())
+                }
+            })?
+        }
+    };
+    validate_patched(current, &document, patched)
+}
+
+enum PatchFormat {
+    Merge,
+    Json,
+}
+
+fn patch_format(headers: &HeaderMap) -> Result<PatchFormat, ApiError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    match mime.to_ascii_lowercase().as_str() {
+        "application/merge-patch+json" => Ok(PatchFormat::Merge),
+        "application/json-patch+json" => Ok(PatchFormat::Json),
+        _ => Err(ApiError::UnsupportedMediaType(format!(
+            "PATCH expects application/merge-patch+json or application/json-patch+json, got '{}'",
+            mime
+        ))),
+    }
+}
+
+fn parse_body<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(body).map_err(|err| {
+        ApiError::BadRequest("invalid_patch", format!("Invalid patch document: {}", err))
+    })
+}
+
+// Set by the server only; a patch may `test` them but not change them.
+// Subtasks are moved with POST /todos/:id/move.
+const READ_ONLY_FIELDS: &[&str] = &[
+    "id",
+    "revision",
+    "created_at",
+    "updated_at",
+    "completed_at",
+    "parent_id",
+    "deleted_at",
+    "owner_id",
+];
+
+// Turn the patched document back into a todo, rejecting anything that is
+// not a valid todo or touches a read-only field
+fn validate_patched(current: &Todo, original: &Value, patched: Value) -> Result<Todo, ApiError> {
+    let invalid = |message: String| ApiError::Unprocessable("invalid_todo", message);
+    let (Value::Object(original), Value::Object(fields)) = (original, &patched) else {
+        return Err(invalid("A patched todo must be a JSON object".to_string()));
+    };
+    if let Some(unknown) = fields.keys().find(|key| !original.contains_key(*key)) {
+        return Err(invalid(format!("Unknown field '{}'", unknown)));
+    }
+    for field in READ_ONLY_FIELDS {
+        // A missing field and `null` are the same thing
+        let value = |object: &serde_json::Map<String, Value>| {
+            object.get(*field).cloned().unwrap_or(Value::Null)
+        };
+        if value(fields) != value(original) {
+            return Err(ApiError::Unprocessable(
+                "read_only_field",
+                format!("Field '{}' cannot be changed with PATCH", field),
+            ));
+        }
+    }
+
+    let mut todo: Todo = serde_json::from_value(patched).map_err(|err| invalid(err.to_string()))?;
+    let now = chrono::Utc::now();
+    let completed = todo.completed;
+    todo.completed = current.completed;
+    todo.set_completed(completed, now);
+    todo.tags = normalize_tags(todo.tags);
+    todo.updated_at = Some(now);
+    Ok(todo)
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteParams {
+    // Also delete all subtasks instead of refusing to delete a parent
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+// DELETE /todos/:id - Delete todo (?cascade=true to delete its subtasks too,
+// 412 if `If-Match` is not current)
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = u32, Path),
+        DeleteParams,
+        ("If-Match" = Option<String>, Header, description = "Only delete this revision"),
+    ),
+    responses(
+        (status = 204, description = "Moved to the trash"),
+        (status = 404, description = "No such todo", body = ErrorBody),
+        (status = 409, description = "Has subtasks and no `cascade`", body = ErrorBody),
+        (status = 412, description = "The todo changed since", body = ErrorBody),
+    )
+)]
+pub async fn delete_todo(
+    Path(id): Path<u32>,
+    Query(params): Query<DeleteParams>,
+    UserRepo(repo): UserRepo,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    let todos = repo.list().await?;
+    let Some(current) = todos.iter().find(|t| t.id == id) else {
+        return Err(ApiError::NotFound);
+    };
+    let expected_revision = etag::if_match(&headers, current)?;
+    let subtasks = hierarchy::descendants(&todos, id);
+    if !subtasks.is_empty() && !params.cascade {
+        return Err(ApiError::Conflict(
+            "has_children",
+            format!(
+                "Todo {} has {} subtask(s), delete with ?cascade=true",
+                id,
+                subtasks.len()
+            ),
+        ));
+    }
+
+    // When the todo changed since the `If-Match` check above, or anything
+    // else fails, none of the subtree is deleted
+    match repo.batch(subtree_deletes(subtasks, id, expected_revision)).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        // Deleted in the meantime
+        Err(BatchError::NotFound { .. }) => Err(ApiError::NotFound),
+        Err(BatchError::RevisionMismatch { id, current, .. }) => {
+            Err(ApiError::Storage(StorageError::RevisionMismatch { id, current }))
+        }
+        Err(BatchError::Storage(err)) => Err(err.into()),
+    }
+}
+
+// Deletes of `id` and its `subtasks` for one batch. Deepest first, so no
+// subtask is ever left without its parent; only `id` checks its revision.
+pub(super) fn subtree_deletes(
+    subtasks: Vec<u32>,
+    id: u32,
+    revision: Option<u64>,
+) -> Vec<BatchOperation> {
+    subtasks
+        .into_iter()
+        .map(|subtask| BatchOperation::Delete {
+            id: subtask,
+            revision: None,
+        })
+        .chain([BatchOperation::Delete { id, revision }])
+        .collect()
+}
+
+// GET /todos/:id/children - Direct subtasks of a todo
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/children",
+    tag = "todos",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, description = "Direct subtasks", body = Vec<Todo>),
+        (status = 404, description = "No such todo", body = ErrorBody),
+    )
+)]
+pub async fn get_children(
+    Path(id): Path<u32>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<Vec<Todo>>, ApiError> {
+    if repo.get(id).await?.is_none() {
+        return Err(ApiError::NotFound);
+    }
+    let todos = repo.list().await?;
+    Ok(Json(hierarchy::children(&todos, id)))
+}
+
+// GET /todos/:id/tree - Todo with all its subtasks nested
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/tree",
+    tag = "todos",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, description = "The todo with its subtasks nested", body = TodoTree),
+        (status = 404, description = "No such todo", body = ErrorBody),
+    )
+)]
+pub async fn get_tree(
+    Path(id): Path<u32>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<TodoTree>, ApiError> {
+    let Some(root) = repo.get(id).await? else {
+        return Err(ApiError::NotFound);
+    };
+    let todos = repo.list().await?;
+    Ok(Json(hierarchy::tree(&todos, root)))
+}
+
+// GET /todos/:id/history - Recorded revisions of a todo, oldest first;
+// still available after the todo was deleted
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/history",
+    tag = "todos",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, description = "Revisions, oldest first", body = Vec<TodoRevision>),
+        (status = 404, description = "No such todo", body = ErrorBody),
+    )
+)]
+pub async fn get_history(
+    Path(id): Path<u32>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<Vec<TodoRevision>>, ApiError> {
+    let entries = repo.history(id).await?;
+    if entries.is_empty() && repo.get(id).await?.is_none() {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(entries))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OccurrencesParams {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OccurrencesResponse {
+    pub recurrence: Recurrence,
+    pub occurrences: Vec<DateTime<Utc>>,
+}
+
+// GET /todos/:id/occurrences?limit=N - Upcoming due dates of a recurring
+// todo, starting with its own (default 10, at most 100)
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/occurrences",
+    tag = "todos",
+    params(("id" = u32, Path), OccurrencesParams),
+    responses(
+        (status = 200, description = "Upcoming due dates", body = OccurrencesResponse),
+        (status = 404, description = "No such todo", body = ErrorBody),
+        (status = 409, description = "The todo does not recur", body = ErrorBody),
+    )
+)]
+pub async fn get_occurrences(
+    Path(id): Path<u32>,
+    Query(params): Query<OccurrencesParams>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<OccurrencesResponse>, ApiError> {
+    let Some(todo) = repo.get(id).await? else {
+        return Err(ApiError::NotFound);
+    };
+    let Some(recurrence) = todo.recurrence else {
+        return Err(ApiError::Conflict(
+            "not_recurring",
+            format!("Todo {} has no recurrence rule", id),
+        ));
+    };
+    let limit = params.limit.unwrap_or(10).clamp(1, 100);
+    let start = todo.due_at.unwrap_or_else(Utc::now);
+    let occurrences = recurrence.occurrences(start).take(limit).collect();
+    Ok(Json(OccurrencesResponse {
+        recurrence,
+        occurrences,
+    }))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RestoreParams {
+    pub rev: u64,
+}
+
+// POST /todos/:id/restore?rev=N - Bring the todo back to how it was at
+// revision N, also if it was deleted since
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/restore",
+    tag = "todos",
+    params(
+        ("id" = u32, Path),
+        RestoreParams,
+        ("If-Match" = Option<String>, Header, description = "Only restore over this revision"),
+    ),
+    responses(
+        (status = 200, description = "The todo as it was", body = Todo),
+        (status = 404, description = "No such todo or revision", body = ErrorBody),
+        (status = 409, description = "Its list is archived", body = ErrorBody),
+        (status = 412, description = "The todo changed since", body = ErrorBody),
+    )
+)]
+pub async fn restore_todo(
+    Path(id): Path<u32>,
+    Query(params): Query<RestoreParams>,
+    UserRepo(repo): UserRepo,
+    headers: HeaderMap,
+) -> Result<TodoResponse, ApiError> {
+    loop {
+        let mut plan = Plan::new(&repo).await?;
+        let entries = repo.history(id).await?;
+        let current = plan.get(id);
+        if entries.is_empty() && current.is_none() {
+            return Err(ApiError::NotFound);
+        }
+        let Some(mut todo) = history::state_at(&entries, params.rev) else {
+            return Err(ApiError::RevisionNotFound);
+        };
+        if let Some(current) = &current {
+            etag::if_match(&headers, current)?;
+        }
+        prepare_restore(&repo, &plan, &mut todo).await?;
+
+        // Bringing back a deleted todo does not complete it anew
+        let was_completed = current.as_ref().is_none_or(|current| current.completed);
+        let new_list = current
+            .filter(|current| current.list_id != todo.list_id)
+            .map(|_| todo.list_id);
+        let todo = plan.restore(todo);
+        plan.after_update(todo, was_completed, new_list);
+        if let Some(outcomes) = plan.commit(&repo).await? {
+            return written(&outcomes, id).map(TodoResponse).ok_or(ApiError::NotFound);
+        }
+    }
+}
+
+// Checks before an old state of a todo is written back. The old list has to
+// still take todos; a parent that is gone (or would now make a cycle) is
+// dropped and the todo comes back at the top level. `plan` has the todos as
+// they are when it is written back.
+pub(super) async fn prepare_restore(
+    repo: &AppState,
+    plan: &Plan,
+    todo: &mut Todo,
+) -> Result<(), ApiError> {
+    usable_list(repo, todo.list_id).await?;
+    if let Some(parent_id) = todo.parent_id {
+        let todos = plan.todos();
+        let parent_fits = todos.iter().any(|t| t.id == parent_id && t.list_id == todo.list_id)
+            && !hierarchy::creates_cycle(todos, todo.id, parent_id);
+        if !parent_fits {
+            todo.parent_id = None;
+        }
+    }
+    Ok(())
+}
+
+// POST /todos/:id/move - Move a todo (and its subtasks) under a new parent
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/move",
+    tag = "todos",
+    params(("id" = u32, Path)),
+    request_body = MoveTodoRequest,
+    responses(
+        (status = 200, description = "The moved todo with its subtasks", body = TodoTree),
+        (status = 400, description = "No such parent", body = ErrorBody),
+        (status = 404, description = "No such todo", body = ErrorBody),
+        (status = 409, description = "A cycle, or the list is archived", body = ErrorBody),
+    )
+)]
+pub async fn move_todo(
+    Path(id): Path<u32>,
+    UserRepo(repo): UserRepo,
+    Json(payload): Json<MoveTodoRequest>,
+) -> Result<Json<TodoTree>, ApiError> {
+    loop {
+        let mut plan = Plan::new(&repo).await?;
+        let Some(todo) = plan.get(id) else {
+            return Err(ApiError::NotFound);
+        };
+        let todo = move_in_plan(&repo, &mut plan, todo, payload.parent_id).await?;
+        if let Some(outcomes) = plan.commit(&repo).await? {
+            let todo = written(&outcomes, id).unwrap_or(todo);
+            return Ok(Json(hierarchy::tree(&repo.list().await?, todo)));
+        }
+    }
+}
+
+// Plans `todo` moving under `parent_id`, with its subtasks and the parents
+// it leaves and joins
+async fn move_in_plan(
+    repo: &AppState,
+    plan: &mut Plan,
+    mut todo: Todo,
+    parent_id: Option<u32>,
+) -> Result<Todo, ApiError> {
+    let id = todo.id;
+    if let Some(parent_id) = parent_id {
+        let parent = ensure_parent_exists(repo, parent_id).await?;
+        if hierarchy::creates_cycle(plan.todos(), id, parent_id) {
+            return Err(ApiError::Conflict(
+                "cycle",
+                format!("Todo {} cannot be moved under itself or its own subtask {}", id, parent_id),
+            ));
+        }
+        // Moving under a parent in another list moves the subtree to that list
+        if parent.list_id != todo.list_id {
+            usable_list(repo, parent.list_id).await?;
+            todo.list_id = parent.list_id;
+        }
+    }
+
+    let old_parent = todo.parent_id;
+    todo.parent_id = parent_id;
+    todo.updated_at = Some(chrono::Utc::now());
+    let todo = plan.replace(todo, false);
+    plan.move_subtasks_to_list(id, todo.list_id);
+
+    // Taking an open subtask away can leave the old parent with only done
+    // subtasks; a done todo can be the last one the new parent waited for
+    if let Some(parent_id) = old_parent {
+        plan.complete_parents(parent_id);
+    }
+    if let Some(parent_id) = todo.parent_id
+        && todo.completed
+    {
+        plan.complete_parents(parent_id);
+    }
+    Ok(todo)
+}
+
+async fn ensure_parent_exists(repo: &AppState, parent_id: u32) -> Result<Todo, ApiError> {
+    repo.get(parent_id).await?.ok_or_else(|| {
+        ApiError::BadRequest(
+            "invalid_parent",
+            format!("Parent todo {} does not exist", parent_id),
+        )
+    })
+}
+
+// A list todos can be added to: it exists and is not archived
+pub(super) async fn usable_list(repo: &AppState, list_id: u32) -> Result<TodoList, ApiError> {
+    let Some(list) = repo.get_list(list_id).await? else {
+        return Err(ApiError::BadRequest(
+            "invalid_list",
+            format!("List {} does not exist", list_id),
+        ));
+    };
+    if list.archived {
+        return Err(ApiError::Conflict(
+            "list_archived",
+            format!("List {} is archived", list_id),
+        ));
+    }
+    Ok(list)
+}
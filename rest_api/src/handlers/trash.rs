@@ -0,0 +1,107 @@
+use super::UserRepo;
+use super::plan::{Plan, written};
+use super::todos::prepare_restore;
+use crate::error::{ApiError, ErrorBody};
+use crate::hierarchy;
+use crate::models::{Todo, TodoTree};
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::Json,
+};
+use chrono::Utc;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PurgeResponse {
+    pub purged: Vec<u32>,
+}
+
+// GET /trash - Deleted todos, most recently deleted first
+#[utoipa::path(
+    get,
+    path = "/trash",
+    tag = "trash",
+    responses((status = 200, description = "Deleted todos, newest first", body = Vec<Todo>))
+)]
+pub async fn get_trash(UserRepo(repo): UserRepo) -> Result<Json<Vec<Todo>>, ApiError> {
+    Ok(Json(repo.trash().await?))
+}
+
+// POST /trash/:id/restore - Take a todo out of the trash, together with the
+// subtasks below it that are in the trash too
+#[utoipa::path(
+    post,
+    path = "/trash/{id}/restore",
+    tag = "trash",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, description = "The todo back with its subtasks", body = TodoTree),
+        (status = 404, description = "Not in the trash", body = ErrorBody),
+        (status = 409, description = "Its list is archived", body = ErrorBody),
+    )
+)]
+pub async fn restore_from_trash(
+    Path(id): Path<u32>,
+    UserRepo(repo): UserRepo,
+) -> Result<Json<TodoTree>, ApiError> {
+    // The whole subtree comes back in one batch
+    loop {
+        let mut plan = Plan::new(&repo).await?;
+        let trash = repo.trash().await?;
+        if !trash.iter().any(|t| t.id == id) {
+            return Err(ApiError::NotFound);
+        }
+
+        // Parents first, so every subtask finds its parent back in place
+        let mut order = vec![id];
+        order.extend(hierarchy::descendants(&trash, id).into_iter().rev());
+        for todo_id in order {
+            let Some(mut todo) = trash.iter().find(|t| t.id == todo_id).cloned() else {
+                continue;
+            };
+            prepare_restore(&repo, &plan, &mut todo).await?;
+            plan.restore(todo);
+        }
+
+        if let Some(outcomes) = plan.commit(&repo).await? {
+            let root = written(&outcomes, id).ok_or(ApiError::NotFound)?;
+            return Ok(Json(hierarchy::tree(&repo.list().await?, root)));
+        }
+    }
+}
+
+// DELETE /trash/:id - Delete a todo in the trash for good
+#[utoipa::path(
+    delete,
+    path = "/trash/{id}",
+    tag = "trash",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 204, description = "Gone for good"),
+        (status = 404, description = "Not in the trash", body = ErrorBody),
+    )
+)]
+pub async fn purge_todo(
+    Path(id): Path<u32>,
+    UserRepo(repo): UserRepo,
+) -> Result<StatusCode, ApiError> {
+    if repo.purge(id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+// DELETE /trash - Empty the trash
+#[utoipa::path(
+    delete,
+    path = "/trash",
+    tag = "trash",
+    responses((status = 200, description = "Ids of the purged todos", body = PurgeResponse))
+)]
+pub async fn empty_trash(UserRepo(repo): UserRepo) -> Result<Json<PurgeResponse>, ApiError> {
+    let purged = repo.purge_deleted_before(Utc::now()).await?;
+    Ok(Json(PurgeResponse { purged }))
+}
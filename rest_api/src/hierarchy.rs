@@ -0,0 +1,88 @@
+use crate::models::{Todo, TodoTree};
+use chrono::Utc;
+use std::collections::HashMap;
+
+// Helpers for subtasks (`Todo::parent_id`). They all work on a full list of
+// todos, so they behave the same for every repository backend.
+
+// Direct subtasks of `id`, ordered by id
+pub fn children(todos: &[Todo], id: u32) -> Vec<Todo> {
+    let mut children: Vec<Todo> = todos
+        .iter()
+        .filter(|todo| todo.parent_id == Some(id))
+        .cloned()
+        .collect();
+    children.sort_by_key(|todo| todo.id);
+    children
+}
+
+// `root` with every level of subtasks nested below it
+pub fn tree(todos: &[Todo], root: Todo) -> TodoTree {
+    let children = children(todos, root.id)
+        .into_iter()
+        .map(|child| tree(todos, child))
+        .collect();
+    TodoTree {
+        todo: root,
+        children,
+    }
+}
+
+// Ids of every subtask below `id`, deepest first, so deleting them in this
+// order never leaves a subtask whose parent is already gone
+pub fn descendants(todos: &[Todo], id: u32) -> Vec<u32> {
+    let mut ids = Vec::new();
+    for child in children(todos, id) {
+        ids.extend(descendants(todos, child.id));
+        ids.push(child.id);
+    }
+    ids
+}
+
+// Would putting `id` under `new_parent` create a cycle?
+// True when `new_parent` is `id` itself or one of its subtasks.
+pub fn creates_cycle(todos: &[Todo], id: u32, new_parent: u32) -> bool {
+    let parents: HashMap<u32, Option<u32>> =
+        todos.iter().map(|todo| (todo.id, todo.parent_id)).collect();
+
+    let mut current = Some(new_parent);
+    // Bounded walk, in case the stored data already contains a cycle
+    for _ in 0..=todos.len() {
+        match current {
+            Some(ancestor) if ancestor == id => return true,
+            Some(ancestor) => current = parents.get(&ancestor).copied().flatten(),
+            None => return false,
+        }
+    }
+    true
+}
+
+// `parent_id` and the todos above it (nearest first) that must be completed
+// now: they have `auto_complete` set and all their subtasks are completed.
+// The returned todos are already marked completed.
+pub fn auto_completed_parents(todos: &[Todo], parent_id: u32) -> Vec<Todo> {
+    let mut todos: HashMap<u32, Todo> = todos.iter().map(|t| (t.id, t.clone())).collect();
+    let mut completed = Vec::new();
+    let now = Utc::now();
+
+    let mut current = Some(parent_id);
+    while let Some(parent_id) = current {
+        let mut subtasks = todos
+            .values()
+            .filter(|todo| todo.parent_id == Some(parent_id))
+            .peekable();
+        let all_done = subtasks.peek().is_some() && subtasks.all(|todo| todo.completed);
+        let Some(parent) = todos.get_mut(&parent_id) else {
+            break;
+        };
+        if !parent.auto_complete || parent.completed || !all_done {
+            break;
+        }
+
+        parent.set_completed(true, now);
+        parent.updated_at = Some(now);
+        completed.push(parent.clone());
+        current = parent.parent_id;
+    }
+    completed
+}
@@ -0,0 +1,96 @@
+use crate::models::Todo;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RevisionOp {
+    Create,
+    Update,
+    Delete,
+    Restore,
+}
+
+// One recorded mutation of a todo
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TodoRevision {
+    pub todo_id: u32,
+    // Revision of the todo this mutation produced; for a delete the
+    // revision that was deleted
+    pub revision: u64,
+    // Position in the change sequence shared by all todos, set by the
+    // storage when the change is written; 0 for older entries
+    #[serde(default)]
+    pub seq: u64,
+    pub op: RevisionOp,
+    pub at: DateTime<Utc>,
+    // `None` for a create (or a restore of a deleted todo)
+    pub before: Option<Todo>,
+    // `None` for a delete
+    pub after: Option<Todo>,
+}
+
+impl TodoRevision {
+    // `before` -> `after`; at least one of them is set
+    pub fn new(op: RevisionOp, before: Option<Todo>, after: Option<Todo>) -> Self {
+        let todo = after.as_ref().or(before.as_ref()).expect("a change touches a todo");
+        TodoRevision {
+            todo_id: todo.id,
+            revision: todo.revision,
+            seq: 0,
+            op,
+            at: Utc::now(),
+            before,
+            after,
+        }
+    }
+}
+
+// The state of the todo at `revision`, from any entry of its history
+pub fn state_at(history: &[TodoRevision], revision: u64) -> Option<Todo> {
+    history
+        .iter()
+        .flat_map(|entry| [entry.after.as_ref(), entry.before.as_ref()])
+        .flatten()
+        .find(|todo| todo.revision == revision)
+        .cloned()
+}
+
+// How much history is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryRetention {
+    // Revisions kept per todo, 0 turns history off
+    pub max_revisions: usize,
+    // Revisions older than this are dropped
+    pub max_age: Option<Duration>,
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        HistoryRetention {
+            max_revisions: 50,
+            max_age: None,
+        }
+    }
+}
+
+impl HistoryRetention {
+    // Oldest timestamp still kept
+    pub fn cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.max_age.map(|age| now - age)
+    }
+
+    // Drop what is beyond the limit or too old, oldest first
+    pub fn prune(&self, entries: &mut VecDeque<TodoRevision>, now: DateTime<Utc>) {
+        while entries.len() > self.max_revisions {
+            entries.pop_front();
+        }
+        if let Some(cutoff) = self.cutoff(now) {
+            while entries.front().is_some_and(|entry| entry.at < cutoff) {
+                entries.pop_front();
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::encryption::{Cipher, JOURNAL_CONTEXT};
+use crate::error::StorageError;
+use serde::{Serialize, de::DeserializeOwned};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// Append-only log of mutations. Each record is written as one line:
+//
+//     <crc32 as 8 hex chars> <json>\n
+//
+// A trailing line that is incomplete (crash mid-write) or whose checksum does
+// not match is a torn record: replay stops there and the file is truncated
+// back to the last valid record.
+//
+// With a cipher the json is sealed, and the line holds its base64 instead.
+// A sealed record with a valid checksum that cannot be opened is not torn
+// but was written with another key, and stops the replay with an error. So
+// does a plain record in the journal of encrypted data: anyone could have
+// written it.
+pub struct Journal {
+    file: File,
+    len: u64,
+    records: usize,
+    cipher: Option<Cipher>,
+}
+
+impl Journal {
+    // Open (or create) the journal and return every valid record in order
+    pub fn open<T: DeserializeOwned>(
+        path: impl AsRef<Path>,
+        cipher: Option<Cipher>,
+    ) -> Result<(Self, Vec<T>), StorageError> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        for line in data.split_inclusive(|b| *b == b'\n') {
+            match decode_line(line, cipher.as_ref()) {
+                Decoded::Record(record) => {
+                    records.push(record);
+                    valid_len += line.len();
+                }
+                Decoded::Torn => break,
+                Decoded::Sealed => {
+                    let reason = match &cipher {
+                        Some(cipher) => format!("not with the configured key {}", cipher.id()),
+                        None => "but no key is configured".to_string(),
+                    };
+                    return Err(StorageError::Encryption(format!(
+                        "{} is encrypted, {}",
+                        path.display(),
+                        reason
+                    )));
+                }
+                Decoded::Plain => {
+                    return Err(StorageError::Encryption(format!(
+                        "{} holds a record that is not encrypted, though the data is",
+                        path.display()
+                    )));
+                }
+            }
+        }
+
+        if valid_len < data.len() {
+            crate::warn!(
+                "⚠️  Journal {}: skipped {} byte(s) of torn/corrupt records",
+                path.display(),
+                data.len() - valid_len
+            );
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+
+        let journal = Journal {
+            file,
+            len: valid_len as u64,
+            records: records.len(),
+            cipher,
+        };
+        Ok((journal, records))
+    }
+
+    pub fn append<T: Serialize>(&mut self, record: &T) -> io::Result<()> {
+        self.append_all(std::slice::from_ref(record))
+    }
+
+    // Several records with a single write and fsync; either all of them
+    // make it into the log or none
+    pub fn append_all<T: Serialize>(&mut self, records: &[T]) -> io::Result<()> {
+        let mut lines = String::new();
+        for record in records {
+            let mut payload = serde_json::to_string(record)?;
+            if let Some(cipher) = &self.cipher {
+                payload = cipher
+                    .seal(JOURNAL_CONTEXT, payload.as_bytes())
+                    .map_err(io::Error::other)?;
+            }
+            let checksum = crc32fast::hash(payload.as_bytes());
+            lines.push_str(&format!("{:08x} {}\n", checksum, payload));
+        }
+        // One write_all, then fsync so the records are durable
+        let written = self
+            .file
+            .write_all(lines.as_bytes())
+            .and_then(|_| self.file.sync_data());
+        if let Err(err) = written {
+            // Cut off whatever part of the records made it to disk, otherwise
+            // the next append would land behind a torn record
+            let _ = self.file.set_len(self.len);
+            return Err(err);
+        }
+        self.len += lines.len() as u64;
+        self.records += records.len();
+        Ok(())
+    }
+
+    // Number of records currently in the log (since the last clear)
+    pub fn record_count(&self) -> usize {
+        self.records
+    }
+
+    // Records appended from now on are sealed with `cipher`, or plain
+    pub fn set_cipher(&mut self, cipher: Option<Cipher>) {
+        self.cipher = cipher;
+    }
+
+    // Drop all records, called after they have been compacted into a snapshot
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.len = 0;
+        self.records = 0;
+        Ok(())
+    }
+}
+
+enum Decoded<T> {
+    Record(T),
+    Torn,
+    // Intact, but sealed with another key or without a key to open it
+    Sealed,
+    // Intact, but not sealed where every record has to be
+    Plain,
+}
+
+fn decode_line<T: DeserializeOwned>(
+    line: &[u8],
+    cipher: Option<&Cipher>,
+) -> Decoded<T> {
+    let Some((checksum, payload)) = line
+        .strip_suffix(b"\n")
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .and_then(|line| line.split_once(' '))
+    else {
+        return Decoded::Torn;
+    };
+    let checksum = u32::from_str_radix(checksum, 16).ok();
+    if checksum != Some(crc32fast::hash(payload.as_bytes())) {
+        return Decoded::Torn;
+    }
+    // Plain records are json objects, sealed ones base64
+    let json = if payload.starts_with('{') {
+        if cipher.is_some() {
+            return Decoded::Plain;
+        }
+        payload.as_bytes().to_vec()
+    } else {
+        match cipher.and_then(|cipher| cipher.open(JOURNAL_CONTEXT, payload)) {
+            Some(json) => json,
+            None => return Decoded::Sealed,
+        }
+    };
+    match serde_json::from_slice(&json) {
+        Ok(record) => Decoded::Record(record),
+        Err(_) => Decoded::Torn,
+    }
+}
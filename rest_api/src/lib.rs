@@ -0,0 +1,24 @@
+pub mod auth;
+pub mod backup;
+pub mod client;
+pub mod commands;
+pub mod config;
+pub mod encryption;
+pub mod error;
+pub mod events;
+pub mod formats;
+pub mod handlers;
+pub mod hierarchy;
+pub mod history;
+pub mod journal;
+pub mod logging;
+pub mod models;
+pub mod openapi;
+pub mod patch;
+pub mod recurrence;
+pub mod repository;
+pub mod routers;
+pub mod schema;
+pub mod storage;
+pub mod trash;
+pub mod writer;
@@ -1,42 +1,198 @@
-mod handlers;
-mod models;
-mod storage;
-
-use axum::{
-    routing::{delete, get, post, put},
-    Router,
-};
-use handlers::AppState;
-use std::sync::{Arc, Mutex};
-use storage::Storage;
+use clap::Parser;
+use rest_api::auth::AccountStore;
+use rest_api::backup::{self, Backups};
+use rest_api::config::{Command, Config, Flags};
+use rest_api::routers::create_routes;
+use rest_api::{commands, error, info, logging, trash};
+use std::env;
+use std::process::ExitCode;
+use std::sync::Arc;
 
 #[tokio::main]
-async fn main() {
-    // Initialize storage
-    let storage = Storage::new().expect("Failed to initialize storage");
-    let shared_state: AppState = Arc::new(Mutex::new(storage));
+async fn main() -> ExitCode {
+    let mut flags = Flags::parse();
+    let command = flags.command.take();
+    if command == Some(Command::Keygen) {
+        return finish(commands::keygen());
+    }
+
+    // Flags, then TODO_* variables, then the TOML file, then defaults
+    let config = match Config::load(flags, |name| env::var(name).ok()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            return ExitCode::from(2);
+        }
+    };
+    logging::set_level(config.log_level);
+    if let Some(command) = command {
+        return finish(commands::run(&command, &config));
+    }
+
+    let cipher = config.cipher.as_ref();
+    let repo = match config.backend.open(config.history, config.durability, cipher) {
+        Ok(repo) => repo,
+        Err(err) => {
+            error!("❌ Failed to initialize storage: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Purge todos that sat in the trash too long
+    trash::spawn_auto_purge(repo.clone(), config.trash);
+    let backups = config.backups.clone().map(|policy| {
+        Arc::new(Backups::new(repo.clone(), policy, config.cipher.clone()))
+    });
+    if let Some(backups) = &backups {
+        backup::spawn_schedule(backups.clone());
+    }
+
+    let accounts = match config.accounts.as_ref().map(AccountStore::open).transpose() {
+        Ok(accounts) => accounts.map(Arc::new),
+        Err(err) => {
+            error!("❌ Failed to load accounts: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let (Some(accounts), Some(store)) = (&accounts, &config.accounts) {
+        let key_file = store.first_key_path();
+        match accounts.bootstrap_to(&key_file) {
+            // Printed whatever the log level; the key itself only goes to the file
+            Ok(Some(admin)) => {
+                println!(
+                    "🔑 Created user '{}' (admin), its API key is in {}",
+                    admin.user.name,
+                    key_file.display()
+                );
+                println!("   Only its hash is stored, move the key somewhere safe");
+            }
+            Ok(None) => {}
+            Err(err) => {
+                error!("❌ Failed to create the first admin: {:?}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
 
     // Define routes
-    let app = Router::new()
-        .route("/todos", get(handlers::get_todos))
-        .route("/todos", post(handlers::create_todo))
-        .route("/todos/:id", get(handlers::get_todo))
-        .route("/todos/:id", put(handlers::update_todo))
-        .route("/todos/:id", delete(handlers::delete_todo))
-        .with_state(shared_state);
+    let app = create_routes(repo.clone(), accounts, backups);
 
     // Start server
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
-    
-    println!("🚀 Server running on http://127.0.0.1:3000");
-    println!("📝 Endpoints:");
-    println!("  GET    /todos     - Get all todos");
-    println!("  POST   /todos     - Create todo");
-    println!("  GET    /todos/:id - Get specific todo");
-    println!("  PUT    /todos/:id - Update todo");
-    println!("  DELETE /todos/:id - Delete todo");
-
-    axum::serve(listener, app).await.unwrap();
+    let listener = match tokio::net::TcpListener::bind(config.addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("❌ Cannot listen on {}: {}", config.addr, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    info!("🚀 Server running on http://{}", config.addr);
+    if let Some(file) = &config.file {
+        info!("⚙️  Configuration: {}", file.display());
+    }
+    info!("💾 Storage backend: {:?}", config.backend);
+    info!("⏱️  Durability: {:?}", config.durability);
+    match &config.cipher {
+        Some(cipher) => info!("🔐 Encryption: key {}", cipher.id()),
+        None => info!("🔓 Data file not encrypted"),
+    }
+    match &config.backups {
+        Some(policy) => info!("🗄️  Backups: {:?}", policy),
+        None => info!("🗄️  Backups disabled"),
+    }
+    match &config.accounts {
+        Some(store) => info!("🔐 Accounts: {:?}", store),
+        None => info!("🔓 Accounts disabled, every request sees every todo, see --auth"),
+    }
+    info!("📝 Endpoints:");
+    info!("  GET    /todos                    - Get todos (default list)");
+    info!("  POST   /todos                    - Create todo");
+    info!("  POST   /todos/batch              - Create/update/delete in one batch");
+    info!("  GET    /todos.ics                - Todos as iCalendar VTODOs for calendar apps");
+    info!("  GET    /todos/export?format=F    - Export todos as csv, markdown, todotxt or ical");
+    info!("  POST   /todos/import?format=F    - Import todos (dry_run, duplicate detection)");
+    info!("  GET    /todos/changes?since=N    - Get todo changes since change N");
+    info!("  POST   /todos/changes            - Push offline changes, report conflicts");
+    info!("  GET    /todos/events             - Stream todo changes (SSE)");
+    info!("  GET    /todos/overdue            - Get overdue todos");
+    info!("  GET    /todos/due-today          - Get todos due today");
+    info!("  GET    /todos/tags/:tag          - Get todos by tag");
+    info!("  GET    /todos/:id                - Get specific todo");
+    info!("  PUT    /todos/:id                - Update todo");
+    info!("  PATCH  /todos/:id                - Patch todo (merge/json patch)");
+    info!("  DELETE /todos/:id                - Move todo to the trash");
+    info!("  GET    /todos/:id/children       - Get subtasks");
+    info!("  GET    /todos/:id/tree           - Get todo with nested subtasks");
+    info!("  POST   /todos/:id/move           - Move todo under another parent");
+    info!("  GET    /todos/:id/history        - Get revision history of a todo");
+    info!("  GET    /todos/:id/occurrences    - Preview next occurrences of a recurring todo");
+    info!("  POST   /todos/:id/restore?rev=N  - Restore todo to revision N");
+    info!("  GET    /trash                    - Get deleted todos");
+    info!("  DELETE /trash                    - Empty the trash");
+    info!("  POST   /trash/:id/restore        - Restore a deleted todo");
+    info!("  DELETE /trash/:id                - Delete a todo for good");
+    info!("  GET    /lists                    - Get all lists");
+    info!("  POST   /lists                    - Create list");
+    info!("  GET    /lists/:list_id           - Get specific list");
+    info!("  PUT    /lists/:list_id           - Update list");
+    info!("  DELETE /lists/:list_id           - Delete list");
+    info!("  GET    /lists/:list_id/todos     - Get todos of a list");
+    info!("  POST   /lists/:list_id/todos     - Create todo in a list");
+    info!("  GET    /lists/:list_id/todos/:id - Get todo of a list");
+    info!("  PUT    /lists/:list_id/todos/:id - Update todo of a list");
+    info!("  PATCH  /lists/:list_id/todos/:id - Patch todo of a list");
+    info!("  DELETE /lists/:list_id/todos/:id - Delete todo of a list");
+    info!("  GET    /me                       - Get the user of the API key");
+    info!("  GET    /admin/users              - Get users and their keys (admin)");
+    info!("  POST   /admin/users              - Create user with a first key (admin)");
+    info!("  POST   /admin/users/:id/keys     - Create another key for a user (admin)");
+    info!("  DELETE /admin/keys/:key_id       - Revoke a key (admin)");
+    info!("  GET    /admin/backups            - Get backups and whether they are intact (admin)");
+    info!("  POST   /admin/backups            - Take a backup now (admin)");
+    info!("  POST   /admin/backups/:name/restore - Restore a backup (admin)");
+    info!("  GET    /openapi.json             - OpenAPI document of this API");
+    info!("  GET    /docs                     - Swagger UI for the API");
+
+    // Not a graceful shutdown: event streams never end on their own
+    tokio::select! {
+        served = axum::serve(listener, app) => {
+            if let Err(err) = served {
+                error!("❌ Server stopped: {}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+        _ = shutdown_signal() => info!("👋 Shutting down"),
+    }
+
+    // Debounced writes still on their way to disk
+    if let Err(err) = repo.flush().await {
+        error!("❌ Failed to write the last changes: {}", err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+// Exit code of a maintenance command
+fn finish(result: Result<(), String>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("❌ {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Ctrl+C, or SIGTERM from a service manager
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate()).expect("cannot listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
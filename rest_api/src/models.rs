@@ -1,19 +1,521 @@
+use crate::recurrence::Recurrence;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// The list todos end up in when no list is given; it always exists
+pub const DEFAULT_LIST_ID: u32 = 1;
+
+fn default_list_id() -> u32 {
+    DEFAULT_LIST_ID
+}
+
+// The first user, the admin created on the first start with accounts. Todos
+// and lists from before accounts existed belong to it.
+pub const DEFAULT_OWNER_ID: u32 = 1;
+
+fn default_owner_id() -> u32 {
+    DEFAULT_OWNER_ID
+}
+
+fn first_revision() -> u64 {
+    1
+}
+
+// Every field added after `completed` has a serde default, so todos.json
+// files written before those fields existed keep loading.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Todo {
     pub id: u32,
     pub title: String,
     pub completed: bool,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // `None` only for todos created before timestamps were tracked
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+    // Subtask of this todo, `None` for top-level todos
+    #[serde(default)]
+    pub parent_id: Option<u32>,
+    // Complete this todo automatically once all its subtasks are completed
+    #[serde(default)]
+    pub auto_complete: bool,
+    #[serde(default = "default_list_id")]
+    pub list_id: u32,
+    // Bumped by the storage on every write, exposed as the `ETag`
+    #[serde(default = "first_revision")]
+    pub revision: u64,
+    // Set while the todo is in the trash
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // Repeats on this rule; completing it creates the next occurrence
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    // User the todo belongs to, invisible to everyone else
+    #[serde(default = "default_owner_id")]
+    pub owner_id: u32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Medium,
+    High,
+    Urgent,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CreateTodoRequest {
     pub title: String,
+    pub description: Option<String>,
+    pub due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub parent_id: Option<u32>,
+    #[serde(default)]
+    pub auto_complete: bool,
+    // Defaults to the default list
+    pub list_id: Option<u32>,
+    pub recurrence: Option<Recurrence>,
+    // Set by the server, never by clients; defaults to the first user
+    #[serde(skip)]
+    pub owner_id: Option<u32>,
+    // Set by imports only: the todo comes in already completed
+    #[serde(skip)]
+    pub completed: bool,
+    // Set by imports only: the parent is the todo created by this earlier
+    // operation of the same batch, see `resolve_parent`
+    #[serde(skip)]
+    pub parent_op: Option<usize>,
+}
+
+impl CreateTodoRequest {
+    // Fills in `parent_id` from `parent_op` once the batch has created the
+    // parent; `outcomes` are those of the operations before this one
+    pub fn resolve_parent(&mut self, outcomes: &[BatchOutcome]) {
+        if let Some(index) = self.parent_op.take()
+            && let Some(BatchOutcome::Created(parent)) = outcomes.get(index)
+        {
+            self.parent_id = Some(parent.id);
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
 pub struct UpdateTodoRequest {
     pub title: Option<String>,
     pub completed: Option<bool>,
+    pub description: Option<String>,
+    pub due_at: Option<DateTime<Utc>>,
+    pub priority: Option<Priority>,
+    pub tags: Option<Vec<String>>,
+    pub auto_complete: Option<bool>,
+    // Move the todo (with its subtasks) to another list
+    pub list_id: Option<u32>,
+    pub recurrence: Option<Recurrence>,
+}
+
+// One operation of POST /todos/batch. `revision` works like `If-Match`.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOperation {
+    Create {
+        todo: CreateTodoRequest,
+    },
+    Update {
+        id: u32,
+        changes: UpdateTodoRequest,
+        revision: Option<u64>,
+    },
+    Delete {
+        id: u32,
+        revision: Option<u64>,
+    },
+    // Overwrites a todo like `TodoRepository::replace`, for the follow-up
+    // writes the server batches together; requests cannot send it
+    #[serde(skip)]
+    Replace {
+        todo: Todo,
+    },
+    // Writes a todo back like `TodoRepository::restore`, also out of the
+    // trash; like `Replace` only for the server's own batches
+    #[serde(skip)]
+    Restore {
+        todo: Todo,
+    },
+}
+
+impl BatchOperation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BatchOperation::Create { .. } => "create",
+            BatchOperation::Update { .. } => "update",
+            BatchOperation::Delete { .. } => "delete",
+            BatchOperation::Replace { .. } => "replace",
+            BatchOperation::Restore { .. } => "restore",
+        }
+    }
+}
+
+// What a committed batch operation did
+#[derive(Debug, Clone)]
+pub enum BatchOutcome {
+    Created(Todo),
+    Updated(Todo),
+    Deleted(u32),
+}
+
+// Todos changed after some point of the change sequence, for clients that
+// sync incrementally
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ChangeSet {
+    // Pass as `since` next time; the newest change included
+    pub seq: u64,
+    // Current state of todos created or changed since then
+    pub upserts: Vec<Todo>,
+    // Todos deleted since then (in the trash or purged)
+    pub deletes: Vec<u32>,
+    // `limit` was reached, ask again from `seq` for the rest
+    pub has_more: bool,
+}
+
+// Body of POST /todos/:id/move, `null` moves the todo to the top level
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MoveTodoRequest {
+    pub parent_id: Option<u32>,
+}
+
+// A todo with all its subtasks nested below it
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TodoTree {
+    #[serde(flatten)]
+    pub todo: Todo,
+    #[schema(no_recursion)]
+    pub children: Vec<TodoTree>,
+}
+
+impl Todo {
+    pub fn new(id: u32, request: CreateTodoRequest) -> Self {
+        let now = Utc::now();
+        Todo {
+            id,
+            title: request.title,
+            completed: request.completed,
+            description: request.description,
+            due_at: request.due_at,
+            priority: request.priority,
+            tags: normalize_tags(request.tags),
+            created_at: Some(now),
+            updated_at: Some(now),
+            completed_at: request.completed.then_some(now),
+            parent_id: request.parent_id,
+            auto_complete: request.auto_complete,
+            list_id: request.list_id.unwrap_or(DEFAULT_LIST_ID),
+            revision: first_revision(),
+            deleted_at: None,
+            recurrence: request.recurrence,
+            owner_id: request.owner_id.unwrap_or(DEFAULT_OWNER_ID),
+        }
+    }
+
+    // Apply the fields that are present in an update request
+    pub fn apply(&mut self, changes: UpdateTodoRequest) {
+        let now = Utc::now();
+        if let Some(t) = changes.title {
+            self.title = t;
+        }
+        if let Some(c) = changes.completed {
+            self.set_completed(c, now);
+        }
+        if let Some(d) = changes.description {
+            self.description = Some(d);
+        }
+        if let Some(d) = changes.due_at {
+            self.due_at = Some(d);
+        }
+        if let Some(p) = changes.priority {
+            self.priority = p;
+        }
+        if let Some(t) = changes.tags {
+            self.tags = normalize_tags(t);
+        }
+        if let Some(a) = changes.auto_complete {
+            self.auto_complete = a;
+        }
+        if let Some(l) = changes.list_id {
+            self.list_id = l;
+        }
+        if let Some(r) = changes.recurrence {
+            self.recurrence = Some(r);
+        }
+        self.updated_at = Some(now);
+    }
+
+    // Keeps `completed_at` in step with `completed`
+    pub fn set_completed(&mut self, completed: bool, now: DateTime<Utc>) {
+        if completed && !self.completed {
+            self.completed_at = Some(now);
+        } else if !completed {
+            self.completed_at = None;
+        }
+        self.completed = completed;
+    }
+
+    // Still open and its due date has passed
+    pub fn is_overdue(&self, now: DateTime<Utc>) -> bool {
+        !self.completed && self.due_at.is_some_and(|due| due < now)
+    }
+
+    // Still open and due some time during the current UTC day
+    pub fn is_due_today(&self, now: DateTime<Utc>) -> bool {
+        let (start, end) = today_bounds(now);
+        !self.completed && self.due_at.is_some_and(|due| due >= start && due < end)
+    }
+}
+
+// A list (project) of todos
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TodoList {
+    pub id: u32,
+    pub name: String,
+    // `#rrggbb`
+    pub color: Option<String>,
+    pub archived: bool,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    // The default list is shared by everyone, but only its owner changes it
+    #[serde(default = "default_owner_id")]
+    pub owner_id: u32,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct CreateListRequest {
+    pub name: String,
+    pub color: Option<String>,
+    // Set by the server; defaults to the first user
+    #[serde(skip)]
+    pub owner_id: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UpdateListRequest {
+    pub name: Option<String>,
+    pub color: Option<String>,
+    pub archived: Option<bool>,
+}
+
+impl TodoList {
+    pub fn new(id: u32, request: CreateListRequest) -> Self {
+        let now = Utc::now();
+        TodoList {
+            id,
+            name: request.name,
+            color: request.color,
+            archived: false,
+            created_at: Some(now),
+            updated_at: Some(now),
+            owner_id: request.owner_id.unwrap_or(DEFAULT_OWNER_ID),
+        }
+    }
+
+    // The list that backs the plain /todos routes
+    pub fn default_list() -> Self {
+        let request = CreateListRequest {
+            name: "Inbox".to_string(),
+            color: None,
+            owner_id: None,
+        };
+        TodoList::new(DEFAULT_LIST_ID, request)
+    }
+
+    pub fn apply(&mut self, changes: UpdateListRequest) {
+        if let Some(n) = changes.name {
+            self.name = n;
+        }
+        if let Some(c) = changes.color {
+            self.color = Some(c);
+        }
+        if let Some(a) = changes.archived {
+            self.archived = a;
+        }
+        self.updated_at = Some(Utc::now());
+    }
+}
+
+// `#` followed by six hex digits
+pub fn is_valid_color(color: &str) -> bool {
+    color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Start (inclusive) and end (exclusive) of the UTC day containing `now`
+pub fn today_bounds(now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = now
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time")
+        .and_utc();
+    (start, start + Duration::days(1))
+}
+
+// Trim tags, drop empty ones and duplicates (first occurrence wins)
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    normalized
+}
+
+// Sort order for GET /todos, `-` prefix means descending
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+pub enum TodoSort {
+    #[default]
+    #[serde(rename = "id")]
+    IdAsc,
+    #[serde(rename = "-id")]
+    IdDesc,
+    #[serde(rename = "title")]
+    TitleAsc,
+    #[serde(rename = "-title")]
+    TitleDesc,
+}
+
+impl TodoSort {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TodoSort::IdAsc => "id",
+            TodoSort::IdDesc => "-id",
+            TodoSort::TitleAsc => "title",
+            TodoSort::TitleDesc => "-title",
+        }
+    }
+
+    // Ties on title are broken by id so paging always sees a stable order
+    pub fn compare(self, a: &Todo, b: &Todo) -> std::cmp::Ordering {
+        match self {
+            TodoSort::IdAsc => a.id.cmp(&b.id),
+            TodoSort::IdDesc => b.id.cmp(&a.id),
+            TodoSort::TitleAsc => a.title.cmp(&b.title).then(a.id.cmp(&b.id)),
+            TodoSort::TitleDesc => b.title.cmp(&a.title).then(a.id.cmp(&b.id)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DueFilter {
+    // Open todos whose due date has passed
+    Overdue,
+    // Open todos due during the current UTC day
+    Today,
+}
+
+impl DueFilter {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DueFilter::Overdue => "overdue",
+            DueFilter::Today => "today",
+        }
+    }
+}
+
+// Query parameters for GET /todos
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TodoQuery {
+    pub list_id: Option<u32>,
+    pub completed: Option<bool>,
+    // Case-insensitive (ASCII) substring of the title
+    pub q: Option<String>,
+    // Only todos carrying this tag
+    pub tag: Option<String>,
+    pub due: Option<DueFilter>,
+    #[serde(default)]
+    pub sort: TodoSort,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+    // Only todos of this user; not a query parameter, set by the server
+    #[serde(skip)]
+    pub owner_id: Option<u32>,
+}
+
+impl TodoQuery {
+    pub const DEFAULT_LIMIT: usize = 100;
+    pub const MAX_LIMIT: usize = 1000;
+
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT)
+    }
+
+    pub fn matches(&self, todo: &Todo) -> bool {
+        if let Some(owner_id) = self.owner_id
+            && todo.owner_id != owner_id
+        {
+            return false;
+        }
+        if let Some(list_id) = self.list_id
+            && todo.list_id != list_id
+        {
+            return false;
+        }
+        if let Some(completed) = self.completed
+            && todo.completed != completed
+        {
+            return false;
+        }
+        if let Some(q) = &self.q
+            && !todo
+                .title
+                .to_ascii_lowercase()
+                .contains(&q.to_ascii_lowercase())
+        {
+            return false;
+        }
+        if let Some(tag) = &self.tag
+            && !todo.tags.contains(tag)
+        {
+            return false;
+        }
+        match self.due {
+            Some(DueFilter::Overdue) => todo.is_overdue(Utc::now()),
+            Some(DueFilter::Today) => todo.is_due_today(Utc::now()),
+            None => true,
+        }
+    }
+}
+
+// One page of GET /todos results
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TodoPage {
+    pub todos: Vec<Todo>,
+    // Number of todos matching the filters, across all pages
+    pub total: usize,
+    pub limit: usize,
+    pub offset: usize,
+    // Link to the next page, `null` on the last page
+    pub next: Option<String>,
 }
@@ -0,0 +1,111 @@
+// The OpenAPI 3 description of the API, put together from the
+// `#[utoipa::path]` of every handler and the types they take and return.
+// Served at /openapi.json, with Swagger UI at /docs; both are compiled in,
+// so they work without network access. tests/openapi.rs fails when a route
+// is missing here or documented but not routed.
+
+use crate::formats::Format;
+use crate::handlers;
+use crate::models::{DueFilter, TodoSort};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+pub const SPEC_PATH: &str = "/openapi.json";
+pub const DOCS_PATH: &str = "/docs";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Todo API",
+        description = "Todos with subtasks, lists, history, a trash and offline sync. \
+            With accounts every request needs an API key and sees only its user's todos; \
+            without them no key is needed."
+    ),
+    paths(
+        handlers::get_todos,
+        handlers::create_todo,
+        handlers::batch_todos,
+        handlers::todos_calendar,
+        handlers::export_todos,
+        handlers::import_todos,
+        handlers::get_changes,
+        handlers::push_changes,
+        handlers::todo_events,
+        handlers::get_overdue_todos,
+        handlers::get_due_today_todos,
+        handlers::get_todos_by_tag,
+        handlers::get_todo,
+        handlers::update_todo,
+        handlers::patch_todo,
+        handlers::delete_todo,
+        handlers::get_children,
+        handlers::get_tree,
+        handlers::move_todo,
+        handlers::get_history,
+        handlers::get_occurrences,
+        handlers::restore_todo,
+        handlers::get_trash,
+        handlers::empty_trash,
+        handlers::purge_todo,
+        handlers::restore_from_trash,
+        handlers::get_lists,
+        handlers::create_list,
+        handlers::get_list,
+        handlers::update_list,
+        handlers::delete_list,
+        handlers::get_list_todos,
+        handlers::create_list_todo,
+        handlers::get_list_todo,
+        handlers::update_list_todo,
+        handlers::patch_list_todo,
+        handlers::delete_list_todo,
+        handlers::get_me,
+        handlers::get_users,
+        handlers::create_user,
+        handlers::create_key,
+        handlers::revoke_key,
+        handlers::get_backups,
+        handlers::create_backup,
+        handlers::restore_backup,
+    ),
+    // Query parameter types, which `IntoParams` refers to but does not add
+    components(schemas(DueFilter, TodoSort, Format)),
+    modifiers(&ApiKey),
+    security(("api_key" = [])),
+    tags(
+        (name = "todos", description = "Todos of the default list, or any list by id"),
+        (name = "lists", description = "Lists and the todos in them"),
+        (name = "trash", description = "Deleted todos, until they are purged"),
+        (name = "sync", description = "Change feed and offline sync"),
+        (name = "import/export", description = "CSV, Markdown, todo.txt and iCalendar"),
+        (name = "accounts", description = "Users and API keys"),
+        (name = "backups", description = "Snapshot backups"),
+    )
+)]
+pub struct ApiDoc;
+
+// `Authorization: Bearer <key>`
+struct ApiKey;
+
+impl Modify for ApiKey {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+pub fn spec() -> utoipa::openapi::OpenApi {
+    let mut spec = ApiDoc::openapi();
+    // Cargo.toml names no license, and an unnamed one is not valid
+    spec.info.license = None;
+    spec
+}
+
+// Routes for the spec and the UI that shows it
+pub fn docs() -> SwaggerUi {
+    SwaggerUi::new(DOCS_PATH).url(SPEC_PATH, spec())
+}
@@ -0,0 +1,200 @@
+// JSON Merge Patch (RFC 7396) and JSON Patch (RFC 6902) over serde_json
+// values. Pure functions; turning the result back into a `Todo` is up to
+// the caller.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fmt;
+
+// One operation of a JSON Patch document
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    // Fails the whole patch unless the value at `path` equals `value`
+    Test { path: String, value: Value },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    // A path that does not exist, a bad pointer or array index, ...
+    Invalid(String),
+    TestFailed(String),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Invalid(reason) => write!(f, "{}", reason),
+            PatchError::TestFailed(path) => write!(f, "test failed at '{}'", path),
+        }
+    }
+}
+
+// RFC 7396: objects are merged recursively, `null` removes a member and
+// anything else replaces the target
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else {
+        unreachable!("target was just made an object");
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+// RFC 6902: apply all operations in order. The patch is all-or-nothing, so
+// `document` is only returned patched if every operation succeeded.
+pub fn json_patch(document: &Value, ops: &[PatchOp]) -> Result<Value, PatchError> {
+    let mut document = document.clone();
+    for op in ops {
+        match op {
+            PatchOp::Add { path, value } => add(&mut document, path, value.clone())?,
+            PatchOp::Remove { path } => {
+                remove(&mut document, path)?;
+            }
+            PatchOp::Replace { path, value } => {
+                *pointer_mut(&mut document, path)? = value.clone();
+            }
+            PatchOp::Move { from, path } => {
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(PatchError::Invalid(format!(
+                        "cannot move '{}' into its own child '{}'",
+                        from, path
+                    )));
+                }
+                let value = remove(&mut document, from)?;
+                add(&mut document, path, value)?;
+            }
+            PatchOp::Copy { from, path } => {
+                let value = pointer(&document, from)?.clone();
+                add(&mut document, path, value)?;
+            }
+            PatchOp::Test { path, value } => {
+                if pointer(&document, path)? != value {
+                    return Err(PatchError::TestFailed(path.clone()));
+                }
+            }
+        }
+    }
+    Ok(document)
+}
+
+// RFC 6901 pointer -> unescaped reference tokens
+fn tokens(path: &str) -> Result<Vec<String>, PatchError> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(rest) = path.strip_prefix('/') else {
+        return Err(PatchError::Invalid(format!(
+            "path '{}' must be empty or start with '/'",
+            path
+        )));
+    };
+    Ok(rest
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+// Array index without leading zeros, below `len` (or equal to it when
+// appending is allowed)
+fn index(token: &str, len: usize, allow_end: bool) -> Result<usize, PatchError> {
+    let valid = !token.is_empty()
+        && token.bytes().all(|b| b.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+    match token.parse::<usize>() {
+        Ok(i) if valid && (i < len || (allow_end && i == len)) => Ok(i),
+        _ => Err(PatchError::Invalid(format!("invalid array index '{}'", token))),
+    }
+}
+
+fn not_found(path: &str) -> PatchError {
+    PatchError::Invalid(format!("path '{}' does not exist", path))
+}
+
+fn pointer<'a>(document: &'a Value, path: &str) -> Result<&'a Value, PatchError> {
+    let mut current = document;
+    for token in tokens(path)? {
+        current = match current {
+            Value::Object(map) => map.get(&token).ok_or_else(|| not_found(path))?,
+            Value::Array(items) => &items[index(&token, items.len(), false)?],
+            _ => return Err(not_found(path)),
+        };
+    }
+    Ok(current)
+}
+
+fn pointer_mut<'a>(document: &'a mut Value, path: &str) -> Result<&'a mut Value, PatchError> {
+    let mut current = document;
+    for token in tokens(path)? {
+        current = match current {
+            Value::Object(map) => map.get_mut(&token).ok_or_else(|| not_found(path))?,
+            Value::Array(items) => {
+                let i = index(&token, items.len(), false)?;
+                &mut items[i]
+            }
+            _ => return Err(not_found(path)),
+        };
+    }
+    Ok(current)
+}
+
+// The container `path` points into, plus the last token
+fn parent_mut<'a>(
+    document: &'a mut Value,
+    path: &str,
+) -> Result<(&'a mut Value, String), PatchError> {
+    let Some((parent, last)) = path.rsplit_once('/') else {
+        return Err(not_found(path));
+    };
+    let last = tokens(&format!("/{}", last))?.remove(0);
+    Ok((pointer_mut(document, parent)?, last))
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<(), PatchError> {
+    if path.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+    match parent_mut(document, path)? {
+        (Value::Object(map), key) => {
+            map.insert(key, value);
+        }
+        (Value::Array(items), token) if token == "-" => items.push(value),
+        (Value::Array(items), token) => {
+            let i = index(&token, items.len(), true)?;
+            items.insert(i, value);
+        }
+        _ => return Err(not_found(path)),
+    }
+    Ok(())
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value, PatchError> {
+    if path.is_empty() {
+        return Err(PatchError::Invalid("cannot remove the whole document".to_string()));
+    }
+    match parent_mut(document, path)? {
+        (Value::Object(map), key) => map.remove(&key).ok_or_else(|| not_found(path)),
+        (Value::Array(items), token) => {
+            let i = index(&token, items.len(), false)?;
+            Ok(items.remove(i))
+        }
+        _ => Err(not_found(path)),
+    }
+}
@@ -0,0 +1,459 @@
+// Recurrence rules for repeating todos: the part of RFC 5545 RRULE that
+// chores need. Supported are FREQ (DAILY, WEEKLY, MONTHLY, YEARLY),
+// INTERVAL, BYDAY (`1MO` / `-1FR` style ordinals only with MONTHLY),
+// BYMONTHDAY (MONTHLY only), COUNT and UNTIL (UTC). Weeks start on Monday.
+// The todo's due date is the start of the series and its first occurrence.
+
+use crate::models::{CreateTodoRequest, Todo};
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveDateTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use utoipa::openapi::{ObjectBuilder, RefOr, Schema, Type};
+use utoipa::{PartialSchema, ToSchema};
+
+// Periods in a row without an occurrence after which a series is taken to
+// have none left, e.g. BYMONTHDAY=30 in a rule that only hits February
+const MAX_EMPTY_PERIODS: u32 = 1000;
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Mon),
+    ("TU", Weekday::Tue),
+    ("WE", Weekday::Wed),
+    ("TH", Weekday::Thu),
+    ("FR", Weekday::Fri),
+    ("SA", Weekday::Sat),
+    ("SU", Weekday::Sun),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+// One BYDAY entry; `nth` picks one of the month (1 = first, -1 = last)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeekdayNum {
+    pub nth: Option<i8>,
+    pub weekday: Weekday,
+}
+
+// Stored and sent as the RRULE string, e.g. `FREQ=WEEKLY;BYDAY=MO,TH`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    // Empty: the weekday (WEEKLY) or day of the month (MONTHLY) of the start
+    pub by_day: Vec<WeekdayNum>,
+    pub by_month_day: Vec<i8>,
+    // Occurrences in the series, counting the first one
+    pub count: Option<u32>,
+    // Last possible occurrence, inclusive
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecurrenceError(pub String);
+
+impl fmt::Display for RecurrenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid recurrence rule: {}", self.0)
+    }
+}
+
+impl std::error::Error for RecurrenceError {}
+
+fn invalid(reason: impl Into<String>) -> RecurrenceError {
+    RecurrenceError(reason.into())
+}
+
+impl FromStr for Recurrence {
+    type Err = RecurrenceError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim().to_ascii_uppercase();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(&rule);
+
+        let mut frequency = None;
+        let mut recurrence = Recurrence {
+            frequency: Frequency::Daily,
+            interval: 1,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            count: None,
+            until: None,
+        };
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let Some((key, value)) = part.split_once('=') else {
+                return Err(invalid(format!("expected KEY=VALUE, got '{}'", part)));
+            };
+            match key {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(invalid(format!("unsupported FREQ '{}'", value))),
+                    })
+                }
+                "INTERVAL" => {
+                    recurrence.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval >= 1)
+                        .ok_or_else(|| invalid(format!("bad INTERVAL '{}'", value)))?;
+                }
+                "BYDAY" => {
+                    recurrence.by_day =
+                        value.split(',').map(parse_weekday_num).collect::<Result<_, _>>()?;
+                }
+                "BYMONTHDAY" => {
+                    recurrence.by_month_day = value
+                        .split(',')
+                        .map(|day| {
+                            day.parse()
+                                .ok()
+                                .filter(|day: &i8| (1..=31).contains(&day.unsigned_abs()))
+                                .ok_or_else(|| invalid(format!("bad BYMONTHDAY '{}'", day)))
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                "COUNT" => {
+                    recurrence.count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|count| *count >= 1)
+                            .ok_or_else(|| invalid(format!("bad COUNT '{}'", value)))?,
+                    );
+                }
+                "UNTIL" => recurrence.until = Some(parse_until(value)?),
+                // The only week start there is
+                "WKST" if value == "MO" => {}
+                _ => return Err(invalid(format!("'{}' is not supported", key))),
+            }
+        }
+
+        recurrence.frequency = frequency.ok_or_else(|| invalid("FREQ is required"))?;
+        if recurrence.count.is_some() && recurrence.until.is_some() {
+            return Err(invalid("COUNT and UNTIL cannot be combined"));
+        }
+        let monthly = recurrence.frequency == Frequency::Monthly;
+        if !monthly && recurrence.by_day.iter().any(|day| day.nth.is_some()) {
+            return Err(invalid("BYDAY ordinals like 1MO need FREQ=MONTHLY"));
+        }
+        if !recurrence.by_day.is_empty() && !recurrence.by_month_day.is_empty() {
+            return Err(invalid("BYDAY and BYMONTHDAY cannot be combined"));
+        }
+        if recurrence.frequency == Frequency::Yearly && !recurrence.by_day.is_empty() {
+            return Err(invalid("BYDAY is not supported with FREQ=YEARLY"));
+        }
+        if !monthly && !recurrence.by_month_day.is_empty() {
+            return Err(invalid("BYMONTHDAY needs FREQ=MONTHLY"));
+        }
+        Ok(recurrence)
+    }
+}
+
+fn parse_weekday_num(value: &str) -> Result<WeekdayNum, RecurrenceError> {
+    let bad = || invalid(format!("bad BYDAY '{}'", value));
+    let split = value.len().checked_sub(2).ok_or_else(bad)?;
+    let (nth, code) = value.split_at_checked(split).ok_or_else(bad)?;
+    let weekday = WEEKDAYS.iter().find(|(name, _)| *name == code).ok_or_else(bad)?.1;
+    let nth = match nth {
+        "" => None,
+        nth => {
+            let nth: i8 = nth.parse().map_err(|_| bad())?;
+            if !(1..=5).contains(&nth.unsigned_abs()) {
+                return Err(bad());
+            }
+            Some(nth)
+        }
+    };
+    Ok(WeekdayNum { nth, weekday })
+}
+
+// `20250131T170000Z`, or a date for the whole day
+fn parse_until(value: &str) -> Result<DateTime<Utc>, RecurrenceError> {
+    if let Ok(at) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ") {
+        return Ok(at.and_utc());
+    }
+    NaiveDate::parse_from_str(value, "%Y%m%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(23, 59, 59))
+        .map(|at| at.and_utc())
+        .ok_or_else(|| invalid(format!("bad UNTIL '{}', expected e.g. 20250131T170000Z", value)))
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", frequency)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|day| {
+                    let code = WEEKDAYS.iter().find(|(_, weekday)| *weekday == day.weekday);
+                    let code = code.map_or("", |(name, _)| name);
+                    match day.nth {
+                        Some(nth) => format!("{}{}", nth, code),
+                        None => code.to_string(),
+                    }
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(i8::to_string).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ"))?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = RecurrenceError;
+
+    fn try_from(rule: String) -> Result<Self, Self::Error> {
+        rule.parse()
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(rule: Recurrence) -> Self {
+        rule.to_string()
+    }
+}
+
+// A string in the API, like it is serialized
+impl PartialSchema for Recurrence {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .description(Some("RFC 5545 RRULE, e.g. `FREQ=WEEKLY;BYDAY=MO,TH`"))
+            .examples(["FREQ=WEEKLY;BYDAY=MO,TH"])
+            .into()
+    }
+}
+
+impl ToSchema for Recurrence {}
+
+impl Recurrence {
+    // The series starting at `start`, oldest first; endless unless the rule
+    // has COUNT or UNTIL
+    pub fn occurrences(&self, start: DateTime<Utc>) -> Occurrences<'_> {
+        Occurrences {
+            rule: self,
+            start,
+            period: 0,
+            pending: VecDeque::new(),
+            returned: 0,
+            done: false,
+        }
+    }
+
+    // Where the series goes on after the occurrence at `due`: the first
+    // later occurrence that is not already past at `now` (missed ones are
+    // skipped), and the rule from there on, with COUNT lowered by the
+    // occurrences used up. `None` once the series is over.
+    pub fn next_after(
+        &self,
+        due: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Option<(DateTime<Utc>, Recurrence)> {
+        let (used, next) = self.occurrences(due).enumerate().skip(1).find(|(_, at)| *at > now)?;
+        let mut rule = self.clone();
+        rule.count = self.count.map(|count| count - used as u32);
+        Some((next, rule))
+    }
+
+    // Dates in period `period` (counted in steps of INTERVAL) of a series
+    // starting on `start`, in order
+    fn expand(&self, start: NaiveDate, period: u32) -> Vec<NaiveDate> {
+        let step = u64::from(period) * u64::from(self.interval);
+        let mut dates: Vec<NaiveDate> = match self.frequency {
+            Frequency::Daily => start
+                .checked_add_days(Days::new(step))
+                .filter(|day| {
+                    self.by_day.is_empty()
+                        || self.by_day.iter().any(|by| by.weekday == day.weekday())
+                })
+                .into_iter()
+                .collect(),
+            Frequency::Weekly => {
+                let monday = start.week(Weekday::Mon).first_day();
+                let Some(week) = step.checked_mul(7).and_then(|days| {
+                    monday.checked_add_days(Days::new(days))
+                }) else {
+                    return Vec::new();
+                };
+                let weekdays: Vec<Weekday> = if self.by_day.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    self.by_day.iter().map(|by| by.weekday).collect()
+                };
+                weekdays
+                    .iter()
+                    .filter_map(|weekday| {
+                        week.checked_add_days(Days::new(weekday.num_days_from_monday().into()))
+                    })
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let Some((year, month)) = add_months(start, step) else {
+                    return Vec::new();
+                };
+                if !self.by_day.is_empty() {
+                    self.by_day.iter().flat_map(|by| weekdays_in_month(year, month, *by)).collect()
+                } else if !self.by_month_day.is_empty() {
+                    let days = self.by_month_day.iter();
+                    days.filter_map(|day| month_day(year, month, *day)).collect()
+                } else {
+                    NaiveDate::from_ymd_opt(year, month, start.day()).into_iter().collect()
+                }
+            }
+            Frequency::Yearly => i32::try_from(step)
+                .ok()
+                .and_then(|step| start.year().checked_add(step))
+                .and_then(|year| NaiveDate::from_ymd_opt(year, start.month(), start.day()))
+                .into_iter()
+                .collect(),
+        };
+        dates.sort();
+        dates.dedup();
+        dates
+    }
+}
+
+// The occurrences of a series, see `Recurrence::occurrences`
+pub struct Occurrences<'a> {
+    rule: &'a Recurrence,
+    start: DateTime<Utc>,
+    // Next period to expand
+    period: u32,
+    // Dates of the last expanded period not returned yet
+    pending: VecDeque<NaiveDate>,
+    returned: u32,
+    done: bool,
+}
+
+impl Iterator for Occurrences<'_> {
+    type Item = DateTime<Utc>;
+
+    fn next(&mut self) -> Option<DateTime<Utc>> {
+        if self.done || self.rule.count.is_some_and(|count| self.returned >= count) {
+            return None;
+        }
+        let next = if self.returned == 0 {
+            self.start
+        } else {
+            let mut empty = 0;
+            loop {
+                if let Some(date) = self.pending.pop_front() {
+                    break date.and_time(self.start.time()).and_utc();
+                }
+                if empty == MAX_EMPTY_PERIODS || self.period == u32::MAX {
+                    self.done = true;
+                    return None;
+                }
+                let start = self.start.date_naive();
+                let dates = self.rule.expand(start, self.period);
+                self.period += 1;
+                // The first period can have dates before the start
+                self.pending.extend(dates.into_iter().filter(|date| *date > start));
+                if self.pending.is_empty() {
+                    empty += 1;
+                }
+            }
+        };
+        if self.rule.until.is_some_and(|until| next > until) {
+            self.done = true;
+            return None;
+        }
+        self.returned += 1;
+        Some(next)
+    }
+}
+
+fn add_months(start: NaiveDate, months: u64) -> Option<(i32, u32)> {
+    let months = i64::try_from(months).ok()?;
+    let months = i64::from(start.year()) * 12 + i64::from(start.month0()) + months;
+    let year = i32::try_from(months.div_euclid(12)).ok()?;
+    Some((year, months.rem_euclid(12) as u32 + 1))
+}
+
+fn days_in_month(year: i32, month: u32) -> Option<u32> {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    Some(NaiveDate::from_ymd_opt(next_year, next_month, 1)?.pred_opt()?.day())
+}
+
+// Day `day` of the month, counted from the end when negative; `None` when
+// the month is too short
+fn month_day(year: i32, month: u32, day: i8) -> Option<NaiveDate> {
+    let day = if day > 0 {
+        u32::from(day.unsigned_abs())
+    } else {
+        days_in_month(year, month)?.checked_sub(u32::from(day.unsigned_abs()) - 1)?
+    };
+    NaiveDate::from_ymd_opt(year, month, day)
+}
+
+fn weekdays_in_month(year: i32, month: u32, by: WeekdayNum) -> Vec<NaiveDate> {
+    let days: Vec<NaiveDate> = (1..=days_in_month(year, month).unwrap_or(0))
+        .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+        .filter(|date| date.weekday() == by.weekday)
+        .collect();
+    match by.nth {
+        None => days,
+        Some(nth) if nth > 0 => days.get(nth as usize - 1).copied().into_iter().collect(),
+        Some(nth) => days
+            .len()
+            .checked_sub(nth.unsigned_abs().into())
+            .and_then(|index| days.get(index).copied())
+            .into_iter()
+            .collect(),
+    }
+}
+
+// The todo for the next occurrence after `todo` was completed: a copy of it
+// (without subtasks) due at the next occurrence and carrying the rule on.
+// A series without due dates goes on from the completion time.
+pub fn next_occurrence(todo: &Todo, now: DateTime<Utc>) -> Option<CreateTodoRequest> {
+    let rule = todo.recurrence.as_ref()?;
+    let due = todo.due_at.or(todo.completed_at).unwrap_or(now);
+    let (due_at, recurrence) = rule.next_after(due, now)?;
+    Some(CreateTodoRequest {
+        title: todo.title.clone(),
+        description: todo.description.clone(),
+        due_at: Some(due_at),
+        priority: todo.priority,
+        tags: todo.tags.clone(),
+        parent_id: todo.parent_id,
+        auto_complete: todo.auto_complete,
+        list_id: Some(todo.list_id),
+        recurrence: Some(recurrence),
+        owner_id: Some(todo.owner_id),
+        ..Default::default()
+    })
+}
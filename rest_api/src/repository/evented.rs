@@ -0,0 +1,194 @@
+use super::TodoRepository;
+use crate::error::{BatchError, StorageError};
+use crate::events::{ChangeFeed, TodoChange};
+use crate::history::TodoRevision;
+use crate::models::{
+    BatchOperation, BatchOutcome, ChangeSet, CreateListRequest, CreateTodoRequest,
+    DEFAULT_OWNER_ID, Todo, TodoList, TodoQuery, UpdateListRequest, UpdateTodoRequest,
+};
+use crate::schema::DataFile;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Any repository, publishing every todo write that went through to a
+// `ChangeFeed`. Reads and list changes are passed straight on.
+pub struct EventedRepository {
+    inner: Arc<dyn TodoRepository>,
+    feed: Arc<ChangeFeed>,
+    // Held from a todo write until its events are published, so the feed
+    // numbers them in the order the writes were stored
+    writing: Mutex<()>,
+}
+
+impl EventedRepository {
+    pub fn new(inner: Arc<dyn TodoRepository>, feed: Arc<ChangeFeed>) -> Self {
+        EventedRepository {
+            inner,
+            feed,
+            writing: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl TodoRepository for EventedRepository {
+    async fn list(&self) -> Result<Vec<Todo>, StorageError> {
+        self.inner.list().await
+    }
+
+    async fn query(&self, query: &TodoQuery) -> Result<(Vec<Todo>, usize), StorageError> {
+        self.inner.query(query).await
+    }
+
+    async fn get(&self, id: u32) -> Result<Option<Todo>, StorageError> {
+        self.inner.get(id).await
+    }
+
+    async fn create(&self, request: CreateTodoRequest) -> Result<Todo, StorageError> {
+        let _writing = self.writing.lock().await;
+        let todo = self.inner.create(request).await?;
+        self.feed.publish(TodoChange::Created { todo: todo.clone() });
+        Ok(todo)
+    }
+
+    async fn update(
+        &self,
+        id: u32,
+        changes: UpdateTodoRequest,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Todo>, StorageError> {
+        let _writing = self.writing.lock().await;
+        let todo = self.inner.update(id, changes, expected_revision).await?;
+        if let Some(todo) = &todo {
+            self.feed.publish(TodoChange::Updated { todo: todo.clone() });
+        }
+        Ok(todo)
+    }
+
+    async fn replace(&self, todo: Todo) -> Result<Option<Todo>, StorageError> {
+        let _writing = self.writing.lock().await;
+        let todo = self.inner.replace(todo).await?;
+        if let Some(todo) = &todo {
+            self.feed.publish(TodoChange::Updated { todo: todo.clone() });
+        }
+        Ok(todo)
+    }
+
+    async fn delete(&self, id: u32, expected_revision: Option<u64>) -> Result<bool, StorageError> {
+        let _writing = self.writing.lock().await;
+        // Read first, the event says whose todo it was
+        let Some(todo) = self.inner.get(id).await? else {
+            return Ok(false);
+        };
+        let deleted = self.inner.delete(id, expected_revision).await?;
+        if deleted {
+            self.feed.publish(TodoChange::Deleted {
+                id,
+                owner_id: todo.owner_id,
+            });
+        }
+        Ok(deleted)
+    }
+
+    async fn trash(&self) -> Result<Vec<Todo>, StorageError> {
+        self.inner.trash().await
+    }
+
+    // Purged todos already left /todos when they were deleted
+    async fn purge(&self, id: u32) -> Result<bool, StorageError> {
+        self.inner.purge(id).await
+    }
+
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<Vec<u32>, StorageError> {
+        self.inner.purge_deleted_before(cutoff).await
+    }
+
+    async fn history(&self, id: u32) -> Result<Vec<TodoRevision>, StorageError> {
+        self.inner.history(id).await
+    }
+
+    // A restored todo that was deleted shows up as created again
+    async fn restore(&self, todo: Todo) -> Result<Todo, StorageError> {
+        let _writing = self.writing.lock().await;
+        let existed = self.inner.get(todo.id).await?.is_some();
+        let todo = self.inner.restore(todo).await?;
+        let change = if existed {
+            TodoChange::Updated { todo: todo.clone() }
+        } else {
+            TodoChange::Created { todo: todo.clone() }
+        };
+        self.feed.publish(change);
+        Ok(todo)
+    }
+
+    async fn changes_since(&self, since: u64, limit: usize) -> Result<ChangeSet, StorageError> {
+        self.inner.changes_since(since, limit).await
+    }
+
+    async fn batch(&self, ops: Vec<BatchOperation>) -> Result<Vec<BatchOutcome>, BatchError> {
+        let _writing = self.writing.lock().await;
+        let mut owners = HashMap::new();
+        for op in &ops {
+            if let BatchOperation::Delete { id, .. } = op
+                && let Some(todo) = self.inner.get(*id).await?
+            {
+                owners.insert(*id, todo.owner_id);
+            }
+        }
+        let outcomes = self.inner.batch(ops).await?;
+        for outcome in &outcomes {
+            self.feed.publish(match outcome {
+                BatchOutcome::Created(todo) => TodoChange::Created { todo: todo.clone() },
+                BatchOutcome::Updated(todo) => TodoChange::Updated { todo: todo.clone() },
+                BatchOutcome::Deleted(id) => TodoChange::Deleted {
+                    id: *id,
+                    owner_id: owners.get(id).copied().unwrap_or(DEFAULT_OWNER_ID),
+                },
+            });
+        }
+        Ok(outcomes)
+    }
+
+    async fn lists(&self) -> Result<Vec<TodoList>, StorageError> {
+        self.inner.lists().await
+    }
+
+    async fn get_list(&self, id: u32) -> Result<Option<TodoList>, StorageError> {
+        self.inner.get_list(id).await
+    }
+
+    async fn create_list(&self, request: CreateListRequest) -> Result<TodoList, StorageError> {
+        self.inner.create_list(request).await
+    }
+
+    async fn update_list(
+        &self,
+        id: u32,
+        changes: UpdateListRequest,
+    ) -> Result<Option<TodoList>, StorageError> {
+        self.inner.update_list(id, changes).await
+    }
+
+    async fn delete_list(&self, id: u32) -> Result<bool, StorageError> {
+        self.inner.delete_list(id).await
+    }
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush().await
+    }
+
+    async fn snapshot(&self) -> Result<DataFile, StorageError> {
+        self.inner.snapshot().await
+    }
+
+    // One `Reset` rather than an event per todo: subscribers reload
+    async fn restore_snapshot(&self, snapshot: DataFile) -> Result<(), StorageError> {
+        let _writing = self.writing.lock().await;
+        self.inner.restore_snapshot(snapshot).await?;
+        self.feed.publish(TodoChange::Reset);
+        Ok(())
+    }
+}
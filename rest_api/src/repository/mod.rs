@@ -0,0 +1,176 @@
+mod evented;
+mod scoped;
+mod sqlite;
+mod storage;
+
+pub use evented::EventedRepository;
+pub use scoped::ScopedRepository;
+pub use sqlite::SqliteRepository;
+pub use storage::StorageRepository;
+
+use crate::encryption::Cipher;
+use crate::error::{BatchError, StorageError};
+use crate::history::{HistoryRetention, TodoRevision};
+use crate::schema::DataFile;
+use crate::writer::Durability;
+use crate::models::{
+    BatchOperation, BatchOutcome, ChangeSet, CreateListRequest, CreateTodoRequest, Todo,
+    TodoList, TodoQuery, UpdateListRequest, UpdateTodoRequest,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Everything the handlers need from a todo store. Every backend has to
+// behave the same way, see tests/repository_conformance.rs.
+#[async_trait]
+pub trait TodoRepository: Send + Sync {
+    async fn list(&self) -> Result<Vec<Todo>, StorageError>;
+
+    // Filtered, sorted page of todos plus the total number of matches.
+    // The default filters `list()` in memory; backends that can do it
+    // natively (SQLite) override it.
+    async fn query(&self, query: &TodoQuery) -> Result<(Vec<Todo>, usize), StorageError> {
+        let mut todos: Vec<Todo> = self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|todo| query.matches(todo))
+            .collect();
+        todos.sort_by(|a, b| query.sort.compare(a, b));
+
+        let total = todos.len();
+        let page = todos
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit())
+            .collect();
+        Ok((page, total))
+    }
+
+    async fn get(&self, id: u32) -> Result<Option<Todo>, StorageError>;
+
+    async fn create(&self, request: CreateTodoRequest) -> Result<Todo, StorageError>;
+
+    // Every write bumps the todo's `revision`. With `expected_revision` set
+    // the write only happens if the todo is still at that revision, otherwise
+    // it fails with `StorageError::RevisionMismatch`; check and write are
+    // one atomic step. Returns `None` when there is no todo with this id.
+    async fn update(
+        &self,
+        id: u32,
+        changes: UpdateTodoRequest,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Todo>, StorageError>;
+
+    // Overwrite an existing todo as a whole, `None` when it does not exist.
+    // `todo.revision` is the expected revision, i.e. the one it was read at.
+    async fn replace(&self, todo: Todo) -> Result<Option<Todo>, StorageError>;
+
+    // Moves the todo to the trash with `deleted_at` set. Returns `false`
+    // when there is no todo with this id.
+    async fn delete(&self, id: u32, expected_revision: Option<u64>) -> Result<bool, StorageError>;
+
+    // Deleted todos, most recently deleted first
+    async fn trash(&self) -> Result<Vec<Todo>, StorageError>;
+
+    // Delete a todo in the trash for good, history included. Returns
+    // `false` when it is not in the trash.
+    async fn purge(&self, id: u32) -> Result<bool, StorageError>;
+
+    // Purge every todo deleted before `cutoff`, returns their ids
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<Vec<u32>, StorageError>;
+
+    // Recorded revisions of a todo, oldest first. Kept after the todo is
+    // deleted, within the configured retention.
+    async fn history(&self, id: u32) -> Result<Vec<TodoRevision>, StorageError>;
+
+    // Write an earlier state of a todo back, recreating it if it was
+    // deleted (which takes it out of the trash). It gets a revision above
+    // every one the todo had so far.
+    async fn restore(&self, todo: Todo) -> Result<Todo, StorageError>;
+
+    // Every todo write gets the next number of one change sequence shared by
+    // all todos; this returns the todos whose last change came after
+    // `since`, oldest first. Deleted todos stay in it as tombstones, also
+    // after they are purged.
+    async fn changes_since(&self, since: u64, limit: usize) -> Result<ChangeSet, StorageError>;
+
+    // Apply the operations in order, all or nothing, and persist them in one
+    // write. On error nothing was applied.
+    async fn batch(&self, ops: Vec<BatchOperation>) -> Result<Vec<BatchOutcome>, BatchError>;
+
+    // Lists, ordered by id. The default list always exists.
+    async fn lists(&self) -> Result<Vec<TodoList>, StorageError>;
+
+    async fn get_list(&self, id: u32) -> Result<Option<TodoList>, StorageError>;
+
+    async fn create_list(&self, request: CreateListRequest) -> Result<TodoList, StorageError>;
+
+    async fn update_list(
+        &self,
+        id: u32,
+        changes: UpdateListRequest,
+    ) -> Result<Option<TodoList>, StorageError>;
+
+    // Only removes the list itself, moving or deleting its todos is up to the caller
+    async fn delete_list(&self, id: u32) -> Result<bool, StorageError>;
+
+    // Wait until every write so far is on disk. Only backends that write in
+    // the background (`Durability::Debounced`) have anything to wait for.
+    async fn flush(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    // The whole store as todos.json holds it, what backups are made of.
+    // Wrappers that show only part of a store cannot be backed up.
+    async fn snapshot(&self) -> Result<DataFile, StorageError> {
+        Err(StorageError::Unavailable("only a whole store can be backed up".to_string()))
+    }
+
+    // Replace everything with `snapshot` in one go. Every todo on either
+    // side counts as changed, and ids handed out since are not reused.
+    async fn restore_snapshot(&self, _snapshot: DataFile) -> Result<(), StorageError> {
+        Err(StorageError::Unavailable("only a whole store can be restored".to_string()))
+    }
+}
+
+// Which repository implementation to run the server with. Only the JSON
+// backend can be encrypted.
+#[derive(Debug, Clone)]
+pub enum Backend {
+    // JSON snapshot + journal (the default)
+    Json(PathBuf),
+    // Nothing is persisted, handy for tests and demos
+    Memory,
+    // Embedded SQLite database, better for big lists
+    Sqlite(PathBuf),
+}
+
+impl Backend {
+    pub fn open(
+        &self,
+        retention: HistoryRetention,
+        durability: Durability,
+        cipher: Option<&Cipher>,
+    ) -> Result<Arc<dyn TodoRepository>, StorageError> {
+        Ok(match self {
+            Backend::Json(path) => {
+                let repo = match cipher {
+                    Some(cipher) => StorageRepository::encrypted_json_file(path, cipher.clone())?,
+                    None => StorageRepository::json_file(path)?,
+                };
+                Arc::new(repo.with_history_retention(retention)?.with_durability(durability)?)
+            }
+            Backend::Memory => {
+                Arc::new(StorageRepository::in_memory().with_history_retention(retention)?)
+            }
+            Backend::Sqlite(path) => Arc::new(
+                SqliteRepository::open(path)?
+                    .with_history_retention(retention)?
+                    .with_durability(durability)?,
+            ),
+        })
+    }
+}
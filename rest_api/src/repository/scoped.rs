@@ -0,0 +1,259 @@
+use super::TodoRepository;
+use crate::error::{BatchError, StorageError};
+use crate::history::TodoRevision;
+use crate::models::{
+    BatchOperation, BatchOutcome, ChangeSet, CreateListRequest, CreateTodoRequest,
+    DEFAULT_LIST_ID, Todo, TodoList, TodoQuery, UpdateListRequest, UpdateTodoRequest,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+// Any repository, narrowed down to what one user owns. Todos and lists of
+// other users look like they do not exist, and whatever the user creates
+// belongs to them. The default list is shared: everyone sees it (with only
+// their own todos in it), but only its owner can change it.
+pub struct ScopedRepository {
+    inner: Arc<dyn TodoRepository>,
+    owner_id: u32,
+}
+
+impl ScopedRepository {
+    pub fn new(inner: Arc<dyn TodoRepository>, owner_id: u32) -> Self {
+        ScopedRepository { inner, owner_id }
+    }
+
+    fn owns(&self, todo: &Todo) -> bool {
+        todo.owner_id == self.owner_id
+    }
+
+    fn sees_list(&self, list: &TodoList) -> bool {
+        list.owner_id == self.owner_id || list.id == DEFAULT_LIST_ID
+    }
+
+    async fn owned(&self, id: u32) -> Result<Option<Todo>, StorageError> {
+        Ok(self.inner.get(id).await?.filter(|todo| self.owns(todo)))
+    }
+
+    // Every recorded state of a todo belongs to the same user, so the newest
+    // one tells whose it is
+    fn owns_history(&self, entries: &[TodoRevision]) -> bool {
+        entries
+            .last()
+            .and_then(|entry| entry.after.as_ref().or(entry.before.as_ref()))
+            .is_some_and(|todo| self.owns(todo))
+    }
+
+    // The todo `id` is ours wherever it is now: live, in the trash, or only
+    // in its history
+    async fn can_restore(&self, id: u32) -> Result<bool, StorageError> {
+        Ok(match self.inner.get(id).await? {
+            Some(current) => self.owns(&current),
+            None => {
+                let trash = self.inner.trash().await?;
+                match trash.iter().find(|trashed| trashed.id == id) {
+                    Some(trashed) => self.owns(trashed),
+                    None => self.owns_history(&self.inner.history(id).await?),
+                }
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl TodoRepository for ScopedRepository {
+    async fn list(&self) -> Result<Vec<Todo>, StorageError> {
+        let mut todos = self.inner.list().await?;
+        todos.retain(|todo| self.owns(todo));
+        Ok(todos)
+    }
+
+    async fn query(&self, query: &TodoQuery) -> Result<(Vec<Todo>, usize), StorageError> {
+        let query = TodoQuery {
+            owner_id: Some(self.owner_id),
+            ..query.clone()
+        };
+        self.inner.query(&query).await
+    }
+
+    async fn get(&self, id: u32) -> Result<Option<Todo>, StorageError> {
+        self.owned(id).await
+    }
+
+    async fn create(&self, request: CreateTodoRequest) -> Result<Todo, StorageError> {
+        let request = CreateTodoRequest {
+            owner_id: Some(self.owner_id),
+            ..request
+        };
+        self.inner.create(request).await
+    }
+
+    async fn update(
+        &self,
+        id: u32,
+        changes: UpdateTodoRequest,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Todo>, StorageError> {
+        if self.owned(id).await?.is_none() {
+            return Ok(None);
+        }
+        self.inner.update(id, changes, expected_revision).await
+    }
+
+    async fn replace(&self, todo: Todo) -> Result<Option<Todo>, StorageError> {
+        if self.owned(todo.id).await?.is_none() {
+            return Ok(None);
+        }
+        let todo = Todo {
+            owner_id: self.owner_id,
+            ..todo
+        };
+        self.inner.replace(todo).await
+    }
+
+    async fn delete(&self, id: u32, expected_revision: Option<u64>) -> Result<bool, StorageError> {
+        if self.owned(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.inner.delete(id, expected_revision).await
+    }
+
+    async fn trash(&self) -> Result<Vec<Todo>, StorageError> {
+        let mut trash = self.inner.trash().await?;
+        trash.retain(|todo| self.owns(todo));
+        Ok(trash)
+    }
+
+    async fn purge(&self, id: u32) -> Result<bool, StorageError> {
+        if !self.trash().await?.iter().any(|todo| todo.id == id) {
+            return Ok(false);
+        }
+        self.inner.purge(id).await
+    }
+
+    // One purge per todo, the trash of other users stays as it is
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<Vec<u32>, StorageError> {
+        let mut ids: Vec<u32> = self
+            .trash()
+            .await?
+            .iter()
+            .filter(|todo| todo.deleted_at.is_some_and(|at| at < cutoff))
+            .map(|todo| todo.id)
+            .collect();
+        ids.sort();
+        let mut purged = Vec::with_capacity(ids.len());
+        for id in ids {
+            if self.inner.purge(id).await? {
+                purged.push(id);
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn history(&self, id: u32) -> Result<Vec<TodoRevision>, StorageError> {
+        let entries = self.inner.history(id).await?;
+        if !self.owns_history(&entries) {
+            return Ok(Vec::new());
+        }
+        Ok(entries)
+    }
+
+    // Only todos the user has (or had) can be brought back
+    async fn restore(&self, todo: Todo) -> Result<Todo, StorageError> {
+        if !self.can_restore(todo.id).await? {
+            return Err(StorageError::NotOwned(todo.id));
+        }
+        let todo = Todo {
+            owner_id: self.owner_id,
+            ..todo
+        };
+        self.inner.restore(todo).await
+    }
+
+    // Deletes of todos known to be someone else's are left out. The
+    // tombstones of purged todos no longer say whose they were and are kept.
+    async fn changes_since(&self, since: u64, limit: usize) -> Result<ChangeSet, StorageError> {
+        let mut set = self.inner.changes_since(since, limit).await?;
+        set.upserts.retain(|todo| self.owns(todo));
+        if !set.deletes.is_empty() {
+            let others: Vec<u32> = self
+                .inner
+                .trash()
+                .await?
+                .iter()
+                .filter(|todo| !self.owns(todo))
+                .map(|todo| todo.id)
+                .collect();
+            set.deletes.retain(|id| !others.contains(id));
+        }
+        Ok(set)
+    }
+
+    async fn batch(&self, mut ops: Vec<BatchOperation>) -> Result<Vec<BatchOutcome>, BatchError> {
+        for (index, op) in ops.iter_mut().enumerate() {
+            match op {
+                BatchOperation::Create { todo } => todo.owner_id = Some(self.owner_id),
+                BatchOperation::Update { id, .. } | BatchOperation::Delete { id, .. } => {
+                    if self.owned(*id).await?.is_none() {
+                        return Err(BatchError::NotFound { index, id: *id });
+                    }
+                }
+                BatchOperation::Replace { todo } => {
+                    if self.owned(todo.id).await?.is_none() {
+                        return Err(BatchError::NotFound { index, id: todo.id });
+                    }
+                    todo.owner_id = self.owner_id;
+                }
+                BatchOperation::Restore { todo } => {
+                    if !self.can_restore(todo.id).await? {
+                        return Err(BatchError::NotFound { index, id: todo.id });
+                    }
+                    todo.owner_id = self.owner_id;
+                }
+            }
+        }
+        self.inner.batch(ops).await
+    }
+
+    async fn lists(&self) -> Result<Vec<TodoList>, StorageError> {
+        let mut lists = self.inner.lists().await?;
+        lists.retain(|list| self.sees_list(list));
+        Ok(lists)
+    }
+
+    async fn get_list(&self, id: u32) -> Result<Option<TodoList>, StorageError> {
+        Ok(self.inner.get_list(id).await?.filter(|list| self.sees_list(list)))
+    }
+
+    async fn create_list(&self, request: CreateListRequest) -> Result<TodoList, StorageError> {
+        let request = CreateListRequest {
+            owner_id: Some(self.owner_id),
+            ..request
+        };
+        self.inner.create_list(request).await
+    }
+
+    async fn update_list(
+        &self,
+        id: u32,
+        changes: UpdateListRequest,
+    ) -> Result<Option<TodoList>, StorageError> {
+        let owned = self.inner.get_list(id).await?;
+        if owned.is_none_or(|list| list.owner_id != self.owner_id) {
+            return Ok(None);
+        }
+        self.inner.update_list(id, changes).await
+    }
+
+    async fn delete_list(&self, id: u32) -> Result<bool, StorageError> {
+        let owned = self.inner.get_list(id).await?;
+        if owned.is_none_or(|list| list.owner_id != self.owner_id) {
+            return Ok(false);
+        }
+        self.inner.delete_list(id).await
+    }
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush().await
+    }
+}
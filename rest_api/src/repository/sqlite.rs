@@ -0,0 +1,848 @@
+use super::TodoRepository;
+use crate::error::{BatchError, StorageError};
+use crate::history::{HistoryRetention, RevisionOp, TodoRevision};
+use crate::models::{
+    BatchOperation, BatchOutcome, ChangeSet, CreateListRequest, CreateTodoRequest,
+    DEFAULT_LIST_ID, DueFilter, Todo, TodoList, TodoQuery, TodoSort, UpdateListRequest,
+    UpdateTodoRequest, today_bounds,
+};
+use crate::schema::{self, ChangeEntry, DataFile, Metadata};
+use crate::storage::{check_revision, trashed};
+use crate::writer::Durability;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use rusqlite::{Connection, OptionalExtension, params, params_from_iter, types::Value};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Todos, lists, history entries and trashed todos live in one table each,
+// one JSON document per row, so adding fields never needs a schema change.
+// `changes` holds the seq of the last change of every todo ever written,
+// `meta` counters such as the next todo id and the last change seq.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS todos (
+        id   INTEGER PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS lists (
+        id   INTEGER PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        seq     INTEGER PRIMARY KEY AUTOINCREMENT,
+        todo_id INTEGER NOT NULL,
+        at      TEXT NOT NULL,
+        data    TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS history_by_todo ON history (todo_id, seq);
+    CREATE TABLE IF NOT EXISTS trash (
+        id         INTEGER PRIMARY KEY,
+        deleted_at TEXT NOT NULL,
+        data       TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS changes (
+        todo_id INTEGER PRIMARY KEY,
+        seq     INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS changes_by_seq ON changes (seq);
+    CREATE TABLE IF NOT EXISTS meta (
+        key   TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );
+";
+
+pub struct SqliteRepository {
+    conn: Arc<Mutex<Connection>>,
+    retention: HistoryRetention,
+}
+
+impl SqliteRepository {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StorageError> {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        if read_list(&conn, DEFAULT_LIST_ID)?.is_none() {
+            write_list(&conn, &TodoList::default_list())?;
+        }
+        number_unsequenced(&conn)?;
+        Ok(SqliteRepository {
+            conn: Arc::new(Mutex::new(conn)),
+            retention: HistoryRetention::default(),
+        })
+    }
+
+    // Also prunes the history that is already stored
+    pub fn with_history_retention(
+        mut self,
+        retention: HistoryRetention,
+    ) -> Result<Self, StorageError> {
+        self.retention = retention;
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| StorageError::Unavailable("sqlite lock poisoned".to_string()))?;
+        conn.execute(
+            "DELETE FROM history WHERE seq NOT IN (
+                SELECT seq FROM (
+                    SELECT seq, ROW_NUMBER() OVER (PARTITION BY todo_id ORDER BY seq DESC) AS n
+                    FROM history
+                ) WHERE n <= ?1
+            )",
+            [retention.max_revisions as i64],
+        )?;
+        if let Some(cutoff) = retention.cutoff(Utc::now()) {
+            conn.execute(
+                "DELETE FROM history WHERE julianday(at) < julianday(?1)",
+                [cutoff.to_rfc3339()],
+            )?;
+        }
+        drop(conn);
+        Ok(self)
+    }
+
+    // SQLite writes in place, so there is nothing to debounce; what comes
+    // closest is to stop syncing the WAL on every commit. A crash can then
+    // lose the last commits, but never corrupts the database.
+    pub fn with_durability(self, durability: Durability) -> Result<Self, StorageError> {
+        let synchronous = match durability {
+            Durability::Sync => "FULL",
+            Durability::Debounced { .. } => "NORMAL",
+        };
+        self.conn
+            .lock()
+            .map_err(|_| StorageError::Unavailable("sqlite lock poisoned".to_string()))?
+            .pragma_update(None, "synchronous", synchronous)?;
+        Ok(self)
+    }
+
+    // rusqlite is blocking, so every query runs on the blocking thread pool
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| StorageError::Unavailable("sqlite lock poisoned".to_string()))?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|err| StorageError::Unavailable(err.to_string()))?
+    }
+}
+
+fn read_todo(conn: &Connection, id: u32) -> Result<Option<Todo>, StorageError> {
+    let data: Option<String> = conn
+        .query_row("SELECT data FROM todos WHERE id = ?1", [id], |row| row.get(0))
+        .optional()?;
+    match data {
+        Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+        None => Ok(None),
+    }
+}
+
+fn write_todo(conn: &Connection, todo: &Todo) -> Result<(), StorageError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO todos (id, data) VALUES (?1, ?2)",
+        params![todo.id, serde_json::to_string(todo)?],
+    )?;
+    Ok(())
+}
+
+// Highest id ever handed out + 1, so ids of deleted todos are never reused
+fn next_todo_id(conn: &Connection) -> Result<u32, StorageError> {
+    let id: u32 = conn.query_row(
+        "SELECT MAX(
+            COALESCE((SELECT MAX(id) FROM todos), 0) + 1,
+            COALESCE((SELECT value FROM meta WHERE key = 'next_todo_id'), 1)
+        )",
+        [],
+        |row| row.get(0),
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('next_todo_id', ?1)",
+        [id + 1],
+    )?;
+    Ok(id)
+}
+
+// Next number of the change sequence, recorded as the last change of `id`
+fn record_change(conn: &Connection, id: u32) -> Result<u64, StorageError> {
+    let seq: i64 = conn.query_row(
+        "SELECT COALESCE((SELECT value FROM meta WHERE key = 'change_seq'), 0) + 1",
+        [],
+        |row| row.get(0),
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('change_seq', ?1)",
+        [seq],
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO changes (todo_id, seq) VALUES (?1, ?2)",
+        params![id, seq],
+    )?;
+    Ok(seq as u64)
+}
+
+// Databases from before the change sequence: number the todos that are
+// there, so a sync from 0 still gets everything
+fn number_unsequenced(conn: &Connection) -> Result<(), StorageError> {
+    let ids = {
+        let mut stmt = conn.prepare(
+            "SELECT id FROM (SELECT id FROM todos UNION SELECT id FROM trash)
+             WHERE id NOT IN (SELECT todo_id FROM changes) ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| row.get::<_, u32>(0))?;
+        rows.collect::<Result<Vec<u32>, _>>()?
+    };
+    for id in ids {
+        record_change(conn, id)?;
+    }
+    Ok(())
+}
+
+// `todo` as `restore` writes it back: above every revision it had so far,
+// also in history and the trash; and the change doing so
+fn restored(tx: &Connection, mut todo: Todo) -> Result<(Todo, TodoRevision), StorageError> {
+    let current = read_todo(tx, todo.id)?;
+    let latest: Option<i64> = tx.query_row(
+        "SELECT MAX(revision) FROM (
+            SELECT json_extract(data, '$.revision') AS revision
+            FROM history WHERE todo_id = ?1
+            UNION ALL
+            SELECT json_extract(data, '$.revision') FROM trash WHERE id = ?1
+        )",
+        [todo.id],
+        |row| row.get(0),
+    )?;
+    let latest = latest
+        .map(|revision| revision as u64)
+        .into_iter()
+        .chain(current.as_ref().map(|t| t.revision))
+        .max()
+        .unwrap_or(todo.revision);
+
+    todo.revision = latest + 1;
+    todo.updated_at = Some(Utc::now());
+    todo.deleted_at = None;
+    let change = TodoRevision::new(RevisionOp::Restore, current, Some(todo.clone()));
+    Ok((todo, change))
+}
+
+// Write the todo side of a change and record it in the todo's history. A
+// delete moves the todo to the trash, writing it back takes it out again.
+fn apply_change(
+    conn: &Connection,
+    mut change: TodoRevision,
+    retention: HistoryRetention,
+) -> Result<(), StorageError> {
+    change.seq = record_change(conn, change.todo_id)?;
+    match (&change.before, &change.after) {
+        (_, Some(todo)) => {
+            write_todo(conn, todo)?;
+            conn.execute("DELETE FROM trash WHERE id = ?1", [change.todo_id])?;
+        }
+        (Some(todo), None) => {
+            conn.execute("DELETE FROM todos WHERE id = ?1", [change.todo_id])?;
+            conn.execute(
+                "INSERT OR REPLACE INTO trash (id, deleted_at, data) VALUES (?1, ?2, ?3)",
+                params![
+                    change.todo_id,
+                    change.at.to_rfc3339(),
+                    serde_json::to_string(&trashed(todo, change.at))?
+                ],
+            )?;
+        }
+        (None, None) => {}
+    }
+    if retention.max_revisions == 0 {
+        return Ok(());
+    }
+
+    conn.execute(
+        "INSERT INTO history (todo_id, at, data) VALUES (?1, ?2, ?3)",
+        params![change.todo_id, change.at.to_rfc3339(), serde_json::to_string(&change)?],
+    )?;
+    conn.execute(
+        "DELETE FROM history WHERE todo_id = ?1 AND seq NOT IN (
+            SELECT seq FROM history WHERE todo_id = ?1 ORDER BY seq DESC LIMIT ?2
+        )",
+        params![change.todo_id, retention.max_revisions as i64],
+    )?;
+    if let Some(cutoff) = retention.cutoff(Utc::now()) {
+        conn.execute(
+            "DELETE FROM history WHERE todo_id = ?1 AND julianday(at) < julianday(?2)",
+            params![change.todo_id, cutoff.to_rfc3339()],
+        )?;
+    }
+    Ok(())
+}
+
+// Remove trashed todos and their history for good
+fn purge_ids(conn: &Connection, ids: &[u32]) -> Result<(), StorageError> {
+    for id in ids {
+        conn.execute("DELETE FROM trash WHERE id = ?1", [id])?;
+        conn.execute("DELETE FROM history WHERE todo_id = ?1", [id])?;
+    }
+    Ok(())
+}
+
+// Every row of a table of JSON documents, in `query` order
+fn read_all<T: DeserializeOwned>(conn: &Connection, query: &str) -> Result<Vec<T>, StorageError> {
+    let mut stmt = conn.prepare(query)?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut all = Vec::new();
+    for data in rows {
+        all.push(serde_json::from_str(&data?)?);
+    }
+    Ok(all)
+}
+
+fn read_meta(conn: &Connection, key: &str) -> Result<u64, StorageError> {
+    let value: Option<i64> = conn
+        .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| row.get(0))
+        .optional()?;
+    Ok(value.unwrap_or(0) as u64)
+}
+
+fn read_list(conn: &Connection, id: u32) -> Result<Option<TodoList>, StorageError> {
+    let data: Option<String> = conn
+        .query_row("SELECT data FROM lists WHERE id = ?1", [id], |row| row.get(0))
+        .optional()?;
+    match data {
+        Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+        None => Ok(None),
+    }
+}
+
+fn write_list(conn: &Connection, list: &TodoList) -> Result<(), StorageError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO lists (id, data) VALUES (?1, ?2)",
+        params![list.id, serde_json::to_string(list)?],
+    )?;
+    Ok(())
+}
+
+#[async_trait]
+impl TodoRepository for SqliteRepository {
+    async fn list(&self) -> Result<Vec<Todo>, StorageError> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT data FROM todos ORDER BY id")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            let mut todos = Vec::new();
+            for data in rows {
+                todos.push(serde_json::from_str(&data?)?);
+            }
+            Ok(todos)
+        })
+        .await
+    }
+
+    async fn query(&self, query: &TodoQuery) -> Result<(Vec<Todo>, usize), StorageError> {
+        let query = query.clone();
+        self.with_conn(move |conn| {
+            let mut filters = Vec::new();
+            let mut values: Vec<Value> = Vec::new();
+            if let Some(owner_id) = query.owner_id {
+                // Rows written before accounts existed have no owner_id
+                filters.push("COALESCE(json_extract(data, '$.owner_id'), 1) = ?");
+                values.push(Value::Integer(owner_id as i64));
+            }
+            if let Some(list_id) = query.list_id {
+                // Rows written before lists existed have no list_id
+                filters.push("COALESCE(json_extract(data, '$.list_id'), 1) = ?");
+                values.push(Value::Integer(list_id as i64));
+            }
+            if let Some(completed) = query.completed {
+                filters.push("json_extract(data, '$.completed') = ?");
+                values.push(Value::Integer(completed as i64));
+            }
+            if let Some(q) = &query.q {
+                filters.push("instr(lower(json_extract(data, '$.title')), lower(?)) > 0");
+                values.push(Value::Text(q.clone()));
+            }
+            if let Some(tag) = &query.tag {
+                filters.push(
+                    "EXISTS (SELECT 1 FROM json_each(data, '$.tags') WHERE json_each.value = ?)",
+                );
+                values.push(Value::Text(tag.clone()));
+            }
+            // julianday() because RFC 3339 strings with different fractional
+            // second precision do not compare correctly as text
+            let now = Utc::now();
+            match query.due {
+                Some(DueFilter::Overdue) => {
+                    filters.push("json_extract(data, '$.completed') = 0");
+                    filters.push("julianday(json_extract(data, '$.due_at')) < julianday(?)");
+                    values.push(Value::Text(now.to_rfc3339()));
+                }
+                Some(DueFilter::Today) => {
+                    let (start, end) = today_bounds(now);
+                    filters.push("json_extract(data, '$.completed') = 0");
+                    filters.push("julianday(json_extract(data, '$.due_at')) >= julianday(?)");
+                    filters.push("julianday(json_extract(data, '$.due_at')) < julianday(?)");
+                    values.push(Value::Text(start.to_rfc3339()));
+                    values.push(Value::Text(end.to_rfc3339()));
+                }
+                None => {}
+            }
+            let where_clause = if filters.is_empty() {
+                String::new()
+            } else {
+                format!("WHERE {}", filters.join(" AND "))
+            };
+            let order_by = match query.sort {
+                TodoSort::IdAsc => "id ASC",
+                TodoSort::IdDesc => "id DESC",
+                TodoSort::TitleAsc => "json_extract(data, '$.title') ASC, id ASC",
+                TodoSort::TitleDesc => "json_extract(data, '$.title') DESC, id ASC",
+            };
+
+            let total: i64 = conn.query_row(
+                &format!("SELECT COUNT(*) FROM todos {}", where_clause),
+                params_from_iter(values.iter()),
+                |row| row.get(0),
+            )?;
+
+            values.push(Value::Integer(query.limit() as i64));
+            values.push(Value::Integer(query.offset as i64));
+            let mut stmt = conn.prepare(&format!(
+                "SELECT data FROM todos {} ORDER BY {} LIMIT ? OFFSET ?",
+                where_clause, order_by
+            ))?;
+            let rows = stmt.query_map(params_from_iter(values.iter()), |row| {
+                row.get::<_, String>(0)
+            })?;
+            let mut todos = Vec::new();
+            for data in rows {
+                todos.push(serde_json::from_str(&data?)?);
+            }
+            Ok((todos, total as usize))
+        })
+        .await
+    }
+
+    async fn get(&self, id: u32) -> Result<Option<Todo>, StorageError> {
+        self.with_conn(move |conn| read_todo(conn, id)).await
+    }
+
+    async fn create(&self, request: CreateTodoRequest) -> Result<Todo, StorageError> {
+        let retention = self.retention;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let todo = Todo::new(next_todo_id(&tx)?, request);
+            let change = TodoRevision::new(RevisionOp::Create, None, Some(todo.clone()));
+            apply_change(&tx, change, retention)?;
+            tx.commit()?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn update(
+        &self,
+        id: u32,
+        changes: UpdateTodoRequest,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Todo>, StorageError> {
+        let retention = self.retention;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let Some(current) = read_todo(&tx, id)? else {
+                return Ok(None);
+            };
+            check_revision(&current, expected_revision)?;
+            let mut todo = current.clone();
+            todo.apply(changes);
+            todo.revision += 1;
+            let change = TodoRevision::new(RevisionOp::Update, Some(current), Some(todo.clone()));
+            apply_change(&tx, change, retention)?;
+            tx.commit()?;
+            Ok(Some(todo))
+        })
+        .await
+    }
+
+    async fn replace(&self, mut todo: Todo) -> Result<Option<Todo>, StorageError> {
+        let retention = self.retention;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let Some(current) = read_todo(&tx, todo.id)? else {
+                return Ok(None);
+            };
+            check_revision(&current, Some(todo.revision))?;
+            todo.revision += 1;
+            let change = TodoRevision::new(RevisionOp::Update, Some(current), Some(todo.clone()));
+            apply_change(&tx, change, retention)?;
+            tx.commit()?;
+            Ok(Some(todo))
+        })
+        .await
+    }
+
+    async fn delete(&self, id: u32, expected_revision: Option<u64>) -> Result<bool, StorageError> {
+        let retention = self.retention;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let Some(current) = read_todo(&tx, id)? else {
+                return Ok(false);
+            };
+            check_revision(&current, expected_revision)?;
+            let change = TodoRevision::new(RevisionOp::Delete, Some(current), None);
+            apply_change(&tx, change, retention)?;
+            tx.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn trash(&self) -> Result<Vec<Todo>, StorageError> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT data FROM trash")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            let mut todos: Vec<Todo> = Vec::new();
+            for data in rows {
+                todos.push(serde_json::from_str(&data?)?);
+            }
+            // Sorted here, julianday() drops everything below milliseconds
+            todos.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(a.id.cmp(&b.id)));
+            Ok(todos)
+        })
+        .await
+    }
+
+    async fn purge(&self, id: u32) -> Result<bool, StorageError> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let trashed: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM trash WHERE id = ?1)",
+                [id],
+                |row| row.get(0),
+            )?;
+            if !trashed {
+                return Ok(false);
+            }
+            purge_ids(&tx, &[id])?;
+            tx.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<Vec<u32>, StorageError> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let ids = {
+                let mut stmt = tx.prepare(
+                    "SELECT id FROM trash WHERE julianday(deleted_at) < julianday(?1) ORDER BY id",
+                )?;
+                let rows = stmt.query_map([cutoff.to_rfc3339()], |row| row.get::<_, u32>(0))?;
+                rows.collect::<Result<Vec<u32>, _>>()?
+            };
+            purge_ids(&tx, &ids)?;
+            tx.commit()?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    async fn history(&self, id: u32) -> Result<Vec<TodoRevision>, StorageError> {
+        self.with_conn(move |conn| {
+            let mut stmt =
+                conn.prepare("SELECT data FROM history WHERE todo_id = ?1 ORDER BY seq")?;
+            let rows = stmt.query_map([id], |row| row.get::<_, String>(0))?;
+            let mut entries = Vec::new();
+            for data in rows {
+                entries.push(serde_json::from_str(&data?)?);
+            }
+            Ok(entries)
+        })
+        .await
+    }
+
+    async fn restore(&self, todo: Todo) -> Result<Todo, StorageError> {
+        let retention = self.retention;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let (todo, change) = restored(&tx, todo)?;
+            apply_change(&tx, change, retention)?;
+            tx.commit()?;
+            Ok(todo)
+        })
+        .await
+    }
+
+    async fn changes_since(&self, since: u64, limit: usize) -> Result<ChangeSet, StorageError> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let mut stmt = tx.prepare(
+                "SELECT changes.todo_id, changes.seq, todos.data FROM changes
+                 LEFT JOIN todos ON todos.id = changes.todo_id
+                 WHERE changes.seq > ?1 ORDER BY changes.seq LIMIT ?2",
+            )?;
+            let rows = stmt.query_map(params![since as i64, limit as i64 + 1], |row| {
+                let seq: i64 = row.get(1)?;
+                Ok((row.get::<_, u32>(0)?, seq as u64, row.get::<_, Option<String>>(2)?))
+            })?;
+            let mut changed = rows.collect::<Result<Vec<_>, _>>()?;
+            let has_more = changed.len() > limit;
+            changed.truncate(limit);
+
+            let seq = match changed.last() {
+                Some((_, seq, _)) => *seq,
+                None => tx
+                    .query_row("SELECT value FROM meta WHERE key = 'change_seq'", [], |row| {
+                        row.get::<_, i64>(0)
+                    })
+                    .optional()?
+                    .map_or(0, |seq| seq as u64)
+                    .max(since),
+            };
+            let mut set = ChangeSet {
+                seq,
+                upserts: Vec::new(),
+                deletes: Vec::new(),
+                has_more,
+            };
+            for (id, _, data) in changed {
+                match data {
+                    Some(data) => set.upserts.push(serde_json::from_str(&data)?),
+                    None => set.deletes.push(id),
+                }
+            }
+            Ok(set)
+        })
+        .await
+    }
+
+    async fn batch(&self, ops: Vec<BatchOperation>) -> Result<Vec<BatchOutcome>, BatchError> {
+        let retention = self.retention;
+        // Dropping the transaction without committing rolls everything back
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let mut outcomes = Vec::with_capacity(ops.len());
+            for (index, op) in ops.into_iter().enumerate() {
+                let current = |id: u32, expected: Option<u64>| -> Result<Todo, BatchError> {
+                    let Some(todo) = read_todo(&tx, id)? else {
+                        return Err(BatchError::NotFound { index, id });
+                    };
+                    match expected {
+                        Some(revision) if revision != todo.revision => {
+                            Err(BatchError::RevisionMismatch { index, id, current: todo.revision })
+                        }
+                        _ => Ok(todo),
+                    }
+                };
+                let (change, outcome) = match op {
+                    BatchOperation::Create { mut todo } => {
+                        todo.resolve_parent(&outcomes);
+                        let todo = Todo::new(next_todo_id(&tx)?, todo);
+                        let change =
+                            TodoRevision::new(RevisionOp::Create, None, Some(todo.clone()));
+                        (change, BatchOutcome::Created(todo))
+                    }
+                    BatchOperation::Update { id, changes, revision } => {
+                        let before = match current(id, revision) {
+                            Ok(todo) => todo,
+                            Err(err) => return Ok(Err(err)),
+                        };
+                        let mut todo = before.clone();
+                        todo.apply(changes);
+                        todo.revision += 1;
+                        let change =
+                            TodoRevision::new(RevisionOp::Update, Some(before), Some(todo.clone()));
+                        (change, BatchOutcome::Updated(todo))
+                    }
+                    BatchOperation::Delete { id, revision } => {
+                        let before = match current(id, revision) {
+                            Ok(todo) => todo,
+                            Err(err) => return Ok(Err(err)),
+                        };
+                        let change = TodoRevision::new(RevisionOp::Delete, Some(before), None);
+                        (change, BatchOutcome::Deleted(id))
+                    }
+                    BatchOperation::Replace { mut todo } => {
+                        let before = match current(todo.id, Some(todo.revision)) {
+                            Ok(todo) => todo,
+                            Err(err) => return Ok(Err(err)),
+                        };
+                        todo.revision += 1;
+                        let change =
+                            TodoRevision::new(RevisionOp::Update, Some(before), Some(todo.clone()));
+                        (change, BatchOutcome::Updated(todo))
+                    }
+                    BatchOperation::Restore { todo } => {
+                        let (todo, change) = restored(&tx, todo)?;
+                        // Back from the trash is a create for anyone watching
+                        match change.before {
+                            Some(_) => (change, BatchOutcome::Updated(todo)),
+                            None => (change, BatchOutcome::Created(todo)),
+                        }
+                    }
+                };
+                apply_change(&tx, change, retention)?;
+                outcomes.push(outcome);
+            }
+            tx.commit()?;
+            Ok(Ok(outcomes))
+        })
+        .await?
+    }
+
+    async fn lists(&self) -> Result<Vec<TodoList>, StorageError> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT data FROM lists ORDER BY id")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            let mut lists = Vec::new();
+            for data in rows {
+                lists.push(serde_json::from_str(&data?)?);
+            }
+            Ok(lists)
+        })
+        .await
+    }
+
+    async fn get_list(&self, id: u32) -> Result<Option<TodoList>, StorageError> {
+        self.with_conn(move |conn| read_list(conn, id)).await
+    }
+
+    async fn create_list(&self, request: CreateListRequest) -> Result<TodoList, StorageError> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let id: u32 = tx.query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM lists", [], |row| {
+                row.get(0)
+            })?;
+            let list = TodoList::new(id, request);
+            write_list(&tx, &list)?;
+            tx.commit()?;
+            Ok(list)
+        })
+        .await
+    }
+
+    async fn update_list(
+        &self,
+        id: u32,
+        changes: UpdateListRequest,
+    ) -> Result<Option<TodoList>, StorageError> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let Some(mut list) = read_list(&tx, id)? else {
+                return Ok(None);
+            };
+            list.apply(changes);
+            write_list(&tx, &list)?;
+            tx.commit()?;
+            Ok(Some(list))
+        })
+        .await
+    }
+
+    async fn delete_list(&self, id: u32) -> Result<bool, StorageError> {
+        self.with_conn(move |conn| {
+            let deleted = conn.execute("DELETE FROM lists WHERE id = ?1", [id])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn snapshot(&self) -> Result<DataFile, StorageError> {
+        self.with_conn(|conn| {
+            let todos: Vec<Todo> = read_all(conn, "SELECT data FROM todos ORDER BY id")?;
+            let lists: Vec<TodoList> = read_all(conn, "SELECT data FROM lists ORDER BY id")?;
+            let trash: Vec<Todo> = read_all(conn, "SELECT data FROM trash ORDER BY id")?;
+            let changes = {
+                let mut stmt = conn.prepare("SELECT todo_id, seq FROM changes ORDER BY seq")?;
+                let rows = stmt.query_map([], |row| {
+                    Ok(ChangeEntry {
+                        todo_id: row.get(0)?,
+                        seq: row.get::<_, i64>(1)? as u64,
+                    })
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+            let highest = todos.iter().chain(&trash).map(|todo| todo.id).max().unwrap_or(0);
+            Ok(DataFile {
+                schema_version: schema::CURRENT_VERSION,
+                metadata: Metadata {
+                    saved_at: None,
+                    next_id: (read_meta(conn, "next_todo_id")? as u32).max(highest + 1),
+                    next_list_id: lists.iter().map(|list| list.id + 1).max().unwrap_or(1),
+                    change_seq: read_meta(conn, "change_seq")?,
+                },
+                history: read_all(conn, "SELECT data FROM history ORDER BY seq")?,
+                todos,
+                lists,
+                trash,
+                changes,
+            })
+        })
+        .await
+    }
+
+    async fn restore_snapshot(&self, snapshot: DataFile) -> Result<(), StorageError> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let mut touched: Vec<u32> = {
+                let mut stmt = tx.prepare("SELECT todo_id FROM changes")?;
+                let rows = stmt.query_map([], |row| row.get::<_, u32>(0))?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+            touched.extend(snapshot.changes.iter().map(|entry| entry.todo_id));
+            touched.extend(snapshot.todos.iter().chain(&snapshot.trash).map(|todo| todo.id));
+            touched.sort();
+            touched.dedup();
+            let next_id = read_meta(&tx, "next_todo_id")?
+                .max(snapshot.metadata.next_id as u64)
+                .max(touched.last().map_or(1, |id| *id as u64 + 1));
+            let change_seq = read_meta(&tx, "change_seq")?.max(snapshot.metadata.change_seq);
+
+            tx.execute_batch(
+                "DELETE FROM todos; DELETE FROM lists; DELETE FROM history;
+                 DELETE FROM trash; DELETE FROM changes;",
+            )?;
+            for todo in &snapshot.todos {
+                write_todo(&tx, todo)?;
+            }
+            for list in &snapshot.lists {
+                write_list(&tx, list)?;
+            }
+            if read_list(&tx, DEFAULT_LIST_ID)?.is_none() {
+                write_list(&tx, &TodoList::default_list())?;
+            }
+            for todo in &snapshot.trash {
+                tx.execute(
+                    "INSERT INTO trash (id, deleted_at, data) VALUES (?1, ?2, ?3)",
+                    params![
+                        todo.id,
+                        todo.deleted_at.unwrap_or_else(Utc::now).to_rfc3339(),
+                        serde_json::to_string(todo)?
+                    ],
+                )?;
+            }
+            for entry in &snapshot.history {
+                tx.execute(
+                    "INSERT INTO history (todo_id, at, data) VALUES (?1, ?2, ?3)",
+                    params![entry.todo_id, entry.at.to_rfc3339(), serde_json::to_string(entry)?],
+                )?;
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES
+                    ('next_todo_id', ?1), ('change_seq', ?2)",
+                params![next_id as i64, change_seq as i64],
+            )?;
+            for id in touched {
+                record_change(&tx, id)?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,256 @@
+use super::TodoRepository;
+use crate::encryption::Cipher;
+use crate::error::{BatchError, StorageError};
+use crate::history::{HistoryRetention, TodoRevision};
+use crate::models::{
+    BatchOperation, BatchOutcome, ChangeSet, CreateListRequest, CreateTodoRequest, Todo, TodoList,
+    UpdateListRequest, UpdateTodoRequest,
+};
+use crate::schema::DataFile;
+use crate::storage::{Staged, Storage};
+use crate::writer::Durability;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// Repository on top of `Storage`: either the JSON snapshot + journal on
+// disk, or the same storage without any backing file for tests. Reads share
+// the lock. Writes run one at a time on the blocking threads and hold the
+// lock alone only while they change memory; the journal write (and its
+// fsync with `Durability::Sync`) happens after the lock is released. A
+// write that cannot be journaled is undone, so reads may briefly see a
+// write that is about to fail, but never one that stays lost.
+pub struct StorageRepository {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    storage: RwLock<Storage>,
+    // Held by a write from changing memory until its record is written
+    writing: Mutex<()>,
+}
+
+impl StorageRepository {
+    pub fn json_file(data_file: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Ok(StorageRepository::new(Storage::open(data_file, None)?))
+    }
+
+    // Snapshot and journal encrypted with `cipher`
+    pub fn encrypted_json_file(
+        data_file: impl AsRef<Path>,
+        cipher: Cipher,
+    ) -> Result<Self, StorageError> {
+        Ok(StorageRepository::new(Storage::open(data_file, Some(cipher))?))
+    }
+
+    pub fn in_memory() -> Self {
+        StorageRepository::new(Storage::in_memory())
+    }
+
+    fn new(storage: Storage) -> Self {
+        StorageRepository {
+            shared: Arc::new(Shared {
+                storage: RwLock::new(storage),
+                writing: Mutex::new(()),
+            }),
+        }
+    }
+
+    pub fn with_history_retention(
+        self,
+        retention: HistoryRetention,
+    ) -> Result<Self, StorageError> {
+        self.shared.write()?.set_history_retention(retention);
+        Ok(self)
+    }
+
+    pub fn with_durability(self, durability: Durability) -> Result<Self, StorageError> {
+        self.shared.write()?.set_durability(durability);
+        Ok(self)
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, Storage>, StorageError> {
+        self.shared.read()
+    }
+
+    // Runs `mutation` and writes its record, see `Shared::commit`
+    async fn commit<T, E>(
+        &self,
+        mutation: impl FnOnce(&mut Storage) -> Result<Staged<T>, E> + Send + 'static,
+    ) -> Result<T, E>
+    where
+        T: Send + 'static,
+        E: From<StorageError> + Send + 'static,
+    {
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || shared.commit(mutation))
+            .await
+            .map_err(|err| StorageError::Unavailable(err.to_string()))?
+    }
+}
+
+impl Shared {
+    // A poisoned lock means a previous request panicked mid-mutation;
+    // report it as 503 instead of taking every following request down with it
+    fn read(&self) -> Result<RwLockReadGuard<'_, Storage>, StorageError> {
+        self.storage.read().map_err(|_| poisoned())
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, Storage>, StorageError> {
+        self.storage.write().map_err(|_| poisoned())
+    }
+
+    // Blocks. The journal is written in the same order memory was changed,
+    // and a compaction snapshot holds exactly what was journaled, because
+    // `writing` keeps other writes out until the record is on disk.
+    fn commit<T, E: From<StorageError>>(
+        &self,
+        mutation: impl FnOnce(&mut Storage) -> Result<Staged<T>, E>,
+    ) -> Result<T, E> {
+        let _writing = self.writing.lock().map_err(|_| poisoned())?;
+        let (staged, disk) = {
+            let mut storage = self.write()?;
+            (mutation(&mut storage)?, storage.disk())
+        };
+        let (Some(pending), Some(disk)) = (staged.pending, disk) else {
+            return Ok(staged.value);
+        };
+        match disk.append(pending) {
+            Ok(false) => {}
+            Ok(true) => {
+                let snapshot = self.read()?.snapshot();
+                if let Err(err) = disk.compact(snapshot) {
+                    crate::warn!("⚠️  Journal compaction failed, will retry: {}", err);
+                }
+            }
+            Err((err, undo)) => {
+                self.write()?.undo(undo);
+                return Err(err.into());
+            }
+        }
+        Ok(staged.value)
+    }
+}
+
+#[async_trait]
+impl TodoRepository for StorageRepository {
+    async fn list(&self) -> Result<Vec<Todo>, StorageError> {
+        Ok(self.read()?.get_all())
+    }
+
+    async fn get(&self, id: u32) -> Result<Option<Todo>, StorageError> {
+        Ok(self.read()?.get_by_id(id))
+    }
+
+    async fn create(&self, request: CreateTodoRequest) -> Result<Todo, StorageError> {
+        self.commit(move |storage| storage.create(request)).await
+    }
+
+    async fn update(
+        &self,
+        id: u32,
+        changes: UpdateTodoRequest,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Todo>, StorageError> {
+        self.commit(move |storage| storage.update(id, changes, expected_revision)).await
+    }
+
+    async fn replace(&self, todo: Todo) -> Result<Option<Todo>, StorageError> {
+        self.commit(move |storage| storage.replace(todo)).await
+    }
+
+    async fn delete(&self, id: u32, expected_revision: Option<u64>) -> Result<bool, StorageError> {
+        self.commit(move |storage| storage.delete(id, expected_revision)).await
+    }
+
+    async fn trash(&self) -> Result<Vec<Todo>, StorageError> {
+        let mut trash = self.read()?.get_trash();
+        trash.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(a.id.cmp(&b.id)));
+        Ok(trash)
+    }
+
+    async fn purge(&self, id: u32) -> Result<bool, StorageError> {
+        self.commit(move |storage| storage.purge(id)).await
+    }
+
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<Vec<u32>, StorageError> {
+        self.commit(move |storage| storage.purge_deleted_before(cutoff)).await
+    }
+
+    async fn history(&self, id: u32) -> Result<Vec<TodoRevision>, StorageError> {
+        Ok(self.read()?.history(id))
+    }
+
+    async fn restore(&self, todo: Todo) -> Result<Todo, StorageError> {
+        self.commit(move |storage| storage.restore(todo)).await
+    }
+
+    async fn changes_since(&self, since: u64, limit: usize) -> Result<ChangeSet, StorageError> {
+        Ok(self.read()?.changes_since(since, limit))
+    }
+
+    async fn batch(&self, ops: Vec<BatchOperation>) -> Result<Vec<BatchOutcome>, BatchError> {
+        self.commit(move |storage| storage.batch(ops)).await
+    }
+
+    async fn lists(&self) -> Result<Vec<TodoList>, StorageError> {
+        let mut lists = self.read()?.get_lists();
+        lists.sort_by_key(|list| list.id);
+        Ok(lists)
+    }
+
+    async fn get_list(&self, id: u32) -> Result<Option<TodoList>, StorageError> {
+        Ok(self.read()?.get_list(id))
+    }
+
+    async fn create_list(&self, request: CreateListRequest) -> Result<TodoList, StorageError> {
+        self.commit(move |storage| storage.create_list(request)).await
+    }
+
+    async fn update_list(
+        &self,
+        id: u32,
+        changes: UpdateListRequest,
+    ) -> Result<Option<TodoList>, StorageError> {
+        self.commit(move |storage| storage.update_list(id, changes)).await
+    }
+
+    async fn delete_list(&self, id: u32) -> Result<bool, StorageError> {
+        self.commit(move |storage| storage.delete_list(id)).await
+    }
+
+    // The wait for the background writer happens off the async threads
+    async fn flush(&self) -> Result<(), StorageError> {
+        let Some(disk) = self.read()?.disk() else {
+            return Ok(());
+        };
+        let Some(flush) = disk.flusher()? else {
+            return Ok(());
+        };
+        tokio::task::spawn_blocking(flush)
+            .await
+            .map_err(|err| StorageError::Unavailable(err.to_string()))?
+    }
+
+    async fn snapshot(&self) -> Result<DataFile, StorageError> {
+        Ok(self.read()?.snapshot())
+    }
+
+    // Writes the new todos.json while holding the lock, as nothing else
+    // may be written in between
+    async fn restore_snapshot(&self, snapshot: DataFile) -> Result<(), StorageError> {
+        self.commit(move |storage| {
+            storage.restore_snapshot(snapshot)?;
+            Ok::<_, StorageError>(Staged {
+                value: (),
+                pending: None,
+            })
+        })
+        .await
+    }
+}
+
+fn poisoned() -> StorageError {
+    StorageError::Unavailable("storage lock poisoned".to_string())
+}
@@ -0,0 +1,88 @@
+use crate::auth::Accounts;
+use crate::backup::Backups;
+use crate::events::ChangeFeed;
+use crate::handlers::{self, AppState};
+use crate::logging;
+use crate::openapi;
+use crate::repository::EventedRepository;
+use axum::{
+    Extension, Router, middleware,
+    routing::{delete, get, patch, post, put},
+};
+use std::sync::Arc;
+
+// Without `accounts` there is no authentication and everyone sees every
+// todo; with them every request needs an API key and sees only its user's.
+// Without `backups` the /admin/backups routes answer 403. /openapi.json and
+// /docs need no key.
+pub fn create_routes(
+    state: AppState,
+    accounts: Option<Arc<Accounts>>,
+    backups: Option<Arc<Backups>>,
+) -> Router {
+    // Every write made through the handlers shows up on GET /todos/events
+    let feed = Arc::new(ChangeFeed::new());
+    let state: AppState = Arc::new(EventedRepository::new(state, feed.clone()));
+
+    let router = Router::new()
+        .route("/todos", get(handlers::get_todos))
+        .route("/todos", post(handlers::create_todo))
+        .route("/todos/batch", post(handlers::batch_todos))
+        .route("/todos.ics", get(handlers::todos_calendar))
+        .route("/todos/export", get(handlers::export_todos))
+        .route("/todos/import", post(handlers::import_todos))
+        .route("/todos/changes", get(handlers::get_changes))
+        .route("/todos/changes", post(handlers::push_changes))
+        .route("/todos/events", get(handlers::todo_events))
+        .route("/todos/overdue", get(handlers::get_overdue_todos))
+        .route("/todos/due-today", get(handlers::get_due_today_todos))
+        .route("/todos/tags/:tag", get(handlers::get_todos_by_tag))
+        .route("/todos/:id", get(handlers::get_todo))
+        .route("/todos/:id", put(handlers::update_todo))
+        .route("/todos/:id", patch(handlers::patch_todo))
+        .route("/todos/:id", delete(handlers::delete_todo))
+        .route("/todos/:id/children", get(handlers::get_children))
+        .route("/todos/:id/tree", get(handlers::get_tree))
+        .route("/todos/:id/move", post(handlers::move_todo))
+        .route("/todos/:id/history", get(handlers::get_history))
+        .route("/todos/:id/occurrences", get(handlers::get_occurrences))
+        .route("/todos/:id/restore", post(handlers::restore_todo))
+        .route("/trash", get(handlers::get_trash))
+        .route("/trash", delete(handlers::empty_trash))
+        .route("/trash/:id", delete(handlers::purge_todo))
+        .route("/trash/:id/restore", post(handlers::restore_from_trash))
+        // List routes, /todos above works on the default list
+        .route("/lists", get(handlers::get_lists))
+        .route("/lists", post(handlers::create_list))
+        .route("/lists/:list_id", get(handlers::get_list))
+        .route("/lists/:list_id", put(handlers::update_list))
+        .route("/lists/:list_id", delete(handlers::delete_list))
+        .route("/lists/:list_id/todos", get(handlers::get_list_todos))
+        .route("/lists/:list_id/todos", post(handlers::create_list_todo))
+        .route("/lists/:list_id/todos/:id", get(handlers::get_list_todo))
+        .route("/lists/:list_id/todos/:id", put(handlers::update_list_todo))
+        .route("/lists/:list_id/todos/:id", patch(handlers::patch_list_todo))
+        .route("/lists/:list_id/todos/:id", delete(handlers::delete_list_todo))
+        // Accounts
+        .route("/me", get(handlers::get_me))
+        .route("/admin/users", get(handlers::get_users))
+        .route("/admin/users", post(handlers::create_user))
+        .route("/admin/users/:user_id/keys", post(handlers::create_key))
+        .route("/admin/keys/:key_id", delete(handlers::revoke_key))
+        .route("/admin/backups", get(handlers::get_backups))
+        .route("/admin/backups", post(handlers::create_backup))
+        .route("/admin/backups/:name/restore", post(handlers::restore_backup))
+        .layer(Extension(feed));
+    let router = match accounts {
+        Some(accounts) => router.layer(Extension(accounts)),
+        None => router,
+    };
+    let router = match backups {
+        Some(backups) => router.layer(Extension(backups)),
+        None => router,
+    };
+    router
+        .merge(openapi::docs())
+        .layer(middleware::from_fn(logging::log_requests))
+        .with_state(state)
+}
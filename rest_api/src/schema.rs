@@ -0,0 +1,189 @@
+use crate::history::TodoRevision;
+use crate::models::{Todo, TodoList};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+// On-disk format of todos.json.
+//
+//   v1: bare array of todos (everything written before versioning)
+//   v2: `{ "schema_version": 2, "metadata": {...}, "todos": [...] }`
+//   v3: adds `lists` and `metadata.next_list_id`
+//   v4: adds `history`, the recorded revisions of every todo
+//   v5: adds `trash`, deleted todos that can still be restored
+//   v6: adds `changes` and `metadata.change_seq` for delta sync
+//
+// Bump CURRENT_VERSION and push one step onto MIGRATIONS for every change.
+pub const CURRENT_VERSION: u32 = 6;
+
+// MIGRATIONS[i] upgrades a document from version i + 1 to version i + 2
+type Step = fn(Value) -> Result<Value, String>;
+const MIGRATIONS: &[Step] = &[v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DataFile {
+    pub schema_version: u32,
+    pub metadata: Metadata,
+    pub todos: Vec<Todo>,
+    pub lists: Vec<TodoList>,
+    // Oldest first
+    pub history: Vec<TodoRevision>,
+    // Deleted todos, `deleted_at` set
+    pub trash: Vec<Todo>,
+    // Last change of every todo ever written, deleted ones included
+    pub changes: Vec<ChangeEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Metadata {
+    pub saved_at: Option<DateTime<Utc>>,
+    // Kept so ids of deleted todos/lists are never handed out again
+    pub next_id: u32,
+    pub next_list_id: u32,
+    // Last number handed out in the change sequence
+    pub change_seq: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ChangeEntry {
+    pub todo_id: u32,
+    pub seq: u64,
+}
+
+impl DataFile {
+    // What a missing todos.json is equivalent to
+    pub fn empty() -> Self {
+        DataFile {
+            schema_version: CURRENT_VERSION,
+            metadata: Metadata {
+                saved_at: None,
+                next_id: 1,
+                next_list_id: 1,
+                change_seq: 0,
+            },
+            todos: Vec::new(),
+            lists: Vec::new(),
+            history: Vec::new(),
+            trash: Vec::new(),
+            changes: Vec::new(),
+        }
+    }
+}
+
+// The file was upgraded while loading
+#[derive(Debug)]
+pub struct Migration {
+    pub from: u32,
+    pub to: u32,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    // Not JSON, unknown layout or a migration step failed
+    Unreadable(String),
+    // Written by a newer version of the server, we must not touch it
+    TooNew(u32),
+}
+
+pub fn parse(data: &str) -> Result<(DataFile, Option<Migration>), LoadError> {
+    let mut value: Value =
+        serde_json::from_str(data).map_err(|err| LoadError::Unreadable(err.to_string()))?;
+    let from = detect_version(&value).map_err(LoadError::Unreadable)?;
+    if from > CURRENT_VERSION {
+        return Err(LoadError::TooNew(from));
+    }
+
+    for version in from..CURRENT_VERSION {
+        let step = MIGRATIONS[(version - 1) as usize];
+        value = step(value)
+            .map_err(|err| LoadError::Unreadable(format!("migration v{}: {}", version, err)))?;
+    }
+
+    let file: DataFile =
+        serde_json::from_value(value).map_err(|err| LoadError::Unreadable(err.to_string()))?;
+    let migration = (from < CURRENT_VERSION).then_some(Migration {
+        from,
+        to: CURRENT_VERSION,
+    });
+    Ok((file, migration))
+}
+
+// Stamps the current version and save time
+pub fn encode(file: &mut DataFile) -> serde_json::Result<String> {
+    file.schema_version = CURRENT_VERSION;
+    file.metadata.saved_at = Some(Utc::now());
+    serde_json::to_string_pretty(file)
+}
+
+fn detect_version(value: &Value) -> Result<u32, String> {
+    match value {
+        Value::Array(_) => Ok(1),
+        Value::Object(object) => object
+            .get("schema_version")
+            .and_then(Value::as_u64)
+            .filter(|version| *version >= 1)
+            .map(|version| version as u32)
+            .ok_or_else(|| "missing or invalid schema_version".to_string()),
+        _ => Err("expected an array or an object".to_string()),
+    }
+}
+
+// Wrap the bare array into the envelope
+fn v1_to_v2(value: Value) -> Result<Value, String> {
+    let Value::Array(todos) = value else {
+        return Err("v1 file must be an array".to_string());
+    };
+    let max_id = todos
+        .iter()
+        .filter_map(|todo| todo.get("id").and_then(Value::as_u64))
+        .max()
+        .unwrap_or(0);
+
+    Ok(json!({
+        "schema_version": 2,
+        "metadata": { "saved_at": null, "next_id": max_id + 1 },
+        "todos": todos,
+    }))
+}
+
+// Add the (still empty) list collection; the default list is created on load
+fn v2_to_v3(mut value: Value) -> Result<Value, String> {
+    let object = value.as_object_mut().ok_or("v2 file must be an object")?;
+    object.insert("schema_version".to_string(), json!(3));
+    object.insert("lists".to_string(), json!([]));
+    object
+        .get_mut("metadata")
+        .and_then(Value::as_object_mut)
+        .ok_or("v2 file has no metadata")?
+        .insert("next_list_id".to_string(), json!(1));
+    Ok(value)
+}
+
+// Start with an empty history
+fn v3_to_v4(mut value: Value) -> Result<Value, String> {
+    let object = value.as_object_mut().ok_or("v3 file must be an object")?;
+    object.insert("schema_version".to_string(), json!(4));
+    object.insert("history".to_string(), json!([]));
+    Ok(value)
+}
+
+// Start with an empty trash
+fn v4_to_v5(mut value: Value) -> Result<Value, String> {
+    let object = value.as_object_mut().ok_or("v4 file must be an object")?;
+    object.insert("schema_version".to_string(), json!(5));
+    object.insert("trash".to_string(), json!([]));
+    Ok(value)
+}
+
+// Start the change sequence; the todos already there get numbered on load
+fn v5_to_v6(mut value: Value) -> Result<Value, String> {
+    let object = value.as_object_mut().ok_or("v5 file must be an object")?;
+    object.insert("schema_version".to_string(), json!(6));
+    object.insert("changes".to_string(), json!([]));
+    object
+        .get_mut("metadata")
+        .and_then(Value::as_object_mut)
+        .ok_or("v5 file has no metadata")?
+        .insert("change_seq".to_string(), json!(0));
+    Ok(value)
+}
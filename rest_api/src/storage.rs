@@ -1,89 +1,987 @@
-use crate::models::Todo;
-use serde_json;
-use std::collections::HashMap;
+use crate::encryption::{self, Cipher};
+use crate::error::{BatchError, StorageError};
+use crate::history::{HistoryRetention, RevisionOp, TodoRevision};
+use crate::journal::Journal;
+use crate::models::{
+    BatchOperation, BatchOutcome, ChangeSet, CreateListRequest, CreateTodoRequest,
+    DEFAULT_LIST_ID, Todo, TodoList, UpdateListRequest, UpdateTodoRequest,
+};
+use crate::schema::{self, ChangeEntry, DataFile, LoadError, Metadata};
+use crate::writer::{Durability, JournalWriter};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::fs;
-use std::io;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-const DATA_FILE: &str = "todos.json";
+// Compact the journal into a fresh snapshot after this many records
+const COMPACT_EVERY: usize = 64;
+
+// One mutation in the journal. All records are idempotent, so replaying a
+// journal that was already folded into the snapshot (crash between writing
+// the snapshot and clearing the journal) gives the same result.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JournalRecord {
+    // A todo mutation together with its history entry; boxed as it holds
+    // two todos
+    Change { change: Box<TodoRevision> },
+    // Todo mutations of journals written before history was recorded
+    Put { todo: Todo },
+    Delete { id: u32 },
+    PutList { list: TodoList },
+    DeleteList { id: u32 },
+    // Everything a batch did, in one line so it is replayed all or nothing
+    Batch { records: Vec<JournalRecord> },
+    // Trashed todos deleted for good, together with their history
+    Purge { ids: Vec<u32> },
+}
+
+// todos.json, encrypted when there is a cipher
+#[derive(Debug, Clone)]
+pub struct SnapshotFile {
+    pub path: PathBuf,
+    pub cipher: Option<Cipher>,
+}
+
+// Snapshot file plus the journal of everything written after it
+enum Persistence {
+    // Written by the mutation itself, see `Durability::Sync`
+    Sync { file: SnapshotFile, journal: Journal },
+    // Handed to the background writer; `records` counts what it got since
+    // the last snapshot
+    Debounced {
+        writer: JournalWriter<JournalRecord>,
+        records: usize,
+    },
+}
+
+// The persistence of a `Storage`, shared so that records can be written
+// while nobody holds the storage itself
+#[derive(Clone)]
+pub struct Disk(Arc<Mutex<Persistence>>);
+
+// Takes back a mutation that was applied in memory but could not be written
+pub struct Undo(Box<dyn FnOnce(&mut Storage) + Send>);
+
+// The journal record of a mutation that is applied in memory but not written
+// yet, and how to take it back if writing it fails
+pub struct Pending {
+    record: JournalRecord,
+    undo: Undo,
+}
+
+// What a mutation returns: its result, and its record unless there is
+// nothing to write (nothing changed, or storage without a file). See `Disk`.
+pub struct Staged<T> {
+    pub value: T,
+    pub pending: Option<Pending>,
+}
 
 pub struct Storage {
     todos: HashMap<u32, Todo>,
+    lists: HashMap<u32, TodoList>,
+    // Per todo, oldest first; kept after the todo is deleted
+    history: HashMap<u32, VecDeque<TodoRevision>>,
+    // Deleted todos until they are restored or purged
+    trash: HashMap<u32, Todo>,
+    // Todo id -> seq of its last change; ids missing from `todos` are the
+    // tombstones of deleted todos
+    changes: HashMap<u32, u64>,
+    change_seq: u64,
+    retention: HistoryRetention,
     next_id: u32,
+    next_list_id: u32,
+    persistence: Option<Disk>,
 }
 
 impl Storage {
-    pub fn new() -> io::Result<Self> {
+    // Storage backed by a JSON snapshot at `data_file` and a journal next to
+    // it (`todos.json` -> `todos.journal`), both encrypted with `cipher` if
+    // there is one. Plain files are encrypted as they are opened.
+    pub fn open(data_file: impl AsRef<Path>, cipher: Option<Cipher>) -> Result<Self, StorageError> {
+        let file = SnapshotFile {
+            path: data_file.as_ref().to_path_buf(),
+            cipher,
+        };
+        let journal_file = file.path.with_extension("journal");
+
+        // Load the last snapshot, upgrading older formats on the way
+        let (snapshot, outdated) = file.load()?;
+        let mut storage = Storage::from_snapshot(snapshot);
+
+        // Replay everything that happened after the snapshot. The journal of
+        // encrypted data has to be encrypted too.
+        let (journal, records) =
+            Journal::open::<JournalRecord>(&journal_file, file.cipher.clone())?;
+        let replayed = records.len();
+        for record in records {
+            storage.replay(record);
+        }
+        storage.persistence = Some(Disk::new(Persistence::Sync { file, journal }));
+
+        if replayed > 0 {
+            crate::info!("📒 Replayed {} journal record(s)", replayed);
+        }
+        if replayed > 0 || outdated || !storage.lists.contains_key(&DEFAULT_LIST_ID) {
+            storage.lists.entry(DEFAULT_LIST_ID).or_insert_with(TodoList::default_list);
+            storage.compact()?;
+        }
+
+        Ok(storage)
+    }
+
+    // Storage that lives only in memory, nothing is ever written to disk
+    pub fn in_memory() -> Self {
+        let mut storage = Storage::from_snapshot(DataFile::empty());
+        storage.lists.insert(DEFAULT_LIST_ID, TodoList::default_list());
+        storage
+    }
+
+    fn from_snapshot(snapshot: DataFile) -> Self {
+        let todos: HashMap<u32, Todo> = snapshot.todos.into_iter().map(|t| (t.id, t)).collect();
+        let lists: HashMap<u32, TodoList> =
+            snapshot.lists.into_iter().map(|l| (l.id, l)).collect();
+        let trash: HashMap<u32, Todo> = snapshot.trash.into_iter().map(|t| (t.id, t)).collect();
+        let next_id = todos.keys().chain(trash.keys()).max().map_or(1, |max_id| max_id + 1);
+        let next_list_id = lists.keys().max().map_or(1, |max_id| max_id + 1);
+        let mut history: HashMap<u32, VecDeque<TodoRevision>> = HashMap::new();
+        for entry in snapshot.history {
+            history.entry(entry.todo_id).or_default().push_back(entry);
+        }
+        let changes: HashMap<u32, u64> = snapshot
+            .changes
+            .into_iter()
+            .map(|entry| (entry.todo_id, entry.seq))
+            .collect();
+        let change_seq = changes.values().copied().max().unwrap_or(0);
+
         let mut storage = Storage {
-            todos: HashMap::new(),
-            next_id: 1,
-        };
-        
-        // Try to load existing data
-        if let Ok(data) = fs::read_to_string(DATA_FILE) {
-            if let Ok(todos_vec) = serde_json::from_str::<Vec<Todo>>(&data) {
-                let mut max_id = 0;
-                for todo in todos_vec {
-                    if todo.id > max_id {
-                        max_id = todo.id;
-                    }
-                    storage.todos.insert(todo.id, todo);
+            todos,
+            lists,
+            history,
+            trash,
+            changes,
+            change_seq: change_seq.max(snapshot.metadata.change_seq),
+            retention: HistoryRetention::default(),
+            next_id: next_id.max(snapshot.metadata.next_id),
+            next_list_id: next_list_id.max(snapshot.metadata.next_list_id).max(DEFAULT_LIST_ID + 1),
+            persistence: None,
+        };
+
+        // Files from before the change sequence: number what is there, so a
+        // sync from 0 still gets everything
+        let mut unnumbered: Vec<u32> = storage
+            .todos
+            .keys()
+            .chain(storage.trash.keys())
+            .filter(|id| !storage.changes.contains_key(id))
+            .copied()
+            .collect();
+        unnumbered.sort();
+        for id in unnumbered {
+            storage.record_change(id, 0);
+        }
+        storage
+    }
+
+    // Applies to what is already loaded; the pruned history is written out
+    // with the next compaction
+    pub fn set_history_retention(&mut self, retention: HistoryRetention) {
+        self.retention = retention;
+        let now = Utc::now();
+        self.history.retain(|_, entries| {
+            retention.prune(entries, now);
+            !entries.is_empty()
+        });
+    }
+
+    // Debounced writes go to a background thread from now on. Storage
+    // without a file has nothing to write either way.
+    pub fn set_durability(&mut self, durability: Durability) {
+        let Durability::Debounced { delay } = durability else {
+            return;
+        };
+        // Only called while setting up, before the disk is shared
+        let Some(Ok(disk)) = self.persistence.take().map(|disk| Arc::try_unwrap(disk.0)) else {
+            return;
+        };
+        let persistence = match disk.into_inner().unwrap_or_else(|p| p.into_inner()) {
+            Persistence::Sync { file, journal } => {
+                let records = journal.record_count();
+                Persistence::Debounced {
+                    writer: JournalWriter::spawn(journal, file, delay),
+                    records,
                 }
-                storage.next_id = max_id + 1;
+            }
+            other => other,
+        };
+        self.persistence = Some(Disk::new(persistence));
+    }
+
+    // Where the records of staged mutations go, `None` without a file
+    pub fn disk(&self) -> Option<Disk> {
+        self.persistence.clone()
+    }
+
+    pub fn undo(&mut self, undo: Undo) {
+        (undo.0)(self)
+    }
+
+    // Write snapshot and journal again with `cipher`, or in plain text
+    // without one. Used to rotate keys, so only storage that writes
+    // synchronously can do it: the new snapshot is in place when this returns.
+    pub fn reencrypt(&mut self, cipher: Option<Cipher>) -> Result<(), StorageError> {
+        let Some(disk) = &self.persistence else {
+            return Ok(());
+        };
+        match &mut *disk.lock()? {
+            Persistence::Sync { file, journal } => {
+                file.cipher = cipher.clone();
+                journal.set_cipher(cipher);
+            }
+            Persistence::Debounced { .. } => {
+                return Err(StorageError::Unavailable(
+                    "cannot re-encrypt while writing in the background".to_string(),
+                ));
             }
         }
-        
-        Ok(storage)
+        self.compact()
+    }
+
+    // Replaces everything with `snapshot`, e.g. a backup, and writes it out
+    // as a new todos.json in one rename. Every todo on either side counts as
+    // changed, so delta sync clients pick the restore up, and ids handed out
+    // since the snapshot are not handed out again.
+    pub fn restore_snapshot(&mut self, snapshot: DataFile) -> Result<(), StorageError> {
+        let mut restored = Storage::from_snapshot(snapshot);
+        restored.change_seq = restored.change_seq.max(self.change_seq);
+        restored.next_id = restored.next_id.max(self.next_id);
+        restored.next_list_id = restored.next_list_id.max(self.next_list_id);
+        let mut touched: Vec<u32> =
+            self.changes.keys().chain(restored.changes.keys()).copied().collect();
+        touched.sort();
+        touched.dedup();
+        for id in touched {
+            restored.record_change(id, 0);
+        }
+        restored.retention = self.retention;
+        restored.persistence = self.persistence.take();
+
+        let mut previous = std::mem::replace(self, restored);
+        if let Err(err) = self.compact() {
+            previous.persistence = self.persistence.take();
+            *self = previous;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Everything in plain todos.json format, journal included
+    pub fn export(&self) -> Result<String, StorageError> {
+        Ok(schema::encode(&mut self.snapshot())?)
     }
-    
+
+    fn replay(&mut self, record: JournalRecord) {
+        match record {
+            JournalRecord::Change { change } => {
+                self.apply_change(*change);
+            }
+            JournalRecord::Put { todo } => {
+                // Ids of todos deleted later in the journal count too
+                self.next_id = self.next_id.max(todo.id + 1);
+                self.record_change(todo.id, 0);
+                self.todos.insert(todo.id, todo);
+            }
+            JournalRecord::Delete { id } => {
+                self.record_change(id, 0);
+                self.todos.remove(&id);
+            }
+            JournalRecord::PutList { list } => {
+                self.next_list_id = self.next_list_id.max(list.id + 1);
+                self.lists.insert(list.id, list);
+            }
+            JournalRecord::DeleteList { id } => {
+                self.lists.remove(&id);
+            }
+            JournalRecord::Batch { records } => {
+                for record in records {
+                    self.replay(record);
+                }
+            }
+            JournalRecord::Purge { ids } => {
+                for id in ids {
+                    self.trash.remove(&id);
+                    self.history.remove(&id);
+                }
+            }
+        }
+    }
+
     pub fn get_all(&self) -> Vec<Todo> {
         self.todos.values().cloned().collect()
     }
-    
+
     pub fn get_by_id(&self, id: u32) -> Option<Todo> {
         self.todos.get(&id).cloned()
     }
-    
-    pub fn create(&mut self, title: String) -> Todo {
-        let todo = Todo {
-            id: self.next_id,
-            title,
-            completed: false,
-        };
-        
-        self.todos.insert(self.next_id, todo.clone());
-        self.next_id += 1;
-        self.save().unwrap(); // In real app, handle this error properly
-        todo
-    }
-    
-    pub fn update(&mut self, id: u32, title: Option<String>, completed: Option<bool>) -> Option<Todo> {
-        if let Some(todo) = self.todos.get_mut(&id) {
-            if let Some(t) = title {
-                todo.title = t;
-            }
-            if let Some(c) = completed {
-                todo.completed = c;
-            }
-            let updated_todo = todo.clone(); // Clone dulu sebelum save
-            self.save().unwrap();
-            Some(updated_todo)
-        } else {
-            None
-        }
-    }
-    
-    pub fn delete(&mut self, id: u32) -> bool {
-        if self.todos.remove(&id).is_some() {
-            self.save().unwrap();
-            true
-        } else {
-            false
-        }
-    }
-    
-    fn save(&self) -> io::Result<()> {
-        let todos_vec: Vec<Todo> = self.todos.values().cloned().collect();
-        let data = serde_json::to_string_pretty(&todos_vec)?;
-        fs::write(DATA_FILE, data)
+
+    // Recorded revisions of a todo, oldest first
+    pub fn history(&self, id: u32) -> Vec<TodoRevision> {
+        self.history
+            .get(&id)
+            .map(|entries| entries.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Trashed todos, in no particular order
+    pub fn get_trash(&self) -> Vec<Todo> {
+        self.trash.values().cloned().collect()
+    }
+
+    pub fn create(&mut self, request: CreateTodoRequest) -> Result<Staged<Todo>, StorageError> {
+        let todo = Todo::new(self.next_id, request);
+        let pending = self.commit(TodoRevision::new(RevisionOp::Create, None, Some(todo.clone())));
+        Ok(Staged::new(todo, pending))
+    }
+
+    // `expected_revision` makes the write conditional: it fails with
+    // `RevisionMismatch` unless the todo is still at that revision
+    pub fn update(
+        &mut self,
+        id: u32,
+        changes: UpdateTodoRequest,
+        expected_revision: Option<u64>,
+    ) -> Result<Staged<Option<Todo>>, StorageError> {
+        let Some(current) = self.todos.get(&id).cloned() else {
+            return Ok(Staged::done(None));
+        };
+        check_revision(&current, expected_revision)?;
+
+        let mut todo = current.clone();
+        todo.apply(changes);
+        todo.revision += 1;
+        let change = TodoRevision::new(RevisionOp::Update, Some(current), Some(todo.clone()));
+        let pending = self.commit(change);
+        Ok(Staged::new(Some(todo), pending))
+    }
+
+    // Overwrite an existing todo as a whole. `todo.revision` has to be the
+    // revision it was read at, so a read-modify-write never clobbers a write
+    // that happened in between.
+    pub fn replace(&mut self, mut todo: Todo) -> Result<Staged<Option<Todo>>, StorageError> {
+        let Some(current) = self.todos.get(&todo.id).cloned() else {
+            return Ok(Staged::done(None));
+        };
+        check_revision(&current, Some(todo.revision))?;
+
+        todo.revision += 1;
+        let change = TodoRevision::new(RevisionOp::Update, Some(current), Some(todo.clone()));
+        let pending = self.commit(change);
+        Ok(Staged::new(Some(todo), pending))
     }
+
+    // Moves the todo to the trash, see `apply_change`
+    pub fn delete(
+        &mut self,
+        id: u32,
+        expected_revision: Option<u64>,
+    ) -> Result<Staged<bool>, StorageError> {
+        let Some(current) = self.todos.get(&id).cloned() else {
+            return Ok(Staged::done(false));
+        };
+        check_revision(&current, expected_revision)?;
+
+        let pending = self.commit(TodoRevision::new(RevisionOp::Delete, Some(current), None));
+        Ok(Staged::new(true, pending))
+    }
+
+    // Write an earlier state of a todo back, whether the todo still exists
+    // or was deleted; a deleted one leaves the trash. It gets a revision
+    // above every one it had so far.
+    pub fn restore(&mut self, todo: Todo) -> Result<Staged<Todo>, StorageError> {
+        let (todo, change) = self.restored(todo);
+        let pending = self.commit(change);
+        Ok(Staged::new(todo, pending))
+    }
+
+    // `todo` as `restore` writes it back, and the change doing so
+    fn restored(&self, mut todo: Todo) -> (Todo, TodoRevision) {
+        let current = self.todos.get(&todo.id).cloned();
+        let latest = self
+            .history
+            .get(&todo.id)
+            .and_then(|entries| entries.back())
+            .map(|entry| entry.revision)
+            .into_iter()
+            .chain(current.as_ref().map(|t| t.revision))
+            .chain(self.trash.get(&todo.id).map(|t| t.revision))
+            .max()
+            .unwrap_or(todo.revision);
+
+        todo.revision = latest + 1;
+        todo.updated_at = Some(Utc::now());
+        todo.deleted_at = None;
+        let change = TodoRevision::new(RevisionOp::Restore, current, Some(todo.clone()));
+        (todo, change)
+    }
+
+    // Every mutation is applied in memory first and rolled back if the journal
+    // write fails, so memory never stays ahead of what is on disk.
+    fn commit(&mut self, change: TodoRevision) -> Option<Pending> {
+        if self.persistence.is_none() {
+            self.apply_change(change);
+            return None;
+        }
+        let id = change.todo_id;
+        let previous = change.before.clone();
+        let previous_history = self.history.get(&id).cloned();
+        let previous_trash = self.trash.get(&id).cloned();
+        let previous_change = self.changes.get(&id).copied();
+        let previous_change_seq = self.change_seq;
+        let previous_next_id = self.next_id;
+
+        let change = self.apply_change(change);
+        let record = JournalRecord::Change {
+            change: Box::new(change),
+        };
+        Some(Pending::new(record, move |storage| {
+            match previous {
+                Some(todo) => storage.todos.insert(id, todo),
+                None => storage.todos.remove(&id),
+            };
+            match previous_history {
+                Some(entries) => storage.history.insert(id, entries),
+                None => storage.history.remove(&id),
+            };
+            match previous_trash {
+                Some(todo) => storage.trash.insert(id, todo),
+                None => storage.trash.remove(&id),
+            };
+            match previous_change {
+                Some(seq) => storage.changes.insert(id, seq),
+                None => storage.changes.remove(&id),
+            };
+            storage.change_seq = previous_change_seq;
+            storage.next_id = previous_next_id;
+        }))
+    }
+
+    // Apply a change in memory, also used when replaying the journal. A
+    // delete moves the todo to the trash, anything that writes the todo back
+    // takes it out again. A new change gets the next number of the change
+    // sequence; returns the change as it has to be journaled.
+    fn apply_change(&mut self, mut change: TodoRevision) -> TodoRevision {
+        let id = change.todo_id;
+        change.seq = self.record_change(id, change.seq);
+        // Ids of todos deleted later count too
+        self.next_id = self.next_id.max(id + 1);
+        match (&change.before, &change.after) {
+            (_, Some(todo)) => {
+                self.todos.insert(id, todo.clone());
+                self.trash.remove(&id);
+            }
+            (Some(todo), None) => {
+                self.todos.remove(&id);
+                self.trash.insert(id, trashed(todo, change.at));
+            }
+            (None, None) => {}
+        }
+
+        if self.retention.max_revisions > 0 {
+            let entries = self.history.entry(id).or_default();
+            entries.push_back(change.clone());
+            self.retention.prune(entries, Utc::now());
+            if entries.is_empty() {
+                self.history.remove(&id);
+            }
+        }
+        change
+    }
+
+    // Mark `id` as changed at `seq`, or at the next number when `seq` is 0
+    fn record_change(&mut self, id: u32, seq: u64) -> u64 {
+        let seq = if seq == 0 { self.change_seq + 1 } else { seq };
+        self.change_seq = self.change_seq.max(seq);
+        self.changes.insert(id, seq);
+        seq
+    }
+
+    // Todos changed after `since`, oldest change first, at most `limit`
+    pub fn changes_since(&self, since: u64, limit: usize) -> ChangeSet {
+        let mut changed: Vec<(u64, u32)> = self
+            .changes
+            .iter()
+            .filter(|(_, seq)| **seq > since)
+            .map(|(id, seq)| (*seq, *id))
+            .collect();
+        changed.sort();
+        let has_more = changed.len() > limit;
+        changed.truncate(limit);
+
+        let mut set = ChangeSet {
+            seq: changed.last().map_or(self.change_seq.max(since), |(seq, _)| *seq),
+            upserts: Vec::new(),
+            deletes: Vec::new(),
+            has_more,
+        };
+        for (_, id) in changed {
+            match self.todos.get(&id) {
+                Some(todo) => set.upserts.push(todo.clone()),
+                None => set.deletes.push(id),
+            }
+        }
+        set
+    }
+
+    // Delete a trashed todo and its history for good; `false` when it is
+    // not in the trash
+    pub fn purge(&mut self, id: u32) -> Result<Staged<bool>, StorageError> {
+        if !self.trash.contains_key(&id) {
+            return Ok(Staged::done(false));
+        }
+        let pending = self.commit_purge(vec![id]);
+        Ok(Staged::new(true, pending))
+    }
+
+    // Purge everything that was deleted before `cutoff`, returns the ids
+    pub fn purge_deleted_before(
+        &mut self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<Staged<Vec<u32>>, StorageError> {
+        let mut ids: Vec<u32> = self
+            .trash
+            .values()
+            .filter(|todo| todo.deleted_at.is_some_and(|at| at < cutoff))
+            .map(|todo| todo.id)
+            .collect();
+        ids.sort();
+        if ids.is_empty() {
+            return Ok(Staged::done(ids));
+        }
+        let pending = self.commit_purge(ids.clone());
+        Ok(Staged::new(ids, pending))
+    }
+
+    fn commit_purge(&mut self, ids: Vec<u32>) -> Option<Pending> {
+        let removed: Vec<(u32, Option<Todo>, Option<VecDeque<TodoRevision>>)> = ids
+            .iter()
+            .map(|id| (*id, self.trash.remove(id), self.history.remove(id)))
+            .collect();
+        self.persistence.as_ref()?;
+        Some(Pending::new(JournalRecord::Purge { ids }, move |storage| {
+            for (id, todo, entries) in removed {
+                if let Some(todo) = todo {
+                    storage.trash.insert(id, todo);
+                }
+                if let Some(entries) = entries {
+                    storage.history.insert(id, entries);
+                }
+            }
+        }))
+    }
+
+    // Apply all operations or none. They are staged in memory one by one and
+    // written to the journal as a single record at the end.
+    pub fn batch(
+        &mut self,
+        ops: Vec<BatchOperation>,
+    ) -> Result<Staged<Vec<BatchOutcome>>, BatchError> {
+        let todos_before = self.todos.clone();
+        let history_before = self.history.clone();
+        let trash_before = self.trash.clone();
+        let changes_before = self.changes.clone();
+        let change_seq_before = self.change_seq;
+        let next_id_before = self.next_id;
+        let rollback = move |storage: &mut Storage| {
+            storage.todos = todos_before;
+            storage.history = history_before;
+            storage.trash = trash_before;
+            storage.changes = changes_before;
+            storage.change_seq = change_seq_before;
+            storage.next_id = next_id_before;
+        };
+
+        let mut records = Vec::with_capacity(ops.len());
+        let mut outcomes = Vec::with_capacity(ops.len());
+        for (index, mut op) in ops.into_iter().enumerate() {
+            if let BatchOperation::Create { todo } = &mut op {
+                todo.resolve_parent(&outcomes);
+            }
+            match self.stage(index, op) {
+                Ok((change, outcome)) => {
+                    let change = self.apply_change(change);
+                    records.push(JournalRecord::Change {
+                        change: Box::new(change),
+                    });
+                    outcomes.push(outcome);
+                }
+                Err(err) => {
+                    rollback(self);
+                    return Err(err);
+                }
+            }
+        }
+        if records.is_empty() || self.persistence.is_none() {
+            return Ok(Staged::done(outcomes));
+        }
+        let pending = Pending::new(JournalRecord::Batch { records }, rollback);
+        Ok(Staged::new(outcomes, Some(pending)))
+    }
+
+    // The change one batch operation makes, against the staged state
+    fn stage(
+        &self,
+        index: usize,
+        op: BatchOperation,
+    ) -> Result<(TodoRevision, BatchOutcome), BatchError> {
+        let current = |id: u32, expected: Option<u64>| {
+            let Some(todo) = self.todos.get(&id) else {
+                return Err(BatchError::NotFound { index, id });
+            };
+            match expected {
+                Some(revision) if revision != todo.revision => Err(BatchError::RevisionMismatch {
+                    index,
+                    id,
+                    current: todo.revision,
+                }),
+                _ => Ok(todo.clone()),
+            }
+        };
+
+        match op {
+            BatchOperation::Create { todo } => {
+                let todo = Todo::new(self.next_id, todo);
+                let change = TodoRevision::new(RevisionOp::Create, None, Some(todo.clone()));
+                Ok((change, BatchOutcome::Created(todo)))
+            }
+            BatchOperation::Update { id, changes, revision } => {
+                let before = current(id, revision)?;
+                let mut todo = before.clone();
+                todo.apply(changes);
+                todo.revision += 1;
+                let change =
+                    TodoRevision::new(RevisionOp::Update, Some(before), Some(todo.clone()));
+                Ok((change, BatchOutcome::Updated(todo)))
+            }
+            BatchOperation::Delete { id, revision } => {
+                let before = current(id, revision)?;
+                let change = TodoRevision::new(RevisionOp::Delete, Some(before), None);
+                Ok((change, BatchOutcome::Deleted(id)))
+            }
+            BatchOperation::Replace { mut todo } => {
+                let before = current(todo.id, Some(todo.revision))?;
+                todo.revision += 1;
+                let change =
+                    TodoRevision::new(RevisionOp::Update, Some(before), Some(todo.clone()));
+                Ok((change, BatchOutcome::Updated(todo)))
+            }
+            BatchOperation::Restore { todo } => {
+                let (todo, change) = self.restored(todo);
+                // Back from the trash is a create for anyone watching
+                let outcome = match change.before {
+                    Some(_) => BatchOutcome::Updated(todo),
+                    None => BatchOutcome::Created(todo),
+                };
+                Ok((change, outcome))
+            }
+        }
+    }
+
+    pub fn get_lists(&self) -> Vec<TodoList> {
+        self.lists.values().cloned().collect()
+    }
+
+    pub fn get_list(&self, id: u32) -> Option<TodoList> {
+        self.lists.get(&id).cloned()
+    }
+
+    pub fn create_list(
+        &mut self,
+        request: CreateListRequest,
+    ) -> Result<Staged<TodoList>, StorageError> {
+        let list = TodoList::new(self.next_list_id, request);
+
+        self.lists.insert(list.id, list.clone());
+        self.next_list_id += 1;
+        let id = list.id;
+        let pending = self.pending(JournalRecord::PutList { list: list.clone() }, move |storage| {
+            storage.lists.remove(&id);
+            storage.next_list_id -= 1;
+        });
+        Ok(Staged::new(list, pending))
+    }
+
+    pub fn update_list(
+        &mut self,
+        id: u32,
+        changes: UpdateListRequest,
+    ) -> Result<Staged<Option<TodoList>>, StorageError> {
+        let Some(list) = self.lists.get_mut(&id) else {
+            return Ok(Staged::done(None));
+        };
+
+        let previous = list.clone();
+        list.apply(changes);
+        let updated_list = list.clone();
+        let record = JournalRecord::PutList {
+            list: updated_list.clone(),
+        };
+        let pending = self.pending(record, move |storage| {
+            storage.lists.insert(id, previous);
+        });
+        Ok(Staged::new(Some(updated_list), pending))
+    }
+
+    pub fn delete_list(&mut self, id: u32) -> Result<Staged<bool>, StorageError> {
+        let Some(removed) = self.lists.remove(&id) else {
+            return Ok(Staged::done(false));
+        };
+
+        let pending = self.pending(JournalRecord::DeleteList { id }, move |storage| {
+            storage.lists.insert(id, removed);
+        });
+        Ok(Staged::new(true, pending))
+    }
+
+    // The record to write for a mutation, unless there is nowhere to write it
+    fn pending(
+        &self,
+        record: JournalRecord,
+        undo: impl FnOnce(&mut Storage) + Send + 'static,
+    ) -> Option<Pending> {
+        self.persistence.as_ref()?;
+        Some(Pending::new(record, undo))
+    }
+
+    // Fold the journal into a new snapshot, see `Disk::compact`
+    fn compact(&self) -> Result<(), StorageError> {
+        match &self.persistence {
+            Some(disk) => disk.compact(self.snapshot()),
+            None => Ok(()),
+        }
+    }
+
+    // Everything in memory, as todos.json holds it
+    pub fn snapshot(&self) -> DataFile {
+        let mut todos_vec: Vec<Todo> = self.todos.values().cloned().collect();
+        todos_vec.sort_by_key(|todo| todo.id);
+        let mut lists_vec: Vec<TodoList> = self.lists.values().cloned().collect();
+        lists_vec.sort_by_key(|list| list.id);
+        let mut history_vec: Vec<TodoRevision> = self.history.values().flatten().cloned().collect();
+        history_vec.sort_by_key(|entry| (entry.at, entry.todo_id));
+        let mut trash_vec: Vec<Todo> = self.trash.values().cloned().collect();
+        trash_vec.sort_by_key(|todo| todo.id);
+        let mut changes_vec: Vec<ChangeEntry> = self
+            .changes
+            .iter()
+            .map(|(id, seq)| ChangeEntry {
+                todo_id: *id,
+                seq: *seq,
+            })
+            .collect();
+        changes_vec.sort_by_key(|entry| entry.seq);
+        DataFile {
+            schema_version: schema::CURRENT_VERSION,
+            metadata: Metadata {
+                saved_at: None,
+                next_id: self.next_id,
+                next_list_id: self.next_list_id,
+                change_seq: self.change_seq,
+            },
+            todos: todos_vec,
+            lists: lists_vec,
+            history: history_vec,
+            trash: trash_vec,
+            changes: changes_vec,
+        }
+    }
+}
+
+impl Disk {
+    fn new(persistence: Persistence) -> Self {
+        Disk(Arc::new(Mutex::new(persistence)))
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Persistence>, StorageError> {
+        self.0
+            .lock()
+            .map_err(|_| StorageError::Unavailable("journal lock poisoned".to_string()))
+    }
+
+    // Append the record of a staged mutation; `true` when the journal has
+    // grown long enough to be compacted. Once the record is in the journal
+    // the mutation is durable. When it cannot be written the error comes
+    // back with the undo, for `Storage::undo`. Debounced, the record only
+    // goes to the background writer and cannot fail here.
+    pub fn append(&self, pending: Pending) -> Result<bool, (StorageError, Undo)> {
+        let mut persistence = match self.lock() {
+            Ok(persistence) => persistence,
+            Err(err) => return Err((err, pending.undo)),
+        };
+        match &mut *persistence {
+            Persistence::Sync { journal, .. } => match journal.append(&pending.record) {
+                Ok(()) => Ok(journal.record_count() >= COMPACT_EVERY),
+                Err(err) => Err((err.into(), pending.undo)),
+            },
+            Persistence::Debounced { writer, records } => {
+                writer.record(pending.record);
+                *records += 1;
+                Ok(*records >= COMPACT_EVERY)
+            }
+        }
+    }
+
+    // Replace the journal with `snapshot`, which has to hold everything
+    // appended so far; the journal is only cleared once the snapshot is in
+    // place, see `SnapshotFile::write`. A failed compaction after an append
+    // is retried on the next one.
+    pub fn compact(&self, mut snapshot: DataFile) -> Result<(), StorageError> {
+        match &mut *self.lock()? {
+            Persistence::Sync { file, journal } => {
+                file.write(&mut snapshot)?;
+                journal.clear()?;
+            }
+            Persistence::Debounced { writer, records } => {
+                writer.snapshot(snapshot);
+                *records = 0;
+            }
+        }
+        Ok(())
+    }
+
+    // Waits until every write so far is on disk when they are written in
+    // the background, `None` otherwise
+    pub fn flusher(
+        &self,
+    ) -> Result<Option<impl FnOnce() -> Result<(), StorageError> + Send + use<>>, StorageError>
+    {
+        Ok(match &*self.lock()? {
+            Persistence::Debounced { writer, .. } => Some(writer.flusher()),
+            Persistence::Sync { .. } => None,
+        })
+    }
+}
+
+impl Pending {
+    fn new(record: JournalRecord, undo: impl FnOnce(&mut Storage) + Send + 'static) -> Self {
+        Pending {
+            record,
+            undo: Undo(Box::new(undo)),
+        }
+    }
+}
+
+impl<T> Staged<T> {
+    fn new(value: T, pending: Option<Pending>) -> Self {
+        Staged { value, pending }
+    }
+
+    // Nothing was changed
+    fn done(value: T) -> Self {
+        Staged::new(value, None)
+    }
+}
+
+impl SnapshotFile {
+    // Written to a temp file and renamed over todos.json, so a crash never
+    // leaves a half-written snapshot behind
+    pub fn write(&self, snapshot: &mut DataFile) -> Result<(), StorageError> {
+        let data = encryption::seal_file(self.cipher.as_ref(), schema::encode(snapshot)?)?;
+        let tmp_file = with_suffix(&self.path, "tmp");
+        let mut file = fs::File::create(&tmp_file)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_file, &self.path)?;
+        Ok(())
+    }
+
+    // Read the snapshot and whether it was migrated, so it has to be written
+    // again. A file we cannot make sense of is moved aside instead of being
+    // overwritten by the next save; a file from a newer server version, one
+    // encrypted with another key, or a plain one while a key is configured
+    // stops startup so it is never lost or silently rewritten.
+    fn load(&self) -> Result<(DataFile, bool), StorageError> {
+        let data_file = self.path.as_path();
+        let data = match fs::read_to_string(data_file) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok((DataFile::empty(), false));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let (data, key_id) = encryption::open_file(self.cipher.as_ref(), data_file, data)?;
+        // Encrypting is a step of its own, `rotate-key`, not a side effect
+        // of configuring a key
+        if let (Some(cipher), None) = (&self.cipher, key_id) {
+            return Err(StorageError::Encryption(format!(
+                "{} is not encrypted, but key {} is configured; encrypt it first with \
+                 `rotate-key --new-key-file <key file>` and no key configured",
+                data_file.display(),
+                cipher.id()
+            )));
+        }
+
+        match schema::parse(&data) {
+            Ok((file, None)) => Ok((file, false)),
+            Ok((file, Some(migration))) => {
+                // Keep the original around in case the migration got something wrong
+                let backup = with_suffix(data_file, &format!("v{}.bak", migration.from));
+                fs::copy(data_file, &backup)?;
+                crate::info!(
+                    "🔄 Migrated {} from schema v{} to v{} ({} todo(s), original kept as {})",
+                    data_file.display(),
+                    migration.from,
+                    migration.to,
+                    file.todos.len(),
+                    backup.display()
+                );
+                Ok((file, true))
+            }
+            Err(LoadError::TooNew(version)) => Err(StorageError::UnsupportedSchema(version)),
+            Err(LoadError::Unreadable(reason)) => {
+                let quarantine = with_suffix(
+                    data_file,
+                    &format!("corrupt-{}", Utc::now().format("%Y%m%dT%H%M%S")),
+                );
+                fs::rename(data_file, &quarantine)?;
+                crate::warn!(
+                    "⚠️  Could not read {} ({}), moved it to {} and starting empty",
+                    data_file.display(),
+                    reason,
+                    quarantine.display()
+                );
+                Ok((DataFile::empty(), false))
+            }
+        }
+    }
+}
+
+// How a deleted todo is kept in the trash
+pub fn trashed(todo: &Todo, deleted_at: DateTime<Utc>) -> Todo {
+    Todo {
+        deleted_at: Some(deleted_at),
+        ..todo.clone()
+    }
+}
+
+// Fails unless `todo` is at `expected` (or no revision is expected)
+pub fn check_revision(todo: &Todo, expected: Option<u64>) -> Result<(), StorageError> {
+    match expected {
+        Some(revision) if revision != todo.revision => Err(StorageError::RevisionMismatch {
+            id: todo.id,
+            current: todo.revision,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// `todos.json` + `bak` -> `todos.json.bak`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
 }
@@ -0,0 +1,64 @@
+use crate::error::StorageError;
+use crate::repository::TodoRepository;
+use chrono::{DateTime, Duration, Utc};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+// How often the background task looks for expired todos in the trash
+const PURGE_EVERY: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+// How long deleted todos stay in the trash before they are purged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrashRetention {
+    // `None` keeps them until they are purged by hand
+    pub max_age: Option<Duration>,
+}
+
+impl Default for TrashRetention {
+    fn default() -> Self {
+        TrashRetention {
+            max_age: Some(Duration::days(30)),
+        }
+    }
+}
+
+impl TrashRetention {
+    // Todos deleted before this are expired
+    pub fn cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.max_age.map(|age| now - age)
+    }
+}
+
+// Purge what has been in the trash longer than `retention` allows
+pub async fn purge_expired(
+    repo: &dyn TodoRepository,
+    retention: TrashRetention,
+) -> Result<Vec<u32>, StorageError> {
+    match retention.cutoff(Utc::now()) {
+        Some(cutoff) => repo.purge_deleted_before(cutoff).await,
+        None => Ok(Vec::new()),
+    }
+}
+
+// Run `purge_expired` now and then every hour. Failures are logged and
+// retried on the next round. `None` when auto-purging is off.
+pub fn spawn_auto_purge(
+    repo: Arc<dyn TodoRepository>,
+    retention: TrashRetention,
+) -> Option<JoinHandle<()>> {
+    // Nothing ever expires
+    retention.max_age?;
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_EVERY);
+        loop {
+            interval.tick().await;
+            match purge_expired(repo.as_ref(), retention).await {
+                Ok(purged) if !purged.is_empty() => {
+                    crate::info!("🗑️  Purged {} todo(s) from the trash", purged.len());
+                }
+                Ok(_) => {}
+                Err(err) => crate::warn!("⚠️  Purging the trash failed, will retry: {}", err),
+            }
+        }
+    }))
+}
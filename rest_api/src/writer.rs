@@ -0,0 +1,186 @@
+use crate::error::StorageError;
+use crate::journal::Journal;
+use crate::schema::DataFile;
+use crate::storage::SnapshotFile;
+use serde::Serialize;
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How long the background writer collects changes before writing them out
+pub const DEFAULT_FLUSH_DELAY: Duration = Duration::from_millis(50);
+
+// When a write counts as done
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    // On disk before the write returns, so an answered write survives a
+    // crash. Writes wait for each other's fsync.
+    #[default]
+    Sync,
+    // Done once it is in memory. A background thread writes everything that
+    // came in within `delay` with a single fsync, so a crash can lose the
+    // writes of the last `delay`.
+    Debounced { delay: Duration },
+}
+
+enum Message<T> {
+    Record(T),
+    // Snapshot of everything sent before it
+    Snapshot(Box<DataFile>),
+    // Answered once everything sent before it is on disk
+    Flush(Sender<Result<(), StorageError>>),
+}
+
+// Handle of the thread writing journal records and snapshots for
+// `Durability::Debounced`. Dropping it writes whatever is left and waits for
+// the thread to finish.
+pub struct JournalWriter<T> {
+    sender: Option<Sender<Message<T>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T: Serialize + Send + 'static> JournalWriter<T> {
+    pub fn spawn(journal: Journal, file: SnapshotFile, delay: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("journal-writer".to_string())
+            .spawn(move || run(journal, file, delay, receiver))
+            .expect("failed to start the journal writer");
+        JournalWriter {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn record(&self, record: T) {
+        self.send(Message::Record(record));
+    }
+
+    // Replaces the journal; records sent before it are not written anymore.
+    // Encoding it is left to the thread as well, and of several snapshots in
+    // one burst only the last one is written.
+    pub fn snapshot(&self, snapshot: DataFile) {
+        self.send(Message::Snapshot(Box::new(snapshot)));
+    }
+
+    // Waits until everything sent so far is on disk. Blocks, so it can be
+    // called without holding on to the storage.
+    pub fn flusher(&self) -> impl FnOnce() -> Result<(), StorageError> + Send + use<T> {
+        let sender = self.sender.clone();
+        move || {
+            let stopped =
+                || StorageError::Unavailable("the journal writer has stopped".to_string());
+            let (ack, done) = mpsc::channel();
+            sender
+                .ok_or_else(stopped)?
+                .send(Message::Flush(ack))
+                .map_err(|_| stopped())?;
+            done.recv().map_err(|_| stopped())?
+        }
+    }
+
+    fn send(&self, message: Message<T>) {
+        // The thread only stops once the sender is dropped
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(message);
+        }
+    }
+}
+
+impl<T> Drop for JournalWriter<T> {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// What is not on disk yet: an optional snapshot and the records after it
+struct Pending<T> {
+    snapshot: Option<Box<DataFile>>,
+    records: Vec<T>,
+    flushes: Vec<Sender<Result<(), StorageError>>>,
+}
+
+impl<T: Serialize> Pending<T> {
+    fn is_empty(&self) -> bool {
+        self.snapshot.is_none() && self.records.is_empty()
+    }
+
+    fn add(&mut self, message: Message<T>) {
+        match message {
+            Message::Record(record) => self.records.push(record),
+            Message::Snapshot(snapshot) => {
+                self.snapshot = Some(snapshot);
+                self.records.clear();
+            }
+            Message::Flush(ack) => self.flushes.push(ack),
+        }
+    }
+
+    // Whatever fails stays pending and is tried again
+    fn write(&mut self, journal: &mut Journal, file: &SnapshotFile) -> Result<(), StorageError> {
+        if let Some(snapshot) = self.snapshot.as_mut() {
+            file.write(snapshot)?;
+            journal.clear()?;
+            self.snapshot = None;
+        }
+        if !self.records.is_empty() {
+            journal.append_all(&self.records)?;
+            self.records.clear();
+        }
+        Ok(())
+    }
+}
+
+fn run<T: Serialize>(
+    mut journal: Journal,
+    file: SnapshotFile,
+    delay: Duration,
+    receiver: Receiver<Message<T>>,
+) {
+    let mut pending = Pending {
+        snapshot: None,
+        records: Vec::new(),
+        flushes: Vec::new(),
+    };
+    let mut open = true;
+    while open {
+        // Sleep until the first change of a burst; after a failed write try
+        // again in `delay` even if nothing new comes in
+        let first = if pending.is_empty() {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            receiver.recv_timeout(delay)
+        };
+        match first {
+            Ok(message) => pending.add(message),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => open = false,
+        }
+
+        // Take in the rest of the burst, unless someone waits for it
+        let deadline = Instant::now() + delay;
+        while open && pending.flushes.is_empty() {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(message) => pending.add(message),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => open = false,
+            }
+        }
+
+        let written = pending.write(&mut journal, &file);
+        if let Err(err) = &written {
+            crate::error!("❌ Writing {} failed, will retry: {}", file.path.display(), err);
+        }
+        for ack in pending.flushes.drain(..) {
+            let result = match &written {
+                Ok(()) => Ok(()),
+                Err(err) => Err(StorageError::Io(io::Error::other(err.to_string()))),
+            };
+            let _ = ack.send(result);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+// API keys and per-user isolation: every request names its user with a key,
+// and users only see their own todos.
+
+mod common;
+
+use common::{create, serve_app};
+use rest_api::auth::{AccountStore, Accounts, Role};
+use rest_api::client::{Client, ListFilter};
+use rest_api::repository::StorageRepository;
+use rest_api::routers::create_routes;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use std::fs;
+use std::sync::Arc;
+
+// Base URL of a fresh in-memory server with accounts, and the admin's key
+async fn serve() -> (String, String) {
+    let accounts = Accounts::in_memory();
+    let (_, key) = accounts.bootstrap().unwrap().unwrap();
+    let repo = Arc::new(StorageRepository::in_memory());
+    let app = create_routes(repo, Some(Arc::new(accounts)), None);
+    (serve_app(app).await, key)
+}
+
+// Creates a user through the admin API and returns their key
+async fn add_user(server: &str, admin_key: &str, name: &str) -> String {
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/users", server))
+        .bearer_auth(admin_key)
+        .json(&json!({ "name": name }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["user"]["role"], "user");
+    body["key"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn keys_are_stored_hashed_and_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("users.json");
+    let accounts = Accounts::open(&path).unwrap();
+    let (admin, admin_key) = accounts.bootstrap().unwrap().unwrap();
+    assert_eq!(admin.user.id, 1);
+    assert!(admin.user.is_admin());
+    assert!(accounts.bootstrap().unwrap().is_none());
+    let (alice, alice_key) = accounts.create_user("alice", Role::User).unwrap();
+    assert!(alice_key.starts_with("tk_"));
+    assert!(accounts.create_user("ALICE", Role::User).is_err());
+    assert!(accounts.create_user("  ", Role::User).is_err());
+
+    let stored = fs::read_to_string(&path).unwrap();
+    assert!(!stored.contains(&alice_key) && !stored.contains(&admin_key));
+
+    let accounts = Accounts::open(&path).unwrap();
+    assert_eq!(accounts.authenticate(&alice_key).unwrap().name, "alice");
+    assert!(accounts.authenticate("tk_nope").is_none());
+
+    // Rotating: a second key, then the first one goes
+    let (second, second_key) = accounts.create_key(alice.user.id).unwrap();
+    accounts.revoke_key(alice.keys[0].id).unwrap();
+    assert!(accounts.authenticate(&alice_key).is_none());
+    assert_eq!(accounts.authenticate(&second_key).unwrap().id, alice.user.id);
+    assert!(accounts.revoke_key(alice.keys[0].id).is_err());
+    assert!(accounts.create_key(99).is_err());
+
+    // Someone has to be able to manage keys
+    assert!(accounts.revoke_key(admin.keys[0].id).is_err());
+    accounts.revoke_key(second.id).unwrap();
+    assert_eq!(accounts.users().unwrap().len(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn first_admin_key_goes_to_a_private_file() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("users.json");
+    let key_file = AccountStore::File(path.clone()).first_key_path();
+    assert_eq!(key_file, dir.path().join("admin.key"));
+
+    // Nowhere to write the key: no admin whose key nobody has
+    let accounts = Accounts::open(&path).unwrap();
+    assert!(accounts.bootstrap_to(&dir.path().join("missing/admin.key")).is_err());
+    assert!(accounts.users().unwrap().is_empty() && !path.exists());
+
+    // A leftover file is replaced, not written through
+    fs::write(&key_file, "tk_old").unwrap();
+    fs::set_permissions(&key_file, fs::Permissions::from_mode(0o644)).unwrap();
+    let admin = accounts.bootstrap_to(&key_file).unwrap().unwrap();
+    let key = fs::read_to_string(&key_file).unwrap();
+    assert_eq!(accounts.authenticate(&key).unwrap().id, admin.user.id);
+    assert_eq!(fs::metadata(&key_file).unwrap().permissions().mode() & 0o777, 0o600);
+
+    // Only once
+    fs::remove_file(&key_file).unwrap();
+    assert!(Accounts::open(&path).unwrap().bootstrap_to(&key_file).unwrap().is_none());
+    assert!(!key_file.exists());
+}
+
+#[tokio::test]
+async fn requests_need_a_valid_key() {
+    let (server, admin_key) = serve().await;
+    let http = reqwest::Client::new();
+
+    let response = http.get(format!("{}/todos", server)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["www-authenticate"], "Bearer");
+    let response = http
+        .get(format!("{}/todos", server))
+        .bearer_auth("tk_0000")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let me: Value = http
+        .get(format!("{}/me", server))
+        .bearer_auth(&admin_key)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(me["name"], "admin");
+    assert_eq!(me["role"], "admin");
+    // The key also works as the password of basic auth
+    let response = http
+        .get(format!("{}/me", server))
+        .basic_auth("admin", Some(&admin_key))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn users_only_see_their_own_todos() {
+    let (server, admin_key) = serve().await;
+    let alice = Client::new(&server).with_token(add_user(&server, &admin_key, "alice").await);
+    let bob = Client::new(&server).with_token(add_user(&server, &admin_key, "bob").await);
+
+    let mine = alice.create(&create("Alice's todo")).await.unwrap();
+    bob.create(&create("Bob's todo")).await.unwrap();
+    let titles: Vec<String> =
+        alice.list(&ListFilter::default()).await.unwrap().into_iter().map(|t| t.title).collect();
+    assert_eq!(titles, ["Alice's todo"]);
+
+    // Someone else's todo looks like it does not exist
+    assert!(bob.get(mine.id).await.unwrap_err().is_not_found());
+    assert!(bob.delete(mine.id, false).await.unwrap_err().is_not_found());
+    assert_eq!(alice.get(mine.id).await.unwrap().title, "Alice's todo");
+    let admin = Client::new(&server).with_token(admin_key);
+    assert!(admin.list(&ListFilter::default()).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn only_admins_manage_keys() {
+    let (server, admin_key) = serve().await;
+    let alice_key = add_user(&server, &admin_key, "alice").await;
+    let http = reqwest::Client::new();
+
+    let response = http
+        .post(format!("{}/admin/users", server))
+        .bearer_auth(&alice_key)
+        .json(&json!({ "name": "mallory", "role": "admin" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let users: Value = http
+        .get(format!("{}/admin/users", server))
+        .bearer_auth(&admin_key)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let alice = &users.as_array().unwrap()[1];
+    assert_eq!(alice["name"], "alice");
+    // Only a hint of the key is ever shown again
+    let hint = alice["keys"][0]["hint"].as_str().unwrap();
+    assert!(alice_key.starts_with(hint.trim_end_matches('…')) && hint.len() < alice_key.len());
+
+    let key_id = alice["keys"][0]["id"].as_u64().unwrap();
+    let response = http
+        .delete(format!("{}/admin/keys/{}", server, key_id))
+        .bearer_auth(&admin_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = http.get(format!("{}/me", server)).bearer_auth(&alice_key).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
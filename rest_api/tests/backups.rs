@@ -0,0 +1,259 @@
+// Snapshot backups: rotation, checksums, atomic restores on every backend
+// and the admin routes.
+
+mod common;
+
+use common::{create, serve_app};
+use rest_api::auth::{Accounts, Role};
+use rest_api::backup::{self, BackupError, BackupPolicy, Backups};
+use rest_api::encryption::Cipher;
+use rest_api::repository::{SqliteRepository, StorageRepository, TodoRepository};
+use rest_api::routers::create_routes;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::fs;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn policy(dir: &TempDir, keep: usize) -> BackupPolicy {
+    BackupPolicy {
+        dir: dir.path().join("backups"),
+        keep,
+        every: None,
+    }
+}
+
+async fn titles(repo: &dyn TodoRepository) -> Vec<String> {
+    let mut titles: Vec<String> =
+        repo.list().await.unwrap().into_iter().map(|todo| todo.title).collect();
+    titles.sort();
+    titles
+}
+
+#[tokio::test]
+async fn backups_rotate_and_restore_on_every_backend() {
+    let dir = TempDir::new().unwrap();
+    let repos: Vec<Arc<dyn TodoRepository>> = vec![
+        Arc::new(StorageRepository::in_memory()),
+        Arc::new(StorageRepository::json_file(dir.path().join("todos.json")).unwrap()),
+        Arc::new(SqliteRepository::open(dir.path().join("todos.db")).unwrap()),
+    ];
+    for (i, repo) in repos.into_iter().enumerate() {
+        let backups = Backups::new(repo.clone(), policy(&dir, 2), None);
+        let _ = fs::remove_dir_all(&backups.policy().dir);
+
+        repo.create(create("keep me")).await.unwrap();
+        let good = backups.create().await.unwrap();
+        assert!(good.valid && good.sha256.is_some());
+        // A bad bulk edit
+        repo.delete(1, None).await.unwrap();
+        repo.create(create("mistake")).await.unwrap();
+        let seq = repo.changes_since(0, 100).await.unwrap().seq;
+        backups.create().await.unwrap();
+        assert_eq!(backups.list().unwrap().len(), 2, "backend {}", i);
+
+        let restored = backups.restore(&good.name).await.unwrap();
+        assert_eq!(restored.restored.name, good.name);
+        assert_eq!(titles(repo.as_ref()).await, ["keep me"], "backend {}", i);
+        assert!(repo.trash().await.unwrap().is_empty());
+        // Delta sync sees both the todo that came back and the one that went
+        let changes = repo.changes_since(seq, 100).await.unwrap();
+        assert_eq!(changes.upserts.len(), 1, "backend {}", i);
+        assert_eq!(changes.deletes, [2], "backend {}", i);
+        // Ids handed out after the backup are not handed out again
+        assert_eq!(repo.create(create("next")).await.unwrap().id, 3, "backend {}", i);
+
+        // The state before the restore was backed up and can be restored too
+        backups.restore(&restored.previous.name).await.unwrap();
+        assert_eq!(titles(repo.as_ref()).await, ["mistake"], "backend {}", i);
+    }
+}
+
+#[tokio::test]
+async fn json_restores_survive_a_restart() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let cipher = Cipher::from_key(&Cipher::generate_key().unwrap()).unwrap();
+    {
+        let repo = Arc::new(StorageRepository::encrypted_json_file(&json, cipher.clone()).unwrap());
+        let backups = Backups::new(repo.clone(), policy(&dir, 7), Some(cipher.clone()));
+        repo.create(create("secret plan")).await.unwrap();
+        let backup = backups.create().await.unwrap();
+        let stored = fs::read_to_string(backups.policy().dir.join(&backup.name)).unwrap();
+        assert!(stored.contains(cipher.id()) && !stored.contains("secret plan"));
+
+        repo.update(1, Default::default(), None).await.unwrap();
+        repo.create(create("other")).await.unwrap();
+        backups.restore(&backup.name).await.unwrap();
+    }
+    let repo = StorageRepository::encrypted_json_file(&json, cipher).unwrap();
+    assert_eq!(titles(&repo).await, ["secret plan"]);
+    assert_eq!(repo.get(1).await.unwrap().unwrap().revision, 1);
+}
+
+#[tokio::test]
+async fn backups_follow_a_new_key() {
+    let dir = TempDir::new().unwrap();
+    let new_cipher = || Cipher::from_key(&Cipher::generate_key().unwrap()).unwrap();
+    let (old, new) = (new_cipher(), new_cipher());
+    let repo = Arc::new(StorageRepository::in_memory());
+    let before = Backups::new(repo.clone(), policy(&dir, 7), Some(old.clone()));
+    repo.create(create("from before")).await.unwrap();
+    let backup = before.create().await.unwrap();
+    assert_eq!(backup.key_id.as_deref(), Some(old.id()));
+    repo.create(create("since")).await.unwrap();
+
+    // Intact, but not for the new key: listed as such and refused up front
+    let after = Backups::new(repo.clone(), policy(&dir, 7), Some(new.clone()));
+    let listed = after.list().unwrap().remove(0);
+    assert_eq!(listed.key_id.as_deref(), Some(old.id()));
+    assert!(!listed.valid);
+    assert!(matches!(after.restore(&backup.name).await, Err(BackupError::KeyMismatch(_))));
+    let unencrypted = Backups::new(repo.clone(), policy(&dir, 7), None);
+    assert!(!unencrypted.list().unwrap()[0].valid);
+    assert_eq!(after.list().unwrap().len(), 1);
+    assert_eq!(titles(repo.as_ref()).await, ["from before", "since"]);
+
+    // What rotate-key does to them
+    let backup_dir = &after.policy().dir;
+    assert_eq!(backup::reencrypt(backup_dir, Some(&old), Some(&new)).unwrap(), (1, vec![]));
+    let listed = after.list().unwrap().remove(0);
+    assert_eq!(listed.key_id.as_deref(), Some(new.id()));
+    assert!(listed.valid);
+    assert!(!before.list().unwrap()[0].valid);
+    after.restore(&backup.name).await.unwrap();
+    assert_eq!(titles(repo.as_ref()).await, ["from before"]);
+
+    // Backups the old key cannot read, like the one taken before the
+    // restore, are left alone; `decrypt --in-place` takes them out of
+    // encryption with the right key
+    let (rewritten, skipped) = backup::reencrypt(backup_dir, Some(&old), None).unwrap();
+    assert_eq!((rewritten, skipped.len()), (0, 2));
+    let (rewritten, skipped) = backup::reencrypt(backup_dir, Some(&new), None).unwrap();
+    assert_eq!((rewritten, skipped.len()), (2, 0));
+    assert!(unencrypted.list().unwrap().iter().all(|b| b.valid && b.key_id.is_none()));
+}
+
+#[tokio::test]
+async fn damaged_backups_are_not_restored() {
+    let dir = TempDir::new().unwrap();
+    let repo = Arc::new(StorageRepository::in_memory());
+    let backups = Backups::new(repo.clone(), policy(&dir, 7), None);
+    repo.create(create("first")).await.unwrap();
+    let backup = backups.create().await.unwrap();
+    repo.create(create("second")).await.unwrap();
+    let path = backups.policy().dir.join(&backup.name);
+
+    let text = fs::read_to_string(&path).unwrap();
+    fs::write(&path, text.replace("first", "fir5t")).unwrap();
+    assert!(!backups.list().unwrap()[0].valid);
+    assert!(matches!(backups.restore(&backup.name).await, Err(BackupError::Corrupt(_))));
+
+    // Right contents, but no checksum to prove it
+    fs::write(&path, text).unwrap();
+    let checksum = path.with_extension("json.sha256");
+    fs::remove_file(&checksum).unwrap();
+    assert!(matches!(backups.restore(&backup.name).await, Err(BackupError::Corrupt(_))));
+
+    for name in ["todos-nope.json", "../todos.json", "users.json"] {
+        assert!(matches!(backups.restore(name).await, Err(BackupError::NotFound(_))));
+    }
+    // Nothing was touched, nor backed up along the way
+    assert_eq!(titles(repo.as_ref()).await, ["first", "second"]);
+    assert_eq!(backups.list().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn only_admins_manage_backups() {
+    let dir = TempDir::new().unwrap();
+    let repo: Arc<dyn TodoRepository> = Arc::new(StorageRepository::in_memory());
+    let accounts = Accounts::in_memory();
+    let (_, admin_key) = accounts.bootstrap().unwrap().unwrap();
+    let (_, user_key) = accounts.create_user("alice", Role::User).unwrap();
+    let backups = Arc::new(Backups::new(repo.clone(), policy(&dir, 7), None));
+    let app = create_routes(repo, Some(Arc::new(accounts)), Some(backups));
+    let server = format!("{}/admin/backups", serve_app(app).await);
+    let http = reqwest::Client::new();
+
+    let response = http.post(&server).bearer_auth(&user_key).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = http.post(&server).bearer_auth(&admin_key).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let backup: Value = response.json().await.unwrap();
+
+    let list: Value =
+        http.get(&server).bearer_auth(&admin_key).send().await.unwrap().json().await.unwrap();
+    assert_eq!(list[0]["name"], backup["name"]);
+    assert_eq!(list[0]["valid"], true);
+    let restore = format!("{}/{}/restore", server, backup["name"].as_str().unwrap());
+    let response = http.post(&restore).bearer_auth(&admin_key).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http.post(format!("{}/nope/restore", server)).bearer_auth(&admin_key);
+    assert_eq!(response.send().await.unwrap().status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn restoring_a_backup_resets_every_event_stream() {
+    let dir = TempDir::new().unwrap();
+    let repo: Arc<dyn TodoRepository> = Arc::new(StorageRepository::in_memory());
+    let accounts = Accounts::in_memory();
+    let (_, admin_key) = accounts.bootstrap().unwrap().unwrap();
+    let (_, user_key) = accounts.create_user("alice", Role::User).unwrap();
+    let backups = Arc::new(Backups::new(repo.clone(), policy(&dir, 7), None));
+    let backup = backups.create().await.unwrap();
+    let app = create_routes(repo, Some(Arc::new(accounts)), Some(backups));
+    let server = serve_app(app).await;
+    let http = reqwest::Client::new();
+
+    // Not an admin, and still told: the restore replaced their todos too
+    let events = format!("{}/todos/events", server);
+    let mut stream = http.get(&events).bearer_auth(&user_key).send().await.unwrap();
+    let restore = format!("{}/admin/backups/{}/restore", server, backup.name);
+    let response = http.post(&restore).bearer_auth(&admin_key).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut received = String::new();
+    while !received.contains("event: reset") {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), stream.chunk());
+        let chunk = chunk.await.expect("a reset event").unwrap().unwrap();
+        received.push_str(&String::from_utf8_lossy(&chunk));
+    }
+    assert!(received.contains("id: "));
+}
+
+#[tokio::test]
+async fn schedule_counts_from_the_newest_backup() {
+    let dir = TempDir::new().unwrap();
+    let repo: Arc<dyn TodoRepository> = Arc::new(StorageRepository::in_memory());
+    let hourly = BackupPolicy {
+        every: Some(std::time::Duration::from_secs(3600)),
+        ..policy(&dir, 7)
+    };
+    let backups = Arc::new(Backups::new(repo, hourly, None));
+    let now = chrono::Utc::now();
+    // None yet: the first one right away
+    assert_eq!(backups.next_scheduled(now), Some(now));
+
+    // A restart half an hour after a backup waits for the other half
+    let backup = backups.create().await.unwrap();
+    let next = backups.next_scheduled(now).unwrap();
+    // The name keeps milliseconds
+    let off = next - (backup.created_at + chrono::Duration::hours(1));
+    assert!(off.num_milliseconds().abs() < 1, "{:?}", off);
+
+    // One from two hours ago is overdue: the schedule backs up on start
+    let old = "todos-20200101T000000.000Z.json";
+    let dir = &backups.policy().dir;
+    fs::rename(dir.join(&backup.name), dir.join(old)).unwrap();
+    fs::remove_file(dir.join(format!("{}.sha256", backup.name))).unwrap();
+    assert!(backups.next_scheduled(now).unwrap() < now);
+    let schedule = backup::spawn_schedule(backups.clone()).unwrap();
+    for _ in 0..50 {
+        if backups.list().unwrap().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    schedule.abort();
+    assert_eq!(backups.list().unwrap().len(), 2);
+}
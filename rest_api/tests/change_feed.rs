@@ -0,0 +1,173 @@
+// Todo change events behind GET /todos/events: what gets published and how
+// reconnecting clients resume.
+
+mod common;
+
+use common::create;
+use rest_api::events::{Backlog, ChangeFeed, EventId, TodoChange, TodoEvent};
+use rest_api::models::{BatchOperation, UpdateTodoRequest};
+use rest_api::repository::{EventedRepository, StorageRepository, TodoRepository};
+use std::sync::Arc;
+
+fn evented() -> (EventedRepository, Arc<ChangeFeed>) {
+    let feed = Arc::new(ChangeFeed::new());
+    let repo = EventedRepository::new(Arc::new(StorageRepository::in_memory()), feed.clone());
+    (repo, feed)
+}
+
+// An id from this feed's run
+fn id(feed: &ChangeFeed, seq: u64) -> EventId {
+    EventId {
+        epoch: feed.epoch(),
+        seq,
+    }
+}
+
+fn backlog(feed: &ChangeFeed, last_seen: Option<u64>) -> Vec<TodoEvent> {
+    match feed.subscribe(last_seen.map(|seq| id(feed, seq))).0 {
+        Backlog::Events(events) => events,
+        Backlog::Reset { .. } => panic!("expected events, got a reset"),
+    }
+}
+
+#[tokio::test]
+async fn writes_are_published_in_order() {
+    let (repo, feed) = evented();
+    let (_, mut receiver) = feed.subscribe(None);
+
+    let todo = repo.create(create("Learn Rust")).await.unwrap();
+    let done = UpdateTodoRequest {
+        completed: Some(true),
+        ..Default::default()
+    };
+    repo.update(todo.id, done, None).await.unwrap();
+    repo.delete(todo.id, None).await.unwrap();
+    let trashed = repo.trash().await.unwrap().remove(0);
+    repo.restore(trashed).await.unwrap();
+    // Nothing happened, nothing is published
+    assert!(!repo.delete(99, None).await.unwrap());
+
+    let mut names = Vec::new();
+    let mut seqs = Vec::new();
+    while let Ok(event) = receiver.try_recv() {
+        names.push(event.change.name());
+        seqs.push(event.seq);
+    }
+    assert_eq!(names, ["created", "updated", "deleted", "created"]);
+    assert_eq!(seqs, [1, 2, 3, 4]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_writes_are_published_in_the_order_they_were_stored() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let feed = Arc::new(ChangeFeed::new());
+    let inner = StorageRepository::json_file(dir.path().join("todos.json")).unwrap();
+    let repo = Arc::new(EventedRepository::new(Arc::new(inner), feed.clone()));
+    let todo = repo.create(create("Counter")).await.unwrap();
+
+    let writers: Vec<_> = (0..8)
+        .map(|writer| {
+            let repo = repo.clone();
+            tokio::spawn(async move {
+                for round in 0..25 {
+                    let rename = UpdateTodoRequest {
+                        title: Some(format!("{} by {}", round, writer)),
+                        ..Default::default()
+                    };
+                    repo.update(todo.id, rename, None).await.unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+
+    let revisions: Vec<u64> = backlog(&feed, Some(0))
+        .into_iter()
+        .map(|event| match event.change {
+            TodoChange::Created { todo } | TodoChange::Updated { todo } => todo.revision,
+            other => panic!("expected writes, got {:?}", other),
+        })
+        .collect();
+    let stored: Vec<u64> = (1..=201).collect();
+    assert_eq!(revisions, stored);
+}
+
+#[tokio::test]
+async fn batch_publishes_one_event_per_operation() {
+    let (repo, feed) = evented();
+    let ops = vec![
+        BatchOperation::Create { todo: create("a") },
+        BatchOperation::Create { todo: create("b") },
+        BatchOperation::Delete {
+            id: 1,
+            revision: None,
+        },
+    ];
+    repo.batch(ops).await.unwrap();
+
+    let events = backlog(&feed, Some(0));
+    assert_eq!(events.len(), 3);
+    assert!(matches!(&events[1].change, TodoChange::Created { todo } if todo.title == "b"));
+    assert!(matches!(events[2].change, TodoChange::Deleted { id: 1, .. }));
+}
+
+#[tokio::test]
+async fn resume_replays_only_missed_events() {
+    let (repo, feed) = evented();
+    for title in ["a", "b", "c"] {
+        repo.create(create(title)).await.unwrap();
+    }
+
+    let seqs: Vec<u64> = backlog(&feed, Some(1)).iter().map(|event| event.seq).collect();
+    assert_eq!(seqs, [2, 3]);
+    assert!(backlog(&feed, Some(3)).is_empty());
+    assert!(backlog(&feed, None).is_empty());
+}
+
+#[test]
+fn unknown_or_evicted_ids_reset_the_client() {
+    let feed = ChangeFeed::new();
+    // Not handed out yet
+    let reset = feed.subscribe(Some(id(&feed, 5))).0;
+    assert!(matches!(reset, Backlog::Reset { last } if last.seq == 0));
+
+    for id in 0..1500 {
+        feed.publish(TodoChange::Deleted { id, owner_id: 1 });
+    }
+    let reset = feed.subscribe(Some(id(&feed, 10))).0;
+    assert!(matches!(reset, Backlog::Reset { last } if last.seq == 1500));
+    // The oldest event still kept is 501
+    assert_eq!(backlog(&feed, Some(500)).len(), 1000);
+}
+
+#[test]
+fn ids_from_before_a_restart_reset_the_client() {
+    let before = ChangeFeed::new();
+    for id in 0..3 {
+        before.publish(TodoChange::Deleted { id, owner_id: 1 });
+    }
+    let last_seen = id(&before, 1);
+
+    // Restarted, and the new run has already gone past the old id
+    let after = ChangeFeed::new();
+    assert_ne!(after.epoch(), before.epoch());
+    for id in 0..5 {
+        after.publish(TodoChange::Deleted { id, owner_id: 1 });
+    }
+    match after.subscribe(Some(last_seen)).0 {
+        Backlog::Reset { last } => assert_eq!(last, id(&after, 5)),
+        Backlog::Events(events) => panic!("replayed {} unrelated events", events.len()),
+    }
+}
+
+#[test]
+fn event_ids_round_trip() {
+    let feed = ChangeFeed::new();
+    let event = feed.publish(TodoChange::Deleted { id: 1, owner_id: 1 });
+    let text = event.id().to_string();
+    assert_eq!(text.parse::<EventId>(), Ok(id(&feed, 1)));
+    // Plain sequence numbers, as sent before epochs, are unknown ids
+    assert!("7".parse::<EventId>().is_err());
+}
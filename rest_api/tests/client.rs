@@ -0,0 +1,87 @@
+// The HTTP client and the `todo` binary against a server on a free port.
+
+mod common;
+
+use common::{create, serve};
+use rest_api::client::{Client, ClientError, ListFilter};
+use rest_api::models::{CreateTodoRequest, Todo, UpdateTodoRequest};
+use tokio::process::Command;
+
+// Exit code and stdout of `todo <args>`
+async fn todo(server: &str, args: &[&str]) -> (i32, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_todo"))
+        .arg("--server")
+        .arg(server)
+        .args(args)
+        .output()
+        .await
+        .unwrap();
+    (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap())
+}
+
+#[tokio::test]
+async fn client_creates_lists_updates_and_deletes() {
+    let client = Client::new(&format!("{}/", serve().await));
+    let first = client.create(&create("Learn Rust")).await.unwrap();
+    let second = client.create(&create("Build REST API")).await.unwrap();
+    let done = UpdateTodoRequest {
+        completed: Some(true),
+        ..Default::default()
+    };
+    assert!(client.update(first.id, &done).await.unwrap().completed);
+
+    let pending = ListFilter {
+        completed: Some(false),
+        ..Default::default()
+    };
+    let ids: Vec<u32> = client.list(&pending).await.unwrap().iter().map(|t| t.id).collect();
+    assert_eq!(ids, [second.id]);
+    assert_eq!(client.list(&ListFilter::default()).await.unwrap().len(), 2);
+
+    client.delete(first.id, false).await.unwrap();
+    let err = client.get(first.id).await.unwrap_err();
+    assert!(err.is_not_found());
+    let orphan = CreateTodoRequest {
+        parent_id: Some(999),
+        ..create("orphan")
+    };
+    let err = client.create(&orphan).await.unwrap_err();
+    assert!(matches!(err, ClientError::Api { ref code, .. } if code == "invalid_parent"));
+}
+
+#[tokio::test]
+async fn client_lists_every_page() {
+    let client = Client::new(&serve().await);
+    for index in 0..1005 {
+        client.create(&create(&format!("todo {}", index))).await.unwrap();
+    }
+    assert_eq!(client.list(&ListFilter::default()).await.unwrap().len(), 1005);
+}
+
+#[tokio::test]
+async fn cli_prints_json_and_exits_with_status() {
+    let server = serve().await;
+    let args = ["add", "Buy milk", "--due", "2025-01-31", "-o", "json"];
+    let (code, out) = todo(&server, &args).await;
+    assert_eq!(code, 0);
+    let todo_id = serde_json::from_str::<Todo>(&out).unwrap().id;
+    let id = todo_id.to_string();
+
+    let (code, out) = todo(&server, &["done", &id, "999", "--output", "json"]).await;
+    assert_eq!(code, 3);
+    let done: Vec<Todo> = serde_json::from_str(&out).unwrap();
+    assert!(done[0].completed);
+
+    let (code, out) = todo(&server, &["list", "--done"]).await;
+    assert_eq!(code, 0);
+    assert!(out.starts_with("ID  DONE  PRIORITY  DUE         TAGS  TITLE\n"));
+    assert!(out.contains("[x]   medium    2025-01-31        Buy milk"));
+    let (_, out) = todo(&server, &["list", "--pending", "-o", "json"]).await;
+    assert_eq!(out.trim(), "[]");
+
+    assert_eq!(todo(&server, &["add", "orphan", "--parent", "999"]).await.0, 1);
+    assert_eq!(todo(&server, &["list", "--pending", "--done"]).await.0, 2);
+    assert_eq!(todo(&server, &["rm", &id]).await, (0, format!("Deleted todo {}\n", id)));
+    assert_eq!(todo(&server, &["show", &id]).await.0, 3);
+    assert_eq!(todo("http://127.0.0.1:1", &["list"]).await.0, 4);
+}
@@ -0,0 +1,28 @@
+// Fixtures shared by the test files; each file uses only some of them.
+#![allow(dead_code)]
+
+use axum::Router;
+use rest_api::models::CreateTodoRequest;
+use rest_api::repository::StorageRepository;
+use rest_api::routers::create_routes;
+use std::sync::Arc;
+
+pub fn create(title: &str) -> CreateTodoRequest {
+    CreateTodoRequest {
+        title: title.to_string(),
+        ..Default::default()
+    }
+}
+
+// Base URL of `app` served on a free port
+pub async fn serve_app(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    server
+}
+
+// Base URL of a fresh in-memory server without accounts
+pub async fn serve() -> String {
+    serve_app(create_routes(Arc::new(StorageRepository::in_memory()), None, None)).await
+}
@@ -0,0 +1,187 @@
+// Server configuration: flags over environment over TOML file over
+// defaults, and validation before startup.
+
+use chrono::Duration;
+use clap::Parser;
+use rest_api::auth::AccountStore;
+use rest_api::config::{Config, Flags, Settings};
+use rest_api::logging::LogLevel;
+use rest_api::repository::Backend;
+use rest_api::writer::Durability;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+fn flags(args: &[&str]) -> Flags {
+    Flags::try_parse_from(std::iter::once("rest_api").chain(args.iter().copied())).unwrap()
+}
+
+// Environment lookup over a fixed set of variables
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> + use<> {
+    let vars: HashMap<String, String> =
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+    move |name| vars.get(name).cloned()
+}
+
+fn problems(result: Result<Config, rest_api::config::ConfigError>) -> Vec<String> {
+    result.unwrap_err().0
+}
+
+#[test]
+fn defaults_apply_without_any_settings() {
+    let config = Config::from_settings(Settings::default()).unwrap();
+    assert_eq!(config.addr.to_string(), "127.0.0.1:3000");
+    assert_eq!(config.log_level, LogLevel::Info);
+    let default_path = PathBuf::from("todos.json");
+    assert!(matches!(config.backend, Backend::Json(ref path) if path == &default_path));
+    assert_eq!(config.history.max_revisions, 50);
+    assert_eq!(config.trash.max_age, Some(Duration::days(30)));
+    assert_eq!(config.durability, Durability::Sync);
+}
+
+#[test]
+fn flags_beat_environment_beat_file() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("data")).unwrap();
+    let file = dir.path().join("server.toml");
+    fs::write(
+        &file,
+        "[server]\n\
+         port = 4000\n\
+         log_level = \"warn\"\n\
+         [storage]\n\
+         backend = \"sqlite\"\n\
+         data_path = \"data/todos.db\"\n\
+         trash_days = 7\n",
+    )
+    .unwrap();
+    let file = file.to_str().unwrap();
+
+    let vars = env(&[("TODO_PORT", "5000"), ("TODO_HISTORY_LIMIT", "10"), ("TODO_HOST", "")]);
+    let config = Config::load(flags(&["--config", file, "--port", "6000"]), vars).unwrap();
+    assert_eq!(config.addr.to_string(), "127.0.0.1:6000");
+    assert_eq!(config.log_level, LogLevel::Warn);
+    // Relative to the file, not the working directory
+    let data = dir.path().join("data/todos.db");
+    assert!(matches!(config.backend, Backend::Sqlite(ref path) if path == &data));
+    assert_eq!(config.history.max_revisions, 10);
+    assert_eq!(config.trash.max_age, Some(Duration::days(7)));
+    assert_eq!(config.file.as_deref(), Some(PathBuf::from(file).as_path()));
+
+    // TODO_CONFIG names the file when the flag does not
+    let vars = env(&[("TODO_CONFIG", file), ("TODO_LOG_LEVEL", "DEBUG")]);
+    let config = Config::load(flags(&[]), vars).unwrap();
+    assert_eq!(config.addr.port(), 4000);
+    assert_eq!(config.log_level, LogLevel::Debug);
+}
+
+#[test]
+fn every_problem_is_reported_at_once() {
+    let vars = [("TODO_PORT", "70000"), ("TODO_TRASH_DAYS", "-1"), ("TODO_BACKEND", "mongo")];
+    let vars = env(&vars);
+    let args = ["--log-level", "loud", "--history-days", "0", "--host", "not a host"];
+    assert_eq!(
+        problems(Config::load(flags(&args), vars)),
+        [
+            "TODO_PORT '70000' is not a port",
+            "host 'not a host' is not an IP address or known host name",
+            "unknown log level 'loud', expected error, warn, info or debug",
+            "unknown backend 'mongo', expected json, memory or sqlite",
+            "history_days must be positive, not 0",
+            "trash_days cannot be negative, not -1",
+        ]
+    );
+    assert!(Flags::try_parse_from(["rest_api", "--port", "http"]).is_err());
+}
+
+#[test]
+fn files_and_data_paths_are_checked() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("server.toml");
+    fs::write(&file, "[server]\nport = 3000\nthreads = 4\n").unwrap();
+    let file = file.to_str().unwrap();
+    let found = problems(Config::load(flags(&["-c", file]), env(&[])));
+    assert!(found[0].contains("unknown field `threads`"), "{:?}", found);
+
+    let missing = dir.path().join("missing.toml");
+    let found = problems(Config::load(flags(&["-c", missing.to_str().unwrap()]), env(&[])));
+    assert!(found[0].starts_with("cannot read"));
+
+    let nowhere = dir.path().join("nowhere/todos.json");
+    let args = ["--data-path", nowhere.to_str().unwrap()];
+    let found = problems(Config::load(flags(&args), env(&[])));
+    let expected = format!("the directory of data_path {} does not exist", nowhere.display());
+    assert_eq!(found, [expected]);
+    let dir_path = dir.path().to_str().unwrap();
+    let found = problems(Config::load(flags(&["--data-path", dir_path]), env(&[])));
+    assert_eq!(found, [format!("data_path {} is a directory", dir_path)]);
+
+    // The memory backend keeps nothing, so its data path is not looked at
+    let vars = env(&[("TODO_BACKEND", "memory"), ("TODO_DATA_PATH", dir_path)]);
+    let config = Config::load(flags(&["--trash-days", "0"]), vars).unwrap();
+    assert!(matches!(config.backend, Backend::Memory));
+    assert_eq!(config.trash.max_age, None);
+}
+
+#[test]
+fn durability_is_sync_or_debounced() {
+    let vars = env(&[("TODO_DURABILITY", "debounced")]);
+    let config = Config::load(flags(&["--flush-ms", "200"]), vars).unwrap();
+    let delay = std::time::Duration::from_millis(200);
+    assert_eq!(config.durability, Durability::Debounced { delay });
+
+    let vars = env(&[("TODO_DURABILITY", "eventually"), ("TODO_FLUSH_MS", "600000")]);
+    assert_eq!(
+        problems(Config::load(flags(&[]), vars)),
+        [
+            "unknown durability 'eventually', expected sync or debounced",
+            "flush_ms cannot be above 10000",
+        ]
+    );
+}
+
+#[test]
+fn backups_go_next_to_the_data_file() {
+    let config = Config::load(flags(&["--data-path", "data.json"]), env(&[])).unwrap();
+    let policy = config.backups.unwrap();
+    assert_eq!(policy.dir, PathBuf::from("backups"));
+    assert_eq!(policy.keep, 7);
+    assert_eq!(policy.every, Some(std::time::Duration::from_secs(24 * 60 * 60)));
+
+    let vars = env(&[("TODO_BACKUP_HOURS", "0"), ("TODO_BACKUP_DIR", "/tmp")]);
+    let policy = Config::load(flags(&["--backup-keep", "3"]), vars).unwrap().backups.unwrap();
+    assert_eq!((policy.dir, policy.keep, policy.every), (PathBuf::from("/tmp"), 3, None));
+    // Nowhere to put them by default, or none to keep
+    assert!(Config::load(flags(&["--backend", "memory"]), env(&[])).unwrap().backups.is_none());
+    let vars = env(&[("TODO_BACKUP_KEEP", "0")]);
+    assert!(Config::load(flags(&[]), vars).unwrap().backups.is_none());
+
+    let vars = env(&[("TODO_BACKUP_HOURS", "100000"), ("TODO_BACKUP_DIR", "Cargo.toml")]);
+    assert_eq!(
+        problems(Config::load(flags(&[]), vars)),
+        ["backup_hours cannot be above 8760", "backup_dir Cargo.toml is not a directory"]
+    );
+}
+
+#[test]
+fn accounts_are_only_on_when_asked_for() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = dir.path().join("todos.json");
+    let args = ["--data-path", data.to_str().unwrap()];
+    let accounts = |extra: &[&str], vars: &[(&str, &str)]| {
+        let args: Vec<&str> = args.iter().chain(extra).copied().collect();
+        Config::load(flags(&args), env(vars)).unwrap().accounts
+    };
+    let users = AccountStore::File(dir.path().join("users.json"));
+    assert_eq!(accounts(&[], &[]), None);
+    assert_eq!(accounts(&["--auth"], &[]), Some(users.clone()));
+    assert_eq!(accounts(&[], &[("TODO_AUTH", "true")]), Some(users.clone()));
+    // Existing users do not turn them on either
+    fs::write(dir.path().join("users.json"), "{}").unwrap();
+    assert_eq!(accounts(&[], &[]), None);
+    assert_eq!(accounts(&["--auth", "false"], &[("TODO_AUTH", "true")]), None);
+
+    // No data directory for the first admin's key
+    let memory = Config::load(flags(&["--backend", "memory", "--auth"]), env(&[]));
+    assert_eq!(problems(memory), ["auth needs the json or sqlite backend"]);
+}
@@ -0,0 +1,163 @@
+// Encryption at rest of the JSON backend: nothing readable on disk, wrong
+// keys and tampering refused, key rotation and recovery.
+
+mod common;
+
+use common::create;
+use clap::Parser;
+use rest_api::config::{Config, Flags};
+use rest_api::encryption::Cipher;
+use rest_api::error::StorageError;
+use rest_api::repository::{StorageRepository, TodoRepository};
+use rest_api::storage::Storage;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn new_cipher() -> Cipher {
+    Cipher::from_key(&Cipher::generate_key().unwrap()).unwrap()
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+fn is_encryption_error(result: Result<StorageRepository, StorageError>) -> bool {
+    matches!(result, Err(StorageError::Encryption(_)))
+}
+
+#[tokio::test]
+async fn nothing_readable_reaches_the_disk() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let cipher = new_cipher();
+    {
+        let repo = StorageRepository::encrypted_json_file(&json, cipher.clone()).unwrap();
+        repo.create(create("Buy a birthday present")).await.unwrap();
+    }
+    // The todo sits in the journal, the list in the snapshot
+    let journal = read(&json.with_extension("journal"));
+    assert!(!journal.is_empty() && !journal.contains("birthday"));
+    assert!(!read(&json).contains("Inbox"));
+    assert!(read(&json).contains(cipher.id()));
+
+    let repo = StorageRepository::encrypted_json_file(&json, cipher).unwrap();
+    assert_eq!(repo.get(1).await.unwrap().unwrap().title, "Buy a birthday present");
+}
+
+#[tokio::test]
+async fn wrong_key_and_tampering_are_refused() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let cipher = new_cipher();
+    {
+        let repo = StorageRepository::encrypted_json_file(&json, cipher.clone()).unwrap();
+        repo.create(create("secret")).await.unwrap();
+    }
+    assert!(is_encryption_error(StorageRepository::json_file(&json)));
+    assert!(is_encryption_error(StorageRepository::encrypted_json_file(&json, new_cipher())));
+    // Refused files are left alone, not quarantined as corrupt
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+    // One changed character of the encrypted snapshot
+    let mut envelope: Value = serde_json::from_str(&read(&json)).unwrap();
+    let data = envelope["data"].as_str().unwrap();
+    let flipped = if data.starts_with('A') { "B" } else { "A" };
+    envelope["data"] = format!("{}{}", flipped, &data[1..]).into();
+    fs::write(&json, envelope.to_string()).unwrap();
+    assert!(is_encryption_error(StorageRepository::encrypted_json_file(&json, cipher)));
+}
+
+#[tokio::test]
+async fn plain_records_in_an_encrypted_journal_are_refused() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let journal = json.with_extension("journal");
+    let cipher = new_cipher();
+    {
+        let repo = StorageRepository::encrypted_json_file(&json, cipher.clone()).unwrap();
+        repo.create(create("keep me")).await.unwrap();
+    }
+    // A well-formed record with a valid checksum, just not sealed
+    let forged = r#"{"op":"delete","id":1}"#;
+    let line = format!("{:08x} {}\n", crc32fast::hash(forged.as_bytes()), forged);
+    let before = read(&journal);
+    fs::write(&journal, format!("{}{}", before, line)).unwrap();
+
+    assert!(is_encryption_error(StorageRepository::encrypted_json_file(&json, cipher.clone())));
+    // Refused, not truncated away
+    assert!(read(&journal).ends_with(&line));
+
+    fs::write(&journal, before).unwrap();
+    let repo = StorageRepository::encrypted_json_file(&json, cipher).unwrap();
+    assert_eq!(repo.get(1).await.unwrap().unwrap().title, "keep me");
+}
+
+#[tokio::test]
+async fn plain_files_are_encrypted_and_keys_rotated() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        repo.create(create("from before")).await.unwrap();
+    }
+    let old = new_cipher();
+    // Configuring a key does not encrypt a plain file, nor touch it
+    let plain = read(&json);
+    assert!(is_encryption_error(StorageRepository::encrypted_json_file(&json, old.clone())));
+    assert_eq!(read(&json), plain);
+    // That takes a rotation without a key
+    Storage::open(&json, None).unwrap().reencrypt(Some(old.clone())).unwrap();
+    assert!(!read(&json).contains("from before"));
+    assert!(read(&json.with_extension("journal")).is_empty());
+
+    let new = new_cipher();
+    let mut storage = Storage::open(&json, Some(old.clone())).unwrap();
+    storage.reencrypt(Some(new.clone())).unwrap();
+    assert!(storage.export().unwrap().contains("from before"));
+    drop(storage);
+    assert!(is_encryption_error(StorageRepository::encrypted_json_file(&json, old)));
+    let repo = StorageRepository::encrypted_json_file(&json, new.clone()).unwrap();
+    assert_eq!(repo.get(1).await.unwrap().unwrap().title, "from before");
+    drop(repo);
+
+    // And back to a plain file
+    Storage::open(&json, Some(new)).unwrap().reencrypt(None).unwrap();
+    assert!(read(&json).contains("from before"));
+}
+
+#[test]
+fn keys_come_from_a_file_or_the_environment() {
+    let dir = TempDir::new().unwrap();
+    let key_file = dir.path().join("todos.key");
+    fs::write(&key_file, format!("{}\n", Cipher::generate_key().unwrap())).unwrap();
+    let key_file = key_file.to_str().unwrap();
+    let load = |args: &[&str], vars: &[(&str, &str)]| {
+        let vars: Vec<(String, String)> =
+            vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        let flags = Flags::try_parse_from(["rest_api"].iter().chain(args)).unwrap();
+        Config::load(flags, move |name| {
+            vars.iter().find(|(var, _)| var == name).map(|(_, value)| value.clone())
+        })
+    };
+
+    let config = load(&["--key-file", key_file], &[]).unwrap();
+    let from_file = config.cipher.unwrap();
+    let key = Cipher::generate_key().unwrap();
+    let config = load(&[], &[("TODO_ENCRYPTION_KEY", &key)]).unwrap();
+    assert_ne!(config.cipher.unwrap().id(), from_file.id());
+    assert!(load(&[], &[]).unwrap().cipher.is_none());
+
+    let problems = load(&["--key-file", key_file], &[("TODO_ENCRYPTION_KEY", "c2hvcnQ=")])
+        .unwrap_err()
+        .0;
+    assert_eq!(problems, ["set TODO_ENCRYPTION_KEY or key_file, not both"]);
+    let problems = load(&[], &[("TODO_ENCRYPTION_KEY", "c2hvcnQ=")]).unwrap_err().0;
+    assert_eq!(
+        problems,
+        ["TODO_ENCRYPTION_KEY: a key is 32 bytes in base64, `rest_api keygen` makes one"]
+    );
+    let problems = load(&["--backend", "sqlite", "--key-file", key_file], &[]).unwrap_err().0;
+    assert_eq!(problems, ["encryption needs the json backend"]);
+}
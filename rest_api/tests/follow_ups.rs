@@ -0,0 +1,231 @@
+// Writes that take other todos along (subtasks, parents, the next occurrence
+// of a series) against a live server: all of it is stored, or none of it.
+
+mod common;
+
+use common::{create, serve, serve_app};
+use rest_api::client::{Client, ListFilter};
+use rest_api::models::{CreateTodoRequest, Todo, UpdateTodoRequest};
+use rest_api::repository::StorageRepository;
+use rest_api::routers::create_routes;
+use serde_json::{Value, json};
+use std::fs;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+// Ids of the todos GET /todos lists, sorted
+async fn ids(client: &Client) -> Vec<u32> {
+    let mut ids: Vec<u32> =
+        client.list(&ListFilter::default()).await.unwrap().iter().map(|t| t.id).collect();
+    ids.sort();
+    ids
+}
+
+// `Move house` with `Pack` below it and `Buy boxes` below that
+async fn subtree(client: &Client) -> [Todo; 3] {
+    let parent = client.create(&create("Move house")).await.unwrap();
+    let child = CreateTodoRequest {
+        parent_id: Some(parent.id),
+        ..create("Pack")
+    };
+    let child = client.create(&child).await.unwrap();
+    let grandchild = CreateTodoRequest {
+        parent_id: Some(child.id),
+        ..create("Buy boxes")
+    };
+    let grandchild = client.create(&grandchild).await.unwrap();
+    [parent, child, grandchild]
+}
+
+#[tokio::test]
+async fn cascade_deletes_the_whole_subtree_or_nothing() {
+    let server = serve().await;
+    let client = Client::new(&server);
+    let [parent, child, grandchild] = subtree(&client).await;
+
+    // A stale `If-Match` keeps the subtasks too, not just the parent
+    let http = reqwest::Client::new();
+    let url = format!("{}/todos/{}?cascade=true", server, parent.id);
+    let stale = format!("\"{}\"", parent.revision + 1);
+    let response = http.delete(&url).header("if-match", stale).send().await.unwrap();
+    assert_eq!(response.status(), 412);
+    assert_eq!(ids(&client).await, [parent.id, child.id, grandchild.id]);
+
+    let current = format!("\"{}\"", parent.revision);
+    let response = http.delete(&url).header("if-match", current).send().await.unwrap();
+    assert_eq!(response.status(), 204);
+    assert!(ids(&client).await.is_empty());
+}
+
+#[tokio::test]
+async fn trash_restore_brings_the_subtree_back_in_one_batch() {
+    let dir = TempDir::new().unwrap();
+    let repo = StorageRepository::json_file(dir.path().join("todos.json")).unwrap();
+    let server = serve_app(create_routes(Arc::new(repo), None, None)).await;
+    let client = Client::new(&server);
+    let [parent, child, grandchild] = subtree(&client).await;
+    let http = reqwest::Client::new();
+    let url = format!("{}/todos/{}?cascade=true", server, parent.id);
+    assert_eq!(http.delete(&url).send().await.unwrap().status(), 204);
+
+    let journal = dir.path().join("todos.journal");
+    let records = fs::read_to_string(&journal).unwrap().lines().count();
+    let url = format!("{}/trash/{}/restore", server, parent.id);
+    let tree: Value = http.post(&url).send().await.unwrap().json().await.unwrap();
+    assert_eq!(tree["children"][0]["children"][0]["id"], grandchild.id);
+    assert_eq!(ids(&client).await, [parent.id, child.id, grandchild.id]);
+    assert_eq!(fs::read_to_string(&journal).unwrap().lines().count(), records + 1);
+}
+
+#[tokio::test]
+async fn stale_pushed_delete_keeps_the_subtasks() {
+    let server = serve().await;
+    let client = Client::new(&server);
+    let [parent, child, grandchild] = subtree(&client).await;
+    // Changed on the server after the client last saw it
+    let renamed = UpdateTodoRequest {
+        title: Some("Move flat".to_string()),
+        ..Default::default()
+    };
+    client.update(parent.id, &renamed).await.unwrap();
+
+    let http = reqwest::Client::new();
+    let push = |revision: u64| {
+        let body = json!({"changes": [{"op": "delete", "id": parent.id, "revision": revision}]});
+        http.post(format!("{}/todos/changes", server)).json(&body).send()
+    };
+    let response: Value = push(parent.revision).await.unwrap().json().await.unwrap();
+    assert_eq!(response["results"][0]["result"], "conflict");
+    assert_eq!(response["results"][0]["current"]["title"], "Move flat");
+    assert_eq!(ids(&client).await, [parent.id, child.id, grandchild.id]);
+
+    let response: Value = push(parent.revision + 1).await.unwrap().json().await.unwrap();
+    assert_eq!(response["results"][0]["result"], "applied");
+    assert!(ids(&client).await.is_empty());
+}
+
+#[tokio::test]
+async fn concurrent_completions_continue_the_series_once() {
+    let server = serve().await;
+    let client = Client::new(&server);
+    let gym: CreateTodoRequest =
+        serde_json::from_value(json!({"title": "Gym", "recurrence": "FREQ=DAILY"})).unwrap();
+    let gym = client.create(&gym).await.unwrap();
+
+    let done = UpdateTodoRequest {
+        completed: Some(true),
+        ..Default::default()
+    };
+    let completions = (0..8).map(|_| client.update(gym.id, &done));
+    for result in futures_util::future::join_all(completions).await {
+        result.unwrap();
+    }
+
+    let todos = client.list(&ListFilter::default()).await.unwrap();
+    assert_eq!(todos.len(), 2);
+    let next = todos.iter().find(|t| t.id != gym.id).unwrap();
+    assert!(!next.completed && next.recurrence.is_some());
+    let gym = todos.iter().find(|t| t.id == gym.id).unwrap();
+    assert!(gym.completed && gym.recurrence.is_none());
+}
+
+#[tokio::test]
+async fn last_subtask_done_completes_the_parent_and_continues_its_series() {
+    let server = serve().await;
+    let client = Client::new(&server);
+    let weekly: CreateTodoRequest = serde_json::from_value(
+        json!({"title": "Clean up", "recurrence": "FREQ=WEEKLY", "auto_complete": true}),
+    )
+    .unwrap();
+    let weekly = client.create(&weekly).await.unwrap();
+    let dishes = CreateTodoRequest {
+        parent_id: Some(weekly.id),
+        ..create("Dishes")
+    };
+    let dishes = client.create(&dishes).await.unwrap();
+
+    let done = UpdateTodoRequest {
+        completed: Some(true),
+        ..Default::default()
+    };
+    client.update(dishes.id, &done).await.unwrap();
+
+    let todos = client.list(&ListFilter::default()).await.unwrap();
+    let parent = todos.iter().find(|t| t.id == weekly.id).unwrap();
+    assert!(parent.completed && parent.recurrence.is_none());
+    let next: Vec<&Todo> = todos.iter().filter(|t| t.title == "Clean up" && !t.completed).collect();
+    assert_eq!(next.len(), 1);
+    assert!(next[0].recurrence.is_some());
+}
+
+#[tokio::test]
+async fn batch_results_include_the_follow_up_writes() {
+    let server = serve().await;
+    let client = Client::new(&server);
+    let gym: CreateTodoRequest =
+        serde_json::from_value(json!({"title": "Gym", "recurrence": "FREQ=DAILY"})).unwrap();
+    let gym = client.create(&gym).await.unwrap();
+    let project: CreateTodoRequest =
+        serde_json::from_value(json!({"title": "Project", "auto_complete": true})).unwrap();
+    let project = client.create(&project).await.unwrap();
+    let report = CreateTodoRequest {
+        parent_id: Some(project.id),
+        ..create("Report")
+    };
+    let report = client.create(&report).await.unwrap();
+
+    // The parent is renamed at the revision the client read, before the
+    // completed subtask completes it
+    let body = json!({"operations": [
+        {"op": "update", "id": gym.id, "changes": {"completed": true}},
+        {"op": "update", "id": report.id, "changes": {"completed": true}},
+        {"op": "update", "id": project.id, "changes": {"title": "Project X"},
+            "revision": project.revision},
+    ]});
+    let response = reqwest::Client::new()
+        .post(format!("{}/todos/batch", server))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response: Value = response.json().await.unwrap();
+    assert_eq!(response["committed"], true);
+    assert_eq!(response["results"][0]["todo"]["recurrence"], Value::Null);
+    assert_eq!(response["results"][0]["todo"]["revision"], gym.revision + 2);
+
+    let todos = client.list(&ListFilter::default()).await.unwrap();
+    assert_eq!(todos.len(), 4);
+    let project = todos.iter().find(|t| t.id == project.id).unwrap();
+    assert!(project.completed);
+    assert_eq!(project.title, "Project X");
+}
+
+#[tokio::test]
+async fn import_is_one_batch() {
+    let dir = TempDir::new().unwrap();
+    let repo = StorageRepository::json_file(dir.path().join("todos.json")).unwrap();
+    let server = serve_app(create_routes(Arc::new(repo), None, None)).await;
+
+    let text = "- [ ] Trip\n  - [x] Book hotel\n    - [ ] Compare prices\n- [ ] Pack\n";
+    let response = reqwest::Client::new()
+        .post(format!("{}/todos/import?format=markdown", server))
+        .body(text)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["created"], 4);
+    let journal = fs::read_to_string(dir.path().join("todos.journal")).unwrap();
+    assert_eq!(journal.lines().count(), 1);
+
+    // Subtasks point at the todos created before them in the same batch
+    let items = report["items"].as_array().unwrap();
+    let id = |index: usize| items[index]["todo"]["id"].clone();
+    assert_eq!(items[1]["todo"]["parent_id"], id(0));
+    assert_eq!(items[2]["todo"]["parent_id"], id(1));
+    assert_eq!(items[1]["todo"]["completed"], true);
+    assert!(items[1]["todo"]["completed_at"].is_string());
+    assert_eq!(items[3]["todo"]["parent_id"], Value::Null);
+}
@@ -0,0 +1,232 @@
+// Export/import formats behind GET /todos/export and POST /todos/import.
+
+use chrono::{TimeZone, Utc};
+use rest_api::formats::{self, Format, ImportedTodo, Target, ical};
+use rest_api::models::{CreateTodoRequest, Priority, Todo};
+
+fn todo(id: u32, title: &str, parent_id: Option<u32>) -> Todo {
+    let request = CreateTodoRequest {
+        title: title.to_string(),
+        parent_id,
+        ..Default::default()
+    };
+    Todo::new(id, request)
+}
+
+// A parent with a completed, tagged subtask and an unrelated todo
+fn sample() -> Vec<Todo> {
+    let mut report = todo(1, "Write report, part \"one\"", None);
+    report.description = Some("Two\nlines".to_string());
+    report.priority = Priority::Urgent;
+    report.due_at = Some(Utc.with_ymd_and_hms(2025, 1, 31, 17, 0, 0).unwrap());
+    let mut outline = todo(3, "Outline", Some(1));
+    outline.tags = vec!["work".to_string(), "writing".to_string()];
+    outline.set_completed(true, Utc::now());
+    let mut milk = todo(2, "Buy milk", None);
+    milk.due_at = Some(Utc.with_ymd_and_hms(2025, 2, 1, 0, 0, 0).unwrap());
+    vec![report, milk, outline]
+}
+
+fn titles(items: &[ImportedTodo]) -> Vec<&str> {
+    items.iter().map(|item| item.title.as_str()).collect()
+}
+
+#[test]
+fn every_format_round_trips() {
+    for format in [Format::Csv, Format::Markdown, Format::Todotxt, Format::Ical] {
+        let text = format.export(&sample());
+        let (items, errors) = format.parse(&text);
+        assert!(errors.is_empty(), "{:?}: {:?}", format, errors);
+        // Parents first
+        assert_eq!(titles(&items), ["Write report, part \"one\"", "Outline", "Buy milk"]);
+        assert!(items[1].completed && !items[0].completed);
+        assert_eq!(items[1].tags, ["work", "writing"]);
+        assert_eq!(items[0].due_at, sample()[0].due_at);
+        assert_eq!(items[2].due_at, sample()[1].due_at);
+        if format != Format::Todotxt {
+            assert_eq!(items[1].parent, Some(0));
+        }
+        if format != Format::Markdown {
+            assert_eq!(items[0].priority, Priority::Urgent);
+        }
+    }
+}
+
+#[test]
+fn csv_handles_quotes_and_missing_columns() {
+    let text = Format::Csv.export(&sample());
+    let (items, _) = Format::Csv.parse(&text);
+    assert_eq!(items[0].description.as_deref(), Some("Two\nlines"));
+    // The record after the two-line field starts on line 4
+    assert_eq!(items[1].line, 4);
+
+    let csv = "Title,Priority,Completed\r\nplain,HIGH,yes\n\n,low,\nfoo,someday,no\n";
+    let (items, errors) = Format::Csv.parse(csv);
+    assert_eq!(titles(&items), ["plain"]);
+    assert_eq!(items[0].priority, Priority::High);
+    assert!(items[0].completed);
+    let lines: Vec<usize> = errors.iter().map(|err| err.line).collect();
+    assert_eq!(lines, [4, 5]);
+
+    let (_, errors) = Format::Csv.parse("name\nfoo\n");
+    assert_eq!(errors[0].error, "the header has no 'title' column");
+    let (_, errors) = Format::Csv.parse("id,title,parent_id\n1,child,2\n2,parent,\n");
+    assert_eq!(errors[0].line, 2);
+}
+
+#[test]
+fn markdown_nests_by_indentation_and_skips_other_lines() {
+    let text = "# Groceries\n\
+                Some notes\n\
+                - [ ] Shop #errands due:2025-03-01\n\
+                \x20 - [x] Milk\n\
+                \x20 * [ ] Bread\n\
+                \x20   1. [ ] Whole grain\n\
+                - [X] Cook\n\
+                - plain bullet\n\
+                - [ ] #only-a-tag\n";
+    let (items, errors) = Format::Markdown.parse(text);
+    assert_eq!(titles(&items), ["Shop", "Milk", "Bread", "Whole grain", "Cook"]);
+    let parents: Vec<Option<usize>> = items.iter().map(|item| item.parent).collect();
+    assert_eq!(parents, [None, Some(0), Some(0), Some(2), None]);
+    assert_eq!(items[0].tags, ["errands"]);
+    assert!(items[4].completed);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 9);
+}
+
+#[test]
+fn todotxt_reads_priorities_dates_and_tags() {
+    let text = "(A) 2025-01-02 Call mom +family @phone due:2025-01-05\n\
+                x 2025-01-03 2025-01-01 Pay rent +home pri:B\n\
+                (D) Water plants\n\
+                \n\
+                Plain task with http://example.com\n";
+    let (items, errors) = Format::Todotxt.parse(text);
+    assert!(errors.is_empty());
+    assert_eq!(
+        titles(&items),
+        ["Call mom", "Pay rent", "Water plants", "Plain task with http://example.com"]
+    );
+    assert_eq!(items[0].priority, Priority::Urgent);
+    assert_eq!(items[0].tags, ["family", "phone"]);
+    assert_eq!(items[0].due_at, Some(Utc.with_ymd_and_hms(2025, 1, 5, 0, 0, 0).unwrap()));
+    assert!(items[1].completed);
+    assert_eq!(items[1].priority, Priority::High);
+    assert_eq!(items[2].priority, Priority::Low);
+    assert_eq!(items[3].priority, Priority::Medium);
+    assert_eq!(items[3].line, 5);
+}
+
+#[test]
+fn duplicates_are_found_by_title_and_parent() {
+    let existing = vec![todo(1, "Groceries", None), todo(2, "Milk", Some(1))];
+    let text = "- [ ] groceries \n  - [ ] Milk\n  - [ ] Eggs\n- [ ] Milk\n- [ ] Eggs\n- [ ] eggs\n";
+    let (items, _) = Format::Markdown.parse(text);
+
+    let plan = formats::plan(&items, &existing, false);
+    let create: Vec<bool> = plan.iter().map(|planned| planned.create).collect();
+    assert_eq!(create, [false, false, true, true, true, false]);
+    assert_eq!(plan[0].duplicate_of, Some(Target::Existing(1)));
+    // Subtasks of a skipped duplicate go below the todo it duplicates
+    assert_eq!(plan[2].parent, Some(Target::Existing(1)));
+    assert_eq!(plan[5].duplicate_of, Some(Target::Imported(4)));
+
+    let plan = formats::plan(&items, &existing, true);
+    assert!(plan.iter().all(|planned| planned.create));
+    assert_eq!(plan[1].duplicate_of, None);
+    assert_eq!(plan[1].parent, Some(Target::Imported(0)));
+}
+
+#[test]
+fn ical_folds_escapes_and_keeps_uids() {
+    let mut todos = sample();
+    let title = format!("{} with a title long enough to be folded, ünïcödé", todos[0].title);
+    todos[0].title = title;
+    todos[1].recurrence = Some("FREQ=WEEKLY;BYDAY=MO".parse().unwrap());
+    let text = Format::Ical.export(&todos);
+    assert!(text.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+    assert!(text.lines().all(|line| line.trim_end_matches('\r').len() <= 75));
+    assert!(text.contains("UID:todo-3@rest-api\r\n"));
+    assert!(text.contains("STATUS:COMPLETED\r\n") && text.contains("STATUS:NEEDS-ACTION\r\n"));
+    assert!(text.contains("DUE;VALUE=DATE:20250201\r\n"));
+    assert!(text.contains("DUE:20250131T170000Z\r\n"));
+    assert!(text.contains("RRULE:FREQ=WEEKLY;BYDAY=MO\r\n"));
+
+    let (items, errors) = Format::Ical.parse(&text);
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(items[0].title, todos[0].title);
+    assert_eq!(items[0].description.as_deref(), Some("Two\nlines"));
+    assert_eq!(items[0].uid.as_deref(), Some("todo-1@rest-api"));
+    assert_eq!(items[2].recurrence, todos[1].recurrence);
+    assert_eq!(ical::id_from_uid("todo-3@rest-api"), Some(3));
+    assert_eq!(ical::id_from_uid("3@elsewhere"), None);
+}
+
+#[test]
+fn ical_reads_vtodos_of_other_apps() {
+    let text = "BEGIN:VCALENDAR\n\
+                VERSION:2.0\n\
+                BEGIN:VEVENT\n\
+                SUMMARY:Not a todo\n\
+                END:VEVENT\n\
+                BEGIN:VTODO\n\
+                UID:abc\n\
+                SUMMARY;LANGUAGE=en:Plan\n \x20trip\n\
+                DUE;TZID=Europe/Berlin:20250310T090000\n\
+                PRIORITY:2\n\
+                CATEGORIES:travel,fun\\, really\n\
+                BEGIN:VALARM\n\
+                DESCRIPTION:Reminder\n\
+                END:VALARM\n\
+                END:VTODO\n\
+                BEGIN:VTODO\n\
+                SUMMARY:Book hotel\n\
+                STATUS:COMPLETED\n\
+                RELATED-TO:abc\n\
+                END:VTODO\n\
+                BEGIN:VTODO\n\
+                DESCRIPTION:no summary\n\
+                END:VTODO\n\
+                BEGIN:VTODO\n\
+                SUMMARY:Bad rule\n\
+                RRULE:FREQ=SOMETIMES\n\
+                END:VTODO\n\
+                END:VCALENDAR\n";
+    let (items, errors) = Format::Ical.parse(text);
+    assert_eq!(titles(&items), ["Plan trip", "Book hotel"]);
+    assert_eq!(items[0].line, 6);
+    assert_eq!(items[0].description, None);
+    assert_eq!(items[0].due_at, Some(Utc.with_ymd_and_hms(2025, 3, 10, 9, 0, 0).unwrap()));
+    assert_eq!(items[0].priority, Priority::High);
+    assert_eq!(items[0].tags, ["travel", "fun, really"]);
+    assert!(items[1].completed);
+    assert_eq!(items[1].parent, Some(0));
+    let lines: Vec<usize> = errors.iter().map(|err| err.line).collect();
+    assert_eq!(lines, [22, 27]);
+}
+
+#[test]
+fn reimported_vtodos_update_their_todo() {
+    let existing = sample();
+    let (mut items, _) = Format::Ical.parse(&Format::Ical.export(&existing));
+    // Buy milk was checked off in the calendar
+    items[2].completed = true;
+    let mut new = items[2].clone();
+    new.title = "Buy eggs".to_string();
+    new.uid = Some("from-my-phone".to_string());
+    items.push(new);
+
+    let plan = formats::plan(&items, &existing, false);
+    let updates: Vec<Option<u32>> = plan.iter().map(|planned| planned.update).collect();
+    assert_eq!(updates, [None, None, Some(2), None]);
+    let create: Vec<bool> = plan.iter().map(|planned| planned.create).collect();
+    assert_eq!(create, [false, false, false, true]);
+    assert_eq!(plan[1].duplicate_of, Some(Target::Existing(3)));
+    assert_eq!(plan[1].parent, Some(Target::Existing(1)));
+
+    let mut milk = existing[1].clone();
+    items[2].apply_to(&mut milk, Utc::now());
+    assert!(milk.completed && milk.completed_at.is_some());
+    assert_eq!(milk.due_at, existing[1].due_at);
+}
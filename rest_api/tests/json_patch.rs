@@ -0,0 +1,111 @@
+// JSON Merge Patch / JSON Patch, mostly the examples from RFC 7396 and
+// RFC 6902 Appendix A
+
+use rest_api::patch::{PatchError, PatchOp, json_patch, merge_patch};
+use serde_json::{Value, json};
+
+fn ops(ops: Value) -> Vec<PatchOp> {
+    serde_json::from_value(ops).unwrap()
+}
+
+#[test]
+fn merge_patch_merges_objects_and_removes_nulls() {
+    let mut target = json!({
+        "title": "Goodbye!",
+        "author": { "givenName": "John", "familyName": "Doe" },
+        "tags": ["example", "sample"],
+        "content": "This will be unchanged"
+    });
+    let patch = json!({
+        "title": "Hello!",
+        "phoneNumber": "+01-123-456-7890",
+        "author": { "familyName": null },
+        "tags": ["example"]
+    });
+
+    merge_patch(&mut target, &patch);
+    assert_eq!(
+        target,
+        json!({
+            "title": "Hello!",
+            "author": { "givenName": "John" },
+            "tags": ["example"],
+            "content": "This will be unchanged",
+            "phoneNumber": "+01-123-456-7890"
+        })
+    );
+}
+
+#[test]
+fn json_patch_adds_removes_and_replaces() {
+    let document = json!({ "foo": ["bar", "baz"], "qux": { "baz": 1 } });
+    let patch = ops(json!([
+        { "op": "add", "path": "/foo/1", "value": "qux" },
+        { "op": "add", "path": "/foo/-", "value": "end" },
+        { "op": "remove", "path": "/qux/baz" },
+        { "op": "replace", "path": "/qux", "value": "new" },
+        { "op": "add", "path": "/a~1b", "value": true }
+    ]));
+
+    assert_eq!(
+        json_patch(&document, &patch).unwrap(),
+        json!({ "foo": ["bar", "qux", "baz", "end"], "qux": "new", "a/b": true })
+    );
+}
+
+#[test]
+fn json_patch_moves_and_copies() {
+    let document = json!({
+        "foo": { "bar": "baz", "waldo": "fred" },
+        "qux": { "corge": "grault" }
+    });
+    let patch = ops(json!([
+        { "op": "move", "from": "/foo/waldo", "path": "/qux/thud" },
+        { "op": "copy", "from": "/qux/corge", "path": "/foo/corge" }
+    ]));
+
+    assert_eq!(
+        json_patch(&document, &patch).unwrap(),
+        json!({
+            "foo": { "bar": "baz", "corge": "grault" },
+            "qux": { "corge": "grault", "thud": "fred" }
+        })
+    );
+
+    let into_child = ops(json!([{ "op": "move", "from": "/foo", "path": "/foo/bar" }]));
+    assert!(matches!(json_patch(&document, &into_child), Err(PatchError::Invalid(_))));
+}
+
+#[test]
+fn failed_test_aborts_the_whole_patch() {
+    let document = json!({ "baz": "qux", "foo": ["a", 2, "c"] });
+    let passing = ops(json!([
+        { "op": "test", "path": "/baz", "value": "qux" },
+        { "op": "test", "path": "/foo/1", "value": 2 }
+    ]));
+    assert_eq!(json_patch(&document, &passing).unwrap(), document);
+
+    let failing = ops(json!([
+        { "op": "replace", "path": "/baz", "value": "changed" },
+        { "op": "test", "path": "/baz", "value": "qux" }
+    ]));
+    assert_eq!(
+        json_patch(&document, &failing),
+        Err(PatchError::TestFailed("/baz".to_string()))
+    );
+}
+
+#[test]
+fn json_patch_rejects_missing_paths_and_bad_indexes() {
+    let document = json!({ "foo": ["bar"] });
+    for patch in [
+        json!([{ "op": "remove", "path": "/missing" }]),
+        json!([{ "op": "replace", "path": "/foo/1", "value": 1 }]),
+        json!([{ "op": "add", "path": "/foo/01", "value": 1 }]),
+        json!([{ "op": "add", "path": "/missing/child", "value": 1 }]),
+        json!([{ "op": "test", "path": "foo", "value": 1 }]),
+    ] {
+        let result = json_patch(&document, &ops(patch.clone()));
+        assert!(matches!(result, Err(PatchError::Invalid(_))), "{} should fail", patch);
+    }
+}
@@ -0,0 +1,318 @@
+// todos.json format handling of the JSON backend: legacy files, migrations,
+// files that cannot be read, journal recovery and debounced writes.
+
+use rest_api::error::StorageError;
+use rest_api::journal::Journal;
+use rest_api::models::{BatchOperation, CreateTodoRequest, Priority};
+use rest_api::repository::{StorageRepository, TodoRepository};
+use rest_api::schema::CURRENT_VERSION;
+use rest_api::writer::Durability;
+use serde_json::{Value, json};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn files_in(dir: &TempDir) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+fn append_raw(path: &Path, bytes: &[u8]) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+// Two good records followed by `tail`: reopening replays the two and cuts
+// the tail off, and what is appended afterwards survives the next reopen
+fn recovers_from_tail(tail: &[u8]) {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("todos.journal");
+    {
+        let (mut journal, records) = Journal::open::<Value>(&path, None).unwrap();
+        assert!(records.is_empty());
+        journal.append(&json!({ "n": 1 })).unwrap();
+        journal.append(&json!({ "n": 2 })).unwrap();
+    }
+    let valid_len = fs::metadata(&path).unwrap().len();
+    append_raw(&path, tail);
+
+    {
+        let (mut journal, records) = Journal::open::<Value>(&path, None).unwrap();
+        assert_eq!(records, [json!({ "n": 1 }), json!({ "n": 2 })]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), valid_len);
+        journal.append(&json!({ "n": 3 })).unwrap();
+    }
+
+    let (_, records) = Journal::open::<Value>(&path, None).unwrap();
+    assert_eq!(records, [json!({ "n": 1 }), json!({ "n": 2 }), json!({ "n": 3 })]);
+}
+
+#[test]
+fn partial_last_journal_line_is_truncated() {
+    // A crash halfway through writing the record
+    recovers_from_tail(br#"0badc0de {"n": 3, "title": "cut o"#);
+}
+
+#[test]
+fn journal_line_with_bad_checksum_is_truncated() {
+    let payload = r#"{"n":3}"#;
+    let wrong = crc32fast::hash(payload.as_bytes()) ^ 1;
+    recovers_from_tail(format!("{:08x} {}\n", wrong, payload).as_bytes());
+}
+
+#[tokio::test]
+async fn torn_journal_tail_keeps_the_todos_before_it() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let request = |title: &str| CreateTodoRequest {
+        title: title.to_string(),
+        ..Default::default()
+    };
+    {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        repo.create(request("first")).await.unwrap();
+        repo.create(request("second")).await.unwrap();
+    }
+    append_raw(&dir.path().join("todos.journal"), br#"1234abcd {"op":"change","chan"#);
+
+    {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        assert_eq!(repo.list().await.unwrap().len(), 2);
+        repo.create(request("after recovery")).await.unwrap();
+    }
+    let repo = StorageRepository::json_file(&json).unwrap();
+    assert_eq!(repo.get(3).await.unwrap().unwrap().title, "after recovery");
+    assert_eq!(repo.list().await.unwrap().len(), 3);
+}
+
+// todos.json written before description/due date/priority/tags existed
+#[tokio::test]
+async fn legacy_array_is_migrated_and_backed_up() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let legacy = r#"[{"id": 7, "title": "Learn Rust", "completed": true}]"#;
+    fs::write(&json, legacy).unwrap();
+
+    let repo = StorageRepository::json_file(&json).unwrap();
+    let todo = repo.get(7).await.unwrap().unwrap();
+    assert_eq!(todo.title, "Learn Rust");
+    assert_eq!(todo.priority, Priority::Medium);
+    assert!(todo.tags.is_empty());
+    assert!(todo.created_at.is_none());
+
+    let request = CreateTodoRequest {
+        title: "next".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(repo.create(request).await.unwrap().id, 8);
+    // Migrated todos are numbered into the change sequence
+    let changes = repo.changes_since(0, 10).await.unwrap();
+    let ids: Vec<u32> = changes.upserts.iter().map(|todo| todo.id).collect();
+    assert_eq!(ids, [7, 8]);
+
+    assert_eq!(fs::read_to_string(dir.path().join("todos.json.v1.bak")).unwrap(), legacy);
+    let migrated: Value = serde_json::from_str(&fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(migrated["schema_version"], CURRENT_VERSION);
+    assert_eq!(migrated["todos"][0]["id"], 7);
+}
+
+#[tokio::test]
+async fn unreadable_file_is_quarantined_not_overwritten() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    fs::write(&json, "{ this is not json").unwrap();
+
+    let repo = StorageRepository::json_file(&json).unwrap();
+    assert!(repo.list().await.unwrap().is_empty());
+
+    let quarantined: Vec<String> = files_in(&dir)
+        .into_iter()
+        .filter(|name| name.starts_with("todos.json.corrupt-"))
+        .collect();
+    assert_eq!(quarantined.len(), 1);
+    let kept = fs::read_to_string(dir.path().join(&quarantined[0])).unwrap();
+    assert_eq!(kept, "{ this is not json");
+}
+
+#[tokio::test]
+async fn newer_schema_version_is_refused() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let future = format!(
+        r#"{{"schema_version": {}, "metadata": {{"next_id": 1}}, "todos": []}}"#,
+        CURRENT_VERSION + 1
+    );
+    fs::write(&json, &future).unwrap();
+
+    let result = StorageRepository::json_file(&json);
+    assert!(matches!(result, Err(StorageError::UnsupportedSchema(_))));
+    assert_eq!(fs::read_to_string(&json).unwrap(), future);
+}
+
+#[tokio::test]
+async fn deleted_ids_are_not_reused_after_restart() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let request = || CreateTodoRequest {
+        title: "todo".to_string(),
+        ..Default::default()
+    };
+
+    {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        repo.create(request()).await.unwrap();
+        let second = repo.create(request()).await.unwrap();
+        repo.delete(second.id, None).await.unwrap();
+    }
+
+    let repo = StorageRepository::json_file(&json).unwrap();
+    assert_eq!(repo.create(request()).await.unwrap().id, 3);
+}
+
+#[tokio::test]
+async fn batch_is_one_journal_record() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let create = |title: &str| BatchOperation::Create {
+        todo: CreateTodoRequest {
+            title: title.to_string(),
+            ..Default::default()
+        },
+    };
+
+    {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        repo.batch((0..10).map(|i| create(&format!("todo {}", i))).collect())
+            .await
+            .unwrap();
+        let journal = fs::read_to_string(dir.path().join("todos.journal")).unwrap();
+        assert_eq!(journal.lines().count(), 1);
+    }
+
+    let repo = StorageRepository::json_file(&json).unwrap();
+    assert_eq!(repo.list().await.unwrap().len(), 10);
+}
+
+#[tokio::test]
+async fn history_survives_restart() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let request = CreateTodoRequest {
+        title: "todo".to_string(),
+        ..Default::default()
+    };
+
+    {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        let todo = repo.create(request).await.unwrap();
+        repo.delete(todo.id, None).await.unwrap();
+    }
+
+    // Once from the journal, once from the compacted snapshot
+    for _ in 0..2 {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        assert_eq!(repo.history(1).await.unwrap().len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn trash_survives_restart() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+
+    {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        for title in ["trashed", "purged"] {
+            let request = CreateTodoRequest {
+                title: title.to_string(),
+                ..Default::default()
+            };
+            let todo = repo.create(request).await.unwrap();
+            repo.delete(todo.id, None).await.unwrap();
+        }
+        repo.purge(2).await.unwrap();
+    }
+
+    // Once from the journal, once from the compacted snapshot
+    for _ in 0..2 {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        let trash = repo.trash().await.unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].title, "trashed");
+        assert!(repo.history(2).await.unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn change_sequence_survives_restart() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+
+    let seq = {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        for title in ["kept", "gone"] {
+            let request = CreateTodoRequest {
+                title: title.to_string(),
+                ..Default::default()
+            };
+            repo.create(request).await.unwrap();
+        }
+        repo.delete(2, None).await.unwrap();
+        repo.purge(2).await.unwrap();
+        repo.changes_since(0, 100).await.unwrap().seq
+    };
+
+    // Once from the journal, once from the compacted snapshot
+    for _ in 0..2 {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        let changes = repo.changes_since(0, 100).await.unwrap();
+        assert_eq!(changes.seq, seq);
+        assert_eq!(changes.upserts.len(), 1);
+        assert_eq!(changes.deletes, [2]);
+        assert!(repo.changes_since(seq, 100).await.unwrap().upserts.is_empty());
+    }
+}
+
+// Debounced writes are done in memory at once and on disk after a flush
+#[tokio::test]
+async fn debounced_writes_reach_disk_in_one_go() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let journal = dir.path().join("todos.journal");
+    // Long enough that nothing is written before the flush
+    let durability = Durability::Debounced {
+        delay: Duration::from_secs(60),
+    };
+    let request = |title: &str| CreateTodoRequest {
+        title: title.to_string(),
+        ..Default::default()
+    };
+
+    let repo = StorageRepository::json_file(&json).unwrap().with_durability(durability).unwrap();
+    for i in 0..5 {
+        repo.create(request(&format!("todo {}", i))).await.unwrap();
+    }
+    assert_eq!(repo.list().await.unwrap().len(), 5);
+    assert_eq!(fs::read_to_string(&journal).unwrap(), "");
+    repo.flush().await.unwrap();
+    assert_eq!(fs::read_to_string(&journal).unwrap().lines().count(), 5);
+
+    // Compaction happens in the background too; dropping the repository
+    // writes what is left
+    for i in 5..100 {
+        repo.create(request(&format!("todo {}", i))).await.unwrap();
+    }
+    repo.delete(1, None).await.unwrap();
+    drop(repo);
+    assert!(fs::read_to_string(&journal).unwrap().lines().count() < 64);
+
+    let repo = StorageRepository::json_file(&json).unwrap();
+    assert_eq!(repo.list().await.unwrap().len(), 99);
+    assert_eq!(repo.trash().await.unwrap()[0].id, 1);
+}
@@ -0,0 +1,152 @@
+// The OpenAPI document against the router: every route documented, nothing
+// documented that is not routed, and the docs served without a network.
+
+mod common;
+
+use common::serve;
+use rest_api::openapi;
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+const ROUTERS: &str = include_str!("../src/routers.rs");
+const METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+
+// `(path, method)` of every `.route("/todos/:id", get(handlers::get_todo))`,
+// with the path in OpenAPI's `/todos/{id}` form
+fn routed() -> BTreeSet<(String, String)> {
+    let mut routes = BTreeSet::new();
+    for line in ROUTERS.lines().map(str::trim) {
+        let Some(rest) = line.strip_prefix(".route(\"") else {
+            continue;
+        };
+        let (path, rest) = rest.split_once('"').unwrap();
+        let method = rest.trim_start_matches([',', ' ']).split('(').next().unwrap();
+        assert!(METHODS.contains(&method), "unexpected route: {}", line);
+        let path: Vec<String> = path
+            .split('/')
+            .map(|part| match part.strip_prefix(':') {
+                Some(name) => format!("{{{}}}", name),
+                None => part.to_string(),
+            })
+            .collect();
+        routes.insert((path.join("/"), method.to_string()));
+    }
+    routes
+}
+
+fn spec() -> Value {
+    serde_json::to_value(openapi::spec()).unwrap()
+}
+
+fn documented(spec: &Value) -> BTreeSet<(String, String)> {
+    let mut operations = BTreeSet::new();
+    for (path, item) in spec["paths"].as_object().unwrap() {
+        for method in METHODS.iter().filter(|method| item.get(**method).is_some()) {
+            operations.insert((path.clone(), method.to_string()));
+        }
+    }
+    operations
+}
+
+// Every `$ref` in `value`
+fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(target)) = object.get("$ref") {
+                found.push(target);
+            }
+            object.values().for_each(|value| refs(value, found));
+        }
+        Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+        _ => {}
+    }
+}
+
+#[test]
+fn spec_and_routes_match() {
+    let spec = spec();
+    let routed = routed();
+    let documented = documented(&spec);
+    assert!(routed.len() > 40, "no routes found in src/routers.rs");
+    let undocumented: Vec<_> = routed.difference(&documented).collect();
+    assert!(undocumented.is_empty(), "missing from the spec: {:?}", undocumented);
+    let unrouted: Vec<_> = documented.difference(&routed).collect();
+    assert!(unrouted.is_empty(), "in the spec but not routed: {:?}", unrouted);
+
+    // Every path parameter is declared
+    for (path, method) in &documented {
+        let operation = &spec["paths"][path][method];
+        let params = operation["parameters"].as_array().map(Vec::as_slice).unwrap_or_default();
+        for name in path.split('/').filter_map(|part| part.strip_prefix('{')) {
+            let name = name.trim_end_matches('}');
+            assert!(
+                params.iter().any(|param| param["name"] == name && param["in"] == "path"),
+                "{} {} does not declare {}",
+                method,
+                path,
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn schemas_are_complete() {
+    let spec = spec();
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    let schemas = &spec["components"]["schemas"];
+    for name in ["Todo", "CreateTodoRequest", "UpdateTodoRequest", "ErrorBody"] {
+        assert!(schemas[name].is_object(), "no {} schema", name);
+    }
+    let priorities = serde_json::json!(["low", "medium", "high", "urgent"]);
+    assert_eq!(schemas["Priority"]["enum"], priorities);
+    // Set by the server, never by clients
+    assert!(schemas["CreateTodoRequest"]["properties"].get("owner_id").is_none());
+
+    let mut found = Vec::new();
+    refs(&spec, &mut found);
+    assert!(!found.is_empty());
+    for target in found {
+        let name = target.strip_prefix("#/components/schemas/").unwrap();
+        assert!(schemas[name].is_object(), "dangling {}", target);
+    }
+}
+
+#[tokio::test]
+async fn every_documented_operation_is_routed() {
+    let server = serve().await;
+    let http = reqwest::Client::new();
+    for (path, method) in documented(&spec()) {
+        let url: Vec<&str> = path
+            .split('/')
+            .map(|part| if part.starts_with('{') { "1" } else { part })
+            .collect();
+        let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+        let request = http.request(method.clone(), format!("{}{}", server, url.join("/")));
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{} {} is not routed", method, path);
+        // The router's own 404 has no body, the handlers' have an error
+        let unrouted = status == StatusCode::NOT_FOUND && response.text().await.unwrap().is_empty();
+        assert!(!unrouted, "{} {} is not routed", method, path);
+    }
+}
+
+#[tokio::test]
+async fn docs_are_served_offline() {
+    let server = serve().await;
+    let http = reqwest::Client::new();
+    let served: Value =
+        http.get(format!("{}/openapi.json", server)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(served, spec());
+
+    let page = http.get(format!("{}/docs/", server)).send().await.unwrap();
+    assert_eq!(page.status(), StatusCode::OK);
+    assert!(page.text().await.unwrap().contains("swagger-ui-bundle.js"));
+    // Everything the page loads comes from the server itself
+    let bundle = http.get(format!("{}/docs/swagger-ui-bundle.js", server)).send().await.unwrap();
+    assert_eq!(bundle.status(), StatusCode::OK);
+    let initializer = http.get(format!("{}/docs/swagger-initializer.js", server)).send();
+    assert!(initializer.await.unwrap().text().await.unwrap().contains("/openapi.json"));
+}
@@ -0,0 +1,155 @@
+// RRULE parsing and the occurrences of recurring todos.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rest_api::models::{CreateTodoRequest, Todo};
+use rest_api::recurrence::{self, Recurrence};
+
+fn at(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(year, month, day, 9, 0, 0).unwrap()
+}
+
+fn rule(rule: &str) -> Recurrence {
+    rule.parse().unwrap()
+}
+
+fn dates(rule: &Recurrence, start: DateTime<Utc>, n: usize) -> Vec<String> {
+    rule.occurrences(start).take(n).map(|at| at.format("%Y-%m-%d").to_string()).collect()
+}
+
+#[test]
+fn rules_parse_and_print_canonically() {
+    let parsed = rule("rrule:freq=weekly;interval=2;byday=MO,th;count=4");
+    assert_eq!(parsed.to_string(), "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=4");
+    assert_eq!(rule("FREQ=MONTHLY;BYDAY=-1FR").to_string(), "FREQ=MONTHLY;BYDAY=-1FR");
+    assert_eq!(
+        rule("FREQ=DAILY;UNTIL=20250131").to_string(),
+        "FREQ=DAILY;UNTIL=20250131T235959Z"
+    );
+
+    for bad in [
+        "",
+        "INTERVAL=2",
+        "FREQ=HOURLY",
+        "FREQ=DAILY;INTERVAL=0",
+        "FREQ=DAILY;COUNT=3;UNTIL=20250101",
+        "FREQ=WEEKLY;BYDAY=1MO",
+        "FREQ=DAILY;BYMONTHDAY=1",
+        "FREQ=MONTHLY;BYSETPOS=1",
+        "FREQ=WEEKLY;BYDAY=XX",
+    ] {
+        assert!(bad.parse::<Recurrence>().is_err(), "{} should be rejected", bad);
+    }
+}
+
+#[test]
+fn day_numbers_are_range_checked_to_the_ends_of_i8() {
+    for good in ["FREQ=MONTHLY;BYMONTHDAY=-31,31", "FREQ=MONTHLY;BYDAY=-5MO,5FR"] {
+        assert!(good.parse::<Recurrence>().is_ok(), "{} should be accepted", good);
+    }
+    for bad in [
+        "FREQ=MONTHLY;BYMONTHDAY=-128",
+        "FREQ=MONTHLY;BYMONTHDAY=127",
+        "FREQ=MONTHLY;BYMONTHDAY=-32",
+        "FREQ=MONTHLY;BYMONTHDAY=0",
+        "FREQ=MONTHLY;BYMONTHDAY=-129",
+        "FREQ=MONTHLY;BYDAY=-128MO",
+        "FREQ=MONTHLY;BYDAY=-6MO",
+        "FREQ=MONTHLY;BYDAY=0MO",
+    ] {
+        assert!(bad.parse::<Recurrence>().is_err(), "{} should be rejected", bad);
+    }
+}
+
+#[test]
+fn todo_serializes_the_rule_as_a_string() {
+    let request: CreateTodoRequest =
+        serde_json::from_str(r#"{"title": "Gym", "recurrence": "FREQ=DAILY;INTERVAL=2"}"#).unwrap();
+    let todo = Todo::new(1, request);
+    let json = serde_json::to_value(&todo).unwrap();
+    assert_eq!(json["recurrence"], "FREQ=DAILY;INTERVAL=2");
+
+    let invalid = r#"{"title": "Gym", "recurrence": "FREQ=SOMETIMES"}"#;
+    let err = serde_json::from_str::<CreateTodoRequest>(invalid).unwrap_err();
+    assert!(err.to_string().contains("invalid recurrence rule"));
+}
+
+#[test]
+fn daily_weekly_and_weekday_patterns() {
+    // 2025-01-06 is a Monday
+    let start = at(2025, 1, 6);
+    assert_eq!(
+        dates(&rule("FREQ=DAILY;INTERVAL=3"), start, 3),
+        ["2025-01-06", "2025-01-09", "2025-01-12"]
+    );
+    assert_eq!(
+        dates(&rule("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR"), at(2025, 1, 9), 4),
+        ["2025-01-09", "2025-01-10", "2025-01-13", "2025-01-14"]
+    );
+    assert_eq!(
+        dates(&rule("FREQ=WEEKLY;INTERVAL=2;BYDAY=TH,MO"), start, 5),
+        ["2025-01-06", "2025-01-09", "2025-01-20", "2025-01-23", "2025-02-03"]
+    );
+    // Keeps the time of day of the start
+    let first = rule("FREQ=WEEKLY").occurrences(start).nth(1).unwrap();
+    assert_eq!(first, start + Duration::weeks(1));
+}
+
+#[test]
+fn monthly_and_yearly_skip_days_that_do_not_exist() {
+    assert_eq!(
+        dates(&rule("FREQ=MONTHLY"), at(2025, 1, 31), 3),
+        ["2025-01-31", "2025-03-31", "2025-05-31"]
+    );
+    assert_eq!(
+        dates(&rule("FREQ=MONTHLY;BYMONTHDAY=-1"), at(2025, 1, 31), 3),
+        ["2025-01-31", "2025-02-28", "2025-03-31"]
+    );
+    assert_eq!(
+        dates(&rule("FREQ=MONTHLY;BYDAY=1MO"), at(2025, 1, 6), 3),
+        ["2025-01-06", "2025-02-03", "2025-03-03"]
+    );
+    assert_eq!(
+        dates(&rule("FREQ=YEARLY"), at(2024, 2, 29), 2),
+        ["2024-02-29", "2028-02-29"]
+    );
+    // Never hits a 30th again
+    assert_eq!(dates(&rule("FREQ=MONTHLY;INTERVAL=12;BYMONTHDAY=30"), at(2025, 2, 1), 5).len(), 1);
+}
+
+#[test]
+fn count_and_until_end_the_series() {
+    assert_eq!(dates(&rule("FREQ=DAILY;COUNT=3"), at(2025, 1, 1), 10).len(), 3);
+    assert_eq!(
+        dates(&rule("FREQ=WEEKLY;UNTIL=20250115"), at(2025, 1, 1), 10),
+        ["2025-01-01", "2025-01-08", "2025-01-15"]
+    );
+}
+
+#[test]
+fn completing_continues_the_series() {
+    let request = CreateTodoRequest {
+        title: "Water plants".to_string(),
+        due_at: Some(at(2025, 1, 6)),
+        tags: vec!["home".to_string()],
+        recurrence: Some(rule("FREQ=DAILY;INTERVAL=2;COUNT=5")),
+        ..Default::default()
+    };
+    let mut todo = Todo::new(1, request);
+    todo.set_completed(true, at(2025, 1, 6));
+
+    // Completed on time: the next occurrence, one of five used up
+    let next = recurrence::next_occurrence(&todo, at(2025, 1, 6)).unwrap();
+    assert_eq!(next.title, "Water plants");
+    assert_eq!(next.tags, ["home"]);
+    assert_eq!(next.due_at, Some(at(2025, 1, 8)));
+    assert_eq!(next.recurrence.unwrap().count, Some(4));
+
+    // Completed three days late: the missed one on the 8th is skipped,
+    // leaving the 10th, 12th and 14th
+    let next = recurrence::next_occurrence(&todo, at(2025, 1, 9)).unwrap();
+    assert_eq!(next.due_at, Some(at(2025, 1, 10)));
+    assert_eq!(next.recurrence.unwrap().count, Some(3));
+
+    // Nothing left after the last one
+    assert!(recurrence::next_occurrence(&todo, at(2025, 2, 1)).is_none());
+}
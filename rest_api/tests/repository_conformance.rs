@@ -0,0 +1,693 @@
+// Shared behaviour every TodoRepository backend has to pass.
+// Each check runs once per backend, see `conformance!` at the bottom.
+
+mod common;
+
+use common::create;
+use chrono::{Duration, Utc};
+use rest_api::encryption::Cipher;
+use rest_api::error::{BatchError, StorageError};
+use rest_api::history::{HistoryRetention, RevisionOp};
+use rest_api::models::{
+    BatchOperation, BatchOutcome, CreateListRequest, CreateTodoRequest, DEFAULT_LIST_ID,
+    DEFAULT_OWNER_ID, DueFilter, Priority, Todo, TodoQuery, TodoSort, UpdateListRequest,
+    UpdateTodoRequest,
+};
+use rest_api::repository::{
+    ScopedRepository, SqliteRepository, StorageRepository, TodoRepository,
+};
+use rest_api::writer::Durability;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+async fn create_assigns_increasing_ids(repo: &dyn TodoRepository) {
+    let first = repo.create(create("Learn Rust")).await.unwrap();
+    let second = repo.create(create("Build REST API")).await.unwrap();
+
+    assert_eq!(first.id, 1);
+    assert_eq!(second.id, 2);
+    assert_eq!(first.title, "Learn Rust");
+    assert!(!first.completed);
+}
+
+async fn get_returns_created_todo(repo: &dyn TodoRepository) {
+    let created = repo.create(create("Learn Rust")).await.unwrap();
+
+    let fetched = repo.get(created.id).await.unwrap().unwrap();
+    assert_eq!(fetched.title, "Learn Rust");
+    assert!(repo.get(999).await.unwrap().is_none());
+}
+
+async fn list_returns_every_todo(repo: &dyn TodoRepository) {
+    assert!(repo.list().await.unwrap().is_empty());
+
+    repo.create(create("a")).await.unwrap();
+    repo.create(create("b")).await.unwrap();
+    repo.create(create("c")).await.unwrap();
+
+    let mut titles: Vec<String> = repo.list().await.unwrap().into_iter().map(|t| t.title).collect();
+    titles.sort();
+    assert_eq!(titles, ["a", "b", "c"]);
+}
+
+async fn update_changes_only_given_fields(repo: &dyn TodoRepository) {
+    let todo = repo.create(create("Learn Rust")).await.unwrap();
+
+    let changes = UpdateTodoRequest {
+        completed: Some(true),
+        ..Default::default()
+    };
+    let updated = repo.update(todo.id, changes, None).await.unwrap().unwrap();
+    assert_eq!(updated.title, "Learn Rust");
+    assert!(updated.completed);
+
+    let changes = UpdateTodoRequest {
+        title: Some("Learn more Rust".to_string()),
+        ..Default::default()
+    };
+    repo.update(todo.id, changes, None).await.unwrap();
+    let fetched = repo.get(todo.id).await.unwrap().unwrap();
+    assert_eq!(fetched.title, "Learn more Rust");
+    assert!(fetched.completed);
+}
+
+async fn update_missing_returns_none(repo: &dyn TodoRepository) {
+    let result = repo.update(42, UpdateTodoRequest::default(), None).await.unwrap();
+    assert!(result.is_none());
+}
+
+async fn replace_overwrites_whole_todo(repo: &dyn TodoRepository) {
+    let parent = repo.create(create("parent")).await.unwrap();
+    let mut child = repo.create(create("child")).await.unwrap();
+
+    child.parent_id = Some(parent.id);
+    child.title = "renamed".to_string();
+    repo.replace(child.clone()).await.unwrap().unwrap();
+
+    let fetched = repo.get(child.id).await.unwrap().unwrap();
+    assert_eq!(fetched.parent_id, Some(parent.id));
+    assert_eq!(fetched.title, "renamed");
+
+    child.id = 99;
+    assert!(repo.replace(child).await.unwrap().is_none());
+    assert!(repo.get(99).await.unwrap().is_none());
+}
+
+async fn delete_removes_todo(repo: &dyn TodoRepository) {
+    let todo = repo.create(create("Learn Rust")).await.unwrap();
+
+    assert!(repo.delete(todo.id, None).await.unwrap());
+    assert!(repo.get(todo.id).await.unwrap().is_none());
+    assert!(!repo.delete(todo.id, None).await.unwrap());
+}
+
+async fn writes_bump_revision_and_check_expected_one(repo: &dyn TodoRepository) {
+    let todo = repo.create(create("Learn Rust")).await.unwrap();
+    assert_eq!(todo.revision, 1);
+
+    let rename = |title: &str| UpdateTodoRequest {
+        title: Some(title.to_string()),
+        ..Default::default()
+    };
+    let updated = repo.update(todo.id, rename("a"), Some(1)).await.unwrap().unwrap();
+    assert_eq!(updated.revision, 2);
+
+    // Stale revisions are refused and leave the todo alone
+    let stale = repo.update(todo.id, rename("b"), Some(1)).await;
+    assert!(matches!(stale, Err(StorageError::RevisionMismatch { current: 2, .. })));
+    assert!(matches!(
+        repo.replace(todo.clone()).await,
+        Err(StorageError::RevisionMismatch { current: 2, .. })
+    ));
+    assert!(matches!(
+        repo.delete(todo.id, Some(1)).await,
+        Err(StorageError::RevisionMismatch { current: 2, .. })
+    ));
+    assert_eq!(repo.get(todo.id).await.unwrap().unwrap().title, "a");
+
+    let replaced = repo.replace(updated).await.unwrap().unwrap();
+    assert_eq!(replaced.revision, 3);
+    assert!(repo.delete(todo.id, Some(3)).await.unwrap());
+}
+
+async fn batch_applies_every_operation(repo: &dyn TodoRepository) {
+    let existing = repo.create(create("existing")).await.unwrap();
+    let doomed = repo.create(create("doomed")).await.unwrap();
+
+    let outcomes = repo
+        .batch(vec![
+            BatchOperation::Create { todo: create("new") },
+            BatchOperation::Update {
+                id: existing.id,
+                changes: UpdateTodoRequest {
+                    completed: Some(true),
+                    ..Default::default()
+                },
+                revision: Some(1),
+            },
+            BatchOperation::Delete { id: doomed.id, revision: None },
+        ])
+        .await
+        .unwrap();
+
+    assert!(matches!(&outcomes[0], BatchOutcome::Created(todo) if todo.id == 3));
+    assert!(matches!(
+        &outcomes[1],
+        BatchOutcome::Updated(todo) if todo.completed && todo.revision == 2
+    ));
+    assert!(matches!(outcomes[2], BatchOutcome::Deleted(2)));
+    let mut titles: Vec<String> = repo.list().await.unwrap().into_iter().map(|t| t.title).collect();
+    titles.sort();
+    assert_eq!(titles, ["existing", "new"]);
+}
+
+async fn failed_batch_applies_nothing(repo: &dyn TodoRepository) {
+    let existing = repo.create(create("existing")).await.unwrap();
+
+    let result = repo
+        .batch(vec![
+            BatchOperation::Create { todo: create("new") },
+            BatchOperation::Delete { id: existing.id, revision: None },
+            BatchOperation::Delete { id: 42, revision: None },
+        ])
+        .await;
+    assert!(matches!(result, Err(BatchError::NotFound { index: 2, id: 42 })));
+
+    let stale = UpdateTodoRequest {
+        title: Some("stale".to_string()),
+        ..Default::default()
+    };
+    let result = repo
+        .batch(vec![BatchOperation::Update { id: existing.id, changes: stale, revision: Some(7) }])
+        .await;
+    assert!(matches!(result, Err(BatchError::RevisionMismatch { index: 0, current: 1, .. })));
+
+    let todos = repo.list().await.unwrap();
+    assert_eq!(todos.len(), 1);
+    assert_eq!(todos[0].title, "existing");
+    // Ids staged by the rolled back create are handed out again
+    assert_eq!(repo.create(create("next")).await.unwrap().id, 2);
+}
+
+async fn batch_replace_checks_the_revision(repo: &dyn TodoRepository) {
+    let todo = repo.create(create("draft")).await.unwrap();
+    let renamed = Todo {
+        title: "final".to_string(),
+        ..todo.clone()
+    };
+    let outcomes = repo
+        .batch(vec![
+            BatchOperation::Create { todo: create("other") },
+            BatchOperation::Replace { todo: renamed.clone() },
+        ])
+        .await
+        .unwrap();
+    assert!(matches!(
+        &outcomes[1],
+        BatchOutcome::Updated(todo) if todo.title == "final" && todo.revision == 2
+    ));
+
+    // Written at a revision that is gone by now: nothing in the batch is kept
+    let result = repo
+        .batch(vec![
+            BatchOperation::Create { todo: create("another") },
+            BatchOperation::Replace { todo: renamed },
+        ])
+        .await;
+    assert!(matches!(result, Err(BatchError::RevisionMismatch { index: 1, current: 2, .. })));
+    assert_eq!(repo.list().await.unwrap().len(), 2);
+}
+
+async fn batch_creates_under_earlier_creates(repo: &dyn TodoRepository) {
+    let outcomes = repo
+        .batch(vec![
+            BatchOperation::Create { todo: create("parent") },
+            BatchOperation::Create {
+                todo: CreateTodoRequest {
+                    parent_op: Some(0),
+                    completed: true,
+                    ..create("child")
+                },
+            },
+        ])
+        .await
+        .unwrap();
+    let (BatchOutcome::Created(parent), BatchOutcome::Created(child)) = (&outcomes[0], &outcomes[1])
+    else {
+        panic!("expected two creates, got {:?}", outcomes);
+    };
+    assert_eq!(child.parent_id, Some(parent.id));
+    assert!(child.completed && child.completed_at.is_some());
+    assert_eq!(repo.get(child.id).await.unwrap().unwrap().parent_id, Some(parent.id));
+}
+
+async fn batch_restore_takes_todos_out_of_the_trash(repo: &dyn TodoRepository) {
+    let todo = repo.create(create("draft")).await.unwrap();
+    let rename = UpdateTodoRequest {
+        title: Some("final".to_string()),
+        ..Default::default()
+    };
+    repo.update(todo.id, rename, None).await.unwrap();
+    repo.delete(todo.id, None).await.unwrap();
+
+    let outcomes = repo.batch(vec![BatchOperation::Restore { todo }]).await.unwrap();
+    // Back from the trash, above the revisions it had before
+    assert!(matches!(
+        &outcomes[0],
+        BatchOutcome::Created(todo) if todo.title == "draft" && todo.revision == 3
+    ));
+    assert!(repo.trash().await.unwrap().is_empty());
+    assert_eq!(repo.get(1).await.unwrap().unwrap().revision, 3);
+}
+
+async fn history_records_every_mutation(repo: &dyn TodoRepository) {
+    let todo = repo.create(create("draft")).await.unwrap();
+    let rename = UpdateTodoRequest {
+        title: Some("final".to_string()),
+        ..Default::default()
+    };
+    repo.update(todo.id, rename, None).await.unwrap();
+    repo.delete(todo.id, None).await.unwrap();
+
+    let history = repo.history(todo.id).await.unwrap();
+    let ops: Vec<RevisionOp> = history.iter().map(|entry| entry.op).collect();
+    assert_eq!(ops, [RevisionOp::Create, RevisionOp::Update, RevisionOp::Delete]);
+    assert_eq!(history[1].before.as_ref().unwrap().title, "draft");
+    assert_eq!(history[1].after.as_ref().unwrap().title, "final");
+    assert_eq!(history[2].revision, 2);
+    assert!(history[2].after.is_none());
+    assert!(repo.history(99).await.unwrap().is_empty());
+}
+
+async fn restore_recreates_deleted_todo(repo: &dyn TodoRepository) {
+    let todo = repo.create(create("draft")).await.unwrap();
+    repo.delete(todo.id, None).await.unwrap();
+
+    let restored = repo.restore(todo.clone()).await.unwrap();
+    assert_eq!(restored.id, todo.id);
+    assert_eq!(restored.revision, 2);
+    assert_eq!(repo.get(todo.id).await.unwrap().unwrap().title, "draft");
+    assert_eq!(repo.history(todo.id).await.unwrap().last().unwrap().op, RevisionOp::Restore);
+}
+
+async fn delete_moves_todo_to_trash(repo: &dyn TodoRepository) {
+    let first = repo.create(create("first")).await.unwrap();
+    let second = repo.create(create("second")).await.unwrap();
+    repo.delete(first.id, None).await.unwrap();
+    repo.delete(second.id, None).await.unwrap();
+
+    let trash = repo.trash().await.unwrap();
+    let ids: Vec<u32> = trash.iter().map(|t| t.id).collect();
+    assert_eq!(ids, [second.id, first.id]);
+    assert!(trash.iter().all(|t| t.deleted_at.is_some()));
+    assert!(repo.list().await.unwrap().is_empty());
+
+    // Restoring takes it out of the trash again
+    let restored = repo.restore(trash[1].clone()).await.unwrap();
+    assert!(restored.deleted_at.is_none());
+    assert!(restored.revision > first.revision);
+    let ids: Vec<u32> = repo.trash().await.unwrap().iter().map(|t| t.id).collect();
+    assert_eq!(ids, [second.id]);
+}
+
+async fn purge_deletes_for_good(repo: &dyn TodoRepository) {
+    let todo = repo.create(create("gone")).await.unwrap();
+    let kept = repo.create(create("kept")).await.unwrap();
+    assert!(!repo.purge(todo.id).await.unwrap());
+
+    repo.delete(todo.id, None).await.unwrap();
+    assert!(repo.purge(todo.id).await.unwrap());
+    assert!(repo.trash().await.unwrap().is_empty());
+    assert!(repo.history(todo.id).await.unwrap().is_empty());
+    assert!(!repo.purge(todo.id).await.unwrap());
+    assert!(repo.get(kept.id).await.unwrap().is_some());
+}
+
+async fn purge_deleted_before_only_takes_older_todos(repo: &dyn TodoRepository) {
+    let todo = repo.create(create("old")).await.unwrap();
+    repo.delete(todo.id, None).await.unwrap();
+
+    let purged = repo.purge_deleted_before(Utc::now() - Duration::hours(1)).await.unwrap();
+    assert!(purged.is_empty());
+    assert_eq!(repo.trash().await.unwrap().len(), 1);
+
+    let purged = repo.purge_deleted_before(Utc::now() + Duration::seconds(1)).await.unwrap();
+    assert_eq!(purged, [todo.id]);
+    assert!(repo.trash().await.unwrap().is_empty());
+}
+
+async fn changes_since_reports_upserts_and_deletes(repo: &dyn TodoRepository) {
+    let start = repo.changes_since(0, 100).await.unwrap();
+    assert!(start.upserts.is_empty() && start.deletes.is_empty());
+
+    let kept = repo.create(create("kept")).await.unwrap();
+    let gone = repo.create(create("gone")).await.unwrap();
+    let all = repo.changes_since(start.seq, 100).await.unwrap();
+    let ids: Vec<u32> = all.upserts.iter().map(|t| t.id).collect();
+    assert_eq!(ids, [kept.id, gone.id]);
+    assert!(!all.has_more);
+
+    // Only the newest state of each todo, in the order of its last change
+    let done = UpdateTodoRequest {
+        completed: Some(true),
+        ..Default::default()
+    };
+    repo.update(kept.id, done, None).await.unwrap();
+    repo.delete(gone.id, None).await.unwrap();
+    let delta = repo.changes_since(all.seq, 100).await.unwrap();
+    assert_eq!(delta.upserts.len(), 1);
+    assert!(delta.upserts[0].completed);
+    assert_eq!(delta.deletes, [gone.id]);
+    assert!(delta.seq > all.seq);
+
+    // The tombstone outlives the trash entry
+    repo.purge(gone.id).await.unwrap();
+    assert_eq!(repo.changes_since(all.seq, 100).await.unwrap().deletes, [gone.id]);
+    assert!(repo.changes_since(delta.seq, 100).await.unwrap().upserts.is_empty());
+}
+
+async fn changes_since_pages_with_limit(repo: &dyn TodoRepository) {
+    for title in ["a", "b", "c"] {
+        repo.create(create(title)).await.unwrap();
+    }
+
+    let first = repo.changes_since(0, 2).await.unwrap();
+    assert_eq!(first.upserts.len(), 2);
+    assert!(first.has_more);
+    let rest = repo.changes_since(first.seq, 2).await.unwrap();
+    let titles: Vec<&str> = rest.upserts.iter().map(|t| t.title.as_str()).collect();
+    assert_eq!(titles, ["c"]);
+    assert!(!rest.has_more);
+    // Caught up: nothing new and the same position
+    let none = repo.changes_since(rest.seq, 2).await.unwrap();
+    assert!(none.upserts.is_empty());
+    assert_eq!(none.seq, rest.seq);
+}
+
+async fn deleted_ids_are_not_reused(repo: &dyn TodoRepository) {
+    repo.create(create("a")).await.unwrap();
+    let second = repo.create(create("b")).await.unwrap();
+    repo.delete(second.id, None).await.unwrap();
+
+    assert_eq!(repo.create(create("c")).await.unwrap().id, 3);
+}
+
+async fn query_filters_sorts_and_pages(repo: &dyn TodoRepository) {
+    for title in ["Write docs", "learn rust", "Deploy", "Learn axum", "Review PR"] {
+        repo.create(create(title)).await.unwrap();
+    }
+    let done = UpdateTodoRequest {
+        completed: Some(true),
+        ..Default::default()
+    };
+    repo.update(2, done, None).await.unwrap();
+
+    let query = TodoQuery {
+        q: Some("LEARN".to_string()),
+        sort: TodoSort::TitleAsc,
+        ..Default::default()
+    };
+    let (todos, total) = repo.query(&query).await.unwrap();
+    let titles: Vec<&str> = todos.iter().map(|t| t.title.as_str()).collect();
+    assert_eq!(total, 2);
+    assert_eq!(titles, ["Learn axum", "learn rust"]);
+
+    let query = TodoQuery {
+        completed: Some(false),
+        sort: TodoSort::IdDesc,
+        limit: Some(2),
+        offset: 1,
+        ..Default::default()
+    };
+    let (todos, total) = repo.query(&query).await.unwrap();
+    let ids: Vec<u32> = todos.iter().map(|t| t.id).collect();
+    assert_eq!(total, 4);
+    assert_eq!(ids, [4, 3]);
+}
+
+async fn rich_fields_round_trip(repo: &dyn TodoRepository) {
+    let due = Utc::now() + Duration::days(3);
+    let request = CreateTodoRequest {
+        title: "Ship release".to_string(),
+        description: Some("Tag, build, publish".to_string()),
+        due_at: Some(due),
+        priority: Priority::High,
+        tags: vec![" work ".to_string(), "release".to_string(), "work".to_string()],
+        ..Default::default()
+    };
+    let todo = repo.create(request).await.unwrap();
+    assert!(todo.created_at.is_some());
+    assert!(todo.completed_at.is_none());
+
+    let done = UpdateTodoRequest {
+        completed: Some(true),
+        ..Default::default()
+    };
+    repo.update(todo.id, done, None).await.unwrap();
+
+    let fetched = repo.get(todo.id).await.unwrap().unwrap();
+    assert_eq!(fetched.description.as_deref(), Some("Tag, build, publish"));
+    assert_eq!(fetched.due_at, Some(due));
+    assert_eq!(fetched.priority, Priority::High);
+    assert_eq!(fetched.tags, ["work", "release"]);
+    assert!(fetched.completed_at.is_some());
+}
+
+async fn query_filters_by_tag_and_due_date(repo: &dyn TodoRepository) {
+    let now = Utc::now();
+    let todos = [
+        ("late", Some(now - Duration::days(2)), vec!["home"]),
+        ("late but done", Some(now - Duration::days(2)), vec![]),
+        ("later today", Some(now + Duration::seconds(1)), vec!["home", "work"]),
+        ("next week", Some(now + Duration::days(7)), vec!["work"]),
+        ("someday", None, vec![]),
+    ];
+    for (title, due_at, tags) in todos {
+        let request = CreateTodoRequest {
+            title: title.to_string(),
+            due_at,
+            tags: tags.into_iter().map(String::from).collect(),
+            ..Default::default()
+        };
+        repo.create(request).await.unwrap();
+    }
+    let done = UpdateTodoRequest {
+        completed: Some(true),
+        ..Default::default()
+    };
+    repo.update(2, done, None).await.unwrap();
+
+    let titles = |query: TodoQuery| async move {
+        let (todos, _) = repo.query(&query).await.unwrap();
+        todos.into_iter().map(|t| t.title).collect::<Vec<_>>()
+    };
+
+    let overdue = TodoQuery {
+        due: Some(DueFilter::Overdue),
+        ..Default::default()
+    };
+    assert_eq!(titles(overdue).await, ["late"]);
+
+    let work = TodoQuery {
+        tag: Some("work".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(titles(work).await, ["later today", "next week"]);
+}
+
+async fn lists_have_default_and_crud(repo: &dyn TodoRepository) {
+    let lists = repo.lists().await.unwrap();
+    assert_eq!(lists.len(), 1);
+    assert_eq!(lists[0].id, DEFAULT_LIST_ID);
+
+    let request = CreateListRequest {
+        name: "Work".to_string(),
+        color: Some("#ff8800".to_string()),
+        ..Default::default()
+    };
+    let work = repo.create_list(request).await.unwrap();
+    assert_ne!(work.id, DEFAULT_LIST_ID);
+
+    let archive = UpdateListRequest {
+        archived: Some(true),
+        ..Default::default()
+    };
+    let updated = repo.update_list(work.id, archive).await.unwrap().unwrap();
+    assert!(updated.archived);
+    assert_eq!(updated.name, "Work");
+
+    assert!(repo.delete_list(work.id).await.unwrap());
+    assert!(repo.get_list(work.id).await.unwrap().is_none());
+    assert!(!repo.delete_list(work.id).await.unwrap());
+}
+
+async fn query_filters_by_list(repo: &dyn TodoRepository) {
+    let work = CreateListRequest {
+        name: "Work".to_string(),
+        ..Default::default()
+    };
+    let work = repo.create_list(work).await.unwrap();
+
+    repo.create(create("inbox")).await.unwrap();
+    let request = CreateTodoRequest {
+        title: "report".to_string(),
+        list_id: Some(work.id),
+        ..Default::default()
+    };
+    let report = repo.create(request).await.unwrap();
+    assert_eq!(report.list_id, work.id);
+
+    let query = TodoQuery {
+        list_id: Some(DEFAULT_LIST_ID),
+        ..Default::default()
+    };
+    let (todos, total) = repo.query(&query).await.unwrap();
+    assert_eq!(total, 1);
+    assert_eq!(todos[0].title, "inbox");
+
+    let move_back = UpdateTodoRequest {
+        list_id: Some(DEFAULT_LIST_ID),
+        ..Default::default()
+    };
+    repo.update(report.id, move_back, None).await.unwrap();
+    let (_, total) = repo.query(&query).await.unwrap();
+    assert_eq!(total, 2);
+}
+
+macro_rules! conformance {
+    ($backend:ident, $open:expr) => {
+        mod $backend {
+            use super::*;
+
+            macro_rules! check {
+                ($name:ident) => {
+                    #[tokio::test]
+                    async fn $name() {
+                        let dir = TempDir::new().unwrap();
+                        let open: fn(&TempDir) -> Box<dyn TodoRepository> = $open;
+                        let repo = open(&dir);
+                        super::$name(repo.as_ref()).await;
+                    }
+                };
+            }
+
+            check!(create_assigns_increasing_ids);
+            check!(get_returns_created_todo);
+            check!(list_returns_every_todo);
+            check!(update_changes_only_given_fields);
+            check!(update_missing_returns_none);
+            check!(replace_overwrites_whole_todo);
+            check!(delete_removes_todo);
+            check!(writes_bump_revision_and_check_expected_one);
+            check!(batch_applies_every_operation);
+            check!(failed_batch_applies_nothing);
+            check!(batch_replace_checks_the_revision);
+            check!(batch_creates_under_earlier_creates);
+            check!(batch_restore_takes_todos_out_of_the_trash);
+            check!(history_records_every_mutation);
+            check!(restore_recreates_deleted_todo);
+            check!(delete_moves_todo_to_trash);
+            check!(purge_deletes_for_good);
+            check!(purge_deleted_before_only_takes_older_todos);
+            check!(changes_since_reports_upserts_and_deletes);
+            check!(changes_since_pages_with_limit);
+            check!(deleted_ids_are_not_reused);
+            check!(query_filters_sorts_and_pages);
+            check!(rich_fields_round_trip);
+            check!(query_filters_by_tag_and_due_date);
+            check!(lists_have_default_and_crud);
+            check!(query_filters_by_list);
+        }
+    };
+}
+
+conformance!(memory, |_| Box::new(StorageRepository::in_memory()));
+conformance!(json, |dir| Box::new(
+    StorageRepository::json_file(dir.path().join("todos.json")).unwrap()
+));
+conformance!(sqlite, |dir| Box::new(
+    SqliteRepository::open(dir.path().join("todos.db")).unwrap()
+));
+conformance!(debounced, |dir| Box::new(
+    StorageRepository::json_file(dir.path().join("todos.json"))
+        .unwrap()
+        .with_durability(Durability::Debounced {
+            delay: std::time::Duration::from_millis(5)
+        })
+        .unwrap()
+));
+conformance!(encrypted, |dir| Box::new(
+    StorageRepository::encrypted_json_file(
+        dir.path().join("todos.json"),
+        Cipher::from_key(&Cipher::generate_key().unwrap()).unwrap()
+    )
+    .unwrap()
+));
+// What one user sees of a shared store
+conformance!(scoped, |dir| Box::new(ScopedRepository::new(
+    Arc::new(SqliteRepository::open(dir.path().join("todos.db")).unwrap()),
+    DEFAULT_OWNER_ID
+)));
+
+// The JSON and SQLite backends must keep their data across a restart
+#[tokio::test]
+async fn persistent_backends_survive_reopen() {
+    let dir = TempDir::new().unwrap();
+    let json = dir.path().join("todos.json");
+    let db = dir.path().join("todos.db");
+
+    {
+        let repo = StorageRepository::json_file(&json).unwrap();
+        repo.create(create("json")).await.unwrap();
+        let repo = SqliteRepository::open(&db).unwrap();
+        repo.create(create("sqlite")).await.unwrap();
+    }
+
+    let repo = StorageRepository::json_file(&json).unwrap();
+    assert_eq!(repo.get(1).await.unwrap().unwrap().title, "json");
+    let repo = SqliteRepository::open(&db).unwrap();
+    assert_eq!(repo.get(1).await.unwrap().unwrap().title, "sqlite");
+}
+
+// Only the newest `max_revisions` entries of a todo are kept, on every backend
+#[tokio::test]
+async fn history_retention_drops_oldest_revisions() {
+    let dir = TempDir::new().unwrap();
+    let retention = HistoryRetention {
+        max_revisions: 2,
+        max_age: None,
+    };
+    let repos: Vec<Box<dyn TodoRepository>> = vec![
+        Box::new(StorageRepository::in_memory().with_history_retention(retention).unwrap()),
+        Box::new(
+            StorageRepository::json_file(dir.path().join("todos.json"))
+                .unwrap()
+                .with_history_retention(retention)
+                .unwrap(),
+        ),
+        Box::new(
+            SqliteRepository::open(dir.path().join("todos.db"))
+                .unwrap()
+                .with_history_retention(retention)
+                .unwrap(),
+        ),
+    ];
+
+    for repo in repos {
+        let todo = repo.create(create("v1")).await.unwrap();
+        for title in ["v2", "v3", "v4"] {
+            let rename = UpdateTodoRequest {
+                title: Some(title.to_string()),
+                ..Default::default()
+            };
+            repo.update(todo.id, rename, None).await.unwrap();
+        }
+
+        let history = repo.history(todo.id).await.unwrap();
+        let titles: Vec<&str> = history
+            .iter()
+            .map(|entry| entry.after.as_ref().unwrap().title.as_str())
+            .collect();
+        assert_eq!(titles, ["v3", "v4"]);
+    }
+}
@@ -0,0 +1,144 @@
+// Storage failures as the API reports them, and writes that could not be
+// journaled leaving nothing behind.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::response::IntoResponse;
+use reqwest::StatusCode;
+use rest_api::error::{ApiError, StorageError};
+use serde_json::{Value, json};
+use std::io;
+
+async fn error_body(err: io::Error) -> (StatusCode, Value) {
+    let response = ApiError::from(StorageError::from(err)).into_response();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn io_errors_map_to_status_and_code() {
+    let (status, body) = error_body(io::ErrorKind::StorageFull.into()).await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(body["code"], "storage_full");
+    assert!(body["error"].as_str().unwrap().starts_with("storage is full"));
+
+    for kind in [io::ErrorKind::PermissionDenied, io::ErrorKind::ReadOnlyFilesystem] {
+        let (status, body) = error_body(kind.into()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "storage_unavailable");
+    }
+
+    let (status, body) = error_body(io::Error::other("disk on fire")).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    let expected = json!({ "error": "storage I/O error: disk on fire", "code": "storage_error" });
+    assert_eq!(body, expected);
+}
+
+#[cfg(target_os = "linux")]
+mod unwritable {
+    use crate::common::{create, serve_app};
+    use reqwest::StatusCode;
+    use rest_api::repository::{StorageRepository, TodoRepository};
+    use rest_api::routers::create_routes;
+    use rustix::fs::{MemfdFlags, SealFlags, fcntl_add_seals, memfd_create};
+    use serde_json::{Value, json};
+    use std::fs;
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    // A JSON repository at `dir` holding one todo, whose journal stops
+    // taking writes once the returned seal is applied. Permissions would
+    // not stop root; a write-sealed memfd, opened through /proc, stops
+    // everyone with EPERM.
+    async fn repository(dir: &Path) -> (StorageRepository, OwnedFd) {
+        let json = dir.join("todos.json");
+        {
+            let repo = StorageRepository::json_file(&json).unwrap();
+            repo.create(create("before")).await.unwrap();
+        }
+        // Compacts the journal into todos.json
+        drop(StorageRepository::json_file(&json).unwrap());
+
+        let journal = json.with_extension("journal");
+        fs::remove_file(&journal).unwrap();
+        let memfd = memfd_create("todos.journal", MemfdFlags::ALLOW_SEALING).unwrap();
+        symlink(format!("/proc/self/fd/{}", memfd.as_raw_fd()), &journal).unwrap();
+        let repo = StorageRepository::json_file(&json).unwrap();
+        (repo, memfd)
+    }
+
+    fn seal(memfd: &OwnedFd) {
+        fcntl_add_seals(memfd, SealFlags::WRITE).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_writes_answer_503_and_are_rolled_back() {
+        let dir = TempDir::new().unwrap();
+        let (repo, memfd) = repository(dir.path()).await;
+        let server = serve_app(create_routes(Arc::new(repo), None, None)).await;
+        seal(&memfd);
+
+        let http = reqwest::Client::new();
+        let todos = format!("{}/todos", server);
+        let unavailable = |response: reqwest::Response| async move {
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["code"], "storage_unavailable");
+        };
+
+        let create = http.post(&todos).json(&json!({ "title": "lost" })).send().await.unwrap();
+        unavailable(create).await;
+        let update = http
+            .put(format!("{}/1", todos))
+            .json(&json!({ "title": "changed", "completed": true }))
+            .send()
+            .await
+            .unwrap();
+        unavailable(update).await;
+        let delete = http.delete(format!("{}/1", todos)).send().await.unwrap();
+        unavailable(delete).await;
+        let batch = json!({ "operations": [
+            { "op": "create", "todo": { "title": "a" } },
+            { "op": "delete", "id": 1 },
+        ] });
+        let batch = http.post(format!("{}/batch", todos)).json(&batch).send().await.unwrap();
+        unavailable(batch).await;
+
+        let get = |url: String| {
+            let http = http.clone();
+            async move { http.get(url).send().await.unwrap().json::<Value>().await.unwrap() }
+        };
+        let list = get(todos.clone()).await;
+        assert_eq!(list["total"], 1);
+        assert_eq!(list["todos"][0]["title"], "before");
+        let todo = get(format!("{}/1", todos)).await;
+        assert_eq!(todo["completed"], false);
+        assert_eq!(todo["revision"], 1);
+        assert_eq!(get(format!("{}/trash", server)).await, json!([]));
+    }
+
+    // Nothing half-written stays in the journal, and no id or change
+    // number is used up by the failed writes
+    #[tokio::test]
+    async fn failed_writes_leave_journal_and_counters_alone() {
+        let dir = TempDir::new().unwrap();
+        let (repo, memfd) = repository(dir.path()).await;
+        let seq = repo.changes_since(0, 100).await.unwrap().seq;
+        seal(&memfd);
+
+        assert!(repo.create(create("lost")).await.is_err());
+        assert!(repo.delete(1, None).await.is_err());
+        assert_eq!(fs::read(dir.path().join("todos.journal")).unwrap(), b"");
+
+        assert_eq!(repo.list().await.unwrap().len(), 1);
+        assert!(repo.get(2).await.unwrap().is_none());
+        assert!(repo.trash().await.unwrap().is_empty());
+        assert_eq!(repo.history(1).await.unwrap().len(), 1);
+        assert_eq!(repo.changes_since(0, 100).await.unwrap().seq, seq);
+    }
+}